@@ -0,0 +1,87 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time resolving taxids against a rankedlineage.dmp-sized dump
+//!
+//! Run with `cargo bench --bench populate`. Parsing is timed on a single thread and on one thread
+//! per CPU.
+
+use std::collections::HashSet;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use asdb_taxa::taxa::TaxId;
+use asdb_taxa::{parallel, TaxonCache};
+
+const LINES: i64 = 500_000;
+
+/// A dump in the domain layout, with a handful of different lineages
+fn taxdump(lines: i64) -> String {
+    let genera = [
+        "Streptomyces",
+        "Aspergillus",
+        "Bacillus",
+        "Nocardia",
+        "Pseudomonas",
+    ];
+    let mut dump = String::new();
+    for tax_id in 1..=lines {
+        let genus = genera[tax_id as usize % genera.len()];
+        dump.push_str(&format!(
+            "{}\t|\t{} sp. {}\t|\t{} sp.\t|\t{}\t|\t{}aceae\t|\t{}ales\t|\tActinomycetes\t|\tActinomycetota\t|\tBacillati\t|\t\t|\tBacteria\t|\n",
+            tax_id, genus, tax_id, genus, genus, genus, genus
+        ));
+    }
+    dump
+}
+
+fn populate(c: &mut Criterion) {
+    let dump = taxdump(LINES);
+    // one needed taxid in a thousand, like a database of a few thousand genomes
+    let taxids: HashSet<TaxId> = (1..=LINES)
+        .step_by(1000)
+        .map(|tax_id| TaxId::new(tax_id).expect("generated taxids are positive"))
+        .collect();
+
+    let mut jobs = vec![1];
+    if parallel::default_jobs() > 1 {
+        jobs.push(parallel::default_jobs());
+    }
+    let mut group = c.benchmark_group("populate");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+    for jobs in jobs {
+        group.bench_with_input(BenchmarkId::new("jobs", jobs), &jobs, |b, &jobs| {
+            b.iter(|| {
+                let mut cache = TaxonCache::new();
+                cache
+                    .initialise_with_jobs(
+                        dump.as_bytes(),
+                        "".as_bytes(),
+                        &mut taxids.clone(),
+                        None,
+                        jobs,
+                        &mut |_| (),
+                    )
+                    .expect("the generated dump is valid");
+                assert_eq!(cache.len(), taxids.len());
+                cache
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, populate);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generates the C header for the `capi` feature
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("set by cargo");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+            .expect("cbindgen.toml is valid");
+        cbindgen::generate_with_config(&crate_dir, config)
+            .expect("C API can be described in a header")
+            .write_to_file(format!("{}/include/asdb_taxa.h", crate_dir));
+    }
+}
@@ -0,0 +1,63 @@
+#ifndef ASDB_TAXA_H
+#define ASDB_TAXA_H
+
+/* Generated by cbindgen from src/capi.rs, don't edit by hand */
+
+#include <stdint.h>
+
+// Opaque handle of a loaded cache
+typedef struct AsdbTaxonCache AsdbTaxonCache;
+
+// An NCBI taxid, always positive
+//
+// Parses from the plain number or the `taxon:12345` notation of GFF and GO annotations, and
+// serializes as the plain number.
+typedef struct TaxId TaxId;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Load the cache at `path`, in any format and compression
+//
+// Returns `NULL` on failure. Free the cache with [asdb_taxa_free].
+//
+// # Safety
+//
+// `path` must be a valid, NUL-terminated string.
+struct AsdbTaxonCache *asdb_taxa_load(const char *path);
+
+// The entry of `taxid` as a JSON object, following merged taxids
+//
+// Returns `NULL` if the taxid is unknown or was deleted. Free the string with
+// [asdb_taxa_string_free].
+//
+// # Safety
+//
+// `cache` must come from [asdb_taxa_load] and not have been freed.
+char *asdb_taxa_lookup(const struct AsdbTaxonCache *cache, int64_t taxid);
+
+// Message of the last failure on this thread, `NULL` if the last call succeeded
+//
+// The message stays valid until the next call on the same thread and must not be freed.
+const char *asdb_taxa_last_error(void);
+
+// Free a cache loaded with [asdb_taxa_load], `NULL` is ignored
+//
+// # Safety
+//
+// `cache` must come from [asdb_taxa_load] and not have been freed already.
+void asdb_taxa_free(struct AsdbTaxonCache *cache);
+
+// Free a string returned by [asdb_taxa_lookup], `NULL` is ignored
+//
+// # Safety
+//
+// `string` must come from [asdb_taxa_lookup] and not have been freed already.
+void asdb_taxa_string_free(char *string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ASDB_TAXA_H */
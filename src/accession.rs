@@ -0,0 +1,150 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! INSDC accession to taxid mappings from NCBI's `accession2taxid` files
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+use log::info;
+use regex::Regex;
+
+use crate::compression;
+use crate::errors::ASDBTaxonError;
+use crate::extract::TaxidSource;
+use crate::taxa::TaxId;
+
+/// Finds the record accessions in data files, without their version
+pub struct AccessionFinder {
+    json: Regex,
+    flatfile: Regex,
+}
+
+impl AccessionFinder {
+    pub fn new() -> Result<AccessionFinder, ASDBTaxonError> {
+        Ok(AccessionFinder {
+            json: Regex::new(r#""id":\s*"([A-Z]{1,6}_?[0-9]{5,}(?:\.[0-9]+)?)""#)?,
+            flatfile: Regex::new(r"(?m)^(?:ACCESSION|AC)\s+([A-Z]{1,6}_?[0-9]{5,})")?,
+        })
+    }
+
+    /// Distinct accessions of the records in `content`, e.g. `NC_003888` for `NC_003888.3`
+    pub fn find(&self, source: TaxidSource, content: &str) -> Vec<String> {
+        let regex = match source {
+            TaxidSource::Json => &self.json,
+            TaxidSource::GenBank => &self.flatfile,
+        };
+        let mut accessions: Vec<String> = Vec::new();
+        for cap in regex.captures_iter(content) {
+            let accession = unversioned(&cap[1]);
+            if !accessions.iter().any(|known| known == accession) {
+                accessions.push(accession.to_string());
+            }
+        }
+        accessions
+    }
+}
+
+/// Look up the taxids of `accessions` in an `accession2taxid` file
+///
+/// Only the needed accessions are kept, as the NCBI files list hundreds of millions. Both the
+/// four column `nucl_gb` layout and the two column `.FULL` layout are understood.
+pub fn lookup(
+    input: impl Read,
+    accessions: &HashSet<String>,
+) -> Result<HashMap<String, TaxId>, ASDBTaxonError> {
+    let mut mappings = HashMap::new();
+    for line in io::BufReader::new(input).lines() {
+        let line = line?;
+        let parts: Vec<&str> = line.split('\t').collect();
+        let (accession, taxid) = match parts.as_slice() {
+            [accession, _, taxid, ..] => (*accession, *taxid),
+            [accession, taxid] => (unversioned(accession), *taxid),
+            _ => continue,
+        };
+        if !accessions.contains(accession) {
+            continue;
+        }
+        // the header line doesn't parse and is skipped here
+        if let Ok(taxid) = taxid.parse::<TaxId>() {
+            mappings.insert(accession.to_string(), taxid);
+            if mappings.len() == accessions.len() {
+                break;
+            }
+        }
+    }
+    info!(
+        "Mapped {} of {} accessions to taxids",
+        mappings.len(),
+        accessions.len()
+    );
+    Ok(mappings)
+}
+
+/// Like [lookup], reading a plain or compressed file
+pub fn lookup_path(
+    path: &Path,
+    accessions: &HashSet<String>,
+) -> Result<HashMap<String, TaxId>, ASDBTaxonError> {
+    lookup(compression::open(path)?, accessions)
+}
+
+fn unversioned(accession: &str) -> &str {
+    accession.split('.').next().unwrap_or(accession)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_find_accessions() {
+        let finder = AccessionFinder::new().unwrap();
+        let json =
+            r#"{"records": [{"id": "NC_003888.3"}, {"id": "AL645882.2"}, {"id": "NC_003888.3"}]}"#;
+        assert_eq!(
+            finder.find(TaxidSource::Json, json),
+            vec!["NC_003888", "AL645882"]
+        );
+        let genbank = "LOCUS       NC_003888\nACCESSION   NC_003888\nVERSION     NC_003888.3\n";
+        assert_eq!(
+            finder.find(TaxidSource::GenBank, genbank),
+            vec!["NC_003888"]
+        );
+        let embl = "ID   X56734; SV 1;\nAC   X56734;\n";
+        assert_eq!(finder.find(TaxidSource::GenBank, embl), vec!["X56734"]);
+        assert!(finder
+            .find(TaxidSource::Json, r#"{"id": "region1"}"#)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let needed = HashSet::from(["NC_003888".to_string(), "X56734".to_string()]);
+        let nucl_gb = "accession\taccession.version\ttaxid\tgi\n\
+            AL645882\tAL645882.2\t100226\t32141095\n\
+            NC_003888\tNC_003888.3\t100226\t21223605\n";
+        let mappings = lookup(nucl_gb.as_bytes(), &needed).unwrap();
+        assert_eq!(
+            mappings,
+            HashMap::from([("NC_003888".to_string(), tid(100226))])
+        );
+
+        let full = "accession.version\ttaxid\nX56734.1\t3899\n";
+        let mappings = lookup(full.as_bytes(), &needed).unwrap();
+        assert_eq!(mappings, HashMap::from([("X56734".to_string(), tid(3899))]));
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replacing files without anyone ever seeing them half-written
+//!
+//! The new content goes to a `<file>.tmp` next to the file first, which is then renamed over it.
+//! Renames within a directory are atomic, so readers see either the old or the new file, even
+//! when the writer crashes.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ASDBTaxonError;
+
+/// Write `data` to `path` via a temporary file
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<(), ASDBTaxonError> {
+    replace_with(path, |tmp_path| Ok(fs::write(tmp_path, data)?))
+}
+
+/// Replace `path` with the file `write` creates at the temporary path it is given
+///
+/// The temporary file is removed again if `write` fails.
+pub(crate) fn replace_with<T>(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<T, ASDBTaxonError>,
+) -> Result<T, ASDBTaxonError> {
+    let tmp_path = tmp_path(path);
+    let result = write(&tmp_path).and_then(|value| {
+        fs::rename(&tmp_path, path)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_replace_with() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("cache.json");
+        write_atomically(&path, b"old").unwrap();
+
+        let failed = replace_with(&path, |tmp_path| {
+            fs::write(tmp_path, "half")?;
+            Err::<(), _>(ASDBTaxonError::Cancelled)
+        });
+        assert!(matches!(failed, Err(ASDBTaxonError::Cancelled)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(!tmp_path(&path).exists());
+
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+}
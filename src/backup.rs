@@ -0,0 +1,105 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rotated backups of a cache, kept before it's overwritten
+//!
+//! The newest backup is `<cache>.1`, older ones `<cache>.2` and so on. The scan manifest is
+//! backed up with the cache as `<cache>.1.manifest`, so a restored backup and its manifest agree
+//! on which data files were scanned.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ASDBTaxonError;
+use crate::manifest::manifest_path;
+
+/// Where backup number `n` of `cache_file` is kept, 1 being the newest
+pub fn backup_path(cache_file: &Path, n: usize) -> PathBuf {
+    let mut path = cache_file.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+}
+
+/// Move `cache_file` to the newest of `keep` backups, shifting the older ones and dropping the
+/// oldest, returning whether there was a cache to back up
+///
+/// The cache is moved rather than copied, so call this right before saving the new one.
+pub fn rotate(cache_file: &Path, keep: usize) -> Result<bool, ASDBTaxonError> {
+    if keep == 0 || !cache_file.exists() {
+        return Ok(false);
+    }
+    for n in (1..keep).rev() {
+        let (from, to) = (backup_path(cache_file, n), backup_path(cache_file, n + 1));
+        replace(&from, &to, false)?;
+        replace(&manifest_path(&from), &manifest_path(&to), false)?;
+    }
+    let newest = backup_path(cache_file, 1);
+    // the manifest is updated in place, so it needs copying
+    replace(&manifest_path(cache_file), &manifest_path(&newest), true)?;
+    replace(cache_file, &newest, false)?;
+    Ok(true)
+}
+
+/// Move or copy `from` to `to`, removing `to` if there is no `from`
+fn replace(from: &Path, to: &Path, copy: bool) -> Result<(), ASDBTaxonError> {
+    match fs::remove_file(to) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => (),
+    }
+    if !from.exists() {
+        return Ok(());
+    }
+    if copy {
+        fs::copy(from, to)?;
+    } else {
+        fs::rename(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_rotate() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let cache_file = dir.join("cache.json");
+        let read = |path: PathBuf| fs::read_to_string(path).ok();
+
+        assert!(!rotate(&cache_file, 2).unwrap());
+        for version in ["first", "second", "third"] {
+            fs::write(&cache_file, version).unwrap();
+            fs::write(manifest_path(&cache_file), version).unwrap();
+            assert!(rotate(&cache_file, 2).unwrap());
+        }
+        assert!(!cache_file.exists());
+        assert_eq!(read(backup_path(&cache_file, 1)).as_deref(), Some("third"));
+        assert_eq!(read(backup_path(&cache_file, 2)).as_deref(), Some("second"));
+        assert_eq!(read(backup_path(&cache_file, 3)), None);
+        assert_eq!(
+            read(manifest_path(&backup_path(&cache_file, 2))).as_deref(),
+            Some("second")
+        );
+        // the manifest stays in place for the next incremental build
+        assert_eq!(read(manifest_path(&cache_file)).as_deref(), Some("third"));
+
+        fs::write(&cache_file, "fourth").unwrap();
+        assert!(!rotate(&cache_file, 0).unwrap());
+        assert!(cache_file.exists());
+    }
+}
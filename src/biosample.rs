@@ -0,0 +1,206 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BioSample and BioProject accession to taxid mappings
+
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use log::warn;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::errors::ASDBTaxonError;
+#[cfg(feature = "fs")]
+use crate::gzip;
+use crate::taxa::TaxId;
+
+/// Maps BioSample accessions like `SAMN02604091` and BioProject accessions like `PRJNA224116`
+/// to taxids
+#[derive(Debug, Clone, Default)]
+pub struct BioSampleMap {
+    pub mappings: HashMap<String, TaxId>,
+}
+
+impl BioSampleMap {
+    pub fn new() -> BioSampleMap {
+        BioSampleMap {
+            mappings: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, accession: &str) -> Option<TaxId> {
+        self.mappings.get(accession).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Load a tab-separated `accession<TAB>taxid` file, skipping comments and malformed lines
+    pub fn load_tsv(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let before = self.mappings.len();
+        for line in io::BufReader::new(input).lines() {
+            let line = line?;
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split('\t');
+            let (Some(accession), Some(taxid)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            if let Ok(taxid) = taxid.trim().parse::<TaxId>() {
+                self.mappings.insert(accession.trim().to_string(), taxid);
+            }
+        }
+        Ok(self.mappings.len() - before)
+    }
+
+    /// Load the NCBI `biosample_set.xml` or `bioproject.xml` dump, pairing each BioSample or
+    /// BioProject with its organism taxid
+    ///
+    /// Records with an invalid taxid are skipped with a warning.
+    pub fn load_xml(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let invalid = |err: &dyn std::fmt::Display| {
+            ASDBTaxonError::InvalidFormat(format!("BioSample/BioProject XML: {}", err))
+        };
+        let attribute = |tag: &BytesStart, name: &str| -> Result<Option<String>, ASDBTaxonError> {
+            match tag.try_get_attribute(name).map_err(|err| invalid(&err))? {
+                Some(value) => Ok(Some(
+                    value
+                        .unescape_value()
+                        .map_err(|err| invalid(&err))?
+                        .into_owned(),
+                )),
+                None => Ok(None),
+            }
+        };
+
+        let before = self.mappings.len();
+        let mut reader = Reader::from_reader(io::BufReader::new(input));
+        let mut buf = Vec::new();
+        let mut current: Option<String> = None;
+        loop {
+            match reader
+                .read_event_into(&mut buf)
+                .map_err(|err| invalid(&err))?
+            {
+                Event::Start(tag) | Event::Empty(tag) => match tag.name().as_ref() {
+                    b"BioSample" | b"ArchiveID" => current = attribute(&tag, "accession")?,
+                    b"Organism" => {
+                        let taxid = match attribute(&tag, "taxonomy_id")? {
+                            Some(taxid) => Some(taxid),
+                            None => attribute(&tag, "taxID")?,
+                        };
+                        if let (Some(taxid), Some(accession)) = (taxid, current.take()) {
+                            match taxid.parse::<TaxId>() {
+                                Ok(taxid) => {
+                                    self.mappings.insert(accession, taxid);
+                                }
+                                Err(err) => warn!("Skipping {}: {}", accession, err),
+                            }
+                        }
+                    }
+                    _ => (),
+                },
+                Event::End(tag) if matches!(tag.name().as_ref(), b"BioSample" | b"Package") => {
+                    current = None
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        Ok(self.mappings.len() - before)
+    }
+
+    /// Load a mapping file, picking the format based on the file name, optionally gzipped
+    #[cfg(feature = "fs")]
+    pub fn load_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        let name = path.to_string_lossy();
+        let mut handle = io::BufReader::new(fs::File::open(path)?);
+        let input: Box<dyn Read> = if gzip::is_gzip(handle.fill_buf()?) {
+            Box::new(gzip::decoder(handle))
+        } else {
+            Box::new(handle)
+        };
+        if name.trim_end_matches(".gz").ends_with(".xml") {
+            self.load_xml(input)
+        } else {
+            self.load_tsv(input)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_load() {
+        let mut map = BioSampleMap::new();
+        let tsv = "# accession\ttaxid\nSAMN00000001\t1883\nbroken line\n";
+        assert_eq!(map.load_tsv(tsv.as_bytes()).unwrap(), 1);
+
+        let xml = r#"<BioSampleSet>
+<BioSample access="public" id="2" accession="SAMEA0000002">
+  <Description>
+    <Organism taxonomy_id="5062" taxonomy_name="Aspergillus oryzae"/>
+  </Description>
+</BioSample>
+<BioSample access="public" id="4"
+    accession="SAMN0000004"><Description><Organism
+    taxonomy_name="Streptomyces &amp; co" taxonomy_id="1883"/></Description></BioSample>
+<BioSample access="public" id="5" accession="SAMN0000005">
+  <Description><Organism taxonomy_id="0"/></Description>
+</BioSample>
+<BioSample access="public" id="6" accession="SAMN0000006"/>
+<BioSample access="public" id="7" accession="SAMN0000007">
+  <Description><Organism taxonomy_id="1902"/></Description>
+</BioSample>
+</BioSampleSet>"#;
+        assert_eq!(map.load_xml(xml.as_bytes()).unwrap(), 3);
+        assert_eq!(map.get("SAMN0000004"), Some(tid(1883)));
+        assert_eq!(map.get("SAMN0000005"), None);
+        assert_eq!(map.get("SAMN0000006"), None);
+        assert_eq!(map.get("SAMN0000007"), Some(tid(1902)));
+        assert!(map.load_xml("<BioSampleSet><BioSample".as_bytes()).is_err());
+
+        let xml = r#"<PackageSet>
+<Package><Project><Project>
+  <ProjectID>
+    <ArchiveID accession="PRJNA0000003" archive="NCBI" id="3"/>
+  </ProjectID>
+  <ProjectType><ProjectTypeSubmission><Target>
+    <Organism species="1883" taxID="1883"><OrganismName>Streptomyces</OrganismName></Organism>
+  </Target></ProjectTypeSubmission></ProjectType>
+</Project></Project></Package>
+</PackageSet>"#;
+        assert_eq!(map.load_xml(xml.as_bytes()).unwrap(), 1);
+
+        assert_eq!(map.get("SAMN00000001"), Some(tid(1883)));
+        assert_eq!(map.get("SAMEA0000002"), Some(tid(5062)));
+        assert_eq!(map.get("PRJNA0000003"), Some(tid(1883)));
+        assert_eq!(map.get("SAMD0000003"), None);
+    }
+}
@@ -0,0 +1,1028 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache building pipeline with hooks into the individual build phases
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::biosample::BioSampleMap;
+use crate::cancel::{CancelReader, CancelToken};
+use crate::deadline::{Deadline, DeadlineReader};
+#[cfg(feature = "network")]
+use crate::entrez::EntrezClient;
+use crate::errors::ASDBTaxonError;
+use crate::extract::{Rule, TaxidExtractor};
+use crate::glob::Pattern;
+use crate::gtdb::GtdbMap;
+use crate::layout::ColumnMapping;
+use crate::manifest::ScanManifest;
+use crate::metadata::{CacheMetadata, DumpInfo};
+use crate::nodes::TaxonTree;
+use crate::overrides::Overrides;
+use crate::parallel;
+use crate::progress::{ProgressHook, ProgressObserver, ProgressReader, Stage};
+use crate::provenance;
+use crate::releases::Release;
+use crate::remote;
+use crate::scan::{self, ScanOptions, ScanReport};
+use crate::silva::SilvaMap;
+use crate::taxa::{EntrySource, NcbiTaxEntry, TaxId};
+use crate::taxdump::{self, TaxdumpDir};
+use crate::taxonomy::Taxonomy;
+use crate::update::{UpdateRecord, UpdateReport};
+use crate::{TaxonCache, MAX_MERGE_DEPTH};
+
+pub type FileScannedHook = Box<dyn FnMut(&Path, Option<TaxId>) + Send>;
+pub type TaxidResolvedHook = Box<dyn FnMut(&NcbiTaxEntry) + Send>;
+pub type MissingTaxidHook = Box<dyn FnMut(&ASDBTaxonError) + Send>;
+
+/// Callbacks invoked while a cache is being built
+#[derive(Default)]
+pub struct Hooks {
+    /// Called for every data file scanned, with the first taxid found in it, if any
+    pub on_file_scanned: Option<FileScannedHook>,
+    /// Called for every entry added to the cache
+    pub on_taxid_resolved: Option<TaxidResolvedHook>,
+    /// Called for every needed taxid that couldn't be resolved, with the reason
+    pub on_missing_taxid: Option<MissingTaxidHook>,
+    /// Called periodically during the long-running stages
+    pub on_progress: Option<ProgressHook>,
+    /// Notified along with [Hooks::on_progress]
+    pub observer: Option<Arc<dyn ProgressObserver>>,
+}
+
+/// Configuration of a cache build from taxdump files and datadirs
+#[derive(Default)]
+pub struct TaxonCacheBuilder {
+    datadirs: Vec<PathBuf>,
+    taxids: Option<HashSet<TaxId>>,
+    taxid_lists: Vec<PathBuf>,
+    taxdump: Option<PathBuf>,
+    merged_id_dump: Option<PathBuf>,
+    deleted_id_dump: Option<PathBuf>,
+    nodes_dump: Option<PathBuf>,
+    max_merge_depth: Option<usize>,
+    jobs: Option<usize>,
+    full_species: bool,
+    columns: Option<ColumnMapping>,
+    gtdb_taxonomy: Vec<PathBuf>,
+    gtdb_metadata: Vec<PathBuf>,
+    silva_taxmap: Vec<PathBuf>,
+    silva_ena_taxmap: Vec<PathBuf>,
+    overrides: Option<Overrides>,
+    scan_options: ScanOptions,
+    deadline: Option<Deadline>,
+    manifest: Option<ScanManifest>,
+    biosamples: Option<BioSampleMap>,
+    release: Option<String>,
+    strict: bool,
+    #[cfg(feature = "network")]
+    entrez: Option<EntrezClient>,
+    hooks: Hooks,
+}
+
+impl TaxonCacheBuilder {
+    pub fn new() -> TaxonCacheBuilder {
+        Default::default()
+    }
+
+    /// Fail the build instead of just reporting needed taxids that couldn't be resolved
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Tag of the taxonomy release built from, instead of the date of the taxdump
+    pub fn release(mut self, tag: impl Into<String>) -> Self {
+        self.release = Some(tag.into());
+        self
+    }
+
+    /// Add a datadir to scan, can be called multiple times
+    pub fn datadir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.datadirs.push(path.into());
+        self
+    }
+
+    /// Taxids to add regardless of the datadir, which becomes optional
+    pub fn taxids(mut self, taxids: impl IntoIterator<Item = TaxId>) -> Self {
+        self.taxids.get_or_insert_with(HashSet::new).extend(taxids);
+        self
+    }
+
+    /// Add the taxids listed in a file, see [scan::read_taxid_list], can be called multiple times
+    pub fn taxid_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.taxid_lists.push(path.into());
+        self
+    }
+
+    pub fn taxdump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.taxdump = Some(path.into());
+        self
+    }
+
+    pub fn merged_id_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.merged_id_dump = Some(path.into());
+        self
+    }
+
+    pub fn deleted_id_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.deleted_id_dump = Some(path.into());
+        self
+    }
+
+    /// Take the taxdump, merged and deleted ID dumps not configured yet from `dir`
+    ///
+    /// nodes.dmp is left out, as it also adds all ancestors, see [Self::nodes_dump].
+    pub fn taxdump_dir(mut self, dir: &TaxdumpDir) -> Self {
+        self.taxdump = self.taxdump.or_else(|| dir.find(taxdump::RANKEDLINEAGE));
+        self.merged_id_dump = self.merged_id_dump.or_else(|| dir.find(taxdump::MERGED));
+        self.deleted_id_dump = self.deleted_id_dump.or_else(|| dir.find(taxdump::DELNODES));
+        self
+    }
+
+    /// Also add all ancestors of the requested taxids, using the parent links in nodes.dmp
+    pub fn nodes_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.nodes_dump = Some(path.into());
+        self
+    }
+
+    /// Fail on chains of more than `depth` merged IDs, see [crate::MAX_MERGE_DEPTH]
+    pub fn max_merge_depth(mut self, depth: usize) -> Self {
+        self.max_merge_depth = Some(depth);
+        self
+    }
+
+    /// Parse the taxdump on `jobs` threads, by default one per CPU, see [crate::parallel]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Keep the full species names in the entries instead of the epithets
+    pub fn full_species(mut self, full_species: bool) -> Self {
+        self.full_species = full_species;
+        self
+    }
+
+    /// Read the taxdump with `columns` instead of the detected rankedlineage.dmp layout
+    pub fn columns(mut self, columns: ColumnMapping) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    /// Load GTDB lineages from a taxonomy file like `bac120_taxonomy.tsv`, can be called
+    /// multiple times
+    pub fn gtdb_taxonomy(mut self, path: impl Into<PathBuf>) -> Self {
+        self.gtdb_taxonomy.push(path.into());
+        self
+    }
+
+    /// Classify entries with GTDB via a metadata file like `bac120_metadata.tsv`, can be called
+    /// multiple times
+    pub fn gtdb_metadata(mut self, path: impl Into<PathBuf>) -> Self {
+        self.gtdb_metadata.push(path.into());
+        self
+    }
+
+    /// Load SILVA lineages from a SILVA taxmap like `taxmap_slv_ssu_ref_nr_138.2.txt`, can be
+    /// called multiple times
+    pub fn silva_taxmap(mut self, path: impl Into<PathBuf>) -> Self {
+        self.silva_taxmap.push(path.into());
+        self
+    }
+
+    /// Classify entries with SILVA via an ENA taxmap like
+    /// `taxmap_embl-ebi_ena_ssu_ref_nr99_138.2.txt`, can be called multiple times
+    pub fn silva_ena_taxmap(mut self, path: impl Into<PathBuf>) -> Self {
+        self.silva_ena_taxmap.push(path.into());
+        self
+    }
+
+    /// Curated field values to keep in the cache, replacing what the dumps say
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides
+            .get_or_insert_with(Overrides::new)
+            .merge(overrides);
+        self
+    }
+
+    /// Only scan `depth` levels of subdirectories of the datadir, see [scan::MAX_SCAN_DEPTH]
+    pub fn max_scan_depth(mut self, depth: usize) -> Self {
+        self.scan_options.max_depth = depth;
+        self
+    }
+
+    /// Only scan data files matching `pattern` or another included pattern
+    pub fn include(mut self, pattern: Pattern) -> Self {
+        self.scan_options.include.push(pattern);
+        self
+    }
+
+    /// Skip data files and directories matching `pattern`
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.scan_options.exclude.push(pattern);
+        self
+    }
+
+    /// Also look for taxids with `rule` in data files the built-in `taxon:` pattern fails on
+    pub fn taxid_rule(mut self, rule: Rule) -> Self {
+        self.scan_options.rules.add(rule);
+        self
+    }
+
+    /// Scan files matching `pattern` with `extractor`, e.g. for a custom file format
+    pub fn extractor(mut self, pattern: Pattern, extractor: impl TaxidExtractor + 'static) -> Self {
+        self.scan_options.register(pattern, extractor);
+        self
+    }
+
+    /// Map the record accessions of files without taxid via an NCBI `accession2taxid` file
+    pub fn accession2taxid(mut self, path: impl Into<PathBuf>) -> Self {
+        self.scan_options.accession2taxid = Some(path.into());
+        self
+    }
+
+    /// Only use the first taxid found in each data file, not all of them
+    pub fn first_taxid_only(mut self, first_only: bool) -> Self {
+        self.scan_options.first_taxid_only = first_only;
+        self
+    }
+
+    /// Stop scanning and parsing once `limit` has passed from now, keeping what's complete
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.deadline = Some(Deadline::after(limit));
+        self
+    }
+
+    /// Fail with [ASDBTaxonError::Cancelled] once `token` is cancelled, e.g. from another thread
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.scan_options.cancel = Some(token);
+        self
+    }
+
+    /// Skip data files `manifest` lists as unchanged, recording newly scanned ones in it
+    pub fn manifest(mut self, manifest: ScanManifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// The manifest with the files scanned by this build added, to save for the next one
+    pub fn take_manifest(&mut self) -> Option<ScanManifest> {
+        self.manifest.take()
+    }
+
+    /// Whether the build stopped at its time limit
+    pub fn partial(&self) -> bool {
+        self.deadline
+            .as_ref()
+            .is_some_and(|deadline| deadline.reached())
+    }
+
+    pub fn biosamples(mut self, biosamples: BioSampleMap) -> Self {
+        self.biosamples = Some(biosamples);
+        self
+    }
+
+    #[cfg(feature = "network")]
+    pub fn entrez_fallback(mut self, client: EntrezClient) -> Self {
+        self.entrez = Some(client);
+        self
+    }
+
+    pub fn on_file_scanned(
+        mut self,
+        hook: impl FnMut(&Path, Option<TaxId>) + Send + 'static,
+    ) -> Self {
+        self.hooks.on_file_scanned = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_taxid_resolved(mut self, hook: impl FnMut(&NcbiTaxEntry) + Send + 'static) -> Self {
+        self.hooks.on_taxid_resolved = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_missing_taxid(mut self, hook: impl FnMut(&ASDBTaxonError) + Send + 'static) -> Self {
+        self.hooks.on_missing_taxid = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_progress(mut self, hook: impl FnMut(Stage, u64, u64) + Send + 'static) -> Self {
+        self.hooks.on_progress = Some(Box::new(hook));
+        self
+    }
+
+    /// Report the progress of the long-running stages to `observer` as well
+    pub fn observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.hooks.observer = Some(observer);
+        self
+    }
+
+    /// Build a new cache
+    pub fn build(mut self) -> Result<(TaxonCache, ScanReport), ASDBTaxonError> {
+        let mut cache = TaxonCache::new();
+        let report = self.run(&mut cache)?;
+        Ok((cache, report))
+    }
+
+    /// Run all build phases, adding the results to an existing cache
+    pub fn run(&mut self, cache: &mut TaxonCache) -> Result<ScanReport, ASDBTaxonError> {
+        let (mut taxids, report) = self.scan()?;
+        let requested = taxids.clone();
+        // only entries resolved again or overridden can change, so only those are kept to
+        // compare against when stamping their provenance
+        let mut before = HashMap::new();
+        let overridden: Vec<TaxId> = self
+            .overrides
+            .iter()
+            .flat_map(|overrides| overrides.taxa.keys().copied())
+            .collect();
+        keep_entries(cache, &overridden, &mut before);
+        self.resolve_keeping(cache, &mut taxids, Some(&mut before))?;
+        self.check_cancelled()?;
+        // a partial cache is missing taxids anyway, and reported as such
+        if self.strict && !self.partial() {
+            if let Some(err) = cache.missing(&requested).into_iter().next() {
+                return Err(err);
+            }
+        }
+        // overrides kept in the cache apply to rebuilt and newly added entries as well
+        match self.overrides.take() {
+            Some(overrides) => cache.add_overrides(overrides),
+            None => cache.apply_overrides(),
+        };
+        cache.intern_strings();
+        let metadata = self.metadata(cache)?;
+        taxids.extend(overridden);
+        provenance::stamp_taxids(
+            cache,
+            &taxids,
+            &before,
+            metadata.taxdump.as_ref(),
+            metadata.built_at,
+        );
+        cache.metadata = Some(metadata);
+        Ok(report)
+    }
+
+    /// Re-resolve all cached taxids against the configured dumps, returning what changed
+    ///
+    /// Merged, deleted and vanished taxids are dropped from the cache, renames and lineage
+    /// changes applied in place, and all changes recorded in the cache history. Custom entries
+    /// are kept as they are, as are parent links and classifications this configuration has no
+    /// dumps to redo. What changed of the previous release is kept in [TaxonCache::releases].
+    pub fn update(&mut self, cache: &mut TaxonCache) -> Result<UpdateReport, ASDBTaxonError> {
+        let (custom, before): (HashMap<TaxId, NcbiTaxEntry>, HashMap<TaxId, NcbiTaxEntry>) =
+            mem::take(cache.mappings_mut())
+                .into_iter()
+                .partition(|(_, entry)| entry.source == EntrySource::Custom);
+        let mut taxids: HashSet<TaxId> = before.keys().copied().collect();
+        let (deprecated_ids, deleted_ids) =
+            (cache.deprecated_ids.clone(), cache.deleted_ids.clone());
+        if let Err(err) = self.resolve(cache, &mut taxids) {
+            // leave a failed or cancelled update's cache as it was
+            *cache.mappings_mut() = before;
+            cache.mappings_mut().extend(custom);
+            cache.deprecated_ids = deprecated_ids;
+            cache.deleted_ids = deleted_ids;
+            return Err(err);
+        }
+
+        let reclassified = [
+            (Taxonomy::Gtdb, !self.gtdb_metadata.is_empty()),
+            (Taxonomy::Silva, !self.silva_ena_taxmap.is_empty()),
+        ];
+        for (tax_id, entry) in cache.mappings_mut().iter_mut() {
+            let Some(previous) = before.get(tax_id) else {
+                continue;
+            };
+            if self.nodes_dump.is_none() {
+                entry.parent_id = previous.parent_id;
+                entry.rank.clone_from(&previous.rank);
+                entry.ancestor_only = previous.ancestor_only;
+            }
+            for (taxonomy, redone) in reclassified {
+                if let (false, Some(classification)) =
+                    (redone, previous.classifications.get(&taxonomy))
+                {
+                    entry
+                        .classifications
+                        .insert(taxonomy, classification.clone());
+                }
+            }
+        }
+        // older merges into taxids merged again now have to point to the current taxid
+        let merged = cache.deprecated_ids.clone();
+        for into in cache.deprecated_ids.values_mut() {
+            if let Some(current) = merged.get(into) {
+                *into = *current;
+            }
+        }
+
+        match self.overrides.take() {
+            Some(overrides) => cache.add_overrides(overrides),
+            None => cache.apply_overrides(),
+        };
+        let report = UpdateReport::compare(&before, cache);
+
+        let previous = cache.metadata.take();
+        let mut metadata = self.metadata(cache)?;
+        if metadata.datadirs.is_empty() {
+            metadata.datadirs = previous
+                .as_ref()
+                .map(|metadata| metadata.datadirs.clone())
+                .unwrap_or_default();
+        }
+        provenance::stamp(cache, &before, metadata.taxdump.as_ref(), metadata.built_at);
+        if let Some(ref previous) = previous {
+            let release = Release::compare(
+                previous.release_tag(),
+                &before,
+                &deprecated_ids,
+                &deleted_ids,
+                cache,
+            );
+            // an unchanged release needs no record, unless renamed
+            if !release.is_empty() || release.tag != metadata.release_tag() {
+                cache.releases.push(release);
+            }
+        }
+        cache.mappings_mut().extend(custom);
+        metadata.entry_count = cache.mappings.len();
+        cache.intern_strings();
+        cache.history.push(UpdateRecord {
+            updated_at: metadata.built_at,
+            taxdump: metadata.taxdump.clone(),
+            changes: report.changes.clone(),
+        });
+        cache.metadata = Some(metadata);
+        Ok(report)
+    }
+
+    /// Provenance of a build of `cache` with this configuration
+    pub fn metadata(&self, cache: &TaxonCache) -> Result<CacheMetadata, ASDBTaxonError> {
+        let taxdump = match self.taxdump {
+            Some(ref path) => Some(DumpInfo::from_path(path)?),
+            None => None,
+        };
+        Ok(CacheMetadata {
+            datadirs: self
+                .datadirs
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            taxdump,
+            entry_count: cache.mappings.len(),
+            partial: self.partial(),
+            release: self.release.clone(),
+            ..CacheMetadata::new()
+        })
+    }
+
+    /// Scan phase: find the taxids referenced in the datadir, plus the explicitly given ones
+    pub fn scan(&mut self) -> Result<(HashSet<TaxId>, ScanReport), ASDBTaxonError> {
+        for path in mem::take(&mut self.taxid_lists) {
+            let taxids = scan::read_taxid_list(remote::open(&path)?)?;
+            self.taxids.get_or_insert_with(HashSet::new).extend(taxids);
+        }
+        if self.datadirs.is_empty() {
+            return match self.taxids {
+                Some(ref taxids) => Ok((taxids.clone(), ScanReport::default())),
+                None => Err(ASDBTaxonError::MissingInput(
+                    "datadir or taxid list".to_string(),
+                )),
+            };
+        }
+        let hook = &mut self.hooks.on_file_scanned;
+        let progress = &mut self.hooks.on_progress;
+        let observer = &self.hooks.observer;
+        let (mut taxids, report) = scan::scan_datadir_with(
+            &self.datadirs,
+            self.biosamples.as_ref(),
+            &self.scan_options,
+            self.deadline.as_ref(),
+            self.manifest.as_mut(),
+            &mut |path, taxid| {
+                if let Some(hook) = hook {
+                    hook(path, taxid)
+                }
+            },
+            &mut |done, total| {
+                if let Some(progress) = progress {
+                    progress(Stage::Scanning, done, total)
+                }
+                if let Some(observer) = observer {
+                    observer.on_stage(Stage::Scanning, done, total)
+                }
+            },
+        )?;
+        if let Some(ref extra) = self.taxids {
+            taxids.extend(extra);
+        }
+        Ok((taxids, report))
+    }
+
+    /// Resolve phase: apply deleted and merged IDs and look up lineages for `taxids`
+    pub fn resolve(
+        &mut self,
+        cache: &mut TaxonCache,
+        taxids: &mut HashSet<TaxId>,
+    ) -> Result<(), ASDBTaxonError> {
+        self.resolve_keeping(cache, taxids, None)
+    }
+
+    /// Like [TaxonCacheBuilder::resolve], keeping the cached entries about to be resolved again in
+    /// `before`
+    ///
+    /// Once done, `taxids` holds all taxids resolved, including ancestors and merge targets.
+    fn resolve_keeping(
+        &mut self,
+        cache: &mut TaxonCache,
+        taxids: &mut HashSet<TaxId>,
+        before: Option<&mut HashMap<TaxId, NcbiTaxEntry>>,
+    ) -> Result<(), ASDBTaxonError> {
+        let requested = taxids.clone();
+        if let Some(ref path) = self.deleted_id_dump {
+            cache.add_deleted_ids(self.open(path)?, taxids)?;
+        }
+
+        let merged_id_path = required(&self.merged_id_dump, "merged ID dump")?;
+        // nodes.dmp only knows the current taxids, and merge chains need the configured limit
+        cache.add_merged_ids_with_depth(
+            self.open(merged_id_path)?,
+            taxids,
+            self.max_merge_depth.unwrap_or(MAX_MERGE_DEPTH),
+        )?;
+        let ancestors = match self.nodes_dump {
+            Some(ref path) => {
+                let tree = TaxonTree::load(self.open(path)?)?;
+                let ancestors = cache.add_ancestors(&tree, taxids);
+                Some((tree, ancestors))
+            }
+            None => None,
+        };
+
+        let taxdump_path = required(&self.taxdump, "taxdump")?;
+        let taxdump = self.open(taxdump_path)?;
+        // streamed dumps have no known size, so their progress jumps straight to done
+        let taxdump_size = remote::size(taxdump_path)?.unwrap_or_default();
+        let merged_id_dump = self.open(merged_id_path)?;
+        let progress = &mut self.hooks.on_progress;
+        let observer = &self.hooks.observer;
+        let mut on_bytes = |done, total| {
+            if let Some(progress) = progress {
+                progress(Stage::Parsing, done, total)
+            }
+            if let Some(observer) = observer {
+                observer.on_stage(Stage::Parsing, done, total)
+            }
+        };
+        let taxdump = ProgressReader::new(taxdump, taxdump_size, &mut on_bytes);
+        let taxdump: Box<dyn Read> = match self.deadline {
+            Some(ref deadline) => Box::new(DeadlineReader::new(taxdump, deadline)),
+            None => Box::new(taxdump),
+        };
+        if let Some(before) = before {
+            keep_entries(cache, taxids.iter(), before);
+        }
+        // an updated cache sticks with full species names once it has them
+        cache.full_species |= self.full_species;
+        let hook = &mut self.hooks.on_taxid_resolved;
+        cache.initialise_with_jobs(
+            taxdump,
+            merged_id_dump,
+            taxids,
+            self.columns.as_ref(),
+            self.jobs.unwrap_or_else(parallel::default_jobs),
+            &mut |entry| {
+                if let Some(hook) = hook {
+                    hook(entry)
+                }
+            },
+        )?;
+        if let Some((tree, ancestors)) = ancestors {
+            cache.link_ancestors(&tree, &ancestors);
+        }
+
+        if self.partial() {
+            warn!("Time limit reached, the cache is incomplete");
+            return Ok(());
+        }
+        self.check_cancelled()?;
+
+        #[cfg(feature = "network")]
+        if let Some(ref mut client) = self.entrez {
+            client.fill_missing(cache, taxids)?;
+            // taxids the E-utilities know as merged were added as the taxids they're merged into
+            let merged: Vec<TaxId> = taxids
+                .iter()
+                .filter_map(|tax_id| cache.deprecated_ids.get(tax_id))
+                .copied()
+                .collect();
+            taxids.extend(merged);
+        }
+
+        if !self.gtdb_metadata.is_empty() {
+            let mut gtdb = GtdbMap::new();
+            for path in &self.gtdb_taxonomy {
+                gtdb.load_taxonomy_path(path)?;
+            }
+            for path in &self.gtdb_metadata {
+                gtdb.load_metadata_path(path)?;
+            }
+            cache.add_classifications(Taxonomy::Gtdb, &gtdb.by_taxid());
+        }
+        if !self.silva_ena_taxmap.is_empty() {
+            let mut silva = SilvaMap::new();
+            for path in &self.silva_taxmap {
+                silva.load_taxmap_path(path)?;
+            }
+            for path in &self.silva_ena_taxmap {
+                silva.load_ena_taxmap_path(path)?;
+            }
+            cache.add_classifications(Taxonomy::Silva, &silva.by_taxid());
+        }
+
+        if let Some(ref mut hook) = self.hooks.on_missing_taxid {
+            for err in cache.missing(&requested) {
+                hook(&err)
+            }
+        }
+        Ok(())
+    }
+
+    /// Open a dump, failing its reads once the build is cancelled
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, ASDBTaxonError> {
+        let dump = remote::open(path)?;
+        Ok(match self.scan_options.cancel {
+            Some(ref token) => Box::new(CancelReader::new(dump, token.clone())),
+            None => dump,
+        })
+    }
+
+    fn check_cancelled(&self) -> Result<(), ASDBTaxonError> {
+        match self.scan_options.cancel {
+            Some(ref token) => token.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Copy the cached entries of `taxids` into `before`, unless already there
+fn keep_entries<'a>(
+    cache: &TaxonCache,
+    taxids: impl IntoIterator<Item = &'a TaxId>,
+    before: &mut HashMap<TaxId, NcbiTaxEntry>,
+) {
+    for tax_id in taxids {
+        if let Some(entry) = cache.mappings.get(tax_id) {
+            before.entry(*tax_id).or_insert_with(|| entry.clone());
+        }
+    }
+}
+
+fn required<'a>(path: &'a Option<PathBuf>, name: &str) -> Result<&'a PathBuf, ASDBTaxonError> {
+    path.as_ref()
+        .ok_or_else(|| ASDBTaxonError::MissingInput(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::provenance::Provenance;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+    use crate::update::Change;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_taxids_without_datadir() {
+        let mut builder = TaxonCacheBuilder::new().taxids([tid(1883), tid(5062)]);
+        let (taxids, report) = builder.scan().unwrap();
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062)]));
+        assert_eq!(report.files_scanned, 0);
+
+        assert!(matches!(
+            TaxonCacheBuilder::new().scan(),
+            Err(ASDBTaxonError::MissingInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_taxid_list_and_strict() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        fs::write(dir.join("taxids.txt"), "# needed\n23456\n\n34567\n").unwrap();
+        fs::write(dir.join("merged.dmp"), "").unwrap();
+        fs::write(
+            dir.join("rankedlineage.dmp"),
+            "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n",
+        )
+        .unwrap();
+        let builder = || {
+            TaxonCacheBuilder::new()
+                .taxid_list(dir.join("taxids.txt"))
+                .taxdump(dir.join("rankedlineage.dmp"))
+                .merged_id_dump(dir.join("merged.dmp"))
+        };
+
+        let (cache, _) = builder().build().unwrap();
+        let strict = builder().strict(true).build();
+
+        assert_eq!(cache.mappings.len(), 1);
+        assert!(cache.lookup(tid(23456)).is_ok());
+        assert!(matches!(
+            strict,
+            Err(ASDBTaxonError::NotFound(tax_id)) if tax_id == 34567
+        ));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let datadir = dir.join("data");
+        fs::create_dir_all(&datadir).unwrap();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:12345"]}"#).unwrap();
+        fs::write(dir.join("merged.dmp"), "").unwrap();
+        fs::write(dir.join("rankedlineage.dmp"), "").unwrap();
+
+        let (cache, report) = TaxonCacheBuilder::new()
+            .datadir(&datadir)
+            .taxdump(dir.join("rankedlineage.dmp"))
+            .merged_id_dump(dir.join("merged.dmp"))
+            .time_limit(Duration::ZERO)
+            .build()
+            .unwrap();
+
+        assert_eq!(report.files_scanned, 0);
+        assert!(cache.metadata.unwrap().partial);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let datadir = dir.join("data");
+        fs::create_dir_all(&datadir).unwrap();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:23456"]}"#).unwrap();
+        fs::write(dir.join("merged.dmp"), "").unwrap();
+        fs::write(
+            dir.join("rankedlineage.dmp"),
+            "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n",
+        )
+        .unwrap();
+        let token = CancelToken::new();
+        let builder = || {
+            TaxonCacheBuilder::new()
+                .taxdump(dir.join("rankedlineage.dmp"))
+                .merged_id_dump(dir.join("merged.dmp"))
+                .cancel_token(token.clone())
+        };
+
+        let (mut cache, _) = builder().datadir(&datadir).build().unwrap();
+        cache.lookup(tid(23456)).unwrap();
+        token.cancel();
+        let cancelled = builder().datadir(&datadir).build();
+        let updated = builder().update(&mut cache);
+
+        assert!(matches!(cancelled, Err(ASDBTaxonError::Cancelled)));
+        assert!(matches!(updated, Err(ASDBTaxonError::Cancelled)));
+        assert_eq!(cache.mappings.len(), 1);
+        assert!(cache.history.is_empty());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let datadir = dir.join("data");
+        fs::create_dir_all(&datadir).unwrap();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:12345"]}"#).unwrap();
+        fs::write(datadir.join("b.json"), r#"{"dbxrefs": ["taxon:34567"]}"#).unwrap();
+        fs::write(dir.join("merged.dmp"), "12345\t|\t23456\t|\n").unwrap();
+        fs::write(
+            dir.join("rankedlineage.dmp"),
+            "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n",
+        )
+        .unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (scanned, resolved, missing) = (events.clone(), events.clone(), events.clone());
+        let (cache, report) = TaxonCacheBuilder::new()
+            .datadir(&datadir)
+            .taxdump(dir.join("rankedlineage.dmp"))
+            .merged_id_dump(dir.join("merged.dmp"))
+            .on_file_scanned(move |path, taxid| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                scanned
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {:?}", name, taxid))
+            })
+            .on_taxid_resolved(move |entry| {
+                resolved.lock().unwrap().push(format!("{}", entry.tax_id))
+            })
+            .on_missing_taxid(move |err| missing.lock().unwrap().push(err.to_string()))
+            .build()
+            .unwrap();
+
+        assert_eq!(report.files_scanned, 2);
+        assert_eq!(cache.mappings.len(), 1);
+        let metadata = cache.metadata.unwrap();
+        assert_eq!(metadata.entry_count, 1);
+        assert_eq!(metadata.taxdump.unwrap().md5.len(), 32);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "a.json Some(TaxId(12345))",
+                "b.json Some(TaxId(34567))",
+                "23456",
+                "TaxID not found: 34567"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer() {
+        struct Stages(Mutex<Vec<(Stage, u64, u64)>>);
+        impl ProgressObserver for Stages {
+            fn on_stage(&self, stage: Stage, done: u64, total: u64) {
+                self.0.lock().unwrap().push((stage, done, total))
+            }
+        }
+
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let datadir = dir.join("data");
+        fs::create_dir_all(&datadir).unwrap();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:12345"]}"#).unwrap();
+        fs::write(dir.join("merged.dmp"), "").unwrap();
+        fs::write(dir.join("rankedlineage.dmp"), "").unwrap();
+
+        let stages = Arc::new(Stages(Mutex::new(Vec::new())));
+        TaxonCacheBuilder::new()
+            .datadir(&datadir)
+            .taxdump(dir.join("rankedlineage.dmp"))
+            .merged_id_dump(dir.join("merged.dmp"))
+            .observer(stages.clone())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            *stages.0.lock().unwrap(),
+            vec![
+                (Stage::Scanning, 0, 1),
+                (Stage::Scanning, 1, 1),
+                (Stage::Parsing, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_provenance() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        fs::write(dir.join("merged.dmp"), "").unwrap();
+        fs::write(
+            dir.join("rankedlineage.dmp"),
+            "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+5062\t|\tAspergillus oryzae\t|\t\t|\tAspergillus\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tEukaryota\t|\n",
+        )
+        .unwrap();
+        let builder = |taxids: &[TaxId]| {
+            TaxonCacheBuilder::new()
+                .taxids(taxids.iter().copied())
+                .taxdump(dir.join("rankedlineage.dmp"))
+                .merged_id_dump(dir.join("merged.dmp"))
+        };
+
+        let (mut cache, _) = builder(&[tid(23456)]).build().unwrap();
+        let old = Provenance::new(1000, None);
+        cache.mappings.get_mut(&tid(23456)).unwrap().provenance = Some(old.clone());
+        cache.mappings.insert(
+            tid(1883),
+            NcbiTaxEntry {
+                name: "Streptomyces".to_string(),
+                ..NcbiTaxEntry::new(tid(1883))
+            },
+        );
+        builder(&[tid(23456), tid(5062)]).run(&mut cache).unwrap();
+
+        // unchanged entries keep their provenance, entries the build didn't touch have none
+        assert_eq!(cache.mappings[&tid(23456)].provenance, Some(old));
+        assert!(cache.mappings[&tid(5062)].provenance.is_some());
+        assert_eq!(cache.mappings[&tid(1883)].provenance, None);
+    }
+
+    #[test]
+    fn test_update() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        fs::write(dir.join("merged.dmp"), "23456\t|\t34567\t|\n").unwrap();
+        fs::write(
+            dir.join("rankedlineage.dmp"),
+            "34567\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+5062\t|\tAspergillus oryzae RIB40\t|\tAspergillus oryzae\t|\tAspergillus\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tEukaryota\t|\n",
+        )
+        .unwrap();
+
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        for (tax_id, name, source) in [
+            (tid(23456), "Streptomyces examplis", EntrySource::Taxdump),
+            (tid(5062), "Aspergillus oryzae", EntrySource::Taxdump),
+            (tid(1883), "Streptomyces", EntrySource::Taxdump),
+            (tid(9000001), "In-house isolate", EntrySource::Custom),
+        ] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    name: name.to_string(),
+                    source,
+                    parent_id: Some(tid(1)),
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        cache.metadata = Some(CacheMetadata {
+            release: Some("2023-01".to_string()),
+            ..CacheMetadata::new()
+        });
+
+        let report = TaxonCacheBuilder::new()
+            .taxdump(dir.join("rankedlineage.dmp"))
+            .merged_id_dump(dir.join("merged.dmp"))
+            .release("2023-06")
+            .update(&mut cache)
+            .unwrap();
+
+        let summary: Vec<String> = report
+            .changes
+            .iter()
+            .filter(|change| !matches!(change, Change::Lineage { .. }))
+            .map(Change::to_string)
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                "1883: removed",
+                "5062: renamed from 'Aspergillus oryzae' to 'Aspergillus oryzae RIB40'",
+                "23456: merged into 34567",
+                "34567: added",
+            ]
+        );
+        assert_eq!(cache.lookup(tid(12345)).unwrap().tax_id, 34567);
+        assert_eq!(cache.lookup(tid(5062)).unwrap().parent_id, Some(tid(1)));
+        assert_eq!(cache.lookup(tid(9000001)).unwrap().name, "In-house isolate");
+        assert!(cache.lookup(tid(1883)).is_err());
+
+        assert_eq!(cache.history.len(), 1);
+        assert_eq!(cache.history[0].changes, report.changes);
+        let history: Vec<String> = cache
+            .history_of(tid(34567))
+            .into_iter()
+            .map(|(_, change)| change.to_string())
+            .collect();
+        assert_eq!(history, vec!["23456: merged into 34567", "34567: added"]);
+
+        assert_eq!(cache.release_tags(), vec!["2023-01", "2023-06"]);
+        assert_eq!(
+            cache.lookup_as_of(tid(12345), "2023-03").unwrap().tax_id,
+            23456
+        );
+        assert_eq!(
+            cache.lookup_as_of(tid(12345), "2023-06").unwrap().tax_id,
+            34567
+        );
+        assert_eq!(
+            cache.lookup_as_of(tid(1883), "2023-03").unwrap().name,
+            "Streptomyces"
+        );
+        assert_eq!(
+            cache.lookup_as_of(tid(5062), "2023-03").unwrap().name,
+            "Aspergillus oryzae"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cancelling long-running builds from another thread
+//!
+//! Unlike a [crate::deadline::Deadline], which keeps what was done so far as a partial cache,
+//! a cancelled build fails with [ASDBTaxonError::Cancelled].
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::errors::ASDBTaxonError;
+
+/// Flag shared between a build and whoever may want to abort it, clones share the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        Default::default()
+    }
+
+    /// Ask all operations checking this token to stop
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [ASDBTaxonError::Cancelled] once cancelled
+    pub fn check(&self) -> Result<(), ASDBTaxonError> {
+        if self.is_cancelled() {
+            return Err(ASDBTaxonError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Reader failing with [ASDBTaxonError::Cancelled] once its token is cancelled
+///
+/// This makes anything reading a dump, like [crate::TaxonCache::initialise], cancellable.
+pub struct CancelReader<R> {
+    inner: R,
+    token: CancelToken,
+}
+
+impl<R: Read> CancelReader<R> {
+    pub fn new(inner: R, token: CancelToken) -> Self {
+        CancelReader { inner, token }
+    }
+}
+
+impl<R: Read> Read for CancelReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.token.check().map_err(io::Error::other)?;
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::taxa::tid;
+    use crate::TaxonCache;
+
+    #[test]
+    fn test_cancel_reader() {
+        let token = CancelToken::new();
+        let mut out = String::new();
+        CancelReader::new("1\n2\n".as_bytes(), token.clone())
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "1\n2\n");
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        let taxdump = CancelReader::new("1\t|\troot\t|\n".as_bytes(), token);
+        let mut taxids = HashSet::from([tid(1)]);
+        assert!(matches!(
+            TaxonCache::new().initialise(taxdump, "".as_bytes(), &mut taxids),
+            Err(ASDBTaxonError::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for looking up taxids in a cache from other languages
+//!
+//! The header `include/asdb_taxa.h` is generated by cbindgen when building with the `capi`
+//! feature. Entries are returned as JSON strings in the layout of the cache file, which the
+//! caller frees with [asdb_taxa_string_free]. Functions failing return `NULL`, with the reason
+//! available from [asdb_taxa_last_error] until the next call on the same thread.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::Path;
+use std::ptr;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+use crate::TaxonCache;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // messages with a NUL byte in them lose the rest
+    let message = CString::new(message).unwrap_or_else(|err| {
+        let end = err.nul_position();
+        CString::new(&err.into_vec()[..end]).expect("no NUL before the first one")
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Opaque handle of a loaded cache
+pub struct AsdbTaxonCache {
+    cache: TaxonCache,
+}
+
+/// Load the cache at `path`, in any format and compression
+///
+/// Returns `NULL` on failure. Free the cache with [asdb_taxa_free].
+///
+/// # Safety
+///
+/// `path` must be a valid, NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn asdb_taxa_load(path: *const c_char) -> *mut AsdbTaxonCache {
+    clear_last_error();
+    if path.is_null() {
+        set_last_error("no cache path given".to_string());
+        return ptr::null_mut();
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        set_last_error("cache path isn't valid UTF-8".to_string());
+        return ptr::null_mut();
+    };
+    let mut cache = TaxonCache::new();
+    match cache.load_path(Path::new(path)) {
+        Ok(_) => Box::into_raw(Box::new(AsdbTaxonCache { cache })),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The entry of `taxid` as a JSON object, following merged taxids
+///
+/// Returns `NULL` if the taxid is unknown or was deleted. Free the string with
+/// [asdb_taxa_string_free].
+///
+/// # Safety
+///
+/// `cache` must come from [asdb_taxa_load] and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn asdb_taxa_lookup(cache: *const AsdbTaxonCache, taxid: i64) -> *mut c_char {
+    clear_last_error();
+    let Some(cache) = cache.as_ref() else {
+        set_last_error("no cache given".to_string());
+        return ptr::null_mut();
+    };
+    let json = TaxId::new(taxid)
+        .and_then(|taxid| cache.cache.lookup(taxid))
+        .and_then(|entry| serde_json::to_string(entry).map_err(ASDBTaxonError::from));
+    match json {
+        // JSON escapes control characters, so there are no NUL bytes in it
+        Ok(json) => CString::new(json).expect("JSON has no NUL").into_raw(),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Message of the last failure on this thread, `NULL` if the last call succeeded
+///
+/// The message stays valid until the next call on the same thread and must not be freed.
+#[no_mangle]
+pub extern "C" fn asdb_taxa_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Free a cache loaded with [asdb_taxa_load], `NULL` is ignored
+///
+/// # Safety
+///
+/// `cache` must come from [asdb_taxa_load] and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn asdb_taxa_free(cache: *mut AsdbTaxonCache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+/// Free a string returned by [asdb_taxa_lookup], `NULL` is ignored
+///
+/// # Safety
+///
+/// `string` must come from [asdb_taxa_lookup] and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn asdb_taxa_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_capi() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("capi.json");
+        fs::write(
+            &path,
+            r#"{"deprecated_ids": {"5": 1883}, "mappings": {"1883": {"tax_id": 1883, "name": "Streptomyces", "species": "Unknown", "genus": "Streptomyces", "family": "Streptomycetaceae", "order": "Kitasatosporales", "class": "Actinomycetes", "phylum": "Actinomycetota", "kingdom": "Unknown", "superkingdom": "Bacteria"}}}"#,
+        )
+        .unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let cache = asdb_taxa_load(c_path.as_ptr());
+            fs::remove_file(&path).unwrap();
+            assert!(!cache.is_null());
+            assert!(asdb_taxa_last_error().is_null());
+
+            let json = asdb_taxa_lookup(cache, 5);
+            assert!(!json.is_null());
+            let entry: serde_json::Value =
+                serde_json::from_slice(CStr::from_ptr(json).to_bytes()).unwrap();
+            assert_eq!(entry["tax_id"], 1883);
+            asdb_taxa_string_free(json);
+
+            assert!(asdb_taxa_lookup(cache, 2).is_null());
+            let message = CStr::from_ptr(asdb_taxa_last_error());
+            assert_eq!(
+                message.to_str().unwrap(),
+                ASDBTaxonError::NotFound(tid(2)).to_string()
+            );
+            asdb_taxa_free(cache);
+
+            assert!(asdb_taxa_load(c_path.as_ptr()).is_null());
+            assert!(!asdb_taxa_last_error().is_null());
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transparent compression of cache files
+//!
+//! gzip goes through flate2, zstd through the zstd crate. Files can be read from URLs as well, see
+//! [remote].
+
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::errors::ASDBTaxonError;
+use crate::gzip::{self, is_gzip};
+use crate::remote;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+impl Compression {
+    /// Compression implied by the file extension, e.g. `cache.json.zst`
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Compression of data starting with `magic`
+    pub fn detect(magic: &[u8]) -> Compression {
+        if is_gzip(magic) {
+            Compression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Compression of an existing file or URL, judging by its contents
+    pub fn detect_file(path: &Path) -> Result<Compression, ASDBTaxonError> {
+        let (magic, _) = read_magic(remote::open(path)?)?;
+        Ok(Compression::detect(&magic))
+    }
+}
+
+/// A stream with its first bytes put back in front, see [read_magic]
+type Peeked<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// First bytes of `input`, along with all of `input` again
+fn read_magic<R: Read>(mut input: R) -> io::Result<(Vec<u8>, Peeked<R>)> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    input
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    Ok((magic.clone(), io::Cursor::new(magic).chain(input)))
+}
+
+/// Open a file or URL for reading, decompressing it if needed
+pub fn open(path: &Path) -> Result<Box<dyn Read>, ASDBTaxonError> {
+    if remote::is_remote(path) {
+        // a stream can only be read once, so look at its first bytes on the way
+        let (magic, input) = read_magic(remote::open(path)?)?;
+        return Ok(match Compression::detect(&magic) {
+            Compression::None => Box::new(input),
+            Compression::Gzip => Box::new(gzip::decoder(input)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(input)?),
+        });
+    }
+    let file = fs::File::open(path)?;
+    Ok(match Compression::detect_file(path)? {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(gzip::decoder(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    })
+}
+
+/// Create a file for writing, compressing everything written to it
+pub fn create(path: &Path, compression: Compression) -> Result<CompressedWriter, ASDBTaxonError> {
+    Ok(match compression {
+        Compression::None => CompressedWriter::Plain(BufWriter::new(fs::File::create(path)?)),
+        Compression::Gzip => {
+            CompressedWriter::Gzip(gzip::encoder(BufWriter::new(fs::File::create(path)?)))
+        }
+        Compression::Zstd => CompressedWriter::Zstd(zstd::Encoder::new(
+            BufWriter::new(fs::File::create(path)?),
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?),
+    })
+}
+
+/// A file being written, see [create]
+pub enum CompressedWriter {
+    Plain(BufWriter<fs::File>),
+    Gzip(flate2::write::GzEncoder<BufWriter<fs::File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<fs::File>>),
+}
+
+impl CompressedWriter {
+    /// Finish compressing and make sure everything is written
+    pub fn finish(self) -> Result<(), ASDBTaxonError> {
+        match self {
+            CompressedWriter::Plain(mut writer) => writer.flush()?,
+            CompressedWriter::Gzip(encoder) => encoder.finish()?.flush()?,
+            CompressedWriter::Zstd(encoder) => encoder.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_roundtrip() {
+        for (extension, expected) in [("gz", Compression::Gzip), ("zst", Compression::Zstd)] {
+            let tmp = temp_dir();
+            let path = tmp.path().join(format!("cache.json.{}", extension));
+            let compression = Compression::from_path(&path);
+            assert_eq!(compression, expected);
+
+            let mut writer = create(&path, compression).unwrap();
+            writer.write_all(b"{\"mappings\": {}}").unwrap();
+            writer.finish().unwrap();
+            assert_eq!(Compression::detect_file(&path).unwrap(), expected);
+
+            let mut content = String::new();
+            open(&path).unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, "{\"mappings\": {}}");
+        }
+    }
+}
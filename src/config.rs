@@ -0,0 +1,201 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Default paths from a config file
+//!
+//! The config file is a TOML file with the paths as top-level keys:
+//! ```toml
+//! cache = "/data/asdb/cache.json"
+//! taxdump = "/data/taxdump/rankedlineage.dmp"
+//! mergeddump = "/data/taxdump/merged.dmp"
+//! # or find all dumps in one directory
+//! taxdump_dir = "~/taxdump"
+//! datadir = "/data/asdb/jsons"
+//! ```
+//! Relative paths are relative to the directory of the config file, a leading `~` stands for the
+//! home directory and URLs are kept as they are. Every value can also be set by an environment
+//! variable, which takes precedence, see [Config::defaults].
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::errors::ASDBTaxonError;
+use crate::remote;
+
+/// Default paths, each also settable by an environment variable
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub cache: Option<String>,
+    pub taxdump: Option<String>,
+    pub mergeddump: Option<String>,
+    pub taxdump_dir: Option<String>,
+    pub delnodes: Option<String>,
+    pub datadir: Option<String>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/asdb-taxa/config.toml`, falling back to `~/.config`
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("asdb-taxa").join("config.toml"))
+    }
+
+    pub fn load(mut input: impl Read) -> Result<Config, ASDBTaxonError> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+        toml::from_str(&text).map_err(|err| ASDBTaxonError::InvalidFormat(err.to_string()))
+    }
+
+    /// Load a config file, resolving relative paths against its directory
+    pub fn load_path(path: &Path) -> Result<Config, ASDBTaxonError> {
+        let mut config = Config::load(fs::File::open(path)?)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let home = env::var_os("HOME").map(PathBuf::from);
+        config
+            .cache
+            .iter_mut()
+            .chain(config.taxdump.iter_mut())
+            .chain(config.mergeddump.iter_mut())
+            .chain(config.taxdump_dir.iter_mut())
+            .chain(config.delnodes.iter_mut())
+            .chain(config.datadir.iter_mut())
+            .for_each(|value| *value = resolve(value, base, home.as_deref()));
+        Ok(config)
+    }
+
+    /// The configured values, by the name of the environment variable overriding them
+    pub fn defaults(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("ASDB_TAXA_CACHE", &self.cache),
+            ("ASDB_TAXA_TAXDUMP", &self.taxdump),
+            ("ASDB_TAXA_MERGEDDUMP", &self.mergeddump),
+            ("ASDB_TAXA_TAXDUMP_DIR", &self.taxdump_dir),
+            ("ASDB_TAXA_DELNODES", &self.delnodes),
+            ("ASDB_TAXA_DATADIR", &self.datadir),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+}
+
+/// The path `value` of a config file in `base`, with a leading `~` replaced by `home`
+///
+/// URLs are kept as they are.
+fn resolve(value: &str, base: &Path, home: Option<&Path>) -> String {
+    if remote::is_remote(Path::new(value)) {
+        return value.to_string();
+    }
+    let path = match value.strip_prefix('~').zip(home) {
+        Some(("", home)) => home.to_path_buf(),
+        Some((rest, home)) if rest.starts_with('/') => home.join(&rest[1..]),
+        _ => base.join(value),
+    };
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_load() {
+        let config = Config::load(
+            r#"
+# default paths
+cache = "cache.json"  # the main one
+taxdump = 'C:\dumps\rankedlineage.dmp'
+datadir = "jsons # and more"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(config.cache.as_deref(), Some("cache.json"));
+        assert_eq!(
+            config.taxdump.as_deref(),
+            Some(r"C:\dumps\rankedlineage.dmp")
+        );
+        assert_eq!(
+            config.defaults(),
+            vec![
+                ("ASDB_TAXA_CACHE", "cache.json"),
+                ("ASDB_TAXA_TAXDUMP", r"C:\dumps\rankedlineage.dmp"),
+                ("ASDB_TAXA_DATADIR", "jsons # and more"),
+            ]
+        );
+
+        let config = Config::load("datadir = \"\"\"\ndata\"\"\"\n".as_bytes()).unwrap();
+        assert_eq!(config.datadir.as_deref(), Some("data"));
+
+        for invalid in [
+            "cache",
+            "cache = cache.json",
+            "cache = 1",
+            "cache = [\"a\", \"b\"]",
+            "colour = \"blue\"",
+            "[defaults]",
+            "cache = \"cache.json\" extra",
+        ] {
+            assert!(Config::load(invalid.as_bytes()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = Path::new("/etc/asdb-taxa");
+        let home = Some(Path::new("/home/user"));
+        assert_eq!(
+            resolve("cache.json", base, home),
+            "/etc/asdb-taxa/cache.json"
+        );
+        assert_eq!(resolve("/data/cache.json", base, home), "/data/cache.json");
+        assert_eq!(resolve("~/cache.json", base, home), "/home/user/cache.json");
+        assert_eq!(resolve("~", base, home), "/home/user");
+        assert_eq!(resolve("~other/x", base, home), "/etc/asdb-taxa/~other/x");
+        assert_eq!(resolve("~/x", base, None), "/etc/asdb-taxa/~/x");
+        assert_eq!(
+            resolve("s3://bucket/cache.json", base, home),
+            "s3://bucket/cache.json"
+        );
+    }
+
+    #[test]
+    fn test_load_path() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("config.toml");
+        fs::write(
+            &path,
+            "cache = \"cache.json\"\ntaxdump = \"https://example.org/rankedlineage.dmp\"\n",
+        )
+        .unwrap();
+        let config = Config::load_path(&path).unwrap();
+        assert_eq!(
+            config.cache,
+            Some(tmp.path().join("cache.json").to_string_lossy().into_owned())
+        );
+        assert_eq!(
+            config.taxdump.as_deref(),
+            Some("https://example.org/rankedlineage.dmp")
+        );
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! User-specified entries for organisms the NCBI doesn't know, like in-house genomes
+//!
+//! Custom taxa are read from TSV files with a header naming the columns as in a
+//! [ColumnMapping], e.g. `taxid name superkingdom genus species strain`. Empty fields and ranks
+//! without a column are `Unknown`.
+
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::errors::ASDBTaxonError;
+use crate::layout::ColumnMapping;
+use crate::taxa::{EntrySource, NcbiTaxEntry};
+
+/// Load the entries of a custom taxa TSV file, skipping empty lines and `#` comments
+pub fn load_tsv(input: impl Read, full_species: bool) -> Result<Vec<NcbiTaxEntry>, ASDBTaxonError> {
+    let mut lines = io::BufReader::new(input).lines().filter(|line| match line {
+        Ok(line) => !line.trim().is_empty() && !line.starts_with('#'),
+        Err(_) => true,
+    });
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: ColumnMapping = header.split('\t').collect::<Vec<_>>().join(",").parse()?;
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line
+            .split('\t')
+            .map(|field| match field.trim() {
+                "" => "Unknown",
+                field => field,
+            })
+            .collect();
+        let mut entry = columns.entry(&fields, full_species)?;
+        entry.source = EntrySource::Custom;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(feature = "fs")]
+pub fn load_path(path: &Path, full_species: bool) -> Result<Vec<NcbiTaxEntry>, ASDBTaxonError> {
+    load_tsv(fs::File::open(path)?, full_species)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_tsv() {
+        let tsv = "# in-house genomes\n\
+taxid\tname\tsuperkingdom\tgenus\tspecies\n\
+9000001\tStreptomyces sp. NBC01 isolate 7\tBacteria\tStreptomyces\tStreptomyces sp. NBC01\n\
+\n\
+9000002\tUnclassified isolate\tBacteria\t\t\n";
+        let entries = load_tsv(tsv.as_bytes(), false).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tax_id, 9000001);
+        assert_eq!(&*entries[0].species, "sp. NBC01");
+        assert_eq!(entries[0].strain.as_deref(), Some("isolate 7"));
+        assert_eq!(&*entries[0].family, "Unknown");
+        assert_eq!(entries[0].source, EntrySource::Custom);
+        assert_eq!(&*entries[1].genus, "Unknown");
+        assert_eq!(entries[1].strain, None);
+
+        assert!(load_tsv("name\tgenus\nx\ty\n".as_bytes(), false).is_err());
+        assert!(load_tsv("taxid\tname\tgenus\n1\tx\n".as_bytes(), false).is_err());
+    }
+}
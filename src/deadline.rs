@@ -0,0 +1,153 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time limits for long-running builds
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::errors::ASDBTaxonError;
+
+/// Point in time after which a build should stop, remembering whether it did
+#[derive(Debug)]
+pub struct Deadline {
+    at: Instant,
+    reached: AtomicBool,
+}
+
+impl Deadline {
+    /// Deadline `limit` from now
+    pub fn after(limit: Duration) -> Deadline {
+        Deadline {
+            at: Instant::now() + limit,
+            reached: AtomicBool::new(false),
+        }
+    }
+
+    /// Check if the deadline has passed, marking it as reached if so
+    pub fn check(&self) -> bool {
+        if !self.reached() && Instant::now() >= self.at {
+            self.reached.store(true, Ordering::Relaxed);
+        }
+        self.reached()
+    }
+
+    /// Whether a check found the deadline passed
+    pub fn reached(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+}
+
+/// Reader ending at the first line break after a deadline passed
+///
+/// This way, line-based parsers never see a partial line.
+pub struct DeadlineReader<'a, R> {
+    inner: R,
+    deadline: &'a Deadline,
+    done: bool,
+}
+
+impl<'a, R: Read> DeadlineReader<'a, R> {
+    pub fn new(inner: R, deadline: &'a Deadline) -> Self {
+        DeadlineReader {
+            inner,
+            deadline,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DeadlineReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let read = self.inner.read(buf)?;
+        if self.deadline.check() {
+            if let Some(pos) = buf[..read].iter().position(|b| *b == b'\n') {
+                self.done = true;
+                return Ok(pos + 1);
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Parse a duration like `90s`, `30m`, `1h30m` or plain seconds
+pub fn parse_duration(text: &str) -> Result<Duration, ASDBTaxonError> {
+    let invalid = || ASDBTaxonError::InvalidFormat(format!("invalid duration {:?}", text));
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = number.parse().map_err(|_| invalid())?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() || text.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("30x").is_err());
+        assert!(parse_duration("1h30").is_err());
+    }
+
+    #[test]
+    fn test_deadline_reader() {
+        let deadline = Deadline::after(Duration::ZERO);
+        let mut out = String::new();
+        DeadlineReader::new("1\t|\ta\t|\n2\t|\tb\t|\n".as_bytes(), &deadline)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "1\t|\ta\t|\n");
+        assert!(deadline.reached());
+
+        let deadline = Deadline::after(Duration::from_secs(3600));
+        let mut out = String::new();
+        DeadlineReader::new("1\n2\n".as_bytes(), &deadline)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "1\n2\n");
+        assert!(!deadline.reached());
+    }
+}
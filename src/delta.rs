@@ -0,0 +1,353 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Delta caches, holding only what changed since a baseline cache
+//!
+//! A [Delta] has the entries added or changed since its baseline, the taxids dropped since and
+//! the changed merged and deleted taxids, so shipping it is far cheaper than the full cache.
+//! [Delta::apply_to] layers it on top of the baseline, which gives the cache it was made from.
+//! Deltas chain, each made against the cache the ones before it result in; the build metadata
+//! of the caches tells whether a delta fits.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "fs")]
+use crate::compression::{self, Compression};
+use crate::errors::ASDBTaxonError;
+use crate::format;
+use crate::metadata::{format_timestamp, CacheMetadata};
+use crate::overrides::Overrides;
+use crate::releases::Release;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::update::UpdateRecord;
+use crate::TaxonCache;
+
+/// Marks delta files, in the `format` field
+const FORMAT: &str = "delta";
+
+/// Changes of a cache since a baseline
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Delta {
+    /// Build of the cache the delta applies to
+    pub baseline: Option<CacheMetadata>,
+    /// Build of the cache the delta updates to
+    pub metadata: Option<CacheMetadata>,
+    /// Entries added or changed since the baseline, sorted by taxid
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<NcbiTaxEntry>,
+    /// Taxids no longer cached
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<TaxId>,
+    /// New and redirected merges
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub merged: HashMap<TaxId, TaxId>,
+    /// Merges dropped since the baseline
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmerged: Vec<TaxId>,
+    /// Newly deleted taxids
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deleted: Vec<TaxId>,
+    /// Deleted taxids dropped since the baseline
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub undeleted: Vec<TaxId>,
+    /// All overrides, if they changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<Overrides>,
+    /// Updates since the baseline
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<UpdateRecord>,
+    /// Releases kept since the baseline
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<Release>,
+}
+
+/// A delta with its format marker and [format::checksum], as written to delta files
+#[derive(Serialize)]
+struct Tagged<'a> {
+    format: &'static str,
+    #[serde(flatten)]
+    delta: &'a Delta,
+    checksum: &'a str,
+}
+
+impl Delta {
+    /// What changed from `baseline` to `cache`
+    ///
+    /// Fails if `cache` has no history or releases continuing those of `baseline`, as for
+    /// unrelated caches.
+    pub fn between(baseline: &TaxonCache, cache: &TaxonCache) -> Result<Delta, ASDBTaxonError> {
+        let continues = cache.history.starts_with(&baseline.history)
+            && cache.releases.len() >= baseline.releases.len()
+            && baseline
+                .releases
+                .iter()
+                .zip(&cache.releases)
+                .all(|(old, new)| old.tag == new.tag);
+        if !continues {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "the cache doesn't continue the history of the baseline".to_string(),
+            ));
+        }
+
+        let mut entries = Vec::new();
+        for entry in cache.iter_sorted_by_taxid() {
+            let changed = match baseline.mappings.get(&entry.tax_id) {
+                Some(old) => serde_json::to_value(old)? != serde_json::to_value(entry)?,
+                None => true,
+            };
+            if changed {
+                entries.push(entry.clone());
+            }
+        }
+        let removed = sorted_ids(baseline.mappings.keys(), |tax_id| {
+            !cache.mappings.contains_key(tax_id)
+        });
+        let merged = cache
+            .deprecated_ids
+            .iter()
+            .filter(|(tax_id, into)| baseline.deprecated_ids.get(tax_id) != Some(into))
+            .map(|(tax_id, into)| (*tax_id, *into))
+            .collect();
+
+        Ok(Delta {
+            baseline: baseline.metadata.clone(),
+            metadata: cache.metadata.clone(),
+            entries,
+            removed,
+            merged,
+            unmerged: sorted_ids(baseline.deprecated_ids.keys(), |tax_id| {
+                !cache.deprecated_ids.contains_key(tax_id)
+            }),
+            deleted: sorted_ids(cache.deleted_ids.iter(), |tax_id| {
+                !baseline.deleted_ids.contains(tax_id)
+            }),
+            undeleted: sorted_ids(baseline.deleted_ids.iter(), |tax_id| {
+                !cache.deleted_ids.contains(tax_id)
+            }),
+            overrides: (cache.overrides != baseline.overrides).then(|| cache.overrides.clone()),
+            history: cache.history[baseline.history.len()..].to_vec(),
+            releases: cache.releases[baseline.releases.len()..].to_vec(),
+        })
+    }
+
+    /// Whether nothing but the metadata changed
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+            && self.removed.is_empty()
+            && self.merged.is_empty()
+            && self.unmerged.is_empty()
+            && self.deleted.is_empty()
+            && self.undeleted.is_empty()
+            && self.overrides.is_none()
+            && self.history.is_empty()
+            && self.releases.is_empty()
+    }
+
+    /// Apply the delta to its baseline `cache`, returning the number of entries added or changed
+    pub fn apply_to(self, cache: &mut TaxonCache) -> Result<usize, ASDBTaxonError> {
+        if cache.metadata != self.baseline {
+            let built = |metadata: &Option<CacheMetadata>| match metadata {
+                Some(metadata) => {
+                    format!("the cache built at {}", format_timestamp(metadata.built_at))
+                }
+                None => "a cache without build information".to_string(),
+            };
+            return Err(ASDBTaxonError::InvalidFormat(format!(
+                "delta applies to {}, not {}",
+                built(&self.baseline),
+                built(&cache.metadata)
+            )));
+        }
+
+        let count = self.entries.len();
+        let mappings = cache.mappings_mut();
+        for tax_id in &self.removed {
+            mappings.remove(tax_id);
+        }
+        for entry in self.entries {
+            mappings.insert(entry.tax_id, entry);
+        }
+        for tax_id in &self.unmerged {
+            cache.deprecated_ids.remove(tax_id);
+        }
+        cache.deprecated_ids.extend(self.merged);
+        for tax_id in &self.undeleted {
+            cache.deleted_ids.remove(tax_id);
+        }
+        cache.deleted_ids.extend(self.deleted);
+        if let Some(overrides) = self.overrides {
+            cache.overrides = overrides;
+        }
+        cache.history.extend(self.history);
+        cache.releases.extend(self.releases);
+        cache.metadata = self.metadata;
+        cache.name_index = OnceLock::new();
+        cache.intern_strings();
+        Ok(count)
+    }
+
+    pub fn load(mut input: impl Read) -> Result<Delta, ASDBTaxonError> {
+        let mut data = String::new();
+        input.read_to_string(&mut data)?;
+        let mut value: serde_json::Value =
+            serde_json::from_str(&data).map_err(format::parse_error)?;
+        let Some(fields) = value.as_object_mut() else {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "delta is not an object".to_string(),
+            ));
+        };
+        if fields.get("format").and_then(serde_json::Value::as_str) != Some(FORMAT) {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "not a delta cache".to_string(),
+            ));
+        }
+        if let Some(stored) = fields.remove("checksum") {
+            let checksum = format::checksum(&value)?;
+            if stored.as_str() != Some(checksum.as_str()) {
+                return Err(ASDBTaxonError::CorruptCache(format!(
+                    "delta checksum {} doesn't match the content's {}",
+                    stored, checksum
+                )));
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Load from `path`, which may be compressed
+    #[cfg(feature = "fs")]
+    pub fn load_path(path: &Path) -> Result<Delta, ASDBTaxonError> {
+        Delta::load(compression::open(path)?)
+    }
+
+    pub fn save(&self, mut output: impl Write) -> Result<(), ASDBTaxonError> {
+        let checksum = format::checksum(&serde_json::to_value(self)?)?;
+        let tagged = Tagged {
+            format: FORMAT,
+            delta: self,
+            checksum: &checksum,
+        };
+        serde_json::to_writer(&mut output, &tagged)?;
+        Ok(())
+    }
+
+    /// Save to `path`, compressed as its extension says
+    #[cfg(feature = "fs")]
+    pub fn save_path(&self, path: &Path) -> Result<(), ASDBTaxonError> {
+        let mut out = compression::create(path, Compression::from_path(path))?;
+        self.save(&mut out)?;
+        out.finish()?;
+        Ok(())
+    }
+}
+
+/// The taxids of `ids` matching `predicate`, sorted
+fn sorted_ids<'a>(
+    ids: impl Iterator<Item = &'a TaxId>,
+    predicate: impl Fn(&TaxId) -> bool,
+) -> Vec<TaxId> {
+    let mut ids: Vec<TaxId> = ids.filter(|id| predicate(id)).copied().collect();
+    ids.sort();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, genus: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: format!("{} examplis", genus),
+            genus: genus.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut baseline = TaxonCache::new();
+        baseline.metadata = Some(CacheMetadata {
+            built_at: 1000,
+            ..Default::default()
+        });
+        for (tax_id, genus) in [
+            (tid(1), "Streptomyces"),
+            (tid(2), "Aspergillus"),
+            (tid(3), "Nocardia"),
+        ] {
+            baseline.insert(entry(tax_id, genus));
+        }
+        baseline.deprecated_ids.insert(tid(10), tid(2));
+        baseline.deleted_ids.insert(tid(99));
+
+        let mut cache = baseline.clone();
+        cache.metadata = Some(CacheMetadata {
+            built_at: 2000,
+            ..Default::default()
+        });
+        cache.insert(entry(tid(1), "Kitasatospora"));
+        cache.insert(entry(tid(4), "Aspergillus"));
+        cache.mappings.remove(&tid(2));
+        cache.deprecated_ids.insert(tid(2), tid(4));
+        cache.deprecated_ids.insert(tid(10), tid(4));
+        cache.deleted_ids.remove(&tid(99));
+        cache.deleted_ids.insert(tid(3));
+        cache.mappings.remove(&tid(3));
+
+        let delta = Delta::between(&baseline, &cache).unwrap();
+        let tax_ids: Vec<TaxId> = delta.entries.iter().map(|entry| entry.tax_id).collect();
+        assert_eq!(tax_ids, vec![1, 4]);
+        assert_eq!(delta.removed, vec![2, 3]);
+        assert_eq!(
+            delta.merged,
+            HashMap::from([(tid(2), tid(4)), (tid(10), tid(4))])
+        );
+        assert_eq!(
+            (delta.deleted.clone(), delta.undeleted.clone()),
+            (vec![tid(3)], vec![tid(99)])
+        );
+        assert!(delta.overrides.is_none());
+
+        let mut data = Vec::new();
+        delta.save(&mut data).unwrap();
+        assert!(TaxonCache::new().load(data.as_slice()).is_err());
+        let delta = Delta::load(data.as_slice()).unwrap();
+
+        let mut layered = baseline.clone();
+        assert_eq!(delta.clone().apply_to(&mut layered).unwrap(), 2);
+        assert_eq!(layered.checksum().unwrap(), cache.checksum().unwrap());
+        // the delta no longer fits once applied
+        assert!(delta.apply_to(&mut layered).is_err());
+
+        let mut tampered = String::from_utf8(data).unwrap();
+        tampered = tampered.replace("Kitasatospora", "Streptomyces");
+        assert!(matches!(
+            Delta::load(tampered.as_bytes()),
+            Err(ASDBTaxonError::CorruptCache(_))
+        ));
+
+        baseline.history.push(UpdateRecord {
+            updated_at: 1500,
+            taxdump: None,
+            changes: Vec::new(),
+        });
+        assert!(Delta::between(&baseline, &cache).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! NCBI E-utilities fallback for taxids missing from the local dumps
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{species_value, strain_from_name, EntrySource, NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+pub const EFETCH_URL: &str = "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi";
+
+/// Number of taxids to request per efetch call
+const BATCH_SIZE: usize = 200;
+
+/// Rate-limited client for the taxonomy database of the NCBI E-utilities
+#[derive(Debug, Clone)]
+pub struct EntrezClient {
+    pub base_url: String,
+    api_key: Option<String>,
+    last_request: Option<Instant>,
+}
+
+impl EntrezClient {
+    pub fn new(api_key: Option<String>) -> EntrezClient {
+        EntrezClient {
+            base_url: EFETCH_URL.to_string(),
+            api_key,
+            last_request: None,
+        }
+    }
+
+    /// NCBI allows 3 requests per second, or 10 with an API key
+    fn min_interval(&self) -> Duration {
+        match self.api_key {
+            Some(_) => Duration::from_millis(100),
+            None => Duration::from_millis(334),
+        }
+    }
+
+    fn wait(&mut self) {
+        if let Some(last) = self.last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval() {
+                thread::sleep(self.min_interval() - elapsed);
+            }
+        }
+        self.last_request = Some(Instant::now());
+    }
+
+    /// Fetch the lineages of `taxids`, returning the requested taxid with each entry
+    ///
+    /// With `full_species`, the entries keep the full species name, see [species_value].
+    pub fn fetch(
+        &mut self,
+        taxids: &[TaxId],
+        full_species: bool,
+    ) -> Result<Vec<(TaxId, NcbiTaxEntry)>, ASDBTaxonError> {
+        let mut entries = Vec::new();
+        for batch in taxids.chunks(BATCH_SIZE) {
+            let ids: Vec<String> = batch.iter().map(|id| id.to_string()).collect();
+            self.wait();
+            let xml = self.post(&ids.join(","))?;
+            for (aka_ids, entry) in parse_taxa_xml(&xml, full_species)? {
+                // the answer might be for the merged taxid rather than the requested one
+                let requested = batch
+                    .iter()
+                    .find(|id| **id == entry.tax_id || aka_ids.contains(id))
+                    .copied()
+                    .unwrap_or(entry.tax_id);
+                entries.push((requested, entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Request the efetch XML of the comma-separated `ids`
+    ///
+    /// The parameters are sent as a form rather than in the URL, keeping the API key out of
+    /// server and proxy logs.
+    fn post(&self, ids: &str) -> Result<String, ASDBTaxonError> {
+        let failed = |err: &dyn std::fmt::Display| {
+            ASDBTaxonError::DownloadError(format!("{}: {}", self.base_url, err))
+        };
+        let mut form = vec![("db", "taxonomy"), ("retmode", "xml"), ("id", ids)];
+        if let Some(ref key) = self.api_key {
+            form.push(("api_key", key));
+        }
+        let response = ureq::post(&self.base_url)
+            .send_form(form)
+            .map_err(|err| failed(&err))?;
+        let mut xml = String::new();
+        response
+            .into_body()
+            .into_reader()
+            .read_to_string(&mut xml)
+            .map_err(|err| failed(&err))?;
+        Ok(xml)
+    }
+
+    /// Look up all `taxids` not resolvable in `cache` and add them, returning the number added
+    pub fn fill_missing(
+        &mut self,
+        cache: &mut TaxonCache,
+        taxids: &HashSet<TaxId>,
+    ) -> Result<usize, ASDBTaxonError> {
+        let mut missing: Vec<TaxId> = taxids
+            .iter()
+            .filter(|id| matches!(cache.lookup(**id), Err(ASDBTaxonError::NotFound(_))))
+            .copied()
+            .collect();
+        missing.sort();
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let entries = self.fetch(&missing, cache.full_species)?;
+        let added = entries.len();
+        for (requested, entry) in entries {
+            if requested != entry.tax_id {
+                cache.deprecated_ids.insert(requested, entry.tax_id);
+            }
+            cache.mappings_mut().insert(entry.tax_id, entry);
+        }
+        Ok(added)
+    }
+}
+
+/// Parse an efetch taxonomy XML document into (merged taxids, entry) pairs
+pub fn parse_taxa_xml(
+    xml: &str,
+    full_species: bool,
+) -> Result<Vec<(Vec<TaxId>, NcbiTaxEntry)>, ASDBTaxonError> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        ASDBTaxonError::InvalidFormat(format!("efetch response: {}", err))
+    };
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut results = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+
+    let mut tax_id: Option<TaxId> = None;
+    let mut name = String::new();
+    let mut aka_ids: Vec<TaxId> = Vec::new();
+    let mut lineage: Vec<(String, String)> = Vec::new();
+    let mut lineage_name = String::new();
+
+    loop {
+        match reader.read_event().map_err(|err| invalid(&err))? {
+            Event::Start(tag) => path.push(String::from_utf8_lossy(tag.name().as_ref()).into()),
+            Event::End(tag) => {
+                path.pop();
+                if tag.name().as_ref() == b"Taxon" {
+                    match path.len() {
+                        // closing a top-level TaxaSet/Taxon
+                        1 => {
+                            let tax_id = tax_id
+                                .take()
+                                .ok_or_else(|| invalid(&"Taxon without a TaxId"))?;
+                            let entry = NcbiTaxEntry {
+                                name: std::mem::take(&mut name),
+                                ..NcbiTaxEntry::new(tax_id)
+                            };
+                            results.push((
+                                std::mem::take(&mut aka_ids),
+                                finish_entry(entry, &lineage, full_species),
+                            ));
+                            lineage.clear();
+                        }
+                        _ => lineage_name.clear(),
+                    }
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|err| invalid(&err))?.into_owned();
+                let parts: Vec<&str> = path.iter().map(|p| p.as_str()).collect();
+                match parts.as_slice() {
+                    ["TaxaSet", "Taxon", "TaxId"] => tax_id = Some(text.parse()?),
+                    ["TaxaSet", "Taxon", "ScientificName"] => name = text,
+                    ["TaxaSet", "Taxon", "AkaTaxIds", "TaxId"] => aka_ids.push(text.parse()?),
+                    ["TaxaSet", "Taxon", "LineageEx", "Taxon", "ScientificName"] => {
+                        lineage_name = text
+                    }
+                    ["TaxaSet", "Taxon", "LineageEx", "Taxon", "Rank"] => {
+                        lineage.push((text, std::mem::take(&mut lineage_name)))
+                    }
+                    _ => (),
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(results)
+}
+
+fn finish_entry(
+    mut entry: NcbiTaxEntry,
+    lineage: &[(String, String)],
+    full_species: bool,
+) -> NcbiTaxEntry {
+    let rank = |wanted: &str| {
+        lineage
+            .iter()
+            .find(|(rank, _)| rank == wanted)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| "Unknown".to_string())
+    };
+    let species = rank("species");
+    entry.strain = strain_from_name(&entry.name, &species);
+    entry.species = species_value(&species, full_species).into();
+    entry.genus = rank("genus").into();
+    entry.family = rank("family").into();
+    entry.order = rank("order").into();
+    entry.class = rank("class").into();
+    entry.phylum = rank("phylum").into();
+    entry.kingdom = rank("kingdom").into();
+    entry.superkingdom = match rank("superkingdom").as_str() {
+        "Unknown" => rank("domain").into(),
+        superkingdom => superkingdom.into(),
+    };
+    let known = |value: String| (value != "Unknown").then_some(value);
+    entry.realm = known(rank("realm"));
+    entry.domain = known(rank("domain"));
+    entry.source = EntrySource::Entrez;
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_taxa_xml() {
+        let xml = r#"<?xml version="1.0" ?>
+<!DOCTYPE TaxaSet PUBLIC "-//NLM//DTD Taxon, 14th January 2002//EN" "https://www.ncbi.nlm.nih.gov/entrez/query/DTD/taxon.dtd">
+<TaxaSet><Taxon>
+    <TaxId>23456</TaxId>
+    <ScientificName>Streptomyces examplis NBC12345</ScientificName>
+    <Rank>strain</Rank>
+    <LineageEx>
+        <Taxon><TaxId>2</TaxId><ScientificName>Bacteria</ScientificName><Rank>superkingdom</Rank></Taxon>
+        <Taxon><TaxId>201174</TaxId><ScientificName>Actinomycetota</ScientificName><Rank>phylum</Rank></Taxon>
+        <Taxon><TaxId>1883</TaxId><ScientificName>Streptomyces</ScientificName><Rank>genus</Rank></Taxon>
+        <Taxon><TaxId>12345</TaxId><ScientificName>Streptomyces examplis</ScientificName><Rank>species</Rank></Taxon>
+    </LineageEx>
+    <AkaTaxIds><TaxId>34567</TaxId></AkaTaxIds>
+</Taxon>
+</TaxaSet>"#;
+        let parsed = parse_taxa_xml(xml, false).unwrap();
+        assert_eq!(parsed.len(), 1);
+        let (aka_ids, entry) = &parsed[0];
+        assert_eq!(aka_ids, &vec![34567]);
+        assert_eq!(entry.tax_id, 23456);
+        assert_eq!(entry.name, "Streptomyces examplis NBC12345");
+        assert_eq!(&*entry.species, "examplis");
+        assert_eq!(&*entry.genus, "Streptomyces");
+        assert_eq!(&*entry.family, "Unknown");
+        assert_eq!(&*entry.phylum, "Actinomycetota");
+        assert_eq!(&*entry.superkingdom, "Bacteria");
+        assert_eq!(entry.source, EntrySource::Entrez);
+        let (_, entry) = &parse_taxa_xml(xml, true).unwrap()[0];
+        assert_eq!(&*entry.species, "Streptomyces examplis");
+
+        let xml = "<TaxaSet><Taxon><TaxId>5</TaxId><ScientificName>A &amp; B</ScientificName></Taxon></TaxaSet>";
+        assert_eq!(parse_taxa_xml(xml, false).unwrap()[0].1.name, "A & B");
+        assert!(matches!(
+            parse_taxa_xml("<TaxaSet><Taxon></TaxaSet>", false),
+            Err(ASDBTaxonError::InvalidFormat(_))
+        ));
+    }
+}
@@ -19,17 +19,32 @@ use std::fmt;
 use std::io;
 use std::num;
 
-use regex;
-use serde_json;
+use crate::taxa::TaxId;
 
 #[derive(Debug)]
 pub enum ASDBTaxonError {
     Io(io::Error),
     InvalidTaxId(String),
-    NotFound(i64),
+    InvalidFilter(String),
+    InvalidFormat(String),
+    NotFound(TaxId),
+    Deleted(TaxId),
+    NameNotFound(String),
+    ReleaseNotFound(String),
     JSONParserError(serde_json::Error),
     IntParserError(num::ParseIntError),
     RegexError(regex::Error),
+    DownloadError(String),
+    ChecksumMismatch(String),
+    CorruptCache(String),
+    CacheLocked(String),
+    MissingInput(String),
+    DatabaseError(String),
+    Inconsistent(usize),
+    UnsupportedCacheVersion(u64),
+    MergeCycle(Vec<TaxId>),
+    TimeLimitReached,
+    Cancelled,
 }
 
 macro_rules! implement_custom_error_from {
@@ -42,20 +57,90 @@ macro_rules! implement_custom_error_from {
     };
 }
 
-implement_custom_error_from!(io::Error, ASDBTaxonError::Io);
+impl From<io::Error> for ASDBTaxonError {
+    fn from(err: io::Error) -> ASDBTaxonError {
+        // a cancelled read can only return an I/O error, see [crate::cancel::CancelReader]
+        if let Some(ASDBTaxonError::Cancelled) = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ASDBTaxonError>())
+        {
+            return ASDBTaxonError::Cancelled;
+        }
+        ASDBTaxonError::Io(err)
+    }
+}
+
 implement_custom_error_from!(serde_json::Error, ASDBTaxonError::JSONParserError);
 implement_custom_error_from!(num::ParseIntError, ASDBTaxonError::IntParserError);
 implement_custom_error_from!(regex::Error, ASDBTaxonError::RegexError);
 
+impl ASDBTaxonError {
+    /// Process exit code for this kind of error, so scripts can tell failures apart
+    ///
+    /// 2 is left for command line usage errors, 1 covers everything without a code of its own.
+    pub fn exit_code(&self) -> u8 {
+        match *self {
+            ASDBTaxonError::MissingInput(_) => 2,
+            ASDBTaxonError::Io(_) => 3,
+            ASDBTaxonError::InvalidTaxId(_)
+            | ASDBTaxonError::InvalidFilter(_)
+            | ASDBTaxonError::InvalidFormat(_)
+            | ASDBTaxonError::JSONParserError(_)
+            | ASDBTaxonError::IntParserError(_)
+            | ASDBTaxonError::RegexError(_)
+            | ASDBTaxonError::CorruptCache(_)
+            | ASDBTaxonError::UnsupportedCacheVersion(_)
+            | ASDBTaxonError::MergeCycle(_) => 4,
+            ASDBTaxonError::NotFound(_)
+            | ASDBTaxonError::Deleted(_)
+            | ASDBTaxonError::NameNotFound(_)
+            | ASDBTaxonError::ReleaseNotFound(_) => 5,
+            ASDBTaxonError::Inconsistent(_) => 6,
+            ASDBTaxonError::TimeLimitReached => 7,
+            ASDBTaxonError::CacheLocked(_) => 8,
+            ASDBTaxonError::DownloadError(_)
+            | ASDBTaxonError::ChecksumMismatch(_)
+            | ASDBTaxonError::DatabaseError(_)
+            | ASDBTaxonError::Cancelled => 1,
+        }
+    }
+}
+
 impl fmt::Display for ASDBTaxonError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ASDBTaxonError::Io(ref err) => write!(f, "IO error: {}", err),
             ASDBTaxonError::InvalidTaxId(ref err) => write!(f, "Invalid TaxID: {}", err),
+            ASDBTaxonError::InvalidFilter(ref err) => write!(f, "Invalid filter: {}", err),
+            ASDBTaxonError::InvalidFormat(ref err) => write!(f, "Invalid format: {}", err),
             ASDBTaxonError::NotFound(ref err) => write!(f, "TaxID not found: {}", err),
+            ASDBTaxonError::Deleted(ref err) => write!(f, "TaxID deleted by NCBI: {}", err),
+            ASDBTaxonError::NameNotFound(ref err) => write!(f, "Name not found: {}", err),
+            ASDBTaxonError::ReleaseNotFound(ref err) => write!(f, "No release as of {}", err),
             ASDBTaxonError::JSONParserError(ref err) => write!(f, "Failed to parse JSON: {}", err),
             ASDBTaxonError::IntParserError(ref err) => write!(f, "Failed to parse int: {}", err),
             ASDBTaxonError::RegexError(ref err) => write!(f, "Failed to generate regex: {}", err),
+            ASDBTaxonError::DownloadError(ref err) => write!(f, "Failed to download: {}", err),
+            ASDBTaxonError::ChecksumMismatch(ref err) => write!(f, "Checksum mismatch: {}", err),
+            ASDBTaxonError::CorruptCache(ref err) => write!(f, "Corrupt cache: {}", err),
+            ASDBTaxonError::CacheLocked(ref err) => {
+                write!(f, "Cache is locked by another process: {}", err)
+            }
+            ASDBTaxonError::MissingInput(ref err) => write!(f, "No {} given", err),
+            ASDBTaxonError::DatabaseError(ref err) => write!(f, "Database error: {}", err),
+            ASDBTaxonError::Inconsistent(ref err) => write!(f, "{} discrepancies found", err),
+            ASDBTaxonError::UnsupportedCacheVersion(ref err) => write!(
+                f,
+                "Unsupported cache version {}, this version of asdb-taxa supports up to {}",
+                err,
+                crate::schema::CACHE_VERSION
+            ),
+            ASDBTaxonError::MergeCycle(ref ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "Merged IDs form a cycle: {}", ids.join(" -> "))
+            }
+            ASDBTaxonError::TimeLimitReached => write!(f, "Time limit reached"),
+            ASDBTaxonError::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
@@ -67,7 +152,24 @@ impl error::Error for ASDBTaxonError {
             ASDBTaxonError::JSONParserError(ref err) => Some(err),
             ASDBTaxonError::IntParserError(ref err) => Some(err),
             ASDBTaxonError::RegexError(ref err) => Some(err),
-            ASDBTaxonError::NotFound(_) | ASDBTaxonError::InvalidTaxId(_) => None,
+            ASDBTaxonError::NotFound(_)
+            | ASDBTaxonError::Deleted(_)
+            | ASDBTaxonError::NameNotFound(_)
+            | ASDBTaxonError::ReleaseNotFound(_)
+            | ASDBTaxonError::InvalidTaxId(_)
+            | ASDBTaxonError::InvalidFilter(_)
+            | ASDBTaxonError::InvalidFormat(_)
+            | ASDBTaxonError::DownloadError(_)
+            | ASDBTaxonError::ChecksumMismatch(_)
+            | ASDBTaxonError::CorruptCache(_)
+            | ASDBTaxonError::CacheLocked(_)
+            | ASDBTaxonError::MissingInput(_)
+            | ASDBTaxonError::DatabaseError(_)
+            | ASDBTaxonError::Inconsistent(_)
+            | ASDBTaxonError::UnsupportedCacheVersion(_)
+            | ASDBTaxonError::MergeCycle(_)
+            | ASDBTaxonError::TimeLimitReached
+            | ASDBTaxonError::Cancelled => None,
         }
     }
-}
\ No newline at end of file
+}
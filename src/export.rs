@@ -0,0 +1,841 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting cache contents for loading into databases and other tools
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+use log::warn;
+use serde::Serialize;
+#[cfg(feature = "fs")]
+use serde_json::json;
+
+use crate::errors::ASDBTaxonError;
+use crate::metadata::format_timestamp;
+use crate::nomenclature::Canonicalizer;
+use crate::provenance::Provenance;
+use crate::taxa::{LineageStyle, NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+/// Columns of the exported taxa table, in order
+pub const COLUMNS: [&str; 10] = [
+    "tax_id",
+    "name",
+    "species",
+    "genus",
+    "family",
+    "order",
+    "class",
+    "phylum",
+    "kingdom",
+    "superkingdom",
+];
+
+/// Definition of the antiSMASH DB taxa table, as created by [export_sql]
+pub const TAXA_TABLE: &str = "\
+CREATE SCHEMA IF NOT EXISTS antismash;
+CREATE TABLE IF NOT EXISTS antismash.taxa (
+    tax_id serial NOT NULL,
+    ncbi_taxid int4,
+    superkingdom text,
+    kingdom text,
+    phylum text,
+    class text,
+    taxonomic_order text,
+    family text,
+    genus text,
+    species text,
+    strain text,
+    name text NOT NULL,
+    CONSTRAINT taxa_pkey PRIMARY KEY (tax_id)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS taxa_ncbi_taxid_idx ON antismash.taxa (ncbi_taxid);";
+
+/// Marker appended to values that had to be truncated
+pub const ELLIPSIS: &str = "...";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Tab-separated values, suitable for `COPY ... FROM`
+    Tsv,
+    /// A directory with one JSON file per genus, see [export_genus_bundles]
+    GenusBundles,
+    /// SQL for sqlite3 creating taxonomizr's `names` and `nodes` tables, see [export_taxonomizr]
+    Taxonomizr,
+    /// Rows of ete3's taxa table, see [export_ete3_tsv]
+    Ete3Tsv,
+    /// SQL for PostgreSQL filling the antiSMASH DB taxa table, see [export_sql]
+    Sql,
+}
+
+impl ExportFormat {
+    /// Whether this format is written to a directory rather than a single file
+    pub fn is_directory(&self) -> bool {
+        matches!(self, ExportFormat::GenusBundles)
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(ExportFormat::Tsv),
+            "genus-bundles" => Ok(ExportFormat::GenusBundles),
+            "taxonomizr" => Ok(ExportFormat::Taxonomizr),
+            "ete3-tsv" => Ok(ExportFormat::Ete3Tsv),
+            "sql" => Ok(ExportFormat::Sql),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Maximum lengths (in characters) of exported text columns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnLimits {
+    /// Limit for all columns without a specific one
+    pub default: Option<usize>,
+    pub columns: HashMap<String, usize>,
+}
+
+impl ColumnLimits {
+    pub fn new(default: Option<usize>) -> ColumnLimits {
+        ColumnLimits {
+            default,
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Parse a `column=limit` specification and add it
+    pub fn add(&mut self, spec: &str) -> Result<(), ASDBTaxonError> {
+        let (column, limit) = spec
+            .split_once('=')
+            .ok_or_else(|| ASDBTaxonError::InvalidFormat(spec.to_string()))?;
+        let column = column.trim();
+        if !COLUMNS[1..].contains(&column) {
+            return Err(ASDBTaxonError::InvalidFormat(spec.to_string()));
+        }
+        self.columns
+            .insert(column.to_string(), limit.trim().parse()?);
+        Ok(())
+    }
+
+    pub fn limit(&self, column: &str) -> Option<usize> {
+        self.columns.get(column).copied().or(self.default)
+    }
+}
+
+/// Settings for an export
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub limits: ColumnLimits,
+    /// Also export entries only cached as ancestors of requested taxids
+    pub include_ancestors: bool,
+    /// Fix the capitalization of names for display
+    pub canonicalize: Option<Canonicalizer>,
+    /// Append the lineage as a single column in this style, for TSV exports
+    pub lineage: Option<LineageStyle>,
+    /// Append the [PROVENANCE_COLUMNS] of each entry, for TSV exports
+    pub provenance: bool,
+}
+
+impl ExportOptions {
+    pub fn new(limits: ColumnLimits) -> ExportOptions {
+        ExportOptions {
+            limits,
+            ..Default::default()
+        }
+    }
+}
+
+/// A value that was shortened to fit its column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    pub tax_id: TaxId,
+    pub column: String,
+    pub original: String,
+    pub truncated: String,
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.tax_id, self.column, self.original, self.truncated
+        )
+    }
+}
+
+/// Make a value safe for line-based export: no control characters, single spaces, trimmed
+pub fn normalize(value: &str) -> Cow<'_, str> {
+    let clean =
+        !value.chars().any(|c| c.is_control()) && !value.contains("  ") && value.trim() == value;
+    if clean {
+        return Cow::Borrowed(value);
+    }
+    let spaced: String = value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    Cow::Owned(spaced.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Shorten `value` to at most `limit` characters, marking the cut with [ELLIPSIS]
+pub fn truncate(value: &str, limit: usize) -> Cow<'_, str> {
+    if value.chars().count() <= limit {
+        return Cow::Borrowed(value);
+    }
+    let marker_len = ELLIPSIS.chars().count();
+    if limit <= marker_len {
+        return Cow::Owned(value.chars().take(limit).collect());
+    }
+    let kept: String = value.chars().take(limit - marker_len).collect();
+    Cow::Owned(format!("{}{}", kept.trim_end(), ELLIPSIS))
+}
+
+pub(crate) fn column_values(entry: &NcbiTaxEntry) -> [&str; 9] {
+    [
+        &entry.name,
+        &entry.species,
+        &entry.genus,
+        &entry.family,
+        &entry.order,
+        &entry.class,
+        &entry.phylum,
+        &entry.kingdom,
+        &entry.superkingdom,
+    ]
+}
+
+/// Apply `canonicalizer` to the value of `column`
+pub fn canonicalize<'a>(
+    value: Cow<'a, str>,
+    column: &str,
+    canonicalizer: Option<&Canonicalizer>,
+) -> Cow<'a, str> {
+    let Some(canonicalizer) = canonicalizer else {
+        return value;
+    };
+    let canonical = match column {
+        "name" => canonicalizer.name(&value),
+        "species" => canonicalizer.epithet(&value),
+        _ => canonicalizer.uninomial(&value),
+    };
+    if canonical == value {
+        value
+    } else {
+        Cow::Owned(canonical)
+    }
+}
+
+/// Text columns of an entry after the normalization chain of `options`, recording truncations
+///
+/// Values are normalized, canonicalized if requested and then truncated to the column limits.
+pub fn export_values(
+    entry: &NcbiTaxEntry,
+    options: &ExportOptions,
+    truncations: &mut Vec<Truncation>,
+) -> Vec<String> {
+    column_values(entry)
+        .iter()
+        .zip(COLUMNS[1..].iter())
+        .map(|(value, column)| export_value(entry.tax_id, value, column, options, truncations))
+        .collect()
+}
+
+/// A single value of `column` after the normalization chain of `options`, see [export_values]
+fn export_value(
+    tax_id: TaxId,
+    value: &str,
+    column: &str,
+    options: &ExportOptions,
+    truncations: &mut Vec<Truncation>,
+) -> String {
+    let normalized = canonicalize(normalize(value), column, options.canonicalize.as_ref());
+    let Some(limit) = options.limits.limit(column) else {
+        return normalized.into_owned();
+    };
+    let truncated = truncate(&normalized, limit);
+    if let Cow::Owned(ref short) = truncated {
+        truncations.push(Truncation {
+            tax_id,
+            column: column.to_string(),
+            original: normalized.to_string(),
+            truncated: short.clone(),
+        });
+    }
+    truncated.into_owned()
+}
+
+/// Columns appended to TSV exports with [ExportOptions::provenance]
+pub const PROVENANCE_COLUMNS: [&str; 6] = [
+    "source",
+    "dump",
+    "dump_modified",
+    "added_at",
+    "updated_at",
+    "overridden",
+];
+
+/// Values of the [PROVENANCE_COLUMNS] of an entry, empty where nothing was recorded
+pub fn provenance_values(cache: &TaxonCache, entry: &NcbiTaxEntry) -> Vec<String> {
+    let provenance = entry.provenance.as_ref();
+    let overridden: Vec<_> = Provenance::overridden(cache, entry.tax_id)
+        .iter()
+        .map(|field| field.to_string())
+        .collect();
+    vec![
+        entry.source.as_str().to_string(),
+        provenance
+            .and_then(|provenance| provenance.dump.clone())
+            .unwrap_or_default(),
+        provenance
+            .and_then(|provenance| provenance.dump_modified)
+            .map(format_timestamp)
+            .unwrap_or_default(),
+        provenance
+            .map(|provenance| format_timestamp(provenance.added_at))
+            .unwrap_or_default(),
+        provenance
+            .map(|provenance| format_timestamp(provenance.updated_at))
+            .unwrap_or_default(),
+        overridden.join(","),
+    ]
+}
+
+/// Entries of the cache to export, sorted by taxid for deterministic output
+pub fn sorted_entries<'a>(cache: &'a TaxonCache, options: &ExportOptions) -> Vec<&'a NcbiTaxEntry> {
+    let mut entries: Vec<&NcbiTaxEntry> = cache
+        .mappings
+        .values()
+        .filter(|entry| options.include_ancestors || !entry.ancestor_only)
+        .collect();
+    entries.sort_by_key(|entry| entry.tax_id);
+    entries
+}
+
+/// Export all entries as tab-separated values, returning the values that had to be truncated
+pub fn export_tsv(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    options: &ExportOptions,
+) -> Result<Vec<Truncation>, ASDBTaxonError> {
+    let mut truncations = Vec::new();
+    for entry in sorted_entries(cache, options) {
+        let mut values = export_values(entry, options, &mut truncations);
+        if let Some(style) = options.lineage {
+            values.push(entry.lineage_string(style));
+        }
+        if options.provenance {
+            values.extend(provenance_values(cache, entry));
+        }
+        writeln!(output, "{}\t{}", entry.tax_id, values.join("\t"))?;
+    }
+    Ok(truncations)
+}
+
+/// Aggregate information on a genus, for rendering genus pages
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GenusBundle {
+    pub genus: String,
+    /// Ranks above the genus, from family up to superkingdom
+    pub lineage: BTreeMap<String, String>,
+    pub taxids: Vec<TaxId>,
+    /// Distinct species epithets
+    pub species: Vec<String>,
+}
+
+/// Group the entries by genus, skipping entries of unknown genus
+pub fn genus_bundles(cache: &TaxonCache, options: &ExportOptions) -> Vec<GenusBundle> {
+    let mut bundles: BTreeMap<String, GenusBundle> = BTreeMap::new();
+    for entry in sorted_entries(cache, options) {
+        let values = export_values(entry, options, &mut Vec::new());
+        let columns: HashMap<&str, &str> = COLUMNS[1..]
+            .iter()
+            .copied()
+            .zip(values.iter().map(String::as_str))
+            .collect();
+        let genus = columns["genus"];
+        if genus.is_empty() || genus == "Unknown" {
+            continue;
+        }
+        let bundle = bundles
+            .entry(genus.to_string())
+            .or_insert_with(|| GenusBundle {
+                genus: genus.to_string(),
+                lineage: COLUMNS[4..]
+                    .iter()
+                    .map(|rank| (rank.to_string(), columns[rank].to_string()))
+                    .collect(),
+                ..Default::default()
+            });
+        bundle.taxids.push(entry.tax_id);
+        let species = columns["species"];
+        if species != "Unknown" && !bundle.species.iter().any(|known| known == species) {
+            bundle.species.push(species.to_string());
+        }
+    }
+    bundles
+        .into_values()
+        .map(|mut bundle| {
+            bundle.species.sort();
+            bundle
+        })
+        .collect()
+}
+
+/// File name of a genus bundle, e.g. `Candidatus_Pelagibacter.json`
+pub fn bundle_file_name(genus: &str) -> String {
+    let name: String = genus
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.json", name)
+}
+
+/// Write one JSON file per genus to `dir`, plus an `index.json` listing them all
+///
+/// Returns the number of genera written.
+#[cfg(feature = "fs")]
+pub fn export_genus_bundles(
+    cache: &TaxonCache,
+    dir: &Path,
+    options: &ExportOptions,
+) -> Result<usize, ASDBTaxonError> {
+    fs::create_dir_all(dir)?;
+    let bundles = genus_bundles(cache, options);
+    let mut index = Vec::with_capacity(bundles.len());
+    for bundle in &bundles {
+        let file_name = bundle_file_name(&bundle.genus);
+        let out = io::BufWriter::new(fs::File::create(dir.join(&file_name))?);
+        serde_json::to_writer(out, bundle)?;
+        index.push(json!({
+            "genus": bundle.genus,
+            "file": file_name,
+            "taxids": bundle.taxids.len(),
+        }));
+    }
+    let out = io::BufWriter::new(fs::File::create(dir.join("index.json"))?);
+    serde_json::to_writer(out, &index)?;
+    Ok(bundles.len())
+}
+
+/// Parent of an entry, falling back to the root for caches built without nodes.dmp
+fn parent_id(entry: &NcbiTaxEntry) -> TaxId {
+    entry.parent_id.unwrap_or(TaxId::ROOT)
+}
+
+/// Rank of an entry from nodes.dmp, or else judging by which lineage column holds its name
+pub fn rank(entry: &NcbiTaxEntry) -> &str {
+    if let Some(ref rank) = entry.rank {
+        return rank;
+    }
+    Rank::ALL
+        .into_iter()
+        .rev()
+        .filter(|rank| *rank != Rank::Species)
+        .find(|rank| entry.rank_value(*rank) == entry.name)
+        .map_or("no rank", |rank| rank.as_str())
+}
+
+/// TaxIDs from `entry` up to the root, following the parent links within the cache
+fn track(cache: &TaxonCache, entry: &NcbiTaxEntry) -> Vec<TaxId> {
+    let mut track = vec![entry.tax_id];
+    let mut current = entry;
+    while let Some(parent) = current.parent_id {
+        if track.contains(&parent) {
+            break;
+        }
+        track.push(parent);
+        match cache.mappings.get(&parent) {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    if track.last() != Some(&TaxId::ROOT) {
+        track.push(TaxId::ROOT);
+    }
+    track
+}
+
+/// Warn if the cache lacks the parent links tree-based formats need
+fn check_parent_links(cache: &TaxonCache) {
+    if cache
+        .mappings
+        .values()
+        .all(|entry| entry.parent_id.is_none())
+    {
+        warn!(
+            "Cache has no parent links, all taxa are attached to the root; build it with --nodes"
+        );
+    }
+}
+
+/// Export all entries as SQL creating taxonomizr's tables, returning the truncated names
+///
+/// Load the result with `sqlite3 nameNode.sqlite < export.sql`. The cache knows no accessions,
+/// so there is no `accessionTaxa` table. Without [ExportOptions::include_ancestors], ancestors
+/// cached only for the parent links are left out, and so are their nodes.
+pub fn export_taxonomizr(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    options: &ExportOptions,
+) -> Result<Vec<Truncation>, ASDBTaxonError> {
+    check_parent_links(cache);
+    let entries = sorted_entries(cache, options);
+    let mut truncations = Vec::new();
+    writeln!(output, "BEGIN TRANSACTION;")?;
+    writeln!(
+        output,
+        "CREATE TABLE names (id INTEGER, name TEXT, scientific INTEGER);"
+    )?;
+    writeln!(
+        output,
+        "CREATE TABLE nodes (id INTEGER PRIMARY KEY, rank TEXT, parent INTEGER);"
+    )?;
+    for entry in &entries {
+        let name = export_value(entry.tax_id, &entry.name, "name", options, &mut truncations);
+        writeln!(
+            output,
+            "INSERT INTO names VALUES ({}, '{}', 1);",
+            entry.tax_id,
+            name.replace('\'', "''")
+        )?;
+    }
+    for entry in &entries {
+        writeln!(
+            output,
+            "INSERT INTO nodes VALUES ({}, '{}', {});",
+            entry.tax_id,
+            rank(entry).replace('\'', "''"),
+            parent_id(entry)
+        )?;
+    }
+    writeln!(output, "CREATE INDEX namesId ON names (id);")?;
+    writeln!(output, "CREATE INDEX namesName ON names (name);")?;
+    writeln!(output, "COMMIT;")?;
+    Ok(truncations)
+}
+
+/// Columns of the antiSMASH DB taxa table filled by [export_sql], in the order of [COLUMNS]
+const SQL_COLUMNS: [&str; 11] = [
+    "ncbi_taxid",
+    "name",
+    "species",
+    "genus",
+    "family",
+    "taxonomic_order",
+    "class",
+    "phylum",
+    "kingdom",
+    "superkingdom",
+    "strain",
+];
+
+/// Export all entries as a PostgreSQL script creating and filling the antiSMASH DB taxa table
+///
+/// The table is only created if it doesn't exist yet, see [TAXA_TABLE]. Values go through the
+/// same normalization as [export_tsv]. Rows already in the table are updated in place, keeping
+/// their `tax_id`, so the script can be run again after the cache changed.
+pub fn export_sql(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    options: &ExportOptions,
+) -> Result<Vec<Truncation>, ASDBTaxonError> {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "''"));
+    let columns = SQL_COLUMNS.join(", ");
+    let updates = SQL_COLUMNS[1..]
+        .iter()
+        .map(|column| format!("{0} = EXCLUDED.{0}", column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut truncations = Vec::new();
+    writeln!(output, "BEGIN;")?;
+    writeln!(output, "{}", TAXA_TABLE)?;
+    for entry in sorted_entries(cache, options) {
+        let mut values: Vec<String> = export_values(entry, options, &mut truncations)
+            .iter()
+            .map(|value| quote(value))
+            .collect();
+        values.push(match entry.strain {
+            Some(ref strain) => quote(&normalize(strain)),
+            None => "NULL".to_string(),
+        });
+        writeln!(
+            output,
+            "INSERT INTO antismash.taxa ({}) VALUES ({}, {}) \
+             ON CONFLICT (ncbi_taxid) DO UPDATE SET {};",
+            columns,
+            entry.tax_id,
+            values.join(", "),
+            updates
+        )?;
+    }
+    writeln!(output, "COMMIT;")?;
+    Ok(truncations)
+}
+
+/// Export all entries as rows of ete3's taxa table, returning the truncated names
+///
+/// Columns are taxid, parent, scientific name, common name (always empty), rank and the
+/// comma-separated taxids from the taxon up to the root, as in the `taxa.tab` ete3 builds.
+/// Entries are picked as for [export_taxonomizr].
+pub fn export_ete3_tsv(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    options: &ExportOptions,
+) -> Result<Vec<Truncation>, ASDBTaxonError> {
+    check_parent_links(cache);
+    let mut truncations = Vec::new();
+    for entry in sorted_entries(cache, options) {
+        let track: Vec<String> = track(cache, entry)
+            .iter()
+            .map(|tax_id| tax_id.to_string())
+            .collect();
+        writeln!(
+            output,
+            "{}\t{}\t{}\t\t{}\t{}",
+            entry.tax_id,
+            parent_id(entry),
+            export_value(entry.tax_id, &entry.name, "name", options, &mut truncations),
+            rank(entry),
+            track.join(",")
+        )?;
+    }
+    Ok(truncations)
+}
+
+/// Export the cache in the given format
+pub fn export(
+    cache: &TaxonCache,
+    format: ExportFormat,
+    output: impl Write,
+    options: &ExportOptions,
+) -> Result<Vec<Truncation>, ASDBTaxonError> {
+    match format {
+        ExportFormat::Tsv => export_tsv(cache, output, options),
+        ExportFormat::Taxonomizr => export_taxonomizr(cache, output, options),
+        ExportFormat::Ete3Tsv => export_ete3_tsv(cache, output, options),
+        ExportFormat::Sql => export_sql(cache, output, options),
+        ExportFormat::GenusBundles => Err(ASDBTaxonError::InvalidFormat(
+            "genus-bundles can only be exported to a directory".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Streptomyces", 20), "Streptomyces");
+        assert_eq!(truncate("Streptomyces", 10), "Strepto...");
+        assert_eq!(truncate("Streptomyces sp.", 15), "Streptomyces...");
+        assert_eq!(truncate("Streptomyces", 2), "St");
+        assert_eq!(truncate("Ästreptomyces", 6), "Äst...");
+        assert_eq!(normalize(" Streptomyces \t sp.\n"), "Streptomyces sp.");
+    }
+
+    #[test]
+    fn test_export_tsv() {
+        let mut cache = TaxonCache::new();
+        cache.mappings.insert(
+            tid(1883),
+            NcbiTaxEntry {
+                name: "Streptomyces examplis with a very long strain name".to_string(),
+                genus: "Streptomyces".into(),
+                ..NcbiTaxEntry::new(tid(1883))
+            },
+        );
+        cache.mappings.insert(
+            tid(2),
+            NcbiTaxEntry {
+                name: "Bacteria".to_string(),
+                ancestor_only: true,
+                ..NcbiTaxEntry::new(tid(2))
+            },
+        );
+        let mut limits = ColumnLimits::new(Some(255));
+        limits.add("name=20").unwrap();
+        assert!(limits.add("tax_id=20").is_err());
+        let mut options = ExportOptions::new(limits);
+
+        let mut out = Vec::new();
+        let truncations = export_tsv(&cache, &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1883\tStreptomyces exam...\t\tStreptomyces\t\t\t\t\t\t\n"
+        );
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].column, "name");
+
+        options.include_ancestors = true;
+        let mut out = Vec::new();
+        export_tsv(&cache, &mut out, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+        options.canonicalize = Some(Canonicalizer::default());
+        cache.mappings.get_mut(&tid(1883)).unwrap().genus = "STREPTOMYCES".into();
+        let values = export_values(&cache.mappings[&tid(1883)], &options, &mut Vec::new());
+        assert_eq!(values[2], "Streptomyces");
+
+        options.include_ancestors = false;
+        options.provenance = true;
+        cache.mappings.get_mut(&tid(1883)).unwrap().provenance = Some(Provenance::new(60, None));
+        let mut out = Vec::new();
+        export_tsv(&cache, &mut out, &options).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("\ttaxdump\t\t\t1970-01-01T00:01:00Z\t1970-01-01T00:01:00Z\t\n"));
+    }
+
+    #[test]
+    fn test_export_sql() {
+        let mut cache = TaxonCache::new();
+        cache.mappings.insert(
+            tid(1902),
+            NcbiTaxEntry {
+                name: "Streptomyces d'examplis A3(2)".to_string(),
+                species: "d'examplis".into(),
+                genus: "Streptomyces".into(),
+                strain: Some("A3(2)".to_string()),
+                ..NcbiTaxEntry::new(tid(1902))
+            },
+        );
+        let mut out = Vec::new();
+        export_sql(&cache, &mut out, &ExportOptions::default()).unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert!(sql.starts_with("BEGIN;\nCREATE SCHEMA IF NOT EXISTS antismash;\n"));
+        assert!(sql.ends_with("\nCOMMIT;\n"));
+        assert!(sql.contains(
+            "VALUES (1902, 'Streptomyces d''examplis A3(2)', 'd''examplis', 'Streptomyces', '', '', '', '', '', '', 'A3(2)') \
+             ON CONFLICT (ncbi_taxid) DO UPDATE SET name = EXCLUDED.name, "
+        ));
+        assert!(sql.contains("strain = EXCLUDED.strain;\n"));
+        assert!(sql.contains("CREATE UNIQUE INDEX IF NOT EXISTS taxa_ncbi_taxid_idx"));
+    }
+
+    #[test]
+    fn test_genus_bundles() {
+        let mut cache = TaxonCache::new();
+        for (tax_id, species, genus) in [
+            (tid(1883), "Unknown", "Streptomyces"),
+            (tid(1902), "coelicolor", "Streptomyces"),
+            (tid(100226), "coelicolor", "Streptomyces"),
+            (tid(5062), "oryzae", "Aspergillus"),
+            (tid(42), "Unknown", "Unknown"),
+        ] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    species: species.into(),
+                    genus: genus.into(),
+                    family: "Unknown".into(),
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        let bundles = genus_bundles(&cache, &ExportOptions::default());
+        assert_eq!(bundles.len(), 2);
+        assert_eq!(bundles[1].genus, "Streptomyces");
+        assert_eq!(bundles[1].taxids, vec![1883, 1902, 100226]);
+        assert_eq!(bundles[1].species, vec!["coelicolor"]);
+        assert_eq!(bundles[1].lineage["family"], "Unknown");
+        assert_eq!(
+            bundle_file_name("Candidatus Pelagibacter"),
+            "Candidatus_Pelagibacter.json"
+        );
+    }
+
+    #[test]
+    fn test_export_tree_formats() {
+        let mut cache = TaxonCache::new();
+        for (tax_id, parent_id, name, rank) in [
+            (tid(2), Some(TaxId::ROOT), "Bacteria", Some("superkingdom")),
+            (tid(1883), Some(tid(2)), "Streptomyces", Some("genus")),
+            (tid(1902), Some(tid(1883)), "Streptomyces coelicolor", None),
+        ] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    parent_id,
+                    name: name.to_string(),
+                    genus: "Streptomyces".into(),
+                    rank: rank.map(str::to_string),
+                    ancestor_only: tax_id != 1902,
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+
+        let mut out = Vec::new();
+        export(&cache, ExportFormat::Ete3Tsv, &mut out, &Default::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1902\t1883\tStreptomyces coelicolor\t\tno rank\t1902,1883,2,1\n"
+        );
+
+        let options = ExportOptions {
+            include_ancestors: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        export(&cache, ExportFormat::Ete3Tsv, &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2\t1\tBacteria\t\tsuperkingdom\t2,1\n\
+             1883\t2\tStreptomyces\t\tgenus\t1883,2,1\n\
+             1902\t1883\tStreptomyces coelicolor\t\tno rank\t1902,1883,2,1\n"
+        );
+
+        cache.mappings.get_mut(&tid(1902)).unwrap().name = "streptomyces o'hara".to_string();
+        let options = ExportOptions {
+            canonicalize: Some(Canonicalizer::default()),
+            ..options
+        };
+        let mut out = Vec::new();
+        export(&cache, ExportFormat::Taxonomizr, &mut out, &options).unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert!(sql.contains("INSERT INTO names VALUES (1902, 'Streptomyces o''hara', 1);"));
+        assert!(sql.contains("INSERT INTO nodes VALUES (1883, 'genus', 2);"));
+        assert!(sql.ends_with("COMMIT;\n"));
+
+        let mut limits = ColumnLimits::new(None);
+        limits.add("name=12").unwrap();
+        let options = ExportOptions::new(limits);
+        let mut out = Vec::new();
+        let truncations = export(&cache, ExportFormat::Ete3Tsv, &mut out, &options).unwrap();
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].truncated, "streptomy...");
+    }
+}
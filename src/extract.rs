@@ -0,0 +1,436 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extracting the taxids referenced by data files
+//!
+//! [TaxidExtractor] is the extension point for file formats the scan doesn't know, register
+//! implementations with [ScanOptions::register](crate::scan::ScanOptions::register).
+
+use std::fmt;
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::errors::ASDBTaxonError;
+use crate::genbank;
+use crate::gzip;
+use crate::taxa::TaxId;
+
+/// Built-in pattern for the `taxon:` xrefs antiSMASH writes
+pub const DEFAULT_TAXON_PATTERN: &str = r#""taxon:(\d+)"#;
+
+/// Finds the taxids referenced by a data file
+pub trait TaxidExtractor: Send + Sync {
+    /// All taxids referenced by the file at `path`, in order of appearance
+    fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError>;
+}
+
+impl fmt::Debug for dyn TaxidExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TaxidExtractor")
+    }
+}
+
+/// Read a data file, decompressing it if gzipped
+pub fn read_data_file(path: &Path) -> Result<String, ASDBTaxonError> {
+    if path.extension() != Some("gz".as_ref()) {
+        return Ok(fs::read_to_string(path)?);
+    }
+    read_data(fs::File::open(path)?, path)
+}
+
+/// Read the data file at `path` from `input`, e.g. an archive member, decompressing it if gzipped
+pub fn read_data(mut input: impl Read, path: &Path) -> Result<String, ASDBTaxonError> {
+    let mut content = String::new();
+    if path.extension() == Some("gz".as_ref()) {
+        gzip::decoder(BufReader::new(input)).read_to_string(&mut content)?;
+    } else {
+        input.read_to_string(&mut content)?;
+    }
+    Ok(content)
+}
+
+/// Kind of data file, deciding how taxids are extracted from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaxidSource {
+    /// antiSMASH results and other JSON files
+    Json,
+    /// GenBank or EMBL flat files
+    GenBank,
+}
+
+impl TaxidSource {
+    /// Source of a data file, judging by its name and ignoring a `.gz` suffix
+    ///
+    /// Files of other kinds aren't data files and give `None`.
+    pub fn from_path(path: &Path) -> Option<TaxidSource> {
+        let name = path.file_name()?.to_string_lossy();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        match name.rsplit_once('.')?.1 {
+            "json" => Some(TaxidSource::Json),
+            "gbk" | "gb" | "gbff" | "genbank" | "embl" => Some(TaxidSource::GenBank),
+            _ => None,
+        }
+    }
+}
+
+/// Taxids captured by the first group of a regex
+#[derive(Debug, Clone)]
+pub struct RegexExtractor {
+    regex: Regex,
+}
+
+impl RegexExtractor {
+    /// Extractor for `pattern`, which needs a capture group for the taxid
+    pub fn new(pattern: &str) -> Result<RegexExtractor, ASDBTaxonError> {
+        let regex = Regex::new(pattern)?;
+        if regex.captures_len() < 2 {
+            return Err(ASDBTaxonError::InvalidFilter(format!(
+                "taxid pattern {:?} has no capture group",
+                pattern
+            )));
+        }
+        Ok(RegexExtractor { regex })
+    }
+
+    /// Taxids in `content`, all of them or just the first match
+    pub fn find(&self, content: &str, all: bool) -> Vec<TaxId> {
+        if all {
+            self.regex
+                .captures_iter(content)
+                .filter_map(|cap| cap[1].parse().ok())
+                .collect()
+        } else {
+            self.regex
+                .captures(content)
+                .and_then(|cap| cap[1].parse().ok())
+                .into_iter()
+                .collect()
+        }
+    }
+}
+
+impl TaxidExtractor for RegexExtractor {
+    fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError> {
+        Ok(distinct(self.find(&read_data_file(path)?, true)))
+    }
+}
+
+/// Taxids held in the values at a path in a JSON file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonExtractor {
+    path: JsonPath,
+}
+
+impl JsonExtractor {
+    /// Extractor for a path like `records.*.annotations.tax_id`, see [JsonPath]
+    pub fn new(path: &str) -> Result<JsonExtractor, ASDBTaxonError> {
+        Ok(JsonExtractor {
+            path: path.parse()?,
+        })
+    }
+
+    /// Taxids in `json`
+    pub fn find(&self, json: &Value) -> Vec<TaxId> {
+        self.path
+            .select(json)
+            .into_iter()
+            .filter_map(taxid_value)
+            .collect()
+    }
+}
+
+impl TaxidExtractor for JsonExtractor {
+    fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError> {
+        let json: Value = serde_json::from_str(&read_data_file(path)?)?;
+        Ok(distinct(self.find(&json)))
+    }
+}
+
+/// Taxids of the source features in GenBank or EMBL files
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenBankExtractor;
+
+impl TaxidExtractor for GenBankExtractor {
+    fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError> {
+        Ok(genbank::source_taxids(&read_data_file(path)?))
+    }
+}
+
+/// A single way of finding a taxid in a data file
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Regex whose first capture group is the taxid
+    Pattern(RegexExtractor),
+    /// Path to a JSON value holding the taxid
+    Path(JsonExtractor),
+}
+
+impl Rule {
+    /// Rule for a regex, which needs a capture group for the taxid
+    pub fn pattern(pattern: &str) -> Result<Rule, ASDBTaxonError> {
+        Ok(Rule::Pattern(RegexExtractor::new(pattern)?))
+    }
+
+    /// Rule for a path like `records.*.annotations.tax_id`, see [JsonPath]
+    pub fn path(path: &str) -> Result<Rule, ASDBTaxonError> {
+        Ok(Rule::Path(JsonExtractor::new(path)?))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Pattern(extractor) => write!(f, "pattern {}", extractor.regex.as_str()),
+            Rule::Path(extractor) => write!(f, "path {}", extractor.path),
+        }
+    }
+}
+
+/// Dotted path into a JSON document
+///
+/// Segments are object keys or array indices, `*` stands for every array element or object
+/// value. A leading `$.` is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<String>,
+}
+
+impl JsonPath {
+    /// All values at this path in `json`
+    pub fn select<'a>(&self, json: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![json];
+        for segment in &self.segments {
+            current = current
+                .into_iter()
+                .flat_map(|value| -> Vec<&'a Value> {
+                    match (value, segment.as_str()) {
+                        (Value::Array(items), "*") => items.iter().collect(),
+                        (Value::Object(map), "*") => map.values().collect(),
+                        (Value::Array(items), index) => index
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|index| items.get(index))
+                            .into_iter()
+                            .collect(),
+                        (Value::Object(map), key) => map.get(key).into_iter().collect(),
+                        _ => Vec::new(),
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+}
+
+impl std::str::FromStr for JsonPath {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = s.strip_prefix("$.").unwrap_or(s);
+        let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            return Err(ASDBTaxonError::InvalidFilter(format!(
+                "invalid JSON path {:?}",
+                s
+            )));
+        }
+        Ok(JsonPath { segments })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.segments.join("."))
+    }
+}
+
+/// Rules to find taxids with, tried in order, starting with the built-in `taxon:` pattern
+///
+/// This is the extractor used for the JSON and GenBank files the scan knows about.
+#[derive(Debug, Clone)]
+pub struct TaxidRules {
+    rules: Vec<Rule>,
+}
+
+impl Default for TaxidRules {
+    fn default() -> Self {
+        TaxidRules {
+            rules: vec![Rule::pattern(DEFAULT_TAXON_PATTERN).unwrap()],
+        }
+    }
+}
+
+impl TaxidRules {
+    pub fn new() -> TaxidRules {
+        Default::default()
+    }
+
+    /// Try `rule` if none of the earlier ones finds a taxid
+    pub fn add(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// The taxid found by the first rule with a positive match
+    ///
+    /// The content is only parsed as JSON if a path rule is reached.
+    pub fn first(&self, content: &str) -> Option<TaxId> {
+        self.find(content, false).first().copied()
+    }
+
+    /// All distinct taxids found by the first rule with positive matches, in order of appearance
+    pub fn all(&self, content: &str) -> Vec<TaxId> {
+        self.find(content, true)
+    }
+
+    /// Taxids in `content` of a `source` file, all of them or just the first
+    ///
+    /// For GenBank files, the `source` features are used, falling back to the rules.
+    pub fn extract(&self, source: TaxidSource, content: &str, all: bool) -> Vec<TaxId> {
+        let mut taxids = match source {
+            TaxidSource::GenBank => genbank::source_taxids(content),
+            TaxidSource::Json => Vec::new(),
+        };
+        if taxids.is_empty() {
+            taxids = self.find(content, all);
+        }
+        if !all {
+            taxids.truncate(1);
+        }
+        taxids
+    }
+
+    fn find(&self, content: &str, all: bool) -> Vec<TaxId> {
+        let mut json: Option<Option<Value>> = None;
+        for rule in &self.rules {
+            let found = match rule {
+                Rule::Pattern(extractor) => extractor.find(content, all),
+                Rule::Path(extractor) => json
+                    .get_or_insert_with(|| serde_json::from_str(content).ok())
+                    .as_ref()
+                    .map(|json| extractor.find(json))
+                    .unwrap_or_default(),
+            };
+            let taxids = distinct(found);
+            if !taxids.is_empty() {
+                return taxids;
+            }
+        }
+        Vec::new()
+    }
+}
+
+impl TaxidExtractor for TaxidRules {
+    fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError> {
+        let source = TaxidSource::from_path(path).unwrap_or(TaxidSource::Json);
+        Ok(TaxidRules::extract(
+            self,
+            source,
+            &read_data_file(path)?,
+            true,
+        ))
+    }
+}
+
+/// The distinct taxids in `found`, keeping their order
+fn distinct(found: Vec<TaxId>) -> Vec<TaxId> {
+    let mut taxids = Vec::new();
+    for taxid in found {
+        if !taxids.contains(&taxid) {
+            taxids.push(taxid);
+        }
+    }
+    taxids
+}
+
+/// Taxid held in a JSON number or a string like `"12345"` or `"NCBITaxon:12345"`
+fn taxid_value(value: &Value) -> Option<TaxId> {
+    match value {
+        Value::Number(number) => number.as_i64().and_then(|value| TaxId::new(value).ok()),
+        Value::String(text) => text.rsplit(':').next()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_extract_all() {
+        let extractor = TaxidRules::new();
+        let content = r#"["taxon:1883", "taxon:0", "taxon:5062", "taxon:1883"]"#;
+        assert_eq!(extractor.all(content), vec![1883, 5062]);
+        assert_eq!(extractor.first(content), Some(tid(1883)));
+        assert!(extractor.all("{}").is_empty());
+
+        let mut extractor = TaxidRules::new();
+        extractor.add(Rule::path("records.*.tax_id").unwrap());
+        let content = r#"{"records": [{"tax_id": 1883}, {"tax_id": "5062"}]}"#;
+        assert_eq!(extractor.all(content), vec![1883, 5062]);
+    }
+
+    #[test]
+    fn test_taxid_source() {
+        let source = |name: &str| TaxidSource::from_path(Path::new(name));
+        assert_eq!(source("a/NC_003888.json"), Some(TaxidSource::Json));
+        assert_eq!(source("NC_003888.json.gz"), Some(TaxidSource::Json));
+        assert_eq!(source("NC_003888.gbk"), Some(TaxidSource::GenBank));
+        assert_eq!(source("GCF_000001.gbff.gz"), Some(TaxidSource::GenBank));
+        assert_eq!(source("X56734.embl"), Some(TaxidSource::GenBank));
+        assert_eq!(source("notes.txt.gz"), None);
+        assert_eq!(source("gbk"), None);
+
+        let extractor = TaxidRules::new();
+        let genbank = "     source          1..10\n                     /db_xref=\"taxon:1883\"\n";
+        assert_eq!(
+            extractor.extract(TaxidSource::GenBank, genbank, true),
+            vec![1883]
+        );
+        assert!(extractor
+            .extract(TaxidSource::Json, r#"{"tax_id": 1883}"#, true)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_extractor() {
+        let content = r#"{"records": [{"dbxrefs": ["NCBITaxon:1883"], "tax_id": 5062}]}"#;
+        let mut extractor = TaxidRules::new();
+        assert_eq!(extractor.first(content), None);
+        assert_eq!(
+            extractor.first(r#"["taxon:1883", "taxon:5062"]"#),
+            Some(tid(1883))
+        );
+        assert_eq!(extractor.first(r#"["taxon:0"]"#), None);
+
+        extractor.add(Rule::pattern(r#""NCBITaxon:(\d+)""#).unwrap());
+        assert_eq!(extractor.first(content), Some(tid(1883)));
+
+        let mut extractor = TaxidRules::new();
+        extractor.add(Rule::path("$.records.*.tax_id").unwrap());
+        assert_eq!(extractor.first(content), Some(tid(5062)));
+        extractor = TaxidRules::new();
+        extractor.add(Rule::path("records.0.dbxrefs.0").unwrap());
+        assert_eq!(extractor.first(content), Some(tid(1883)));
+        assert_eq!(extractor.first("not json"), None);
+
+        assert!(Rule::pattern("taxon:\\d+").is_err());
+        assert!(Rule::pattern("(").is_err());
+        assert!(Rule::path("records..tax_id").is_err());
+    }
+}
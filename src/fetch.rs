@@ -0,0 +1,209 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Download NCBI taxdump files and published caches
+//!
+//! Besides `http://` and `https://` URLs, downloads can be local `file://` URLs, e.g. of a mirror
+//! on a shared file system.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::errors::ASDBTaxonError;
+use crate::gzip;
+use crate::md5sum::md5_hex;
+use crate::readahead;
+use crate::tarball;
+use crate::TaxonCache;
+
+pub const TAXDUMP_URL: &str =
+    "https://ftp.ncbi.nlm.nih.gov/pub/taxonomy/new_taxdump/new_taxdump.tar.gz";
+
+/// Dump files extracted from the taxdump archive
+pub const DUMP_FILES: [&str; 3] = ["rankedlineage.dmp", "merged.dmp", "delnodes.dmp"];
+
+/// Stream the contents of `url`, failing if the server answers with an error status
+pub fn open_url(url: &str) -> Result<Box<dyn Read + Send>, ASDBTaxonError> {
+    let failed =
+        |err: &dyn std::fmt::Display| ASDBTaxonError::DownloadError(format!("{}: {}", url, err));
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(Box::new(fs::File::open(path).map_err(|err| failed(&err))?));
+    }
+    let response = ureq::get(url).call().map_err(|err| failed(&err))?;
+    Ok(Box::new(response.into_body().into_reader()))
+}
+
+/// Download `url` into memory
+pub fn download(url: &str) -> Result<Vec<u8>, ASDBTaxonError> {
+    let mut data = Vec::new();
+    open_url(url)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Download `url` to `dest`, only moving the file into place once it is complete
+pub fn download_to(url: &str, dest: &Path) -> Result<(), ASDBTaxonError> {
+    let partial = dest.with_extension("part");
+    let copied = open_url(url).and_then(|mut input| {
+        io::copy(&mut input, &mut fs::File::create(&partial)?)?;
+        Ok(())
+    });
+    if let Err(err) = copied {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(partial, dest)?;
+    Ok(())
+}
+
+/// Parse the checksum out of a `md5sum`-style line
+fn parse_md5(data: &[u8]) -> Result<String, ASDBTaxonError> {
+    String::from_utf8_lossy(data)
+        .split_whitespace()
+        .next()
+        .filter(|sum| sum.len() == 32 && sum.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|sum| sum.to_lowercase())
+        .ok_or_else(|| ASDBTaxonError::DownloadError("invalid md5 file".to_string()))
+}
+
+/// Fetch the taxdump archive at `url` into `dest` and extract the needed dump files
+///
+/// An archive already present in `dest` is only downloaded again if its checksum doesn't
+/// match the published one, and dump files are only extracted again if the archive changed.
+pub fn fetch_taxdump(url: &str, dest: &Path) -> Result<Vec<PathBuf>, ASDBTaxonError> {
+    fetch_taxdump_with(url, dest, readahead::default_buffer_size())
+}
+
+/// Like [fetch_taxdump], decompressing in chunks of `buffer_size`, see [extract_dumps_with]
+pub fn fetch_taxdump_with(
+    url: &str,
+    dest: &Path,
+    buffer_size: usize,
+) -> Result<Vec<PathBuf>, ASDBTaxonError> {
+    fs::create_dir_all(dest)?;
+    let expected = parse_md5(&download(&format!("{}.md5", url))?)?;
+
+    let file_name = url.rsplit('/').next().unwrap_or("new_taxdump.tar.gz");
+    let archive = dest.join(file_name);
+    let mut fresh = false;
+    if !archive.exists() || md5_hex(fs::File::open(&archive)?)? != expected {
+        download_to(url, &archive)?;
+        fresh = true;
+        let actual = md5_hex(fs::File::open(&archive)?)?;
+        if actual != expected {
+            return Err(ASDBTaxonError::ChecksumMismatch(format!(
+                "{}: expected {}, got {}",
+                archive.display(),
+                expected,
+                actual
+            )));
+        }
+    }
+
+    let paths: Vec<PathBuf> = DUMP_FILES.iter().map(|name| dest.join(name)).collect();
+    if fresh || paths.iter().any(|path| !path.exists()) {
+        extract_dumps_with(fs::File::open(&archive)?, dest, buffer_size)?;
+    }
+    Ok(paths)
+}
+
+/// Fetch the published cache at `url`, verified against the `expected` MD5 checksum or else the
+/// one published next to it as `<url>.md5`
+///
+/// The download is kept next to `dest` until it is verified and loaded, and removed after, so
+/// `dest` can be the cache the download replaces. It may be compressed with any supported
+/// [Compression](crate::compression::Compression).
+pub fn fetch_cache(
+    url: &str,
+    expected: Option<&str>,
+    dest: &Path,
+) -> Result<TaxonCache, ASDBTaxonError> {
+    let expected = match expected {
+        Some(sum) => parse_md5(sum.as_bytes())?,
+        None => parse_md5(&download(&format!("{}.md5", url))?)?,
+    };
+    let mut download = dest.as_os_str().to_owned();
+    download.push(".download");
+    let download = PathBuf::from(download);
+
+    let result = download_to(url, &download).and_then(|()| {
+        let actual = md5_hex(fs::File::open(&download)?)?;
+        if actual != expected {
+            return Err(ASDBTaxonError::ChecksumMismatch(format!(
+                "{}: expected {}, got {}",
+                url, expected, actual
+            )));
+        }
+        let mut cache = TaxonCache::new();
+        cache.load_path(&download)?;
+        Ok(cache)
+    });
+    let _ = fs::remove_file(&download);
+    result
+}
+
+/// Extract the needed dump files from a gzipped taxdump tarball into `dest`
+pub fn extract_dumps(archive: impl Read + Send, dest: &Path) -> Result<(), ASDBTaxonError> {
+    extract_dumps_with(archive, dest, readahead::default_buffer_size())
+}
+
+/// Like [extract_dumps], decompressing in a separate thread handing over `buffer_size` chunks
+///
+/// A `buffer_size` of 0 decompresses in the calling thread.
+pub fn extract_dumps_with(
+    archive: impl Read + Send,
+    dest: &Path,
+    buffer_size: usize,
+) -> Result<(), ASDBTaxonError> {
+    readahead::with_read_ahead(gzip::decoder(archive), buffer_size, |decompressed| {
+        tarball::for_each_entry(decompressed, |name, contents| {
+            let base = name.rsplit('/').next().unwrap_or(name);
+            if DUMP_FILES.contains(&base) {
+                let path = dest.join(base);
+                let partial = path.with_extension("part");
+                std::io::copy(contents, &mut fs::File::create(&partial)?)?;
+                fs::rename(partial, path)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_parse_md5() {
+        assert_eq!(
+            parse_md5(b"D41D8CD98F00B204E9800998ECF8427E  new_taxdump.tar.gz\n").unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert!(parse_md5(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_download() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("merged.dmp");
+        fs::write(&path, "23456\t|\t34567\t|\n").unwrap();
+        let data = download(&format!("file://{}", path.display())).unwrap();
+        assert_eq!(data, b"23456\t|\t34567\t|\n");
+        assert!(matches!(
+            download("file:///nonexistent"),
+            Err(ASDBTaxonError::DownloadError(_))
+        ));
+    }
+}
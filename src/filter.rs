@@ -0,0 +1,346 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filter expressions on cache entries, like `superkingdom=Bacteria AND genus~^Strepto`
+//!
+//! An expression combines comparisons of an entry field with a value using `AND`, `OR`, `NOT`
+//! and parentheses, `AND` binding tighter than `OR`. Comparisons are `=` and `!=` for equality
+//! and `~` and `!~` for regular expression matches. Values containing spaces or parentheses can
+//! be quoted with `'` or `"`.
+
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{NcbiTaxEntry, Rank};
+use crate::taxonomy::Taxonomy;
+
+/// Entry field a comparison looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    TaxId,
+    Name,
+    /// Strain designation split off the name, empty if there is none
+    Strain,
+    /// A lineage column like `genus`
+    Lineage(Rank),
+    /// The rank from nodes.dmp, empty if the cache was built without it
+    Rank,
+    /// Parent taxid, empty if the cache was built without nodes.dmp
+    ParentId,
+    /// Lineage by another taxonomy like `d__Bacteria;p__...`, empty if the entry has none
+    Classification(Taxonomy),
+    /// Where the entry came from, `taxdump` or `entrez`
+    Source,
+}
+
+impl Field {
+    fn value(&self, entry: &NcbiTaxEntry) -> String {
+        match self {
+            Field::TaxId => entry.tax_id.to_string(),
+            Field::Name => entry.name.clone(),
+            Field::Strain => entry.strain.clone().unwrap_or_default(),
+            Field::Lineage(rank) => entry.rank_value(*rank).to_string(),
+            Field::Rank => entry.rank.clone().unwrap_or_default(),
+            Field::ParentId => entry
+                .parent_id
+                .map(|parent_id| parent_id.to_string())
+                .unwrap_or_default(),
+            Field::Classification(taxonomy) => entry
+                .classifications
+                .get(taxonomy)
+                .map(|classification| classification.to_string())
+                .unwrap_or_default(),
+            Field::Source => entry.source.as_str().to_string(),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "taxid" | "tax_id" => Ok(Field::TaxId),
+            "name" => Ok(Field::Name),
+            "strain" => Ok(Field::Strain),
+            "rank" => Ok(Field::Rank),
+            "parent_id" | "parent" => Ok(Field::ParentId),
+            "gtdb" => Ok(Field::Classification(Taxonomy::Gtdb)),
+            "silva" => Ok(Field::Classification(Taxonomy::Silva)),
+            "source" => Ok(Field::Source),
+            other => other.parse().map(Field::Lineage),
+        }
+    }
+}
+
+/// Test applied to the value of a field
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Equals(String),
+    NotEquals(String),
+    Matches(Regex),
+    NotMatches(Regex),
+}
+
+impl Condition {
+    fn test(&self, value: &str) -> bool {
+        match self {
+            Condition::Equals(expected) => value == expected,
+            Condition::NotEquals(expected) => value != expected,
+            Condition::Matches(regex) => regex.is_match(value),
+            Condition::NotMatches(regex) => !regex.is_match(value),
+        }
+    }
+}
+
+/// A parsed filter expression
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Compare(Field, Condition),
+    Not(Box<Expression>),
+    And(Vec<Expression>),
+    Or(Vec<Expression>),
+}
+
+impl Expression {
+    pub fn matches(&self, entry: &NcbiTaxEntry) -> bool {
+        match self {
+            Expression::Compare(field, condition) => condition.test(&field.value(entry)),
+            Expression::Not(inner) => !inner.matches(entry),
+            Expression::And(parts) => parts.iter().all(|part| part.matches(entry)),
+            Expression::Or(parts) => parts.iter().any(|part| part.matches(entry)),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let expression = parser.or()?;
+        parser.skip_whitespace();
+        if parser.pos < s.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(expression)
+    }
+}
+
+/// Recursive descent parser over the expression text
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn or(&mut self) -> Result<Expression, ASDBTaxonError> {
+        let mut parts = vec![self.and()?];
+        while self.keyword("OR") {
+            parts.push(self.and()?);
+        }
+        Ok(single_or(parts, Expression::Or))
+    }
+
+    fn and(&mut self) -> Result<Expression, ASDBTaxonError> {
+        let mut parts = vec![self.unary()?];
+        while self.keyword("AND") {
+            parts.push(self.unary()?);
+        }
+        Ok(single_or(parts, Expression::And))
+    }
+
+    fn unary(&mut self) -> Result<Expression, ASDBTaxonError> {
+        if self.keyword("NOT") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        self.skip_whitespace();
+        if self.rest().starts_with('(') {
+            self.pos += 1;
+            let inner = self.or()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with(')') {
+                return Err(self.error("missing ')'"));
+            }
+            self.pos += 1;
+            return Ok(inner);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ASDBTaxonError> {
+        let field_len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        if field_len == 0 {
+            return Err(self.error("expected a field"));
+        }
+        let field_name = &self.rest()[..field_len];
+        let field: Field = field_name
+            .parse()
+            .map_err(|_| self.error(&format!("unknown field '{}'", field_name)))?;
+        self.pos += field_len;
+        self.skip_whitespace();
+
+        let operator = ["!=", "!~", "=", "~"]
+            .into_iter()
+            .find(|operator| self.rest().starts_with(operator))
+            .ok_or_else(|| self.error("expected one of =, !=, ~, !~"))?;
+        self.pos += operator.len();
+        self.skip_whitespace();
+
+        let value = self.value()?;
+        let condition = match operator {
+            "=" => Condition::Equals(value),
+            "!=" => Condition::NotEquals(value),
+            "~" => Condition::Matches(Regex::new(&value)?),
+            _ => Condition::NotMatches(Regex::new(&value)?),
+        };
+        Ok(Expression::Compare(field, condition))
+    }
+
+    fn value(&mut self) -> Result<String, ASDBTaxonError> {
+        let rest = self.rest();
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+            let end = rest[1..]
+                .find(quote)
+                .ok_or_else(|| self.error("unterminated quote"))?;
+            self.pos += end + 2;
+            return Ok(rest[1..end + 1].to_string());
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == ')')
+            .unwrap_or(rest.len());
+        self.pos += end;
+        Ok(rest[..end].to_string())
+    }
+
+    /// Consume `keyword` if it comes next as a whole word, ignoring case
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let Some(candidate) = rest.get(..keyword.len()) else {
+            return false;
+        };
+        let ends = rest[keyword.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || c == '(');
+        if candidate.eq_ignore_ascii_case(keyword) && ends {
+            self.pos += keyword.len();
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn error(&self, problem: &str) -> ASDBTaxonError {
+        ASDBTaxonError::InvalidFilter(format!(
+            "{} at position {} of '{}'",
+            problem,
+            self.pos + 1,
+            self.input
+        ))
+    }
+}
+
+fn single_or(mut parts: Vec<Expression>, combine: fn(Vec<Expression>) -> Expression) -> Expression {
+    if parts.len() == 1 {
+        return parts.remove(0);
+    }
+    combine(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::{tid, TaxId};
+
+    fn entry(tax_id: TaxId, name: &str, genus: &str, phylum: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: name.to_string(),
+            genus: genus.into(),
+            phylum: phylum.into(),
+            superkingdom: "Bacteria".into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_expression() {
+        let coelicolor = entry(
+            tid(1902),
+            "Streptomyces coelicolor",
+            "Streptomyces",
+            "Actinomycetota",
+        );
+        let subtilis = entry(tid(1423), "Bacillus subtilis", "Bacillus", "Bacillota");
+        let matching = |expression: &str| -> Vec<TaxId> {
+            let expression: Expression = expression.parse().unwrap();
+            [&coelicolor, &subtilis]
+                .into_iter()
+                .filter(|entry| expression.matches(entry))
+                .map(|entry| entry.tax_id)
+                .collect()
+        };
+
+        assert_eq!(matching("genus=Streptomyces"), vec![1902]);
+        assert_eq!(
+            matching("superkingdom=Bacteria AND phylum!=Actinomycetota"),
+            vec![1423]
+        );
+        assert_eq!(matching("genus~^Strepto"), vec![1902]);
+        assert_eq!(matching("name!~coelicolor and not taxid=1"), vec![1423]);
+        assert_eq!(
+            matching("(genus=Bacillus OR genus=Streptomyces) AND tax_id!=1423"),
+            vec![1902]
+        );
+        assert_eq!(matching("name='Bacillus subtilis'"), vec![1423]);
+        assert_eq!(
+            matching("genus=Bacillus OR genus=Streptomyces AND phylum=Bacillota"),
+            vec![1423]
+        );
+    }
+
+    #[test]
+    fn test_invalid_expression() {
+        for invalid in [
+            "",
+            "serotype=x",
+            "genus",
+            "genus=Streptomyces AND",
+            "(genus=Streptomyces",
+            "name='unterminated",
+            "genus~(",
+            "genus=a genus=b",
+        ] {
+            assert!(
+                invalid.parse::<Expression>().is_err(),
+                "'{}' should not parse",
+                invalid
+            );
+        }
+    }
+}
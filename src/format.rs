@@ -0,0 +1,287 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Layouts of the cache file
+//!
+//! Besides the compact JSON default, caches can be written as pretty-printed JSON for human
+//! inspection, or as newline-delimited JSON for streaming consumers and line-oriented diffing.
+//! An NDJSON cache starts with a header line holding everything but the entries, marked with
+//! `"format":"ndjson"`, followed by one entry per line, sorted by taxid.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Read, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+use md5::{Digest, Md5};
+use serde::Serialize;
+
+#[cfg(feature = "fs")]
+use crate::compression;
+use crate::errors::ASDBTaxonError;
+#[cfg(feature = "fs")]
+use crate::index::IndexedCache;
+use crate::md5sum;
+use crate::metadata::CacheMetadata;
+use crate::overrides::Overrides;
+use crate::releases::Release;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::update::UpdateRecord;
+use crate::TaxonCache;
+
+/// Start of the header line of an NDJSON cache
+const NDJSON_MARKER: &str = r#"{"format":"ndjson""#;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheFormat {
+    /// Compact JSON on a single line
+    #[default]
+    Json,
+    /// Indented JSON
+    Pretty,
+    /// A header line, then one entry per line
+    Ndjson,
+    /// Uncompressed NDJSON with an index for looking up single entries, see [crate::index]
+    Indexed,
+}
+
+impl FromStr for CacheFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(CacheFormat::Json),
+            "pretty" => Ok(CacheFormat::Pretty),
+            "ndjson" => Ok(CacheFormat::Ndjson),
+            "indexed" => Ok(CacheFormat::Indexed),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+impl CacheFormat {
+    /// Format of cache data starting with `head`
+    pub fn detect(head: &[u8]) -> CacheFormat {
+        if head.starts_with(NDJSON_MARKER.as_bytes()) {
+            CacheFormat::Ndjson
+        } else if head.starts_with(b"{\n") {
+            CacheFormat::Pretty
+        } else {
+            CacheFormat::Json
+        }
+    }
+
+    /// Format of an existing cache file, which may be compressed
+    ///
+    /// NDJSON caches with an up to date index are [CacheFormat::Indexed].
+    #[cfg(feature = "fs")]
+    pub fn detect_file(path: &Path) -> Result<CacheFormat, ASDBTaxonError> {
+        let (head, _) = peek(compression::open(path)?)?;
+        match CacheFormat::detect(&head) {
+            CacheFormat::Ndjson if IndexedCache::open(path)?.is_some() => Ok(CacheFormat::Indexed),
+            format => Ok(format),
+        }
+    }
+}
+
+/// Read enough of `input` to [CacheFormat::detect] its format, returning the bytes read and a
+/// reader over all of the data
+pub(crate) fn peek<R: Read>(mut input: R) -> io::Result<(Vec<u8>, impl Read)> {
+    let mut head = Vec::with_capacity(NDJSON_MARKER.len());
+    (&mut input)
+        .take(NDJSON_MARKER.len() as u64)
+        .read_to_end(&mut head)?;
+    Ok((head.clone(), io::Cursor::new(head).chain(input)))
+}
+
+/// Everything of a cache but its entries, keeping field order and skipping rules of [TaxonCache]
+#[derive(Serialize)]
+struct Header<'a> {
+    format: &'static str,
+    version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: &'a Option<CacheMetadata>,
+    deprecated_ids: &'a HashMap<TaxId, TaxId>,
+    deleted_ids: &'a HashSet<TaxId>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    full_species: bool,
+    #[serde(skip_serializing_if = "Overrides::is_empty")]
+    overrides: &'a Overrides,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    history: &'a Vec<UpdateRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    releases: &'a Vec<Release>,
+    checksum: &'a str,
+}
+
+/// A cache with its [checksum], as written to JSON caches
+#[derive(Serialize)]
+pub(crate) struct Checksummed<'a> {
+    #[serde(flatten)]
+    pub cache: &'a TaxonCache,
+    pub checksum: &'a str,
+}
+
+/// MD5 digest of the cache data in `value`, without its `format` and `checksum` fields
+///
+/// The fields of `value` are serialized sorted, so every layout of the same cache has the same
+/// checksum.
+pub fn checksum(value: &serde_json::Value) -> Result<String, ASDBTaxonError> {
+    let mut md5 = Md5::new();
+    match value.as_object() {
+        Some(fields) => {
+            let content: BTreeMap<&String, &serde_json::Value> = fields
+                .iter()
+                .filter(|(key, _)| *key != "format" && *key != "checksum")
+                .collect();
+            serde_json::to_writer(&mut md5, &content)?;
+        }
+        None => serde_json::to_writer(&mut md5, value)?,
+    }
+    Ok(md5sum::hex(&md5.finalize()))
+}
+
+/// Turn errors of cache data ending early or not being JSON at all into
+/// [ASDBTaxonError::CorruptCache], e.g. for files truncated by a dying job
+pub(crate) fn parse_error(err: serde_json::Error) -> ASDBTaxonError {
+    if err.is_eof() || err.is_syntax() {
+        ASDBTaxonError::CorruptCache(err.to_string())
+    } else {
+        err.into()
+    }
+}
+
+/// Write `cache` as NDJSON
+pub fn write_ndjson(cache: &TaxonCache, output: impl Write) -> Result<(), ASDBTaxonError> {
+    write_ndjson_with(cache, output, &mut |_, _, _| ())
+}
+
+/// Write `cache` as NDJSON, calling `on_entry` with every entry and the byte offset and length
+/// of its line, without the newline
+pub fn write_ndjson_with(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    on_entry: &mut dyn FnMut(&NcbiTaxEntry, u64, u64),
+) -> Result<(), ASDBTaxonError> {
+    let checksum = cache.checksum()?;
+    let header = Header {
+        format: "ndjson",
+        version: cache.version,
+        metadata: &cache.metadata,
+        deprecated_ids: &cache.deprecated_ids,
+        deleted_ids: &cache.deleted_ids,
+        full_species: cache.full_species,
+        overrides: &cache.overrides,
+        history: &cache.history,
+        releases: &cache.releases,
+        checksum: &checksum,
+    };
+    let mut line = serde_json::to_vec(&header)?;
+    line.push(b'\n');
+    output.write_all(&line)?;
+    let mut offset = line.len() as u64;
+    for entry in cache.iter_sorted_by_taxid() {
+        line.clear();
+        serde_json::to_writer(&mut line, entry)?;
+        on_entry(entry, offset, line.len() as u64);
+        line.push(b'\n');
+        output.write_all(&line)?;
+        offset += line.len() as u64;
+    }
+    Ok(())
+}
+
+/// Parse NDJSON into the JSON value of a regular cache, so it can be migrated like one
+pub fn read_ndjson(input: impl Read) -> Result<serde_json::Value, ASDBTaxonError> {
+    let mut lines = io::BufReader::new(input).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let mut value: serde_json::Value = serde_json::from_str(&header).map_err(parse_error)?;
+    let Some(fields) = value.as_object_mut() else {
+        return Err(ASDBTaxonError::InvalidFormat(
+            "NDJSON header is not an object".to_string(),
+        ));
+    };
+    fields.remove("format");
+
+    let mut mappings = serde_json::Map::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: serde_json::Value = serde_json::from_str(&line).map_err(parse_error)?;
+        let Some(tax_id) = entry.get("tax_id").and_then(serde_json::Value::as_i64) else {
+            return Err(ASDBTaxonError::InvalidFormat(format!(
+                "NDJSON entry without a taxid: {}",
+                line
+            )));
+        };
+        mappings.insert(tax_id.to_string(), entry);
+    }
+    fields.insert("mappings".to_string(), serde_json::Value::Object(mappings));
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_ndjson() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        for tax_id in [tid(23456), tid(5062)] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+
+        let mut data = Vec::new();
+        write_ndjson(&cache, &mut data).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(NDJSON_MARKER));
+        assert!(lines[1].contains(r#""tax_id":5062"#));
+        assert!(lines[2].contains(r#""tax_id":23456"#));
+        assert_eq!(CacheFormat::detect(text.as_bytes()), CacheFormat::Ndjson);
+
+        let value = read_ndjson(text.as_bytes()).unwrap();
+        assert!(value.get("format").is_none());
+        assert_eq!(value["deprecated_ids"]["12345"], 23456);
+        assert_eq!(value["mappings"]["23456"]["tax_id"], 23456);
+
+        assert!(read_ndjson("[]\n".as_bytes()).is_err());
+        assert!(read_ndjson(format!("{}}}\n{{}}\n", NDJSON_MARKER).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(CacheFormat::detect(b"{\"version\":5"), CacheFormat::Json);
+        assert_eq!(
+            CacheFormat::detect(b"{\n  \"version\""),
+            CacheFormat::Pretty
+        );
+        assert_eq!(
+            "ndjson".parse::<CacheFormat>().unwrap(),
+            CacheFormat::Ndjson
+        );
+        assert!("yaml".parse::<CacheFormat>().is_err());
+    }
+}
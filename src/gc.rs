@@ -0,0 +1,148 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dropping merged and deleted taxids a cache no longer needs
+//!
+//! Every update adds merged taxids, and entries removed since leave merges pointing nowhere.
+//! [collect] drops those, points chains of merges straight at the current taxid and, given the
+//! taxids the datadir still references, drops all merged and deleted taxids it doesn't.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::taxa::TaxId;
+use crate::TaxonCache;
+
+/// What [collect] changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Merged taxids whose current taxid is neither cached nor deleted
+    pub dangling: usize,
+    /// Merged taxids pointed at the end of their chain of merges
+    pub shortened: usize,
+    /// Merged taxids not referenced by the datadir
+    pub unreferenced_merged: usize,
+    /// Deleted taxids not referenced by the datadir
+    pub unreferenced_deleted: usize,
+}
+
+impl GcReport {
+    /// Number of merged and deleted taxids dropped
+    pub fn removed(&self) -> usize {
+        self.dangling + self.unreferenced_merged + self.unreferenced_deleted
+    }
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} merged taxids without a current one", self.dangling)?;
+        writeln!(f, "{} chains of merges shortened", self.shortened)?;
+        writeln!(
+            f,
+            "{} merged and {} deleted taxids not referenced by the datadir",
+            self.unreferenced_merged, self.unreferenced_deleted
+        )?;
+        write!(f, "{} taxids removed", self.removed())
+    }
+}
+
+/// Compact the merged and deleted taxids of `cache`, also dropping the ones not in `referenced`
+/// if given
+pub fn collect(cache: &mut TaxonCache, referenced: Option<&HashSet<TaxId>>) -> GcReport {
+    let mut report = GcReport::default();
+
+    let merged = cache.deprecated_ids.clone();
+    for into in cache.deprecated_ids.values_mut() {
+        // merges were checked for cycles when read, the limit only guards against edited caches
+        let mut current = *into;
+        for _ in 0..merged.len() {
+            match merged.get(&current) {
+                Some(next) => current = *next,
+                None => break,
+            }
+        }
+        if current != *into {
+            *into = current;
+            report.shortened += 1;
+        }
+    }
+
+    let mappings = &cache.mappings;
+    let deleted_ids = &cache.deleted_ids;
+    cache.deprecated_ids.retain(|tax_id, into| {
+        if !mappings.contains_key(into) && !deleted_ids.contains(into) {
+            report.dangling += 1;
+            return false;
+        }
+        if referenced.is_some_and(|referenced| !referenced.contains(tax_id)) {
+            report.unreferenced_merged += 1;
+            return false;
+        }
+        true
+    });
+
+    if let Some(referenced) = referenced {
+        let merged_into: HashSet<TaxId> = cache.deprecated_ids.values().copied().collect();
+        cache.deleted_ids.retain(|tax_id| {
+            // still needed to explain lookups of merges into them
+            let keep = referenced.contains(tax_id) || merged_into.contains(tax_id);
+            if !keep {
+                report.unreferenced_deleted += 1;
+            }
+            keep
+        });
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::{tid, NcbiTaxEntry};
+
+    #[test]
+    fn test_collect() {
+        let mut cache = TaxonCache::new();
+        cache.insert(NcbiTaxEntry {
+            name: "Streptomyces examplis".to_string(),
+            ..NcbiTaxEntry::new(tid(23456))
+        });
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.deprecated_ids.insert(tid(11111), tid(12345));
+        cache.deprecated_ids.insert(tid(22222), tid(33333));
+        cache.deprecated_ids.insert(tid(44444), tid(99999));
+        cache.deleted_ids.insert(tid(99999));
+        cache.deleted_ids.insert(tid(88888));
+
+        let mut without_datadir = cache.clone();
+        let report = collect(&mut without_datadir, None);
+        assert_eq!(report.dangling, 1);
+        assert_eq!(report.shortened, 1);
+        assert_eq!(report.removed(), 1);
+        assert_eq!(
+            without_datadir.deprecated_ids.get(&tid(11111)),
+            Some(&tid(23456))
+        );
+        assert_eq!(without_datadir.deleted_ids.len(), 2);
+
+        let referenced: HashSet<TaxId> = [tid(11111), tid(23456)].into_iter().collect();
+        let report = collect(&mut cache, Some(&referenced));
+        assert_eq!(report.unreferenced_merged, 2);
+        assert_eq!(report.unreferenced_deleted, 2);
+        assert_eq!(report.removed(), 5);
+        assert_eq!(cache.deprecated_ids.len(), 1);
+        assert!(cache.deleted_ids.is_empty());
+        assert_eq!(cache.lookup(tid(11111)).unwrap().tax_id, 23456);
+    }
+}
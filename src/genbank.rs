@@ -0,0 +1,96 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Taxids from GenBank and EMBL flat files
+
+use crate::taxa::TaxId;
+
+/// Taxids in the `/db_xref="taxon:..."` qualifiers of `source` features, in order of appearance
+///
+/// Handles both GenBank feature tables and the `FT` lines of EMBL files, so multi-record files
+/// yield the taxid of every record.
+pub fn source_taxids(content: &str) -> Vec<TaxId> {
+    let mut taxids = Vec::new();
+    let mut in_source = false;
+    for line in content.lines() {
+        let feature_line = match line.strip_prefix("FT") {
+            Some(rest) => rest,
+            None if line.starts_with("     ") => line,
+            None => {
+                in_source = false;
+                continue;
+            }
+        };
+        let trimmed = feature_line.trim_start();
+        let indent = feature_line.len() - trimmed.len();
+        if let Some(qualifier) = trimmed.strip_prefix('/') {
+            if !in_source {
+                continue;
+            }
+            let taxid = qualifier
+                .strip_prefix("db_xref=\"")
+                .and_then(|rest| rest.strip_suffix('"'))
+                .filter(|xref| xref.starts_with("taxon:"))
+                .and_then(|xref| xref.parse::<TaxId>().ok());
+            if let Some(taxid) = taxid {
+                if !taxids.contains(&taxid) {
+                    taxids.push(taxid);
+                }
+            }
+        } else if indent <= 5 {
+            // a new feature key, anything more indented continues a qualifier
+            in_source = trimmed.split_whitespace().next() == Some("source");
+        }
+    }
+    taxids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_taxids() {
+        let genbank = r#"LOCUS       NC_003888            8667507 bp    DNA     linear   BCT 01-JAN-2020
+FEATURES             Location/Qualifiers
+     source          1..8667507
+                     /organism="Streptomyces coelicolor A3(2)"
+                     /db_xref="taxon:100226"
+     CDS             1..100
+                     /db_xref="taxon:1"
+ORIGIN
+        1 gatcgatc
+//
+LOCUS       NC_000001
+FEATURES             Location/Qualifiers
+     source          1..100
+                     /db_xref="taxon:1883"
+//
+"#;
+        assert_eq!(source_taxids(genbank), vec![100226, 1883]);
+
+        let embl = r#"ID   X56734; SV 1; linear; mRNA; STD; PLN; 1859 BP.
+FH   Key             Location/Qualifiers
+FT   source          1..1859
+FT                   /organism="Trifolium repens"
+FT                   /db_xref="taxon:3899"
+XX
+SQ   Sequence 1859 BP;
+     aaacaaacca aatatggatt ttattgtagc catatttgct ctgtttgtta ttagctcatt        60
+//
+"#;
+        assert_eq!(source_taxids(embl), vec![3899]);
+        assert!(source_taxids("{\"taxon\": 1}").is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shell-style glob patterns for selecting data files
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::ASDBTaxonError;
+
+/// A glob pattern supporting `*`, `?` and `**`
+///
+/// Patterns without a `/` match the file name only, others match the whole path relative to
+/// the datadir. `*` and `?` don't match `/`, `**` matches across directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pattern: String,
+    chars: Vec<char>,
+}
+
+impl Pattern {
+    /// Whether the pattern matches `path`, given relative to the datadir with `/` separators
+    pub fn matches(&self, path: &str) -> bool {
+        let text = if self.pattern.contains('/') {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        let text: Vec<char> = text.chars().collect();
+        matches(&self.chars, &text)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ASDBTaxonError::InvalidFilter(
+                "empty glob pattern".to_string(),
+            ));
+        }
+        Ok(Pattern {
+            pattern: s.to_string(),
+            chars: s.chars().collect(),
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+fn matches(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // zero or more whole directories
+            matches(rest, text)
+                || (0..text.len()).any(|i| text[i] == '/' && matches(rest, &text[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+        ['*', rest @ ..] => {
+            for i in 0..=text.len() {
+                if matches(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => !text.is_empty() && text[0] != '/' && matches(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(pattern("*.json").matches("GCF_000001/results/NC_003888.json"));
+        assert!(!pattern("*.json").matches("config.yaml"));
+        assert!(pattern("config?.json").matches("a/config1.json"));
+        assert!(!pattern("config?.json").matches("config.json"));
+
+        assert!(pattern("GCF_*/*.json").matches("GCF_000001/x.json"));
+        assert!(!pattern("GCF_*/*.json").matches("GCF_000001/results/x.json"));
+        assert!(pattern("GCF_*/**/*.json").matches("GCF_000001/x.json"));
+        assert!(pattern("GCF_*/**/*.json").matches("GCF_000001/results/x.json"));
+        assert!(pattern("**/regions.js*").matches("a/b/regions.json"));
+
+        assert!("".parse::<Pattern>().is_err());
+    }
+}
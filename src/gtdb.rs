@@ -0,0 +1,143 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GTDB classifications of NCBI taxids
+//!
+//! GTDB classifies genomes, so the taxonomy files (`bac120_taxonomy.tsv`, `ar53_taxonomy.tsv`)
+//! are keyed by genome accession. The metadata files (`bac120_metadata.tsv`, ...) link these
+//! accessions to NCBI taxids; a taxid gets the classification most of its genomes have.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use crate::compression;
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+use crate::taxonomy::{most_common, Classification};
+
+/// GTDB classifications by genome accession and NCBI taxid
+#[derive(Debug, Clone, Default)]
+pub struct GtdbMap {
+    /// Lineages by genome accession, like `RS_GCF_000203835.1`
+    pub lineages: HashMap<String, Classification>,
+    /// NCBI taxids by genome accession
+    pub taxids: HashMap<String, TaxId>,
+}
+
+impl GtdbMap {
+    pub fn new() -> GtdbMap {
+        Default::default()
+    }
+
+    /// Load a taxonomy file with `accession<TAB>lineage` lines
+    pub fn load_taxonomy(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let before = self.lineages.len();
+        for line in io::BufReader::new(input).lines() {
+            let line = line?;
+            let Some((accession, lineage)) = line.split_once('\t') else {
+                continue;
+            };
+            self.lineages
+                .insert(accession.trim().to_string(), lineage.parse()?);
+        }
+        Ok(self.lineages.len() - before)
+    }
+
+    /// Load the accessions and NCBI taxids of a metadata file, using its header
+    ///
+    /// The lineages in the `gtdb_taxonomy` column are loaded as well, so the taxonomy files are
+    /// optional.
+    pub fn load_metadata(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let mut lines = io::BufReader::new(input).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let column = |name: &str| header.split('\t').position(|column| column == name);
+        let (Some(accession_column), Some(taxid_column)) =
+            (column("accession"), column("ncbi_taxid"))
+        else {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "GTDB metadata without accession and ncbi_taxid columns".to_string(),
+            ));
+        };
+        let lineage_column = column("gtdb_taxonomy");
+
+        let before = self.taxids.len();
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (Some(accession), Some(Ok(taxid))) = (
+                fields.get(accession_column),
+                fields.get(taxid_column).map(|taxid| taxid.parse()),
+            ) else {
+                continue;
+            };
+            self.taxids.insert(accession.to_string(), taxid);
+            if let Some(lineage) = lineage_column.and_then(|column| fields.get(column)) {
+                self.lineages
+                    .insert(accession.to_string(), lineage.parse()?);
+            }
+        }
+        Ok(self.taxids.len() - before)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_taxonomy_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        self.load_taxonomy(compression::open(path)?)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_metadata_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        self.load_metadata(compression::open(path)?)
+    }
+
+    /// The most common lineage of the genomes of every taxid, see [most_common]
+    pub fn by_taxid(&self) -> HashMap<TaxId, Classification> {
+        most_common(self.taxids.iter().filter_map(|(accession, taxid)| {
+            self.lineages
+                .get(accession)
+                .map(|lineage| (*taxid, lineage))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_by_taxid() {
+        let coelicolor = "d__Bacteria;p__Actinomycetota;c__Actinomycetes;o__Streptomycetales;f__Streptomycetaceae;g__Streptomyces;s__Streptomyces coelicolor";
+        let other = "d__Bacteria;p__Actinomycetota;c__Actinomycetes;o__Streptomycetales;f__Streptomycetaceae;g__Streptomyces;s__Streptomyces violaceoruber";
+        let taxonomy = format!(
+            "RS_GCF_1\t{}\nGB_GCA_2\t{}\nRS_GCF_3\t{}\n",
+            coelicolor, coelicolor, other
+        );
+        let metadata = "accession\tncbi_genome_category\tncbi_taxid\nRS_GCF_1\tnone\t1902\nGB_GCA_2\tnone\t1902\nRS_GCF_3\tnone\t1902\nRS_GCF_4\tnone\t1883\n";
+
+        let mut map = GtdbMap::new();
+        assert_eq!(map.load_taxonomy(taxonomy.as_bytes()).unwrap(), 3);
+        assert_eq!(map.load_metadata(metadata.as_bytes()).unwrap(), 4);
+
+        let by_taxid = map.by_taxid();
+        assert_eq!(by_taxid.len(), 1);
+        assert_eq!(by_taxid[&tid(1902)].species, "Streptomyces coelicolor");
+
+        assert!(GtdbMap::new()
+            .load_metadata("accession\ttaxid\n".as_bytes())
+            .is_err());
+    }
+}
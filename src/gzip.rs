@@ -0,0 +1,39 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gzip helpers on top of flate2
+
+use std::io::Read;
+#[cfg(any(feature = "fs", test))]
+use std::io::Write;
+
+use flate2::read::MultiGzDecoder;
+#[cfg(any(feature = "fs", test))]
+use flate2::write::GzEncoder;
+
+/// Check if a buffer starts with the gzip magic bytes
+pub(crate) fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Decompress `input`, reading all members of concatenated gzip files like `zcat` does
+pub(crate) fn decoder<R: Read>(input: R) -> MultiGzDecoder<R> {
+    MultiGzDecoder::new(input)
+}
+
+/// Compress everything written to `output` at the default level
+#[cfg(any(feature = "fs", test))]
+pub(crate) fn encoder<W: Write>(output: W) -> GzEncoder<W> {
+    GzEncoder::new(output, flate2::Compression::default())
+}
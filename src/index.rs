@@ -0,0 +1,316 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Looking up single entries of an NDJSON cache without loading it
+//!
+//! An uncompressed NDJSON cache saved as [CacheFormat::Indexed](crate::format::CacheFormat) gets
+//! an index next to it, `<cache>.idx`. The index starts with a header of five little-endian
+//! 64 bit fields: the magic `ASDBIDX1`, the cache version, the size and modification time of the
+//! cache file it belongs to, and the number of records. The records follow, sorted by taxid, each
+//! holding the taxid, the taxid it resolves to, and the offset and length of the entry line in
+//! the cache. Merged taxids get a record pointing to the line of their current entry, deleted
+//! ones a record with the [DELETED] offset.
+//!
+//! [IndexedCache] binary searches the records on disk, so opening it only reads the header.
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomically;
+use crate::errors::ASDBTaxonError;
+use crate::manifest::FileState;
+use crate::schema::CACHE_VERSION;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+const MAGIC: &[u8; 8] = b"ASDBIDX1";
+const HEADER_SIZE: u64 = 40;
+const RECORD_SIZE: u64 = 28;
+
+/// Offset of the records of deleted taxids
+pub const DELETED: u64 = u64::MAX;
+/// Offset of the records of merged taxids whose current entry is missing
+pub const MISSING: u64 = u64::MAX - 1;
+
+/// Where the index for `cache_file` is kept, next to it
+pub fn index_path(cache_file: &Path) -> PathBuf {
+    let mut path = cache_file.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// Position of an entry line in the cache file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub tax_id: TaxId,
+    /// The taxid after following merges
+    pub target: TaxId,
+    pub offset: u64,
+    pub length: u32,
+}
+
+impl Record {
+    fn to_bytes(self) -> [u8; RECORD_SIZE as usize] {
+        let mut bytes = [0; RECORD_SIZE as usize];
+        bytes[..8].copy_from_slice(&self.tax_id.get().to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.target.get().to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[24..].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE as usize]) -> Result<Record, ASDBTaxonError> {
+        let field = |start: usize| -> [u8; 8] { bytes[start..start + 8].try_into().unwrap() };
+        Ok(Record {
+            tax_id: TaxId::new(i64::from_le_bytes(field(0)))?,
+            target: TaxId::new(i64::from_le_bytes(field(8)))?,
+            offset: u64::from_le_bytes(field(16)),
+            length: u32::from_le_bytes(bytes[24..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Write the index of `cache`, saved to `cache_file` with its entry lines at `lines`
+///
+/// `lines` holds the taxid, offset and length of every entry line, as passed to
+/// [write_ndjson_with](crate::format::write_ndjson_with).
+pub fn write_index(
+    cache: &TaxonCache,
+    cache_file: &Path,
+    lines: &[(TaxId, u64, u64)],
+) -> Result<(), ASDBTaxonError> {
+    let mut records: Vec<Record> = Vec::with_capacity(lines.len() + cache.deprecated_ids.len());
+    for &(tax_id, offset, length) in lines {
+        let length = u32::try_from(length)
+            .map_err(|_| ASDBTaxonError::InvalidFormat(format!("entry {} too long", tax_id)))?;
+        records.push(Record {
+            tax_id,
+            target: tax_id,
+            offset,
+            length,
+        });
+    }
+    records.sort_unstable_by_key(|record| record.tax_id);
+
+    let mut extra = Vec::new();
+    for (&old, &new) in &cache.deprecated_ids {
+        let offset = match records.binary_search_by_key(&new, |record| record.tax_id) {
+            Ok(found) => {
+                let mut record = records[found];
+                record.tax_id = old;
+                extra.push(record);
+                continue;
+            }
+            Err(_) if cache.deleted_ids.contains(&new) => DELETED,
+            Err(_) => MISSING,
+        };
+        extra.push(Record {
+            tax_id: old,
+            target: new,
+            offset,
+            length: 0,
+        });
+    }
+    for &tax_id in &cache.deleted_ids {
+        if !cache.deprecated_ids.contains_key(&tax_id) && !cache.mappings.contains_key(&tax_id) {
+            extra.push(Record {
+                tax_id,
+                target: tax_id,
+                offset: DELETED,
+                length: 0,
+            });
+        }
+    }
+    records.extend(extra);
+    records.sort_unstable_by_key(|record| record.tax_id);
+
+    let state = FileState::from_path(cache_file)?;
+    let mut data = Vec::with_capacity((HEADER_SIZE + RECORD_SIZE * records.len() as u64) as usize);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+    data.extend_from_slice(&state.size.to_le_bytes());
+    data.extend_from_slice(&state.modified.to_le_bytes());
+    data.extend_from_slice(&(records.len() as u64).to_le_bytes());
+    for record in records {
+        data.extend_from_slice(&record.to_bytes());
+    }
+    write_atomically(&index_path(cache_file), &data)
+}
+
+/// A cache file looked up through its index
+#[derive(Debug)]
+pub struct IndexedCache {
+    cache: File,
+    index: File,
+    count: u64,
+}
+
+impl IndexedCache {
+    /// Open the cache at `cache_file`, or `None` if it has no index or changed since it was indexed
+    pub fn open(cache_file: &Path) -> Result<Option<IndexedCache>, ASDBTaxonError> {
+        let mut index = match File::open(index_path(cache_file)) {
+            Ok(index) => index,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut header = [0; HEADER_SIZE as usize];
+        if index.read_exact(&mut header).is_err() || &header[..8] != MAGIC {
+            return Ok(None);
+        }
+        let field = |start: usize| u64::from_le_bytes(header[start..start + 8].try_into().unwrap());
+        let state = FileState::from_path(cache_file)?;
+        let indexed = FileState {
+            size: field(16),
+            modified: field(24),
+            taxid: None,
+        };
+        if field(8) != CACHE_VERSION || !state.unchanged(&indexed) {
+            return Ok(None);
+        }
+        let count = field(32);
+        let size = count
+            .checked_mul(RECORD_SIZE)
+            .and_then(|records| records.checked_add(HEADER_SIZE))
+            .ok_or_else(|| {
+                ASDBTaxonError::InvalidFormat(format!("index claims {} records", count))
+            })?;
+        if fs::metadata(index_path(cache_file))?.len() != size {
+            return Ok(None);
+        }
+        Ok(Some(IndexedCache {
+            cache: File::open(cache_file)?,
+            index,
+            count,
+        }))
+    }
+
+    /// Number of indexed taxids, merged and deleted ones included
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Look up `tax_id` like [TaxonCache::lookup], reading only its entry
+    pub fn lookup(&self, tax_id: TaxId) -> Result<NcbiTaxEntry, ASDBTaxonError> {
+        let Some(record) = self.find(tax_id)? else {
+            return Err(ASDBTaxonError::NotFound(tax_id));
+        };
+        match record.offset {
+            DELETED => return Err(ASDBTaxonError::Deleted(record.target)),
+            MISSING => return Err(ASDBTaxonError::NotFound(record.target)),
+            _ => (),
+        }
+        let mut line = vec![0; record.length as usize];
+        let mut cache = &self.cache;
+        cache.seek(SeekFrom::Start(record.offset))?;
+        cache.read_exact(&mut line)?;
+        Ok(serde_json::from_slice(&line)?)
+    }
+
+    fn find(&self, tax_id: TaxId) -> Result<Option<Record>, ASDBTaxonError> {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let record = self.record(middle)?;
+            match record.tax_id.cmp(&tax_id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Ok(Some(record)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn record(&self, position: u64) -> Result<Record, ASDBTaxonError> {
+        let mut bytes = [0; RECORD_SIZE as usize];
+        let mut index = &self.index;
+        index.seek(SeekFrom::Start(HEADER_SIZE + position * RECORD_SIZE))?;
+        index.read_exact(&mut bytes)?;
+        Record::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::format::CacheFormat;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_indexed_cache() {
+        let mut cache = TaxonCache::new();
+        for (tax_id, name) in [
+            (tid(23456), "Streptomyces examplis"),
+            (tid(5062), "Aspergillus oryzae"),
+        ] {
+            cache.insert(NcbiTaxEntry {
+                name: name.to_string(),
+                genus: name.split(' ').next().unwrap().into(),
+                ..NcbiTaxEntry::new(tax_id)
+            });
+        }
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.deprecated_ids.insert(tid(11111), tid(99999));
+        cache.deprecated_ids.insert(tid(22222), tid(33333));
+        cache.deleted_ids.insert(tid(99999));
+
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let path = dir.join("cache.ndjson");
+        cache
+            .save_path_as(&path, Compression::None, CacheFormat::Indexed)
+            .unwrap();
+        assert_eq!(
+            CacheFormat::detect_file(&path).unwrap(),
+            CacheFormat::Indexed
+        );
+
+        let indexed = IndexedCache::open(&path).unwrap().unwrap();
+        assert_eq!(indexed.len(), 6);
+        assert_eq!(
+            indexed.lookup(tid(5062)).unwrap().name,
+            "Aspergillus oryzae"
+        );
+        let merged = indexed.lookup(tid(12345)).unwrap();
+        assert_eq!(merged.tax_id, 23456);
+        assert_eq!(&*merged.genus, "Streptomyces");
+        for tax_id in [tid(11111), tid(99999), tid(22222), tid(1)] {
+            assert_eq!(
+                indexed.lookup(tax_id).unwrap_err().to_string(),
+                cache.lookup(tax_id).unwrap_err().to_string()
+            );
+        }
+
+        // a record count overflowing the index size is rejected
+        let mut index = fs::read(index_path(&path)).unwrap();
+        index[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(index_path(&path), &index).unwrap();
+        assert!(matches!(
+            IndexedCache::open(&path),
+            Err(ASDBTaxonError::InvalidFormat(_))
+        ));
+
+        // a changed cache makes the index stale
+        fs::write(&path, "{}").unwrap();
+        assert!(IndexedCache::open(&path).unwrap().is_none());
+        fs::remove_file(index_path(&path)).unwrap();
+        assert!(IndexedCache::open(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sharing the lineage names repeated across entries
+//!
+//! Names like `Bacteria` or `Streptomycetaceae` are part of the lineage of many entries. The
+//! lineage fields of [NcbiTaxEntry] are reference-counted, so an [Interner] can make all entries
+//! point to a single copy of every name.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::taxa::{NcbiTaxEntry, Rank};
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Replace `value` with the shared copy of it, keeping it as the shared copy if it's new
+    pub fn intern(&mut self, value: &mut Arc<str>) {
+        match self.strings.get(value) {
+            Some(shared) => *value = Arc::clone(shared),
+            None => {
+                self.strings.insert(Arc::clone(value));
+            }
+        }
+    }
+
+    /// Intern all lineage fields of `entry`
+    pub fn intern_entry(&mut self, entry: &mut NcbiTaxEntry) {
+        for rank in Rank::ALL {
+            self.intern(entry.rank_value_mut(rank));
+        }
+    }
+
+    /// Number of distinct strings
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_intern_entry() {
+        let entry = |species: &str| NcbiTaxEntry {
+            species: species.into(),
+            genus: "Streptomyces".into(),
+            superkingdom: "Bacteria".into(),
+            ..NcbiTaxEntry::new(tid(1))
+        };
+        let mut first = entry("coelicolor");
+        let mut second = entry("lividans");
+        assert!(!Arc::ptr_eq(&first.genus, &second.genus));
+
+        let mut interner = Interner::new();
+        interner.intern_entry(&mut first);
+        interner.intern_entry(&mut second);
+        assert!(Arc::ptr_eq(&first.genus, &second.genus));
+        assert!(Arc::ptr_eq(&first.superkingdom, &second.superkingdom));
+        assert_eq!(&*second.species, "lividans");
+        // coelicolor, lividans, Streptomyces, Bacteria and the empty default of the other ranks
+        assert_eq!(interner.len(), 5);
+    }
+}
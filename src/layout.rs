@@ -0,0 +1,310 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Column layouts of rankedlineage.dmp
+//!
+//! The NCBI replaced the superkingdom rank by domain and added realms for viruses, so newer
+//! dumps have an extra column. The layout is told apart by the number of fields of each line.
+//! Other lineage dumps in the same pipe-delimited format can be read with a [ColumnMapping]
+//! naming the entry member of every field, like `tax_id,name,-,genus,superkingdom`.
+
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::Read;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{species_value, strain_from_name, EntrySource, NcbiTaxEntry, Rank, TaxId};
+
+/// Known rankedlineage.dmp column layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpLayout {
+    /// tax_id, name, species, genus, family, order, class, phylum, kingdom, superkingdom
+    Superkingdom,
+    /// tax_id, name, species, genus, family, order, class, phylum, kingdom, realm, domain
+    Domain,
+}
+
+impl DumpLayout {
+    /// The layout of a line with `fields` fields
+    pub fn detect(fields: usize) -> Result<DumpLayout, ASDBTaxonError> {
+        match fields {
+            10 => Ok(DumpLayout::Superkingdom),
+            11 => Ok(DumpLayout::Domain),
+            _ => Err(ASDBTaxonError::InvalidFormat(format!(
+                "rankedlineage.dmp line with {} fields, expected 10 or 11",
+                fields
+            ))),
+        }
+    }
+
+    /// The column mapping of this layout
+    pub fn columns(&self) -> ColumnMapping {
+        let mut columns = vec![Column::TaxId, Column::Name];
+        columns.extend(
+            Rank::ALL
+                .into_iter()
+                .rev()
+                .filter(|rank| *rank != Rank::Superkingdom)
+                .map(Column::Lineage),
+        );
+        match self {
+            DumpLayout::Superkingdom => columns.push(Column::Lineage(Rank::Superkingdom)),
+            DumpLayout::Domain => columns.extend([Column::Realm, Column::Domain]),
+        }
+        ColumnMapping { columns }
+    }
+}
+
+/// Entry member a dump field is read into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    TaxId,
+    Name,
+    Lineage(Rank),
+    Realm,
+    /// The domain, also used as superkingdom if that has no column of its own
+    Domain,
+    /// Strain designation, taken from the name if there is no strain column
+    Strain,
+    /// A field that isn't used
+    Skip,
+}
+
+impl FromStr for Column {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "tax_id" | "taxid" => Ok(Column::TaxId),
+            "name" => Ok(Column::Name),
+            "realm" => Ok(Column::Realm),
+            "domain" => Ok(Column::Domain),
+            "strain" => Ok(Column::Strain),
+            "-" | "skip" => Ok(Column::Skip),
+            other => other
+                .parse()
+                .map(Column::Lineage)
+                .map_err(|_| ASDBTaxonError::InvalidFormat(format!("unknown column '{}'", s))),
+        }
+    }
+}
+
+/// Which field of a dump line goes into which entry member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    columns: Vec<Column>,
+}
+
+impl ColumnMapping {
+    /// A mapping of the fields in order, which has to include the taxid and name once each
+    pub fn new(columns: Vec<Column>) -> Result<ColumnMapping, ASDBTaxonError> {
+        for required in [Column::TaxId, Column::Name] {
+            let count = columns.iter().filter(|column| **column == required).count();
+            if count != 1 {
+                return Err(ASDBTaxonError::InvalidFormat(format!(
+                    "column mapping needs exactly one {:?} column, found {}",
+                    required, count
+                )));
+            }
+        }
+        Ok(ColumnMapping { columns })
+    }
+
+    /// Read a mapping file, with the columns separated by commas or newlines and `#` comments
+    pub fn load(mut input: impl Read) -> Result<ColumnMapping, ASDBTaxonError> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+        let spec: Vec<&str> = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split(','))
+            .filter(|column| !column.trim().is_empty())
+            .collect();
+        spec.join(",").parse()
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_path(path: &Path) -> Result<ColumnMapping, ASDBTaxonError> {
+        ColumnMapping::load(fs::File::open(path)?)
+    }
+
+    /// The taxid of a dump line, without building the whole entry
+    pub fn tax_id(&self, fields: &[&str]) -> Result<TaxId, ASDBTaxonError> {
+        let index = self
+            .columns
+            .iter()
+            .position(|column| *column == Column::TaxId)
+            .unwrap_or_default();
+        match fields.get(index) {
+            Some(field) => Ok(field.parse()?),
+            None => Err(self.field_count_error(fields)),
+        }
+    }
+
+    /// Build the entry of a dump line
+    pub fn entry(
+        &self,
+        fields: &[&str],
+        full_species: bool,
+    ) -> Result<NcbiTaxEntry, ASDBTaxonError> {
+        if fields.len() != self.columns.len() {
+            return Err(self.field_count_error(fields));
+        }
+        let mut entry = NcbiTaxEntry {
+            source: EntrySource::Taxdump,
+            ..NcbiTaxEntry::new(self.tax_id(fields)?)
+        };
+        let unknown: Arc<str> = Arc::from("Unknown");
+        for rank in Rank::ALL {
+            *entry.rank_value_mut(rank) = Arc::clone(&unknown);
+        }
+        let known = |value: &str| (value != "Unknown").then(|| value.to_owned());
+        let mut species = "Unknown";
+        for (column, &value) in self.columns.iter().zip(fields) {
+            match column {
+                Column::Name => entry.name = value.to_owned(),
+                Column::Lineage(Rank::Species) => species = value,
+                Column::Lineage(rank) if value != "Unknown" => {
+                    *entry.rank_value_mut(*rank) = value.into()
+                }
+                Column::Lineage(_) => (),
+                Column::Realm => entry.realm = known(value),
+                Column::Domain => entry.domain = known(value),
+                Column::Strain => entry.strain = known(value),
+                Column::TaxId | Column::Skip => (),
+            }
+        }
+        entry.species = species_value(species, full_species).into();
+        if !self.columns.contains(&Column::Strain) {
+            entry.strain = strain_from_name(&entry.name, species);
+        }
+        // the domain takes the place of the superkingdom for everything reading the lineage
+        if !self.columns.contains(&Column::Lineage(Rank::Superkingdom)) {
+            if let Some(ref domain) = entry.domain {
+                entry.superkingdom = domain.as_str().into();
+            }
+        }
+        Ok(entry)
+    }
+
+    fn field_count_error(&self, fields: &[&str]) -> ASDBTaxonError {
+        ASDBTaxonError::InvalidFormat(format!(
+            "dump line with {} fields, the column mapping has {}",
+            fields.len(),
+            self.columns.len()
+        ))
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<Column>, _>>()?;
+        ColumnMapping::new(columns)
+    }
+}
+
+/// The fields of a dump line, empty ones as `Unknown`
+pub fn split_line(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|')
+        .map(|field| match field.trim() {
+            "" => "Unknown",
+            field => field,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts() {
+        let old = split_line("1902\t|\tStreptomyces coelicolor\t|\tStreptomyces coelicolor\t|\tStreptomyces\t|\tStreptomycetaceae\t|\tKitasatosporales\t|\tActinomycetes\t|\tActinomycetota\t|\t\t|\tBacteria\t|");
+        let layout = DumpLayout::detect(old.len()).unwrap();
+        assert_eq!(layout, DumpLayout::Superkingdom);
+        let entry = layout.columns().entry(&old, false).unwrap();
+        assert_eq!(entry.tax_id, 1902);
+        assert_eq!(&*entry.species, "coelicolor");
+        assert_eq!(&*entry.family, "Streptomycetaceae");
+        assert_eq!(&*entry.superkingdom, "Bacteria");
+        assert_eq!(&*entry.kingdom, "Unknown");
+        assert_eq!(entry.domain, None);
+
+        let new = split_line("1902\t|\tStreptomyces coelicolor\t|\tStreptomyces coelicolor\t|\tStreptomyces\t|\tStreptomycetaceae\t|\tKitasatosporales\t|\tActinomycetes\t|\tActinomycetota\t|\tBacillati\t|\t\t|\tBacteria\t|");
+        let layout = DumpLayout::detect(new.len()).unwrap();
+        assert_eq!(layout, DumpLayout::Domain);
+        let entry = layout.columns().entry(&new, false).unwrap();
+        assert_eq!(&*entry.superkingdom, "Bacteria");
+        assert_eq!(&*entry.kingdom, "Bacillati");
+        assert_eq!(entry.domain.as_deref(), Some("Bacteria"));
+        assert_eq!(entry.realm, None);
+
+        let virus = split_line("10298\t|\tHuman alphaherpesvirus 1\t|\t\t|\tSimplexvirus\t|\tOrthoherpesviridae\t|\tHerpesvirales\t|\tHerviviricetes\t|\tPeploviricota\t|\tHeunggongvirae\t|\tDuplodnaviria\t|\t\t|");
+        let entry = DumpLayout::detect(virus.len())
+            .unwrap()
+            .columns()
+            .entry(&virus, false)
+            .unwrap();
+        assert_eq!(entry.realm.as_deref(), Some("Duplodnaviria"));
+        assert_eq!(&*entry.superkingdom, "Unknown");
+
+        assert!(
+            DumpLayout::detect(split_line("1902\t|\tStreptomyces coelicolor\t|").len()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_column_mapping() {
+        let mapping: ColumnMapping = "taxid, name, strain, -, genus, domain".parse().unwrap();
+        let fields = split_line(
+            "1902 | Streptomyces coelicolor | A3(2) | ignored | Streptomyces | Bacteria |",
+        );
+        assert_eq!(mapping.tax_id(&fields).unwrap(), 1902);
+        let entry = mapping.entry(&fields, false).unwrap();
+        assert_eq!(entry.name, "Streptomyces coelicolor");
+        assert_eq!(entry.strain.as_deref(), Some("A3(2)"));
+        assert_eq!(&*entry.genus, "Streptomyces");
+        assert_eq!(&*entry.family, "Unknown");
+        assert_eq!(&*entry.species, "Unknown");
+        assert_eq!(&*entry.superkingdom, "Bacteria");
+        assert!(mapping.entry(&fields[..5], false).is_err());
+
+        let loaded = ColumnMapping::load(
+            "# in-house dump\ntax_id\nname,strain\n-\ngenus\ndomain\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(loaded, mapping);
+
+        assert_eq!(
+            "tax_id,name,species,genus,family,order,class,phylum,kingdom,superkingdom"
+                .parse::<ColumnMapping>()
+                .unwrap(),
+            DumpLayout::Superkingdom.columns()
+        );
+        for invalid in ["name,genus", "tax_id,name,tax_id", "tax_id,name,serotype"] {
+            assert!(invalid.parse::<ColumnMapping>().is_err(), "{}", invalid);
+        }
+    }
+}
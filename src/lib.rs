@@ -14,32 +14,188 @@
 
 //! Library implementation
 
+use std::collections::hash_map::{self, Entry};
 use std::collections::{HashMap, HashSet};
+#[cfg(feature = "fs")]
 use std::fs;
 use std::io::{self, BufRead, Read, Write};
-use std::path::PathBuf;
+use std::ops::ControlFlow;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 
-use regex::Regex;
+use log::{debug, info, trace};
 use serde::{Deserialize, Serialize};
-use serde_json;
 
+#[cfg(feature = "fs")]
+pub mod accession;
+#[cfg(feature = "fs")]
+pub(crate) mod atomic;
+#[cfg(feature = "fs")]
+pub mod backup;
+pub mod biosample;
+#[cfg(feature = "fs")]
+pub mod builder;
+pub mod cancel;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "fs")]
+pub mod compression;
+#[cfg(feature = "fs")]
+pub mod config;
+pub mod custom;
+#[cfg(feature = "postgres")]
+pub mod db;
+pub mod deadline;
+pub mod delta;
+#[cfg(feature = "network")]
+pub mod entrez;
 pub mod errors;
+pub mod export;
+#[cfg(feature = "fs")]
+pub mod extract;
+#[cfg(feature = "network")]
+pub mod fetch;
+pub mod filter;
+pub mod format;
+pub mod gc;
+pub mod genbank;
+pub mod glob;
+pub mod gtdb;
+#[cfg(any(feature = "fs", feature = "wasm"))]
+pub(crate) mod gzip;
+#[cfg(feature = "fs")]
+pub mod index;
+pub mod intern;
+pub mod layout;
+pub mod listing;
+#[cfg(feature = "fs")]
+pub mod lock;
+#[cfg(feature = "fs")]
+pub mod manifest;
+pub(crate) mod md5sum;
+pub mod metadata;
+pub mod nodes;
+pub mod nomenclature;
+pub mod overrides;
+pub mod parallel;
+pub mod progress;
+pub mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod readahead;
+pub mod releases;
+pub mod remap;
+#[cfg(feature = "fs")]
+pub mod remote;
+pub mod resolve;
+#[cfg(feature = "fs")]
+pub mod scan;
+pub mod schema;
+pub mod search;
+#[cfg(feature = "fs")]
+pub mod serve;
+pub mod shared;
+pub mod silva;
+pub mod stats;
+pub mod stream;
+#[cfg(feature = "fs")]
+pub(crate) mod tarball;
 pub mod taxa;
+#[cfg(feature = "fs")]
+pub mod taxdump;
+pub mod taxonomy;
+#[cfg(feature = "fs")]
+pub mod telemetry;
+pub mod tree;
+pub mod update;
+pub mod validate;
+pub mod verify;
+pub mod view;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "fs")]
+use crate::biosample::BioSampleMap;
+#[cfg(feature = "fs")]
+use crate::builder::TaxonCacheBuilder;
+#[cfg(feature = "fs")]
+use crate::compression::Compression;
 use crate::errors::ASDBTaxonError;
-use crate::taxa::NcbiTaxEntry;
+use crate::format::CacheFormat;
+use crate::intern::Interner;
+use crate::layout::{split_line, ColumnMapping, DumpLayout};
+use crate::metadata::CacheMetadata;
+use crate::nodes::TaxonTree;
+use crate::overrides::Overrides;
+use crate::releases::Release;
+#[cfg(feature = "fs")]
+use crate::scan::{ScanOptions, ScanReport};
+use crate::schema::CACHE_VERSION;
+use crate::search::{NameIndex, NameQuery};
+use crate::taxa::{EntrySource, NcbiTaxEntry, Rank, TaxId};
+use crate::taxonomy::{Classification, Taxonomy};
+use crate::tree::{Ancestor, Descendants};
+use crate::update::{Change, UpdateRecord};
+use crate::view::{CacheView, ViewFilter};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Scratch directory for tests, removed again when dropped
+#[cfg(all(test, feature = "fs"))]
+pub(crate) fn temp_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("asdb-taxa-")
+        .tempdir()
+        .expect("temporary directory can be created")
+}
+
+/// Default limit on how many merges of a taxid are followed, guarding against malformed dumps
+pub const MAX_MERGE_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct TaxonCache {
-    pub deprecated_ids: HashMap<i64, i64>,
-    pub mappings: HashMap<i64, NcbiTaxEntry>,
+    /// Layout version of the cache file, see [schema]
+    #[serde(default)]
+    pub version: u64,
+    /// Provenance of the last build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<CacheMetadata>,
+    /// Merged taxids with their current ones
+    pub deprecated_ids: HashMap<TaxId, TaxId>,
+    /// TaxIDs the NCBI deleted outright, as listed in delnodes.dmp
+    #[serde(default)]
+    pub deleted_ids: HashSet<TaxId>,
+    /// Entries by current taxid, see [TaxonCache::mappings]
+    mappings: HashMap<TaxId, NcbiTaxEntry>,
+    /// Entries keep the full species name rather than the epithet, see [taxa::species_value]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub full_species: bool,
+    /// Curated field values, applied again after every build
+    #[serde(default, skip_serializing_if = "Overrides::is_empty")]
+    pub overrides: Overrides,
+    /// Changes of all updates against newer dumps, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<UpdateRecord>,
+    /// What updates changed of the earlier releases, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<Release>,
+    /// Name index for [TaxonCache::find_by_name], built on first use
+    #[serde(skip)]
+    name_index: OnceLock<NameIndex>,
 }
 
 impl TaxonCache {
     pub fn new() -> TaxonCache {
         TaxonCache {
+            version: CACHE_VERSION,
+            metadata: None,
             deprecated_ids: HashMap::new(),
+            deleted_ids: HashSet::new(),
             mappings: HashMap::new(),
+            full_species: false,
+            overrides: Overrides::new(),
+            history: Vec::new(),
+            releases: Vec::new(),
+            name_index: OnceLock::new(),
         }
     }
 
@@ -47,170 +203,1009 @@ impl TaxonCache {
         &mut self,
         taxdump: impl Read,
         merged_id_dump: impl Read,
-        taxids: &mut HashSet<i64>,
+        taxids: &mut HashSet<TaxId>,
+    ) -> Result<(), ASDBTaxonError> {
+        self.initialise_with(taxdump, merged_id_dump, taxids, &mut |_| ())
+    }
+
+    /// Like [TaxonCache::initialise], calling `on_resolved` with every entry added
+    pub fn initialise_with(
+        &mut self,
+        taxdump: impl Read,
+        merged_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+        on_resolved: &mut dyn FnMut(&NcbiTaxEntry),
+    ) -> Result<(), ASDBTaxonError> {
+        self.initialise_with_columns(taxdump, merged_id_dump, taxids, None, on_resolved)
+    }
+
+    /// Like [TaxonCache::initialise_with], reading the taxdump with `columns` instead of the
+    /// detected rankedlineage.dmp layout
+    pub fn initialise_with_columns(
+        &mut self,
+        taxdump: impl Read,
+        merged_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+        columns: Option<&ColumnMapping>,
+        on_resolved: &mut dyn FnMut(&NcbiTaxEntry),
     ) -> Result<(), ASDBTaxonError> {
-        populate_merged_ids(merged_id_dump, taxids, &mut self.deprecated_ids)?;
+        self.initialise_with_jobs(taxdump, merged_id_dump, taxids, columns, 1, on_resolved)
+    }
 
-        populate_mappings(taxdump, taxids, &self.deprecated_ids, &mut self.mappings)?;
+    /// Like [TaxonCache::initialise_with_columns], parsing the taxdump on `jobs` threads, see
+    /// [parallel]
+    pub fn initialise_with_jobs(
+        &mut self,
+        taxdump: impl Read,
+        merged_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+        columns: Option<&ColumnMapping>,
+        jobs: usize,
+        on_resolved: &mut dyn FnMut(&NcbiTaxEntry),
+    ) -> Result<(), ASDBTaxonError> {
+        let merged = populate_merged_ids(
+            merged_id_dump,
+            taxids,
+            &mut self.deprecated_ids,
+            MAX_MERGE_DEPTH,
+        )?;
+        info!("Applied {} merged IDs", merged);
+
+        let resolved = populate_mappings(self, taxdump, taxids, columns, jobs, on_resolved)?;
+        info!(
+            "Resolved {} of {} taxids from the taxdump",
+            resolved,
+            taxids.len()
+        );
 
         Ok(())
     }
 
-    pub fn initialise_from_paths(
+    /// Like [TaxonCache::initialise], for a known set of taxids instead of ones found in a datadir
+    pub fn initialise_with_taxids(
         &mut self,
-        taxdump_path: PathBuf,
-        merged_id_dump_path: PathBuf,
-        datadir_path: PathBuf,
+        taxdump: impl Read,
+        merged_id_dump: impl Read,
+        mut taxids: HashSet<TaxId>,
     ) -> Result<(), ASDBTaxonError> {
-        let mut taxids = self.find_taxids(datadir_path)?;
-        let taxdump = fs::File::open(taxdump_path)?;
-        let mergeddump = fs::File::open(merged_id_dump_path)?;
+        self.initialise(taxdump, merged_id_dump, &mut taxids)
+    }
 
-        self.initialise(taxdump, mergeddump, &mut taxids)?;
+    /// Replace all merged taxids in `taxids` by their current ones
+    pub fn add_merged_ids(
+        &mut self,
+        merged_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+    ) -> Result<(), ASDBTaxonError> {
+        self.add_merged_ids_with_depth(merged_id_dump, taxids, MAX_MERGE_DEPTH)
+    }
 
+    /// Like [TaxonCache::add_merged_ids], following chains of at most `max_depth` merges
+    pub fn add_merged_ids_with_depth(
+        &mut self,
+        merged_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+        max_depth: usize,
+    ) -> Result<(), ASDBTaxonError> {
+        let merged =
+            populate_merged_ids(merged_id_dump, taxids, &mut self.deprecated_ids, max_depth)?;
+        info!("Applied {} merged IDs", merged);
         Ok(())
     }
 
-    pub fn find_taxids(&self, datadir: PathBuf) -> Result<HashSet<i64>, ASDBTaxonError> {
-        let re = Regex::new(r#""taxon:(\d+)"#)?;
-        let mut taxids: HashSet<i64> = HashSet::new();
-        let mut entries = fs::read_dir(datadir)?
-            .map(|res| res.map(|e| e.path()))
-            .filter(|p| p.is_ok() && p.as_ref().unwrap().extension() == Some("json".as_ref()))
-            .collect::<Result<Vec<_>, io::Error>>()?;
+    /// Add the ancestors of `taxids` to the set, returning the ones not yet cached as regular entries
+    pub fn add_ancestors(&self, tree: &TaxonTree, taxids: &mut HashSet<TaxId>) -> HashSet<TaxId> {
+        let mut ancestors = tree.add_ancestors(taxids);
+        ancestors.retain(|tax_id| {
+            self.mappings
+                .get(tax_id)
+                .is_none_or(|entry| entry.ancestor_only)
+        });
+        info!("Added {} ancestor taxids", ancestors.len());
+        ancestors
+    }
 
-        entries.sort();
+    /// Set the parent links and ranks of all entries, flagging the `ancestors` as ancestor-only
+    pub fn link_ancestors(&mut self, tree: &TaxonTree, ancestors: &HashSet<TaxId>) {
+        for (tax_id, entry) in self.mappings_mut().iter_mut() {
+            entry.parent_id = tree.parent(*tax_id);
+            entry.rank = tree.rank(*tax_id).map(str::to_string);
+            if ancestors.contains(tax_id) {
+                entry.ancestor_only = true;
+            }
+        }
+    }
 
-        for path in entries {
-            let content = fs::read_to_string(&path)?;
-            let cap = re.captures(&content);
-            if cap.is_none() {
-                continue;
+    /// Set the `taxonomy` classifications of the cached entries, returning the number classified
+    pub fn add_classifications(
+        &mut self,
+        taxonomy: Taxonomy,
+        classifications: &HashMap<TaxId, Classification>,
+    ) -> usize {
+        let mut classified = 0;
+        for (tax_id, entry) in self.mappings_mut().iter_mut() {
+            match classifications.get(tax_id) {
+                Some(classification) => {
+                    entry
+                        .classifications
+                        .insert(taxonomy, classification.clone());
+                    classified += 1;
+                }
+                None => {
+                    entry.classifications.remove(&taxonomy);
+                }
             }
-            let taxid_match = cap.unwrap().get(1);
-            if taxid_match.is_none() {
-                continue;
+        }
+        info!(
+            "Found {} classifications for {} entries",
+            taxonomy, classified
+        );
+        classified
+    }
+
+    /// Add `overrides` to the ones kept in the cache and apply them all
+    pub fn add_overrides(&mut self, overrides: Overrides) -> usize {
+        self.overrides.merge(overrides);
+        self.apply_overrides()
+    }
+
+    /// Insert user-specified entries, returning how many there were
+    ///
+    /// The entries are marked as custom and get the cached overrides applied. Custom entries may
+    /// replace earlier custom ones, but not entries of taxids the NCBI knows.
+    pub fn insert_custom(
+        &mut self,
+        entries: impl IntoIterator<Item = NcbiTaxEntry>,
+    ) -> Result<usize, ASDBTaxonError> {
+        let entries: Vec<NcbiTaxEntry> = entries.into_iter().collect();
+        for entry in &entries {
+            let known = match self.mappings.get(&entry.tax_id) {
+                Some(existing) => existing.source != EntrySource::Custom,
+                None => {
+                    self.deprecated_ids.contains_key(&entry.tax_id)
+                        || self.deleted_ids.contains(&entry.tax_id)
+                }
+            };
+            if known {
+                return Err(ASDBTaxonError::InvalidTaxId(format!(
+                    "{} is an NCBI taxid, custom taxa need taxids of their own",
+                    entry.tax_id
+                )));
             }
-            if let Ok(taxid) = taxid_match.unwrap().as_str().parse::<i64>() {
-                taxids.insert(taxid);
+        }
+        let count = entries.len();
+        let now = metadata::now();
+        for mut entry in entries {
+            entry.source = EntrySource::Custom;
+            self.overrides.apply(&mut entry);
+            let previous = self.mappings.get(&entry.tax_id);
+            provenance::stamp_entry(&mut entry, previous, None, now);
+            self.mappings.insert(entry.tax_id, entry);
+        }
+        self.name_index = OnceLock::new();
+        info!("Inserted {} custom entries", count);
+        Ok(count)
+    }
+
+    /// The recorded changes concerning `tax_id`, with the update they were part of, oldest first
+    pub fn history_of(&self, tax_id: TaxId) -> Vec<(&UpdateRecord, &Change)> {
+        self.history
+            .iter()
+            .flat_map(|record| {
+                record
+                    .changes_of(tax_id)
+                    .map(move |change| (record, change))
+            })
+            .collect()
+    }
+
+    /// Set the overridden fields of the cached entries, returning the number of entries changed
+    pub fn apply_overrides(&mut self) -> usize {
+        let mut applied = 0;
+        for entry in self.mappings.values_mut() {
+            if self.overrides.apply(entry) {
+                applied += 1;
             }
         }
+        self.name_index = OnceLock::new();
+        info!("Applied overrides to {} entries", applied);
+        applied
+    }
+
+    /// Make all entries share their lineage names, returning the number of distinct names
+    pub fn intern_strings(&mut self) -> usize {
+        let mut interner = Interner::new();
+        for entry in self.mappings_mut().values_mut() {
+            interner.intern_entry(entry);
+        }
+        debug!("Interned {} distinct lineage names", interner.len());
+        interner.len()
+    }
+
+    /// Copy of the cache with the lineages of all entries taken from `taxonomy`
+    pub fn with_taxonomy(&self, taxonomy: Taxonomy) -> TaxonCache {
+        let mut cache = self.clone();
+        for entry in cache.mappings_mut().values_mut() {
+            *entry = entry.with_taxonomy(taxonomy);
+        }
+        cache
+    }
+
+    /// Record all requested taxids the NCBI deleted, removing them from `taxids`
+    pub fn add_deleted_ids(
+        &mut self,
+        deleted_id_dump: impl Read,
+        taxids: &mut HashSet<TaxId>,
+    ) -> Result<(), ASDBTaxonError> {
+        let deleted = populate_deleted_ids(deleted_id_dump, taxids, &mut self.deleted_ids)?;
+        info!("Found {} deleted taxids", deleted);
+        Ok(())
+    }
+
+    /// Add the taxids needed for the datadir at `datadir_path`
+    ///
+    /// Shorthand for the most basic [TaxonCacheBuilder] configuration, which has all the other
+    /// options.
+    #[cfg(feature = "fs")]
+    pub fn initialise_from_paths(
+        &mut self,
+        taxdump_path: PathBuf,
+        merged_id_dump_path: PathBuf,
+        deleted_id_dump_path: Option<PathBuf>,
+        datadir_path: PathBuf,
+    ) -> Result<(), ASDBTaxonError> {
+        let mut builder = TaxonCacheBuilder::new()
+            .taxdump(taxdump_path)
+            .merged_id_dump(merged_id_dump_path)
+            .datadir(datadir_path);
+        if let Some(path) = deleted_id_dump_path {
+            builder = builder.deleted_id_dump(path);
+        }
+        builder.run(self)?;
+        Ok(())
+    }
+
+    /// Find the taxids needed for all `datadirs`
+    #[cfg(feature = "fs")]
+    pub fn find_taxids(&self, datadirs: &[PathBuf]) -> Result<HashSet<TaxId>, ASDBTaxonError> {
+        let (taxids, _) = scan::scan_datadirs(datadirs, None)?;
         Ok(taxids)
     }
 
-    pub fn save(&self, mut output: impl Write) -> Result<usize, ASDBTaxonError> {
-        let json_data = serde_json::to_string(self)?;
-        output.write(json_data.as_bytes())?;
+    /// Like [TaxonCache::find_taxids], only reading the files selected by `options`
+    #[cfg(feature = "fs")]
+    pub fn find_taxids_with(
+        &self,
+        datadirs: &[PathBuf],
+        options: &ScanOptions,
+    ) -> Result<HashSet<TaxId>, ASDBTaxonError> {
+        let (taxids, _) = scan::scan_datadir_with(
+            datadirs,
+            None,
+            options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )?;
+        Ok(taxids)
+    }
+
+    /// Find the taxids needed for all `datadirs`, with a report on how they were resolved
+    #[cfg(feature = "fs")]
+    pub fn find_taxids_with_report(
+        &self,
+        datadirs: &[PathBuf],
+        biosamples: Option<&BioSampleMap>,
+    ) -> Result<(HashSet<TaxId>, ScanReport), ASDBTaxonError> {
+        scan::scan_datadirs(datadirs, biosamples)
+    }
+
+    pub fn save(&self, output: impl Write) -> Result<usize, ASDBTaxonError> {
+        self.save_as(output, CacheFormat::Json)
+    }
+
+    /// Save in `format`, see [format]
+    pub fn save_as(
+        &self,
+        mut output: impl Write,
+        format: CacheFormat,
+    ) -> Result<usize, ASDBTaxonError> {
+        match format {
+            CacheFormat::Json | CacheFormat::Pretty => {
+                let checksum = self.checksum()?;
+                let cache = format::Checksummed {
+                    cache: self,
+                    checksum: &checksum,
+                };
+                if format == CacheFormat::Json {
+                    serde_json::to_writer(&mut output, &cache)?
+                } else {
+                    serde_json::to_writer_pretty(&mut output, &cache)?
+                }
+            }
+            // the index needs a file, see save_path_as
+            CacheFormat::Ndjson | CacheFormat::Indexed => format::write_ndjson(self, &mut output)?,
+        }
+        info!("Wrote {} entries", self.mappings.len());
+
+        Ok(self.mappings.len())
+    }
+
+    /// Save to `outfile`, compressed according to its extension
+    #[cfg(feature = "fs")]
+    pub fn save_path(&self, outfile: &Path) -> Result<usize, ASDBTaxonError> {
+        self.save_path_with(outfile, Compression::from_path(outfile))
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn save_path_with(
+        &self,
+        outfile: &Path,
+        compression: Compression,
+    ) -> Result<usize, ASDBTaxonError> {
+        self.save_path_as(outfile, compression, CacheFormat::Json)
+    }
 
+    /// Save the cache to `outfile` in `format`
+    ///
+    /// The cache is written next to `outfile` and only renamed over it once complete, so readers
+    /// never see a partly written cache.
+    #[cfg(feature = "fs")]
+    pub fn save_path_as(
+        &self,
+        outfile: &Path,
+        compression: Compression,
+        format: CacheFormat,
+    ) -> Result<usize, ASDBTaxonError> {
+        if format != CacheFormat::Indexed {
+            let count = atomic::replace_with(outfile, |tmp_path| {
+                let mut out = compression::create(tmp_path, compression)?;
+                let count = self.save_as(&mut out, format)?;
+                out.finish()?;
+                Ok(count)
+            })?;
+            // an index left over from an earlier save no longer matches
+            match fs::remove_file(index::index_path(outfile)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+            return Ok(count);
+        }
+        if compression != Compression::None {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "indexed caches can't be compressed".to_string(),
+            ));
+        }
+        let mut lines = Vec::with_capacity(self.mappings.len());
+        atomic::replace_with(outfile, |tmp_path| {
+            let mut out = compression::create(tmp_path, compression)?;
+            format::write_ndjson_with(self, &mut out, &mut |entry, offset, length| {
+                lines.push((entry.tax_id, offset, length))
+            })?;
+            out.finish()
+        })?;
+        index::write_index(self, outfile, &lines)?;
+        info!("Wrote {} entries", self.mappings.len());
         Ok(self.mappings.len())
     }
 
-    pub fn save_path(&self, outfile: &PathBuf) -> Result<usize, ASDBTaxonError> {
-        let out = fs::File::create(outfile)?;
-        self.save(out)
+    /// Checksum of the cache content, as saved with it and verified on [TaxonCache::load]
+    pub fn checksum(&self) -> Result<String, ASDBTaxonError> {
+        format::checksum(&serde_json::to_value(self)?)
     }
 
-    pub fn load(&mut self, mut input: impl Read) -> Result<usize, ASDBTaxonError> {
-        let mut json_data = String::new();
-        input.read_to_string(&mut json_data)?;
-        let loaded_cache: TaxonCache = serde_json::from_str(&json_data)?;
+    /// Load a cache in any of the [CacheFormat]s
+    ///
+    /// Caches that were cut short or don't match their checksum fail with
+    /// [ASDBTaxonError::CorruptCache].
+    pub fn load(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let (head, mut input) = format::peek(input)?;
+        let mut value: serde_json::Value = match CacheFormat::detect(&head) {
+            CacheFormat::Ndjson => format::read_ndjson(input)?,
+            _ => {
+                let mut json_data = String::new();
+                input.read_to_string(&mut json_data)?;
+                serde_json::from_str(&json_data).map_err(format::parse_error)?
+            }
+        };
+        if value.get("format").and_then(serde_json::Value::as_str) == Some("delta") {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "this is a delta cache, load it on top of its baseline".to_string(),
+            ));
+        }
+        // caches saved before checksums were added have none to verify
+        let stored = value
+            .as_object_mut()
+            .and_then(|fields| fields.remove("checksum"));
+        if let Some(stored) = stored {
+            let stored = stored.as_str().unwrap_or_default().to_string();
+            let checksum = format::checksum(&value)?;
+            if stored != checksum {
+                return Err(ASDBTaxonError::CorruptCache(format!(
+                    "checksum {} doesn't match the content's {}",
+                    stored, checksum
+                )));
+            }
+        }
+        schema::migrate(&mut value)?;
+        let loaded_cache: TaxonCache = serde_json::from_value(value)?;
         self.mappings = loaded_cache.mappings;
         self.deprecated_ids = loaded_cache.deprecated_ids;
+        self.deleted_ids = loaded_cache.deleted_ids;
+        self.metadata = loaded_cache.metadata;
+        self.version = loaded_cache.version;
+        self.full_species = loaded_cache.full_species;
+        self.overrides = loaded_cache.overrides;
+        self.history = loaded_cache.history;
+        self.releases = loaded_cache.releases;
+        self.name_index = OnceLock::new();
+        self.intern_strings();
+        info!("Loaded {} entries", self.mappings.len());
 
         Ok(self.mappings.len())
     }
 
-    pub fn load_path(&mut self, infile: &PathBuf) -> Result<usize, ASDBTaxonError> {
-        let handle = fs::File::open(infile)?;
-        self.load(handle)
+    /// Load from `infile`, which may be compressed
+    #[cfg(feature = "fs")]
+    pub fn load_path(&mut self, infile: &Path) -> Result<usize, ASDBTaxonError> {
+        self.load(compression::open(infile)?)
+    }
+
+    /// Entries by current taxid
+    pub fn mappings(&self) -> &HashMap<TaxId, NcbiTaxEntry> {
+        &self.mappings
+    }
+
+    /// Entries by current taxid, to change them in place
+    ///
+    /// This drops the name index, which the next search builds again. Unlike
+    /// [TaxonCache::insert] and [TaxonCache::remove], changes made here don't keep the merged and
+    /// deleted taxids consistent with the entries.
+    pub fn mappings_mut(&mut self) -> &mut HashMap<TaxId, NcbiTaxEntry> {
+        self.name_index = OnceLock::new();
+        &mut self.mappings
+    }
+
+    /// Number of cached entries, ancestors included
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// All entries, in no particular order
+    pub fn iter(&self) -> hash_map::Values<'_, TaxId, NcbiTaxEntry> {
+        self.mappings.values()
+    }
+
+    /// All entries, sorted by taxid
+    pub fn iter_sorted_by_taxid(&self) -> impl Iterator<Item = &NcbiTaxEntry> {
+        let mut entries: Vec<&NcbiTaxEntry> = self.iter().collect();
+        entries.sort_by_key(|entry| entry.tax_id);
+        entries.into_iter()
+    }
+
+    /// The entries of taxa at `rank`, see [NcbiTaxEntry::taxon_rank]
+    pub fn iter_by_rank(&self, rank: Rank) -> impl Iterator<Item = &NcbiTaxEntry> {
+        self.iter()
+            .filter(move |entry| entry.taxon_rank() == Some(rank))
+    }
+
+    /// Whether [TaxonCache::lookup] finds an entry for `tax_id`, following merged IDs
+    pub fn contains(&self, tax_id: TaxId) -> bool {
+        self.lookup(tax_id).is_ok()
+    }
+
+    /// Add or replace the entry of its taxid, returning the replaced one
+    ///
+    /// The taxid is current from then on, so it's no longer listed as merged or deleted.
+    pub fn insert(&mut self, entry: NcbiTaxEntry) -> Option<NcbiTaxEntry> {
+        self.deprecated_ids.remove(&entry.tax_id);
+        self.deleted_ids.remove(&entry.tax_id);
+        self.mappings_mut().insert(entry.tax_id, entry)
+    }
+
+    /// Remove the entry of `tax_id`, following merged IDs, returning it
+    ///
+    /// Merged IDs pointing to the removed entry are dropped as well, so they aren't left dangling.
+    pub fn remove(&mut self, tax_id: TaxId) -> Option<NcbiTaxEntry> {
+        let tax_id = *self.deprecated_ids.get(&tax_id).unwrap_or(&tax_id);
+        let entry = self.mappings_mut().remove(&tax_id)?;
+        self.deprecated_ids.retain(|_, current| *current != tax_id);
+        Some(entry)
+    }
+
+    /// Look up a taxid, following merged IDs
+    pub fn lookup(&self, tax_id: TaxId) -> Result<&NcbiTaxEntry, ASDBTaxonError> {
+        let tax_id = *self.deprecated_ids.get(&tax_id).unwrap_or(&tax_id);
+        if let Some(entry) = self.mappings.get(&tax_id) {
+            return Ok(entry);
+        }
+        if self.deleted_ids.contains(&tax_id) {
+            return Err(ASDBTaxonError::Deleted(tax_id));
+        }
+        Err(ASDBTaxonError::NotFound(tax_id))
+    }
+
+    /// Tags of the kept releases and the current one, oldest first
+    pub fn release_tags(&self) -> Vec<String> {
+        self.releases
+            .iter()
+            .map(|release| release.tag.clone())
+            .chain(self.metadata.as_ref().map(CacheMetadata::release_tag))
+            .collect()
+    }
+
+    /// Look up `tax_id` as it was in the last release up to the tag `as_of`, see [releases]
+    pub fn lookup_as_of(
+        &self,
+        tax_id: TaxId,
+        as_of: &str,
+    ) -> Result<&NcbiTaxEntry, ASDBTaxonError> {
+        let start = self
+            .release_tags()
+            .iter()
+            .rposition(|tag| releases::matches(tag, as_of))
+            .ok_or_else(|| ASDBTaxonError::ReleaseNotFound(as_of.to_string()))?;
+        let mut tax_id = tax_id;
+        // merges redirected since the release lead to the entries of that release
+        for _ in 0..MAX_MERGE_DEPTH {
+            let mut redirected = None;
+            for release in &self.releases[start..] {
+                if let Some(entry) = release.entries.get(&tax_id) {
+                    return Ok(entry);
+                }
+                if let Some(into) = release.merged.get(&tax_id) {
+                    redirected = Some(*into);
+                    break;
+                }
+                if release.absent.contains(&tax_id) {
+                    return Err(ASDBTaxonError::NotFound(tax_id));
+                }
+            }
+            match redirected {
+                Some(into) => tax_id = into,
+                None => return self.lookup(tax_id),
+            }
+        }
+        Err(ASDBTaxonError::NotFound(tax_id))
+    }
+
+    /// List the reasons for all taxids that can't be looked up, sorted by taxid
+    pub fn missing(&self, taxids: &HashSet<TaxId>) -> Vec<ASDBTaxonError> {
+        let mut sorted: Vec<&TaxId> = taxids.iter().collect();
+        sorted.sort();
+        sorted
+            .into_iter()
+            .filter_map(|tax_id| self.lookup(*tax_id).err())
+            .collect()
+    }
+
+    /// Find the entries named exactly `name`
+    pub fn find_by_name(&self, name: &str) -> Vec<&NcbiTaxEntry> {
+        self.find_by_name_with(name, &NameQuery::default())
+    }
+
+    /// Find the entries with names matching `name` as set by `query`, sorted by name
+    ///
+    /// The name index is built on the first search, and again after entries changed.
+    pub fn find_by_name_with(&self, name: &str, query: &NameQuery) -> Vec<&NcbiTaxEntry> {
+        self.with_name_index(|index| index.find(name, query))
+            .iter()
+            .filter_map(|tax_id| self.mappings.get(tax_id))
+            .collect()
+    }
+
+    /// Find the entries with names similar to `name`, best first, with their scores from 0 to 1
+    ///
+    /// Candidates scoring below `min_score` are left out, see [search::DEFAULT_MIN_SCORE].
+    pub fn find_by_name_fuzzy(&self, name: &str, min_score: f64) -> Vec<(&NcbiTaxEntry, f64)> {
+        self.with_name_index(|index| index.fuzzy(name, min_score))
+            .into_iter()
+            .filter_map(|(tax_id, score)| Some((self.mappings.get(&tax_id)?, score)))
+            .collect()
+    }
+
+    fn with_name_index<T>(&self, search: impl FnOnce(&NameIndex) -> T) -> T {
+        search(
+            self.name_index
+                .get_or_init(|| NameIndex::build(&self.mappings)),
+        )
+    }
+
+    /// All entries named `value` at `rank`, e.g. all of genus Streptomyces, sorted by taxid
+    pub fn query_by_rank(&self, rank: Rank, value: &str) -> Vec<&NcbiTaxEntry> {
+        let mut entries: Vec<&NcbiTaxEntry> = self
+            .mappings
+            .values()
+            .filter(|entry| entry.rank_value(rank) == value)
+            .collect();
+        entries.sort_by_key(|entry| entry.tax_id);
+        entries
+    }
+
+    /// All cached descendants of `tax_id`, following merged IDs, see [Descendants]
+    pub fn descendants(&self, tax_id: TaxId) -> Result<Descendants<'_>, ASDBTaxonError> {
+        Ok(Descendants::new(self, self.lookup(tax_id)?))
+    }
+
+    /// Lowest common ancestor of `taxids`, following merged IDs
+    ///
+    /// Uses the parent links if all entries have them, or else the deepest lineage rank all of
+    /// them share, which might not be cached itself. Taxa without anything in common meet at the
+    /// root, taxid 1.
+    pub fn lca(&self, taxids: &[TaxId]) -> Result<Ancestor, ASDBTaxonError> {
+        let entries = taxids
+            .iter()
+            .map(|tax_id| self.lookup(*tax_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(first) = entries.first() else {
+            return Err(ASDBTaxonError::MissingInput("taxids".to_string()));
+        };
+        if entries.iter().all(|entry| entry.tax_id == first.tax_id) {
+            return Ok(Ancestor::Cached((*first).clone()));
+        }
+
+        if entries.iter().all(|entry| entry.parent_id.is_some()) {
+            let track = |entry: &NcbiTaxEntry| {
+                let mut track = vec![entry.tax_id];
+                let mut current = entry;
+                while let Some(parent) = current.parent_id.filter(|parent| !track.contains(parent))
+                {
+                    track.push(parent);
+                    match self.mappings.get(&parent) {
+                        Some(next) => current = next,
+                        None => break,
+                    }
+                }
+                track
+            };
+            let others: Vec<Vec<TaxId>> = entries[1..].iter().map(|entry| track(entry)).collect();
+            let shared = track(first)
+                .into_iter()
+                .find(|tax_id| others.iter().all(|other| other.contains(tax_id)));
+            if let Some(entry) = shared.and_then(|tax_id| self.mappings.get(&tax_id)) {
+                return Ok(Ancestor::Cached(entry.clone()));
+            }
+            return Ok(Ancestor::Cached(root_entry()));
+        }
+
+        let known = |value: &str| !value.is_empty() && value != "Unknown";
+        let mut shared = None;
+        for rank in Rank::ALL {
+            let value = first.rank_value(rank);
+            if entries.iter().all(|entry| !known(entry.rank_value(rank))) {
+                continue;
+            }
+            if !known(value) || entries.iter().any(|entry| entry.rank_value(rank) != value) {
+                break;
+            }
+            shared = Some(rank);
+        }
+        let Some(rank) = shared else {
+            return Ok(Ancestor::Cached(root_entry()));
+        };
+
+        let mut lca = root_entry();
+        for ancestor_rank in Rank::ALL.into_iter().filter(|other| *other <= rank) {
+            *lca.rank_value_mut(ancestor_rank) = first.rank_value(ancestor_rank).into();
+        }
+        lca.name = match rank {
+            Rank::Species => lca.species_name().unwrap_or_default(),
+            _ => first.rank_value(rank).to_string(),
+        };
+        lca.rank = Some(rank.to_string());
+        if let Some(cached) = self
+            .find_by_name(&lca.name)
+            .into_iter()
+            .find(|entry| export::rank(entry) == rank.as_str())
+        {
+            return Ok(Ancestor::Cached(cached.clone()));
+        }
+        Ok(Ancestor::Uncached(lca))
+    }
+
+    /// Copy of the cache with only the entries for `taxids`, following merged IDs
+    ///
+    /// Deleted IDs among `taxids` are kept as well, see [TaxonCache::subset_by] for the rest.
+    pub fn subset(&self, taxids: &[TaxId]) -> TaxonCache {
+        let wanted: HashSet<TaxId> = taxids
+            .iter()
+            .map(|tax_id| *self.deprecated_ids.get(tax_id).unwrap_or(tax_id))
+            .collect();
+        let mut subset = self.subset_by(|entry| wanted.contains(&entry.tax_id));
+        subset.deleted_ids = taxids
+            .iter()
+            .filter(|tax_id| self.deleted_ids.contains(tax_id))
+            .copied()
+            .collect();
+        subset
+    }
+
+    /// Copy of the cache with only the entries matching `predicate`
+    ///
+    /// Merged IDs of the kept entries are kept, and so are their ancestors if the cache was
+    /// built with nodes.dmp, flagged as `ancestor_only` unless they match themselves.
+    pub fn subset_by(&self, predicate: impl Fn(&NcbiTaxEntry) -> bool) -> TaxonCache {
+        let mut subset = TaxonCache::new();
+        for entry in self.mappings.values().filter(|entry| predicate(entry)) {
+            subset.mappings.insert(entry.tax_id, entry.clone());
+        }
+
+        let mut selected: Vec<TaxId> = subset.mappings.keys().copied().collect();
+        while let Some(tax_id) = selected.pop() {
+            let parent = self.mappings[&tax_id]
+                .parent_id
+                .and_then(|parent_id| self.mappings.get(&parent_id));
+            if let Some(parent) = parent {
+                if let Entry::Vacant(vacant) = subset.mappings.entry(parent.tax_id) {
+                    let mut ancestor = parent.clone();
+                    ancestor.ancestor_only = true;
+                    vacant.insert(ancestor);
+                    selected.push(parent.tax_id);
+                }
+            }
+        }
+
+        subset.deprecated_ids = self
+            .deprecated_ids
+            .iter()
+            .filter(|(_, current)| subset.mappings.contains_key(current))
+            .map(|(old, current)| (*old, *current))
+            .collect();
+        subset.metadata = self.metadata.clone().map(|metadata| CacheMetadata {
+            entry_count: subset.mappings.len(),
+            ..metadata
+        });
+        subset
+    }
+
+    /// Get a read-only view of the cache that only exposes entries matching `filter`
+    pub fn view(&self, filter: ViewFilter) -> CacheView<'_> {
+        CacheView::new(self, filter)
+    }
+}
+
+/// The NCBI taxonomy root, for taxa without a common ancestor in the cache
+fn root_entry() -> NcbiTaxEntry {
+    let unknown = || Arc::from("Unknown");
+    NcbiTaxEntry {
+        name: "root".to_string(),
+        species: unknown(),
+        genus: unknown(),
+        family: unknown(),
+        order: unknown(),
+        class: unknown(),
+        phylum: unknown(),
+        kingdom: unknown(),
+        superkingdom: unknown(),
+        rank: Some("no rank".to_string()),
+        ..NcbiTaxEntry::new(TaxId::ROOT)
     }
 }
 
 fn populate_merged_ids(
     merged_id_dump: impl Read,
-    taxids: &mut HashSet<i64>,
-    deprecated_ids: &mut HashMap<i64, i64>,
-) -> Result<(), ASDBTaxonError> {
-    for line_option in io::BufReader::new(merged_id_dump).lines() {
-        if let Ok(line) = line_option {
-            let parts: Vec<String> = line
-                .trim()
-                .splitn(3, "|")
-                .map(|part| part.trim().to_string())
-                .collect();
-
-            let old_id: i64 = parts[0].parse()?;
-            if !taxids.contains(&old_id) {
-                continue;
-            }
+    taxids: &mut HashSet<TaxId>,
+    deprecated_ids: &mut HashMap<TaxId, TaxId>,
+    max_depth: usize,
+) -> Result<usize, ASDBTaxonError> {
+    // merges can be chained, so collect all of them before following them
+    let mut merges = HashMap::new();
+    for line in io::BufReader::new(merged_id_dump)
+        .lines()
+        .map_while(Result::ok)
+    {
+        let parts: Vec<String> = line
+            .trim()
+            .splitn(3, '|')
+            .map(|part| part.trim().to_string())
+            .collect();
+
+        let old_id: TaxId = parts[0].parse()?;
+        let new_id: TaxId = parts[1].parse()?;
+        merges.insert(old_id, new_id);
+    }
 
-            let new_id: i64 = parts[1].parse()?;
+    let mut requested: Vec<TaxId> = taxids.iter().copied().collect();
+    requested.sort();
+    let mut merged = 0;
+    for old_id in requested {
+        let Some(new_id) = follow_merges(&merges, old_id, max_depth)? else {
+            continue;
+        };
 
-            deprecated_ids.insert(old_id, new_id);
-            taxids.remove(&old_id);
-            taxids.insert(new_id);
+        debug!("Taxid {} was merged into {}", old_id, new_id);
+        deprecated_ids.insert(old_id, new_id);
+        taxids.remove(&old_id);
+        taxids.insert(new_id);
+        merged += 1;
+    }
+    Ok(merged)
+}
+
+/// Follow the merges of `tax_id` to its current taxid, if it was merged at all
+fn follow_merges(
+    merges: &HashMap<TaxId, TaxId>,
+    tax_id: TaxId,
+    max_depth: usize,
+) -> Result<Option<TaxId>, ASDBTaxonError> {
+    let mut chain = vec![tax_id];
+    let mut current = tax_id;
+    while let Some(&next) = merges.get(&current) {
+        if let Some(start) = chain.iter().position(|id| *id == next) {
+            chain.push(next);
+            return Err(ASDBTaxonError::MergeCycle(chain.split_off(start)));
         }
+        chain.push(next);
+        if chain.len() > max_depth + 1 {
+            return Err(ASDBTaxonError::InvalidFormat(format!(
+                "more than {} chained merges of taxid {}",
+                max_depth, tax_id
+            )));
+        }
+        current = next;
     }
-    Ok(())
+    Ok((current != tax_id).then_some(current))
 }
 
+fn populate_deleted_ids(
+    deleted_id_dump: impl Read,
+    taxids: &mut HashSet<TaxId>,
+    deleted_ids: &mut HashSet<TaxId>,
+) -> Result<usize, ASDBTaxonError> {
+    let mut deleted = 0;
+    for line in io::BufReader::new(deleted_id_dump)
+        .lines()
+        .map_while(Result::ok)
+    {
+        let tax_id: TaxId = match line.split('|').next() {
+            Some(part) => part.trim().parse()?,
+            None => continue,
+        };
+
+        if taxids.remove(&tax_id) {
+            debug!("Taxid {} was deleted", tax_id);
+            deleted_ids.insert(tax_id);
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Add the entries of the needed `taxids` in `taxdump` to `cache`, parsing it on `jobs` threads
 fn populate_mappings(
+    cache: &mut TaxonCache,
     taxdump: impl Read,
-    taxids: &HashSet<i64>,
-    deprecated_ids: &HashMap<i64, i64>,
-    mappings: &mut HashMap<i64, NcbiTaxEntry>,
-) -> Result<(), ASDBTaxonError> {
-    for line_option in io::BufReader::new(taxdump).lines() {
-        if let Ok(line) = line_option {
-            let parts: Vec<String> = line
-                .trim()
-                .splitn(11, "|")
-                .map(|part| match part.trim() {
-                    "" => "Unknown".to_string(),
-                    part => part.to_string(),
-                })
-                .collect();
-
-            let mut tax_id: i64 = parts[0].parse()?;
-            if deprecated_ids.contains_key(&tax_id) {
-                tax_id = *deprecated_ids.get(&tax_id).unwrap();
-            }
-
-            if !taxids.contains(&tax_id) {
-                continue;
-            }
+    taxids: &HashSet<TaxId>,
+    columns: Option<&ColumnMapping>,
+    jobs: usize,
+    on_resolved: &mut dyn FnMut(&NcbiTaxEntry),
+) -> Result<usize, ASDBTaxonError> {
+    cache.name_index = OnceLock::new();
+    let TaxonCache {
+        ref deprecated_ids,
+        ref mut mappings,
+        full_species,
+        ..
+    } = *cache;
+    let superkingdom_columns = DumpLayout::Superkingdom.columns();
+    let domain_columns = DumpLayout::Domain.columns();
+    // the entries of needed taxids, and whether they're from the line of a merged taxid
+    let resolve_line = |line: &str| -> Result<Option<(NcbiTaxEntry, bool)>, ASDBTaxonError> {
+        let parts = split_line(line);
+        let mapping = match columns {
+            Some(mapping) => mapping,
+            None => match DumpLayout::detect(parts.len())? {
+                DumpLayout::Superkingdom => &superkingdom_columns,
+                DumpLayout::Domain => &domain_columns,
+            },
+        };
 
-            let entry = NcbiTaxEntry {
-                tax_id,
-                name: parts[1].to_owned(),
-                species: parts[2]
-                    .split_whitespace()
-                    .next_back()
-                    .unwrap_or(parts[2].as_str())
-                    .to_owned(),
-                genus: parts[3].to_owned(),
-                family: parts[4].to_owned(),
-                order: parts[5].to_owned(),
-                class: parts[6].to_owned(),
-                phylum: parts[7].to_owned(),
-                kingdom: parts[8].to_owned(),
-                superkingdom: parts[9].to_owned(),
-            };
+        let line_tax_id = mapping.tax_id(&parts)?;
+        let tax_id = *deprecated_ids.get(&line_tax_id).unwrap_or(&line_tax_id);
 
-            mappings.insert(tax_id, entry.to_owned());
+        if !taxids.contains(&tax_id) {
+            return Ok(None);
         }
+
+        let mut entry = mapping.entry(&parts, full_species)?;
+        entry.tax_id = tax_id;
+        Ok(Some((entry, tax_id != line_tax_id)))
+    };
+
+    if taxids.is_empty() {
+        return Ok(0);
+    }
+    // every current taxid has a single line, which replaces entries from lines of taxids merged
+    // into it, so the rest of the dump can be skipped once all of them are read
+    let mut unresolved = taxids.clone();
+    let mut resolved = 0;
+    let mut add = |(entry, merged): (NcbiTaxEntry, bool)| {
+        trace!("Resolved taxid {}: {}", entry.tax_id, entry.name);
+        on_resolved(&entry);
+        if !merged {
+            unresolved.remove(&entry.tax_id);
+        }
+        mappings.insert(entry.tax_id, entry);
+        resolved += 1;
+        if unresolved.is_empty() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+
+    if jobs > 1 {
+        parallel::map_chunks(
+            taxdump,
+            jobs,
+            parallel::DEFAULT_CHUNK_SIZE,
+            |chunk| {
+                chunk
+                    .lines()
+                    .filter_map(|line| resolve_line(line).transpose())
+                    .collect::<Result<Vec<_>, ASDBTaxonError>>()
+            },
+            |entries| {
+                for entry in entries {
+                    if add(entry).is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                Ok(ControlFlow::Continue(()))
+            },
+        )?;
+    } else {
+        // a single line buffer and borrowed fields keep the allocations of lines that aren't
+        // needed, almost all of them, to a minimum
+        let mut reader = io::BufReader::new(taxdump);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            if let Some(entry) = resolve_line(&line)? {
+                if add(entry).is_break() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+impl<'a> IntoIterator for &'a TaxonCache {
+    type Item = &'a NcbiTaxEntry;
+    type IntoIter = hash_map::Values<'a, TaxId, NcbiTaxEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for TaxonCache {
+    type Item = NcbiTaxEntry;
+    type IntoIter = hash_map::IntoValues<TaxId, NcbiTaxEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.mappings.into_values()
     }
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::taxa::tid;
 
     #[test]
     fn test_initialise() {
-        let mut taxids: HashSet<i64> = HashSet::new();
-        taxids.insert(12345);
+        let mut taxids: HashSet<TaxId> = HashSet::new();
+        taxids.insert(tid(12345));
 
         let merged_ids = "12345   |    23456  |".as_bytes();
         let taxdump = "23456  |       Streptomyces examplis NBC12345   |       Streptomyces examplis |       Streptomyces    |       Streptomycetaceae       |       Streptomycetales        |       Actinomycetia   |       Actinobacteria  |               |      Bacteria |".as_bytes();
@@ -218,7 +1213,7 @@ mod tests {
         let mut taxon_cache = TaxonCache::new();
 
         let res = taxon_cache.initialise(taxdump, merged_ids, &mut taxids);
-        assert_eq!(res.is_ok(), true);
+        assert!(res.is_ok());
         assert_eq!(
             taxon_cache.deprecated_ids.len(),
             1,
@@ -232,8 +1227,537 @@ mod tests {
             taxon_cache.mappings.len()
         );
         assert_eq!(
-            taxon_cache.mappings.get(&23456).unwrap().name,
+            taxon_cache.mappings.get(&tid(23456)).unwrap().name,
             "Streptomyces examplis NBC12345"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_initialise_with_taxids() {
+        let merged_ids = "12345\t|\t23456\t|\n".as_bytes();
+        let taxdump = "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n".as_bytes();
+
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache
+            .initialise_with_taxids(taxdump, merged_ids, HashSet::from([tid(12345), tid(34567)]))
+            .unwrap();
+
+        assert_eq!(taxon_cache.lookup(tid(12345)).unwrap().tax_id, 23456);
+        assert!(taxon_cache.lookup(tid(34567)).is_err());
+    }
+
+    #[test]
+    fn test_full_species() {
+        let taxdump = "23456\t|\tStreptomyces examplis NBC12345\t|\tStreptomyces examplis\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n".as_bytes();
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache.full_species = true;
+        taxon_cache
+            .initialise_with_taxids(taxdump, "".as_bytes(), HashSet::from([tid(23456)]))
+            .unwrap();
+
+        let entry = taxon_cache.lookup(tid(23456)).unwrap();
+        assert_eq!(&*entry.species, "Streptomyces examplis");
+        assert_eq!(entry.strain.as_deref(), Some("NBC12345"));
+
+        let mut saved = Vec::new();
+        taxon_cache.save(&mut saved).unwrap();
+        let mut loaded = TaxonCache::new();
+        loaded.load(saved.as_slice()).unwrap();
+        assert!(loaded.full_species);
+    }
+
+    #[test]
+    fn test_save_as() {
+        let taxdump = "23456\t|\tStreptomyces examplis NBC12345\t|\tStreptomyces examplis\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n".as_bytes();
+        let merged_ids = "12345\t|\t23456\t|\n".as_bytes();
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache.full_species = true;
+        taxon_cache
+            .initialise_with_taxids(taxdump, merged_ids, HashSet::from([tid(12345)]))
+            .unwrap();
+
+        for format in [CacheFormat::Json, CacheFormat::Pretty, CacheFormat::Ndjson] {
+            let mut saved = Vec::new();
+            assert_eq!(taxon_cache.save_as(&mut saved, format).unwrap(), 1);
+            assert_eq!(CacheFormat::detect(&saved), format);
+
+            let mut loaded = TaxonCache::new();
+            assert_eq!(loaded.load(saved.as_slice()).unwrap(), 1);
+            assert!(loaded.full_species);
+            assert_eq!(loaded.deprecated_ids, taxon_cache.deprecated_ids);
+            assert_eq!(
+                loaded.lookup(tid(12345)).unwrap().strain.as_deref(),
+                Some("NBC12345")
+            );
+            assert_eq!(loaded.checksum().unwrap(), taxon_cache.checksum().unwrap());
+
+            // cut off in the middle of an entry
+            let truncated = &saved[..saved.len() - 10];
+            assert!(matches!(
+                TaxonCache::new().load(truncated),
+                Err(ASDBTaxonError::CorruptCache(_))
+            ));
+            let edited = String::from_utf8(saved)
+                .unwrap()
+                .replace("NBC12345", "NBC54321");
+            assert!(matches!(
+                TaxonCache::new().load(edited.as_bytes()),
+                Err(ASDBTaxonError::CorruptCache(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_initialise_with_jobs() {
+        let taxdump = "12345\t|\tStreptomyces oldname\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+5062\t|\tAspergillus oryzae\t|\t\t|\tAspergillus\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tEukaryota\t|\n";
+        let merged_ids = "12345\t|\t23456\t|\n";
+        let build = |jobs| {
+            let mut taxon_cache = TaxonCache::new();
+            taxon_cache
+                .initialise_with_jobs(
+                    taxdump.as_bytes(),
+                    merged_ids.as_bytes(),
+                    &mut HashSet::from([tid(12345), tid(5062)]),
+                    None,
+                    jobs,
+                    &mut |_| (),
+                )
+                .unwrap();
+            taxon_cache
+        };
+        let sequential = build(1);
+        let parallel = build(4);
+        assert_eq!(parallel.len(), 2);
+        for tax_id in [tid(12345), tid(23456), tid(5062)] {
+            assert_eq!(
+                parallel.lookup(tax_id).unwrap().name,
+                sequential.lookup(tax_id).unwrap().name
+            );
+        }
+        // the line of the current taxid comes last and wins, as when reading line by line
+        assert_eq!(
+            parallel.lookup(tid(12345)).unwrap().name,
+            "Streptomyces examplis"
+        );
+    }
+
+    #[test]
+    fn test_stop_when_resolved() {
+        // the broken last line is never read
+        let taxdump = "12345\t|\tStreptomyces oldname\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+broken\n";
+        let merged_ids = "12345\t|\t23456\t|\n";
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache
+            .initialise_with_taxids(
+                taxdump.as_bytes(),
+                merged_ids.as_bytes(),
+                HashSet::from([tid(12345)]),
+            )
+            .unwrap();
+        // the line of the merged taxid doesn't count, the current one still replaces it
+        assert_eq!(
+            taxon_cache.lookup(tid(12345)).unwrap().name,
+            "Streptomyces examplis"
+        );
+
+        let mut taxon_cache = TaxonCache::new();
+        assert!(taxon_cache
+            .initialise_with_taxids(
+                taxdump.as_bytes(),
+                merged_ids.as_bytes(),
+                HashSet::from([tid(5062)])
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_intern_strings() {
+        let taxdump = "23456\t|\tStreptomyces examplis\t|\t\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n\
+1883\t|\tStreptomyces\t|\t\t|\t\t|\t\t|\t\t|\t\t|\t\t|\t\t|\tBacteria\t|\n".as_bytes();
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache
+            .initialise_with_taxids(
+                taxdump,
+                "".as_bytes(),
+                HashSet::from([tid(23456), tid(1883)]),
+            )
+            .unwrap();
+
+        let mut saved = Vec::new();
+        taxon_cache.save(&mut saved).unwrap();
+        let mut loaded = TaxonCache::new();
+        loaded.load(saved.as_slice()).unwrap();
+        let species = loaded.lookup(tid(23456)).unwrap();
+        let genus = loaded.lookup(tid(1883)).unwrap();
+        assert!(Arc::ptr_eq(&species.superkingdom, &genus.superkingdom));
+        assert!(Arc::ptr_eq(&species.family, &genus.family));
+        assert_eq!(loaded.intern_strings(), 3);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut taxon_cache = TaxonCache::new();
+        assert!(taxon_cache.is_empty());
+        let entry = |tax_id, name: &str| NcbiTaxEntry {
+            name: name.to_string(),
+            ..NcbiTaxEntry::new(tax_id)
+        };
+        assert!(taxon_cache
+            .insert(entry(tid(23456), "Streptomyces"))
+            .is_none());
+        taxon_cache.deprecated_ids.insert(tid(12345), tid(23456));
+        taxon_cache.deleted_ids.insert(tid(99999));
+        assert_eq!(taxon_cache.len(), 1);
+        assert!(taxon_cache.contains(tid(12345)));
+        assert!(!taxon_cache.contains(tid(99999)));
+        assert_eq!(taxon_cache.find_by_name("Streptomyces").len(), 1);
+
+        let replaced = taxon_cache.insert(entry(tid(23456), "Streptomyces examplis"));
+        assert_eq!(replaced.unwrap().name, "Streptomyces");
+        assert!(taxon_cache.find_by_name("Streptomyces").is_empty());
+        taxon_cache
+            .mappings_mut()
+            .get_mut(&tid(23456))
+            .unwrap()
+            .name = "Streptomyces".into();
+        assert_eq!(taxon_cache.find_by_name("Streptomyces").len(), 1);
+
+        taxon_cache.insert(entry(tid(99999), "Resurrected"));
+        assert!(taxon_cache.contains(tid(99999)));
+        assert!(taxon_cache.deleted_ids.is_empty());
+
+        assert_eq!(taxon_cache.remove(tid(12345)).unwrap().tax_id, 23456);
+        assert!(taxon_cache.deprecated_ids.is_empty());
+        assert!(!taxon_cache.contains(tid(23456)));
+        assert!(taxon_cache.remove(tid(23456)).is_none());
+        assert_eq!(taxon_cache.len(), 1);
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut taxon_cache = TaxonCache::new();
+        for (tax_id, name, species, rank) in [
+            (tid(1902), "Streptomyces coelicolor", "coelicolor", None),
+            (
+                tid(100226),
+                "Streptomyces coelicolor A3(2)",
+                "coelicolor",
+                None,
+            ),
+            (tid(1883), "Streptomyces", "Unknown", None),
+            (tid(2), "Bacteria", "Unknown", Some("domain")),
+        ] {
+            taxon_cache.insert(NcbiTaxEntry {
+                name: name.to_string(),
+                species: species.into(),
+                genus: "Streptomyces".into(),
+                superkingdom: "Bacteria".into(),
+                rank: rank.map(str::to_string),
+                ..NcbiTaxEntry::new(tax_id)
+            });
+        }
+
+        let sorted: Vec<TaxId> = taxon_cache
+            .iter_sorted_by_taxid()
+            .map(|entry| entry.tax_id)
+            .collect();
+        assert_eq!(sorted, vec![2, 1883, 1902, 100226]);
+        let by_rank = |rank| -> Vec<TaxId> {
+            taxon_cache
+                .iter_by_rank(rank)
+                .map(|entry| entry.tax_id)
+                .collect()
+        };
+        assert_eq!(by_rank(Rank::Species), vec![1902]);
+        assert_eq!(by_rank(Rank::Genus), vec![1883]);
+        assert_eq!(by_rank(Rank::Superkingdom), vec![2]);
+        assert!(by_rank(Rank::Family).is_empty());
+
+        assert_eq!((&taxon_cache).into_iter().count(), 4);
+        let mut owned: Vec<TaxId> = taxon_cache.into_iter().map(|entry| entry.tax_id).collect();
+        owned.sort();
+        assert_eq!(owned, sorted);
+    }
+
+    #[test]
+    fn test_insert_custom() {
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache.mappings.insert(
+            tid(1883),
+            NcbiTaxEntry {
+                ..NcbiTaxEntry::new(tid(1883))
+            },
+        );
+        taxon_cache.deprecated_ids.insert(tid(12345), tid(1883));
+        taxon_cache
+            .overrides
+            .load_tsv("9000001\tgenus\tKitasatospora\n".as_bytes())
+            .unwrap();
+        let custom = |tax_id| NcbiTaxEntry {
+            name: "Streptomyces sp. NBC01".to_string(),
+            genus: "Streptomyces".into(),
+            ..NcbiTaxEntry::new(tax_id)
+        };
+
+        assert_eq!(
+            taxon_cache.insert_custom([custom(tid(9000001))]).unwrap(),
+            1
+        );
+        let entry = taxon_cache.lookup(tid(9000001)).unwrap();
+        assert_eq!(entry.source, EntrySource::Custom);
+        assert_eq!(&*entry.genus, "Kitasatospora");
+        assert_eq!(
+            taxon_cache.find_by_name("Streptomyces sp. NBC01")[0].tax_id,
+            9000001
+        );
+        assert_eq!(
+            taxon_cache.insert_custom([custom(tid(9000001))]).unwrap(),
+            1
+        );
+
+        for tax_id in [tid(1883), tid(12345)] {
+            let err = taxon_cache
+                .insert_custom([custom(tid(9000002)), custom(tax_id)])
+                .unwrap_err();
+            assert!(matches!(err, ASDBTaxonError::InvalidTaxId(_)));
+        }
+        assert!(taxon_cache.lookup(tid(9000002)).is_err());
+    }
+
+    #[test]
+    fn test_merge_chains() {
+        let mut taxids = HashSet::from([tid(1), tid(10)]);
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache
+            .add_merged_ids("2\t|\t3\t|\n1\t|\t2\t|\n".as_bytes(), &mut taxids)
+            .unwrap();
+        assert_eq!(taxids, HashSet::from([tid(3), tid(10)]));
+        assert_eq!(
+            taxon_cache.deprecated_ids,
+            HashMap::from([(tid(1), tid(3))])
+        );
+
+        let err = TaxonCache::new()
+            .add_merged_ids_with_depth(
+                "1\t|\t2\t|\n2\t|\t3\t|\n".as_bytes(),
+                &mut HashSet::from([tid(1)]),
+                1,
+            )
+            .unwrap_err();
+        assert!(matches!(err, ASDBTaxonError::InvalidFormat(_)));
+    }
+
+    #[test]
+    fn test_merge_cycles() {
+        let err = TaxonCache::new()
+            .add_merged_ids("5\t|\t5\t|\n".as_bytes(), &mut HashSet::from([tid(5)]))
+            .unwrap_err();
+        assert!(matches!(err, ASDBTaxonError::MergeCycle(ref ids) if *ids == vec![5, 5]));
+
+        let err = TaxonCache::new()
+            .add_merged_ids(
+                "1\t|\t2\t|\n2\t|\t3\t|\n3\t|\t2\t|\n".as_bytes(),
+                &mut HashSet::from([tid(1)]),
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Merged IDs form a cycle: 2 -> 3 -> 2");
+    }
+
+    #[test]
+    fn test_subset() {
+        let entry = |tax_id, parent_id, name: &str| NcbiTaxEntry {
+            name: name.to_string(),
+            parent_id,
+            ..NcbiTaxEntry::new(tax_id)
+        };
+        let mut taxon_cache = TaxonCache::new();
+        for entry in [
+            entry(tid(1883), Some(tid(2062)), "Streptomyces"),
+            entry(tid(1902), Some(tid(1883)), "Streptomyces coelicolor"),
+            entry(tid(1911), Some(tid(1883)), "Streptomyces griseus"),
+            entry(tid(2062), None, "Streptomycetaceae"),
+        ] {
+            taxon_cache.mappings.insert(entry.tax_id, entry);
+        }
+        taxon_cache.deprecated_ids.insert(tid(100), tid(1902));
+        taxon_cache.deprecated_ids.insert(tid(200), tid(1911));
+        taxon_cache.deleted_ids.insert(tid(300));
+
+        let subset = taxon_cache.subset(&[tid(100), tid(300), tid(400)]);
+        let mut kept: Vec<TaxId> = subset.mappings.keys().copied().collect();
+        kept.sort();
+        assert_eq!(kept, vec![1883, 1902, 2062]);
+        assert!(!subset.mappings[&tid(1902)].ancestor_only);
+        assert!(subset.mappings[&tid(1883)].ancestor_only);
+        assert_eq!(
+            subset.deprecated_ids,
+            HashMap::from([(tid(100), tid(1902))])
+        );
+        assert_eq!(subset.deleted_ids, HashSet::from([tid(300)]));
+
+        let subset = taxon_cache.subset_by(|entry| entry.name.starts_with("Streptomyces "));
+        assert_eq!(subset.mappings.len(), 4);
+        assert!(!subset.mappings[&tid(1911)].ancestor_only);
+        assert!(subset.deleted_ids.is_empty());
+    }
+
+    #[test]
+    fn test_query_by_rank() {
+        let entry = |tax_id, genus: &str, phylum: &str| NcbiTaxEntry {
+            genus: genus.into(),
+            phylum: phylum.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        };
+        let mut taxon_cache = TaxonCache::new();
+        for entry in [
+            entry(tid(1911), "Streptomyces", "Actinomycetota"),
+            entry(tid(1902), "Streptomyces", "Actinomycetota"),
+            entry(tid(1866), "Actinoplanes", "Actinomycetota"),
+            entry(tid(5062), "Aspergillus", "Ascomycota"),
+        ] {
+            taxon_cache.mappings.insert(entry.tax_id, entry);
+        }
+
+        let taxids = |entries: Vec<&NcbiTaxEntry>| -> Vec<TaxId> {
+            entries.iter().map(|entry| entry.tax_id).collect()
+        };
+        assert_eq!(
+            taxids(taxon_cache.query_by_rank(Rank::Genus, "Streptomyces")),
+            vec![1902, 1911]
+        );
+        assert_eq!(
+            taxids(taxon_cache.query_by_rank(Rank::Phylum, "Actinomycetota")),
+            vec![1866, 1902, 1911]
+        );
+        assert!(taxon_cache
+            .query_by_rank(Rank::Family, "Streptomyces")
+            .is_empty());
+        assert!("strain".parse::<Rank>().is_err());
+    }
+
+    #[test]
+    fn test_lca() {
+        let entry = |tax_id, name: &str, genus: &str, parent_id| NcbiTaxEntry {
+            name: name.to_string(),
+            genus: genus.into(),
+            family: "Streptomycetaceae".into(),
+            kingdom: "Unknown".into(),
+            superkingdom: "Bacteria".into(),
+            parent_id,
+            ..NcbiTaxEntry::new(tax_id)
+        };
+        let mut taxon_cache = TaxonCache::new();
+        for entry in [
+            entry(tid(2062), "Streptomycetaceae", "Unknown", Some(tid(85011))),
+            entry(tid(1883), "Streptomyces", "Streptomyces", Some(tid(2062))),
+            entry(
+                tid(1902),
+                "Streptomyces coelicolor",
+                "Streptomyces",
+                Some(tid(1883)),
+            ),
+            entry(
+                tid(1911),
+                "Streptomyces griseus",
+                "Streptomyces",
+                Some(tid(1883)),
+            ),
+            entry(
+                tid(2063),
+                "Kitasatospora setae",
+                "Kitasatospora",
+                Some(tid(2062)),
+            ),
+        ] {
+            taxon_cache.mappings.insert(entry.tax_id, entry);
+        }
+        taxon_cache.deprecated_ids.insert(tid(100), tid(1911));
+
+        assert_eq!(
+            taxon_cache.lca(&[tid(1902), tid(100)]).unwrap().tax_id(),
+            Some(tid(1883))
+        );
+        assert_eq!(
+            taxon_cache.lca(&[tid(1902), tid(1883)]).unwrap().tax_id(),
+            Some(tid(1883))
+        );
+        assert_eq!(
+            taxon_cache
+                .lca(&[tid(1902), tid(1911), tid(2063)])
+                .unwrap()
+                .tax_id(),
+            Some(tid(2062))
+        );
+        assert_eq!(
+            taxon_cache.lca(&[tid(1902)]).unwrap().tax_id(),
+            Some(tid(1902))
+        );
+        assert!(taxon_cache.lca(&[tid(1902), tid(42)]).is_err());
+        assert!(taxon_cache.lca(&[]).is_err());
+
+        // without parent links, the lineage decides
+        for entry in taxon_cache.mappings.values_mut() {
+            entry.parent_id = None;
+        }
+        assert_eq!(
+            taxon_cache.lca(&[tid(1902), tid(1911)]).unwrap().tax_id(),
+            Some(tid(1883))
+        );
+        let family = taxon_cache.lca(&[tid(1902), tid(2063)]).unwrap();
+        assert_eq!(family.tax_id(), Some(tid(2062)));
+        taxon_cache.mappings.remove(&tid(2062));
+        let family = taxon_cache.lca(&[tid(1902), tid(2063)]).unwrap();
+        assert_eq!(
+            (family.tax_id(), family.entry().name.as_str()),
+            (None, "Streptomycetaceae")
+        );
+        assert_eq!(&*family.entry().genus, "Unknown");
+        assert_eq!(
+            serde_json::to_value(&family).unwrap()["tax_id"],
+            serde_json::Value::Null
+        );
+
+        let mut aspergillus = entry(tid(5062), "Aspergillus oryzae", "Aspergillus", None);
+        aspergillus.superkingdom = "Eukaryota".into();
+        aspergillus.family = "Aspergillaceae".into();
+        taxon_cache.mappings.insert(tid(5062), aspergillus);
+        assert_eq!(
+            taxon_cache.lca(&[tid(1902), tid(5062)]).unwrap().tax_id(),
+            Some(tid(1))
+        );
+    }
+
+    #[test]
+    fn test_deleted_ids() {
+        let mut taxids: HashSet<TaxId> = HashSet::new();
+        taxids.insert(tid(12345));
+        taxids.insert(tid(34567));
+
+        let deleted_ids = "34567\t|\n".as_bytes();
+        let merged_ids = "".as_bytes();
+        let taxdump = "12345  |       Streptomyces examplis NBC12345   |       Streptomyces examplis |       Streptomyces    |       Streptomycetaceae       |       Streptomycetales        |       Actinomycetia   |       Actinobacteria  |               |      Bacteria |".as_bytes();
+
+        let mut taxon_cache = TaxonCache::new();
+        taxon_cache
+            .add_deleted_ids(deleted_ids, &mut taxids)
+            .unwrap();
+        taxon_cache
+            .initialise(taxdump, merged_ids, &mut taxids)
+            .unwrap();
+
+        assert!(!taxids.contains(&tid(34567)));
+        assert!(taxon_cache.lookup(tid(12345)).is_ok());
+        assert!(matches!(
+            taxon_cache.lookup(tid(34567)),
+            Err(ASDBTaxonError::Deleted(tax_id)) if tax_id == 34567
+        ));
+        assert!(matches!(
+            taxon_cache.lookup(tid(56789)),
+            Err(ASDBTaxonError::NotFound(tax_id)) if tax_id == 56789
+        ));
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing lists of entries for people and scripts
+//!
+//! An [EntryWriter] takes entries one at a time, so listings can be streamed from caches too big
+//! to load. Only [ListFormat::Table] holds on to the rows, to align the columns at the end.
+
+use std::cmp::Ordering;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{LineageStyle, NcbiTaxEntry, Rank};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// `taxid: name`, other fields tab-separated after it
+    #[default]
+    Text,
+    /// Tab-separated values with a header line
+    Tsv,
+    /// An array with one object per entry
+    Json,
+    /// Columns aligned with spaces, with a header line
+    Table,
+}
+
+impl FromStr for ListFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "tsv" => Ok(ListFormat::Tsv),
+            "json" => Ok(ListFormat::Json),
+            "table" => Ok(ListFormat::Table),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// A column of a listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    TaxId,
+    Name,
+    Strain,
+    Rank(Rank),
+    /// The lineage as a single string
+    Lineage(LineageStyle),
+}
+
+impl Field {
+    /// Parse a comma-separated list of fields like `tax_id,name,genus`
+    pub fn parse_list(spec: &str) -> Result<Vec<Field>, ASDBTaxonError> {
+        spec.split(',').map(str::parse).collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::TaxId => "tax_id",
+            Field::Name => "name",
+            Field::Strain => "strain",
+            Field::Rank(rank) => rank.as_str(),
+            Field::Lineage(_) => "lineage",
+        }
+    }
+
+    pub fn value(&self, entry: &NcbiTaxEntry) -> String {
+        match self {
+            Field::TaxId => entry.tax_id.to_string(),
+            Field::Name => entry.name.clone(),
+            Field::Strain => entry.strain.clone().unwrap_or_default(),
+            Field::Rank(rank) => entry.rank_value(*rank).to_string(),
+            Field::Lineage(style) => entry.lineage_string(*style),
+        }
+    }
+
+    fn json_value(&self, entry: &NcbiTaxEntry) -> serde_json::Value {
+        match self {
+            Field::TaxId => entry.tax_id.get().into(),
+            Field::Strain => entry.strain.clone().into(),
+            _ => self.value(entry).into(),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "tax_id" | "taxid" => Ok(Field::TaxId),
+            "name" => Ok(Field::Name),
+            "strain" => Ok(Field::Strain),
+            "lineage" => Ok(Field::Lineage(LineageStyle::default())),
+            rank => rank
+                .parse()
+                .map(Field::Rank)
+                .map_err(|_| ASDBTaxonError::InvalidFormat(format!("unknown field {}", rank))),
+        }
+    }
+}
+
+/// Order of a sorted listing, ties going to the lower taxid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    TaxId,
+    Name,
+    Genus,
+}
+
+impl SortKey {
+    pub fn compare(&self, a: &NcbiTaxEntry, b: &NcbiTaxEntry) -> Ordering {
+        let by_key = match self {
+            SortKey::TaxId => Ordering::Equal,
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Genus => a.genus.cmp(&b.genus).then_with(|| a.name.cmp(&b.name)),
+        };
+        by_key.then(a.tax_id.cmp(&b.tax_id))
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "taxid" | "tax_id" => Ok(SortKey::TaxId),
+            "name" => Ok(SortKey::Name),
+            "genus" => Ok(SortKey::Genus),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Settings of a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOptions {
+    pub format: ListFormat,
+    pub fields: Vec<Field>,
+    /// Number of entries to skip
+    pub offset: usize,
+    /// Maximum number of entries to list
+    pub limit: Option<usize>,
+}
+
+impl Default for ListOptions {
+    fn default() -> ListOptions {
+        ListOptions {
+            format: ListFormat::default(),
+            fields: vec![Field::TaxId, Field::Name],
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+/// Writes entries in a [ListFormat], skipping and limiting them as configured
+pub struct EntryWriter<W: Write> {
+    options: ListOptions,
+    output: W,
+    seen: usize,
+    written: usize,
+    /// Rows of a [ListFormat::Table], header first
+    rows: Vec<Vec<String>>,
+}
+
+impl<W: Write> EntryWriter<W> {
+    pub fn new(options: ListOptions, output: W) -> EntryWriter<W> {
+        EntryWriter {
+            options,
+            output,
+            seen: 0,
+            written: 0,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Whether the limit is reached, so later entries would be skipped anyway
+    pub fn is_full(&self) -> bool {
+        self.options
+            .limit
+            .is_some_and(|limit| self.written >= limit)
+    }
+
+    /// Write `entry`, unless it falls before the offset or after the limit
+    pub fn write(&mut self, entry: &NcbiTaxEntry) -> Result<(), ASDBTaxonError> {
+        self.seen += 1;
+        if self.seen <= self.options.offset || self.is_full() {
+            return Ok(());
+        }
+        if self.written == 0 {
+            self.start()?;
+        }
+        let fields = &self.options.fields;
+        match self.options.format {
+            ListFormat::Text => {
+                let mut values = fields.iter().map(|field| field.value(entry));
+                let first = values.next().unwrap_or_default();
+                match values.next() {
+                    Some(second) => write!(self.output, "{}: {}", first, second)?,
+                    None => write!(self.output, "{}", first)?,
+                }
+                for value in values {
+                    write!(self.output, "\t{}", value)?;
+                }
+                writeln!(self.output)?;
+            }
+            ListFormat::Tsv => {
+                let values: Vec<String> = fields.iter().map(|field| field.value(entry)).collect();
+                writeln!(self.output, "{}", values.join("\t"))?;
+            }
+            ListFormat::Json => {
+                // written by hand to keep the fields in the requested order
+                let mut members = Vec::with_capacity(fields.len());
+                for field in fields {
+                    members.push(format!(
+                        "{}:{}",
+                        serde_json::to_string(field.name())?,
+                        field.json_value(entry)
+                    ));
+                }
+                if self.written > 0 {
+                    writeln!(self.output, ",")?;
+                }
+                write!(self.output, "{{{}}}", members.join(","))?;
+            }
+            ListFormat::Table => self
+                .rows
+                .push(fields.iter().map(|field| field.value(entry)).collect()),
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), ASDBTaxonError> {
+        let header: Vec<String> = self
+            .options
+            .fields
+            .iter()
+            .map(|field| field.name().to_string())
+            .collect();
+        match self.options.format {
+            ListFormat::Text => (),
+            ListFormat::Tsv => writeln!(self.output, "{}", header.join("\t"))?,
+            ListFormat::Json => writeln!(self.output, "[")?,
+            ListFormat::Table => self.rows.push(header),
+        }
+        Ok(())
+    }
+
+    /// Finish the listing, returning the number of entries written
+    pub fn finish(mut self) -> Result<usize, ASDBTaxonError> {
+        if self.written == 0 {
+            self.start()?;
+        }
+        match self.options.format {
+            ListFormat::Text | ListFormat::Tsv => (),
+            ListFormat::Json if self.written == 0 => writeln!(self.output, "]")?,
+            ListFormat::Json => writeln!(self.output, "\n]")?,
+            ListFormat::Table => {
+                let mut widths = vec![0; self.options.fields.len()];
+                for row in &self.rows {
+                    for (width, value) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(value.chars().count());
+                    }
+                }
+                for row in &self.rows {
+                    let mut line = String::new();
+                    for (value, width) in row.iter().zip(&widths) {
+                        line.push_str(&format!("{:<width$}  ", value, width = width));
+                    }
+                    writeln!(self.output, "{}", line.trim_end())?;
+                }
+            }
+        }
+        self.output.flush()?;
+        Ok(self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::{tid, TaxId};
+
+    fn entries() -> Vec<NcbiTaxEntry> {
+        [
+            (tid(23456), "Streptomyces examplis", "Streptomyces"),
+            (tid(5062), "Aspergillus oryzae", "Aspergillus"),
+            (tid(1883), "Streptomyces", "Streptomyces"),
+        ]
+        .into_iter()
+        .map(|(tax_id, name, genus)| NcbiTaxEntry {
+            name: name.to_string(),
+            genus: genus.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        })
+        .collect()
+    }
+
+    fn list(options: ListOptions) -> String {
+        let mut output = Vec::new();
+        let mut writer = EntryWriter::new(options, &mut output);
+        for entry in entries() {
+            writer.write(&entry).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_entry_writer() {
+        assert_eq!(
+            list(ListOptions::default()),
+            "23456: Streptomyces examplis\n5062: Aspergillus oryzae\n1883: Streptomyces\n"
+        );
+
+        let fields = Field::parse_list("taxid,genus").unwrap();
+        let options = ListOptions {
+            format: ListFormat::Tsv,
+            fields: fields.clone(),
+            offset: 1,
+            limit: Some(1),
+        };
+        assert_eq!(list(options), "tax_id\tgenus\n5062\tAspergillus\n");
+
+        let options = ListOptions {
+            format: ListFormat::Json,
+            fields: fields.clone(),
+            offset: 1,
+            ..Default::default()
+        };
+        let json: serde_json::Value = serde_json::from_str(&list(options)).unwrap();
+        assert_eq!(json[1]["tax_id"], 1883);
+        assert_eq!(json[0]["genus"], "Aspergillus");
+
+        let options = ListOptions {
+            format: ListFormat::Table,
+            fields,
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(options),
+            "tax_id  genus\n23456   Streptomyces\n5062    Aspergillus\n"
+        );
+
+        let options = ListOptions {
+            format: ListFormat::Json,
+            offset: 10,
+            ..Default::default()
+        };
+        assert_eq!(list(options), "[\n]\n");
+        assert!(Field::parse_list("tax_id,colour").is_err());
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let mut entries = entries();
+        entries.sort_by(|a, b| SortKey::Genus.compare(a, b));
+        let order: Vec<TaxId> = entries.iter().map(|entry| entry.tax_id).collect();
+        assert_eq!(order, vec![5062, 1883, 23456]);
+        entries.sort_by(|a, b| SortKey::TaxId.compare(a, b));
+        assert_eq!(entries[0].tax_id, 1883);
+    }
+}
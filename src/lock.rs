@@ -0,0 +1,89 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Advisory locks keeping concurrent jobs from overwriting each other's changes to a cache
+//!
+//! The lock is taken on a `<cache>.lock` file next to the cache rather than the cache itself,
+//! which is replaced on every save. The lock file is left in place, removing it would let another
+//! process lock a new file while the old one is still held.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use crate::errors::ASDBTaxonError;
+
+/// Where the lock for `cache_file` is kept, next to it
+pub fn lock_path(cache_file: &Path) -> PathBuf {
+    let mut path = cache_file.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// An exclusive lock on a cache, released when dropped
+#[derive(Debug)]
+pub struct CacheLock {
+    _file: File,
+}
+
+impl CacheLock {
+    /// Lock `cache_file`, waiting for other holders to release it if `wait` is set and failing
+    /// with [ASDBTaxonError::CacheLocked] otherwise
+    pub fn acquire(cache_file: &Path, wait: bool) -> Result<CacheLock, ASDBTaxonError> {
+        let path = lock_path(cache_file);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        if wait {
+            file.lock()?;
+        } else {
+            match file.try_lock() {
+                Ok(()) => (),
+                Err(TryLockError::WouldBlock) => {
+                    return Err(ASDBTaxonError::CacheLocked(path.display().to_string()))
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+        Ok(CacheLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_cache_lock() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let cache_file = dir.join("cache.json");
+
+        let lock = CacheLock::acquire(&cache_file, false).unwrap();
+        assert!(lock_path(&cache_file).exists());
+        assert!(matches!(
+            CacheLock::acquire(&cache_file, false),
+            Err(ASDBTaxonError::CacheLocked(_))
+        ));
+
+        let waiting = std::thread::spawn({
+            let cache_file = cache_file.clone();
+            move || CacheLock::acquire(&cache_file, true).is_ok()
+        });
+        drop(lock);
+        assert!(waiting.join().unwrap());
+    }
+}
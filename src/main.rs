@@ -12,15 +12,84 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use clap::{Args, Parser, Subcommand};
+use clap::builder::Resettable;
+use clap::{Arg, ArgAction, Args, Command, CommandFactory, FromArgMatches, Id, Parser, Subcommand};
+use serde::Serialize;
+use serde_json::json;
 
+use asdb_taxa::biosample::BioSampleMap;
+use asdb_taxa::builder::TaxonCacheBuilder;
+use asdb_taxa::compression::{self, Compression};
+use asdb_taxa::config::Config;
+use asdb_taxa::custom;
+use asdb_taxa::deadline;
+use asdb_taxa::delta::Delta;
+use asdb_taxa::errors::ASDBTaxonError;
+use asdb_taxa::export::{ColumnLimits, ExportFormat, ExportOptions, Truncation};
+use asdb_taxa::extract::Rule;
+use asdb_taxa::filter::Expression;
+use asdb_taxa::format::CacheFormat;
+use asdb_taxa::index::IndexedCache;
+use asdb_taxa::layout::ColumnMapping;
+use asdb_taxa::listing::{EntryWriter, Field, ListFormat, ListOptions, SortKey};
+use asdb_taxa::lock::CacheLock;
+use asdb_taxa::manifest::{self, ScanManifest};
+use asdb_taxa::metadata::format_timestamp;
+use asdb_taxa::nomenclature::Canonicalizer;
+use asdb_taxa::overrides::Overrides;
+use asdb_taxa::progress::{Eta, Stage};
+use asdb_taxa::provenance::Provenance;
+use asdb_taxa::remap::{RemapFormat, RemapTarget};
+use asdb_taxa::remote;
+use asdb_taxa::resolve::ResolveFormat;
+use asdb_taxa::scan::{self, ScanOptions, ScanReport};
+use asdb_taxa::search::NameQuery;
+use asdb_taxa::serve::Server;
+use asdb_taxa::stats::GroupCounts;
+use asdb_taxa::taxa::{LineageStyle, NcbiTaxEntry, Rank, TaxId};
+use asdb_taxa::taxdump::TaxdumpDir;
+use asdb_taxa::taxonomy::Taxonomy;
+use asdb_taxa::telemetry::Telemetry;
+use asdb_taxa::tree::TreeFormat;
+use asdb_taxa::view::ViewFilter;
 use asdb_taxa::TaxonCache;
 
 #[derive(Debug, Parser)]
 #[command(name = "asdb-taxa", about = "Create a taxon cache for ASDB")]
 struct Cli {
+    #[arg(short, long, global = true, action = ArgAction::Count, help = "Log more details, repeat for even more")]
+    verbose: u8,
+
+    #[arg(short, long, global = true, help = "Only log errors")]
+    quiet: bool,
+
+    #[arg(long, global = true, help = "Log JSON lines instead of plain text")]
+    log_json: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Print the results as a single JSON document"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "DELTA",
+        help = "Delta cache to layer on top of the cache used, can be given multiple times, in order"
+    )]
+    delta: Vec<String>,
+
     #[command(subcommand)]
     cmd: Commands,
 }
@@ -33,100 +102,2920 @@ enum Commands {
     #[command(name = "add", about = "Add more entries to an existing cache")]
     Add(AddOpts),
 
+    #[command(
+        name = "add-custom",
+        about = "Add user-specified entries for taxa the NCBI doesn't know"
+    )]
+    AddCustom(AddCustomOpts),
+
+    #[command(
+        name = "update",
+        about = "Refresh all cached entries against newer dumps, printing what changed"
+    )]
+    Update(UpdateOpts),
+
+    #[command(
+        name = "history",
+        about = "Show the changes of a taxid recorded by cache updates"
+    )]
+    History(HistoryOpts),
+
     #[command(name = "list", about = "List current cache entries")]
     List(ListOpts),
+
+    #[command(name = "info", about = "Show how and from what a cache was built")]
+    Info(InfoOpts),
+
+    #[command(name = "lookup", about = "Look up a single taxid in the cache")]
+    Lookup(LookupOpts),
+
+    #[command(name = "descendants", about = "List all cached entries below a taxid")]
+    Descendants(DescendantsOpts),
+
+    #[command(
+        name = "lca",
+        about = "Find the lowest common ancestor of taxids in the cache"
+    )]
+    Lca(LcaOpts),
+
+    #[command(
+        name = "search",
+        about = "Find the taxids of an organism name in the cache"
+    )]
+    Search(SearchOpts),
+
+    #[command(
+        name = "resolve",
+        alias = "shell-pipe",
+        about = "Resolve taxids read from stdin, one per line, writing one lineage per line"
+    )]
+    Resolve(ResolveOpts),
+
+    #[command(
+        name = "annotate",
+        about = "Append lineage columns to a TSV table, looking up the taxid in one of its columns"
+    )]
+    Annotate(AnnotateOpts),
+
+    #[command(
+        name = "missing",
+        about = "List datadir taxids that can't be found in the cache"
+    )]
+    Missing(MissingOpts),
+
+    #[command(
+        name = "taxids",
+        about = "Print the taxids referenced in the datadir, one per line, without any dumps"
+    )]
+    Taxids(TaxidsOpts),
+
+    #[command(
+        name = "count",
+        about = "Count the cached taxa per lineage group, and optionally the datadir records referencing them"
+    )]
+    Count(CountOpts),
+
+    #[command(
+        name = "top",
+        about = "Rank the taxa at a rank by how many datadir records reference them"
+    )]
+    Top(TopOpts),
+
+    #[command(
+        name = "verify",
+        about = "Check that the datadir taxids are in the cache, and the cache matches the database"
+    )]
+    Verify(VerifyOpts),
+
+    #[command(
+        name = "validate",
+        about = "Check the internal consistency of a cache, and optionally that it matches the dumps"
+    )]
+    Validate(ValidateOpts),
+
+    #[command(
+        name = "gc",
+        about = "Drop merged and deleted taxids the cache no longer needs"
+    )]
+    Gc(GcOpts),
+
+    #[command(
+        name = "export",
+        about = "Export the cache for loading into a database"
+    )]
+    Export(ExportOpts),
+
+    #[command(
+        name = "tree",
+        about = "Show the taxonomy tree of the cached taxa, or write it as Newick or GraphViz DOT"
+    )]
+    Tree(TreeOpts),
+
+    #[command(
+        name = "remap",
+        about = "Write the merged taxids with their current ones, for updating database tables"
+    )]
+    Remap(RemapOpts),
+
+    #[command(
+        name = "subset",
+        about = "Write a smaller cache with only the entries for a list of taxids"
+    )]
+    Subset(SubsetOpts),
+
+    #[command(
+        name = "export-delta",
+        about = "Write a delta cache with only what changed since a baseline cache"
+    )]
+    ExportDelta(ExportDeltaOpts),
+
+    #[command(
+        name = "convert",
+        about = "Convert a cache to a different file format, e.g. compressed"
+    )]
+    Convert(ConvertOpts),
+
+    #[command(name = "serve", about = "Serve the cache as a REST API via HTTP")]
+    Serve(ServeOpts),
+
+    #[cfg(feature = "network")]
+    #[command(name = "fetch", about = "Download and extract the NCBI taxdump files")]
+    Fetch(FetchOpts),
+
+    #[cfg(feature = "network")]
+    #[command(
+        name = "fetch-cache",
+        about = "Download, verify and install a published cache instead of building one"
+    )]
+    FetchCache(FetchCacheOpts),
 }
 
 #[derive(Debug, Args)]
 struct InitOpts {
-    #[arg(short, long, help = "Cache file to use")]
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
     cache: String,
 
-    #[arg(short, long, help = "ASDB json data directory to determine needed taxids")]
-    datadir: String,
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
 
-    #[arg(short, long, help = "TaxonDB merged ID dump file to load from")]
-    mergeddump: String,
+    #[arg(
+        long,
+        help = "Compress the cache (none, gzip, zstd), by default judging by the file extension"
+    )]
+    compression: Option<String>,
 
-    #[arg(short, long, help = "TaxonDB ranked lineage dump file to load from")]
-    taxdump: String,
+    #[arg(
+        long,
+        help = "Layout of the cache (json, pretty, ndjson, indexed), by default compact json"
+    )]
+    output_format: Option<String>,
+
+    #[command(flatten)]
+    build: BuildOpts,
 }
 
 #[derive(Debug, Args)]
 struct AddOpts {
-    #[arg(short, long, help = "Cache file to use")]
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
     cache: String,
 
-    #[arg(short, long, help = "ASDB json data directory to determine needed taxids")]
-    datadir: String,
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+
+    #[arg(
+        long,
+        help = "Compress the cache (none, gzip, zstd), by default judging by the file extension"
+    )]
+    compression: Option<String>,
+
+    #[arg(
+        long,
+        help = "Layout of the cache (json, pretty, ndjson, indexed), by default keeping the current one"
+    )]
+    output_format: Option<String>,
 
-    #[arg(short, long, help = "TaxonDB merged ID dump file to load from")]
-    mergeddump: String,
+    #[arg(
+        long,
+        help = "Scan all data files again, not only the ones new or changed since the last build"
+    )]
+    full_rescan: bool,
 
-    #[arg(short, long, help = "TaxonDB ranked lineage dump file to load from")]
-    taxdump: String,
+    #[command(flatten)]
+    build: BuildOpts,
 }
 
 #[derive(Debug, Args)]
-struct ListOpts {
-    #[arg(short, long, help = "Cache file to use")]
+struct AddCustomOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+
+    #[arg(
+        short,
+        long,
+        help = "TSV file of custom taxa, with a header naming the columns (taxid, name, ranks, strain)"
+    )]
+    file: String,
+}
+
+#[derive(Debug, Args)]
+struct UpdateOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_TAXDUMP",
+        help = "TaxonDB ranked lineage dump file or URL to load from"
+    )]
+    taxdump: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_MERGEDDUMP",
+        help = "TaxonDB merged ID dump file or URL to load from"
+    )]
+    mergeddump: Option<String>,
+
+    #[arg(
+        long,
+        env = "ASDB_TAXA_TAXDUMP_DIR",
+        help = "Directory of an extracted taxdump to find the dumps not given above in [default: $TAXONKIT_DB or ~/.taxonkit, if present]"
+    )]
+    taxdump_dir: Option<String>,
+
+    #[arg(
+        long,
+        env = "ASDB_TAXA_DELNODES",
+        help = "TaxonDB deleted nodes dump file or URL to load from"
+    )]
+    delnodes: Option<String>,
+
+    #[arg(
+        long,
+        help = "TaxonDB nodes dump file, to also update the ancestors and parent links"
+    )]
+    nodes: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = asdb_taxa::MAX_MERGE_DEPTH,
+        help = "Fail on chains of more merged IDs than this"
+    )]
+    max_merge_depth: usize,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = asdb_taxa::parallel::default_jobs(),
+        help = "Number of threads parsing the taxdump"
+    )]
+    jobs: usize,
+
+    #[arg(
+        long,
+        conflicts_with = "columns_file",
+        help = "Columns of the taxdump, e.g. 'tax_id,name,-,genus,superkingdom', instead of the rankedlineage.dmp ones"
+    )]
+    columns: Option<String>,
+
+    #[arg(long, help = "File listing the columns of the taxdump, see --columns")]
+    columns_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Tag of the taxonomy release updated to, e.g. '2023-06' [default: the date of the taxdump]"
+    )]
+    release: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct HistoryOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
     cache: String,
+
+    #[arg(short, long, help = "Taxid to show the changes of")]
+    taxid: TaxId,
+}
+
+#[derive(Debug, Args)]
+struct BuildOpts {
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_DATADIR",
+        help = "ASDB json data directory or tarball to determine needed taxids, can be given multiple times"
+    )]
+    #[cfg_attr(
+        feature = "postgres",
+        arg(required_unless_present_any = ["taxids_file", "taxids_from_stdin", "taxid", "taxids_from_db"])
+    )]
+    #[cfg_attr(
+        not(feature = "postgres"),
+        arg(required_unless_present_any = ["taxids_file", "taxids_from_stdin", "taxid"])
+    )]
+    datadir: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = asdb_taxa::scan::MAX_SCAN_DEPTH,
+        help = "Levels of subdirectories of the datadir to scan"
+    )]
+    max_depth: usize,
+
+    #[arg(
+        long,
+        help = "Only scan data files matching this glob, e.g. 'GCF_*/*.json', can be given multiple times"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Skip data files and directories matching this glob, can be given multiple times"
+    )]
+    exclude: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+
+    #[arg(long, help = "File listing needed taxids, one per line, '-' for stdin")]
+    taxids_file: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "taxids_file",
+        help = "Read needed taxids from stdin, one per line"
+    )]
+    taxids_from_stdin: bool,
+
+    #[arg(long, help = "Needed taxid, can be given multiple times")]
+    taxid: Vec<TaxId>,
+
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        help = "PostgreSQL connection string of an antiSMASH database to take the needed taxids from"
+    )]
+    taxids_from_db: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_MERGEDDUMP",
+        help = "TaxonDB merged ID dump file or URL to load from"
+    )]
+    mergeddump: Option<String>,
+
+    #[arg(
+        long,
+        env = "ASDB_TAXA_TAXDUMP_DIR",
+        help = "Directory of an extracted taxdump to find the dumps not given above in [default: $TAXONKIT_DB or ~/.taxonkit, if present]"
+    )]
+    taxdump_dir: Option<String>,
+
+    #[arg(
+        long,
+        env = "ASDB_TAXA_DELNODES",
+        help = "TaxonDB deleted nodes dump file or URL to load from"
+    )]
+    delnodes: Option<String>,
+
+    #[arg(
+        long,
+        help = "TaxonDB nodes dump file, to also add all ancestors of the needed taxids"
+    )]
+    nodes: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = asdb_taxa::MAX_MERGE_DEPTH,
+        help = "Fail on chains of more merged IDs than this"
+    )]
+    max_merge_depth: usize,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = asdb_taxa::parallel::default_jobs(),
+        help = "Number of threads parsing the taxdump"
+    )]
+    jobs: usize,
+
+    #[arg(
+        long,
+        help = "Store full species names like 'Streptomyces coelicolor' instead of the epithet"
+    )]
+    full_species: bool,
+
+    #[arg(
+        long,
+        help = "Fail instead of warning when needed taxids can't be found in the dumps"
+    )]
+    strict: bool,
+
+    #[arg(
+        long,
+        help = "Tag of the taxonomy release built from, e.g. '2023-06' [default: the date of the taxdump]"
+    )]
+    release: Option<String>,
+
+    #[arg(
+        long,
+        help = "Stop after this long (e.g. 30m, 1h30m), saving a partial cache and exiting with 7"
+    )]
+    time_limit: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_TAXDUMP",
+        help = "TaxonDB ranked lineage dump file or URL to load from"
+    )]
+    taxdump: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "columns_file",
+        help = "Columns of the taxdump, e.g. 'tax_id,name,-,genus,superkingdom', instead of the rankedlineage.dmp ones"
+    )]
+    columns: Option<String>,
+
+    #[arg(long, help = "File listing the columns of the taxdump, see --columns")]
+    columns_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "GTDB metadata file (e.g. bac120_metadata.tsv) to add GTDB lineages, can be given multiple times"
+    )]
+    gtdb_metadata: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "gtdb_metadata",
+        help = "GTDB taxonomy file (e.g. bac120_taxonomy.tsv) for metadata without lineages, can be given multiple times"
+    )]
+    gtdb_taxonomy: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "silva_ena_taxmap",
+        help = "SILVA taxmap (e.g. taxmap_slv_ssu_ref_nr_138.2.txt) to add SILVA lineages, can be given multiple times"
+    )]
+    silva_taxmap: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "silva_taxmap",
+        help = "SILVA's ENA taxmap (e.g. taxmap_embl-ebi_ena_ssu_ref_nr99_138.2.txt) linking sequences to taxids, can be given multiple times"
+    )]
+    silva_ena_taxmap: Vec<String>,
+
+    #[arg(
+        long,
+        help = "File with curated field values (TSV 'taxid, field, value' lines or JSON), kept in the cache"
+    )]
+    overrides: Option<String>,
+
+    #[arg(
+        long,
+        help = "BioSample and BioProject to taxid mapping (TSV or NCBI XML dump) for records without taxon xref"
+    )]
+    biosamples: Option<String>,
+
+    #[cfg(feature = "network")]
+    #[arg(long, help = "Query NCBI Entrez for taxids missing from the dumps")]
+    entrez_fallback: bool,
+
+    #[cfg(feature = "network")]
+    #[arg(
+        long,
+        env = "NCBI_API_KEY",
+        hide_env_values = true,
+        help = "NCBI API key to use for Entrez queries"
+    )]
+    entrez_api_key: Option<String>,
 }
 
-pub fn main() {
-    let cli = Cli::parse();
+#[derive(Debug, Args)]
+struct LockOpts {
+    #[arg(
+        long,
+        conflicts_with = "no_lock",
+        help = "Wait for other processes using the cache instead of failing"
+    )]
+    wait: bool,
+
+    #[arg(
+        long,
+        help = "Don't lock the cache, e.g. on file systems without locking support"
+    )]
+    no_lock: bool,
+}
 
-    match cli.cmd {
-        Commands::Init(cfg) => init(cfg),
-        Commands::Add(cfg) => add(cfg),
-        Commands::List(cfg) => list(cfg),
+impl LockOpts {
+    /// Lock `cache_file` for a load and save cycle, unless locking is disabled
+    fn acquire(&self, cache_file: &Path) -> Result<Option<CacheLock>, CliError> {
+        if remote::is_remote(cache_file) {
+            return Err(ASDBTaxonError::InvalidFormat(format!(
+                "{} is a URL, caches can only be read from URLs",
+                cache_file.display()
+            )))
+            .context("Failed to lock cache");
+        }
+        if self.no_lock {
+            return Ok(None);
+        }
+        CacheLock::acquire(cache_file, self.wait)
+            .map(Some)
+            .context("Failed to lock cache")
     }
 }
 
-fn init(args: InitOpts) {
-    let mut taxon_cache = TaxonCache::new();
+#[derive(Debug, Args)]
+struct ExtractOpts {
+    #[arg(
+        long,
+        help = "Regex finding the taxid in data files without 'taxon:' xref, e.g. 'NCBITaxon:(\\d+)', can be given multiple times"
+    )]
+    taxid_pattern: Vec<String>,
 
-    taxon_cache
-        .initialise_from_paths(
-            PathBuf::from(args.taxdump),
-            PathBuf::from(args.mergeddump),
-            PathBuf::from(args.datadir),
-        )
-        .expect("Failed to initialise cache");
+    #[arg(
+        long,
+        help = "JSON path to the taxid in data files without 'taxon:' xref, e.g. 'records.*.tax_id', can be given multiple times"
+    )]
+    taxid_path: Vec<String>,
 
-    taxon_cache
-        .save_path(&PathBuf::from(args.cache))
-        .expect("Failed to save cache");
+    #[arg(
+        long,
+        help = "Only use the first taxid found in each data file, not every one it references"
+    )]
+    first_taxid_only: bool,
+
+    #[arg(
+        long,
+        help = "NCBI accession2taxid file (e.g. nucl_gb.accession2taxid.gz) to map the record accessions of data files without taxid"
+    )]
+    accession2taxid: Option<String>,
 }
 
-fn add(args: AddOpts) {
-    let mut taxon_cache = TaxonCache::new();
-    let cache_file = PathBuf::from(args.cache);
-    taxon_cache
-        .load_path(&cache_file)
-        .expect("Failed to load cache file");
+impl ExtractOpts {
+    /// Extraction rules in the order given, patterns first
+    fn rules(self) -> Result<Vec<Rule>, CliError> {
+        let mut rules = Vec::new();
+        for pattern in self.taxid_pattern {
+            rules.push(Rule::pattern(&pattern).context("Failed to parse taxid pattern")?);
+        }
+        for path in self.taxid_path {
+            rules.push(Rule::path(&path).context("Failed to parse taxid path")?);
+        }
+        Ok(rules)
+    }
 
-    taxon_cache
-        .initialise_from_paths(
-            PathBuf::from(args.taxdump),
-            PathBuf::from(args.mergeddump),
-            PathBuf::from(args.datadir),
-        )
-        .expect("Failed to initialise cache");
+    fn scan_options(self) -> Result<ScanOptions, CliError> {
+        let mut options = ScanOptions {
+            first_taxid_only: self.first_taxid_only,
+            accession2taxid: self.accession2taxid.clone().map(PathBuf::from),
+            ..Default::default()
+        };
+        for rule in self.rules()? {
+            options.rules.add(rule);
+        }
+        Ok(options)
+    }
+}
 
-    taxon_cache
-        .save_path(&cache_file)
-        .expect("Failed to save cache");
+#[derive(Debug, Args)]
+struct ListOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        long,
+        help = "Only list entries with a name at a rank, e.g. 'genus=Streptomyces', can be given multiple times"
+    )]
+    filter: Vec<String>,
+
+    #[arg(
+        long = "where",
+        help = "Only include entries matching an expression, e.g. 'superkingdom=Bacteria AND genus~^Strepto'"
+    )]
+    where_expression: Option<String>,
+
+    #[arg(
+        long,
+        help = "Append the lineage as a single column (plain, greengenes, metaphlan)"
+    )]
+    lineage_format: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only show entries in a clade, e.g. 'Eukaryota' or 'genus=Streptomyces'"
+    )]
+    view: Option<String>,
+
+    #[arg(
+        long,
+        help = "Taxonomy to take the lineages from (ncbi, gtdb, silva) [default: ncbi]"
+    )]
+    taxonomy: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Output format (text, tsv, json, table) [default: text, 'taxid: name']"
+    )]
+    format: Option<String>,
+
+    #[arg(
+        long,
+        help = "Comma-separated fields to show, e.g. 'tax_id,name,genus,lineage' [default: tax_id,name]"
+    )]
+    fields: Option<String>,
+
+    #[arg(
+        long,
+        help = "Sort the entries (taxid, name, genus), which needs the whole list in memory"
+    )]
+    sort: Option<String>,
+
+    #[arg(long, default_value_t = 0, help = "Skip this many entries")]
+    offset: usize,
+
+    #[arg(long, help = "List at most this many entries")]
+    limit: Option<usize>,
 }
 
-fn list(args: ListOpts) {
-    let mut taxon_cache = TaxonCache::new();
+#[derive(Debug, Args)]
+struct InfoOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+}
 
-    taxon_cache
-        .load_path(&PathBuf::from(args.cache))
-        .expect("Failed to load cache file");
-    for (tax_id, entry) in &taxon_cache.mappings {
-        println!("{}: {}", tax_id, entry.name)
+#[derive(Debug, Args)]
+struct LookupOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        required_unless_present = "batch",
+        conflicts_with = "batch",
+        help = "TaxID to look up"
+    )]
+    taxid: Option<TaxId>,
+
+    #[arg(
+        long,
+        help = "File with taxids to look up, one per line, '-' for stdin, writing one result line each"
+    )]
+    batch: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        requires = "batch",
+        help = "Output format of a batch lookup (tsv, json) [default: tsv]"
+    )]
+    format: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only show entries in a clade, e.g. 'Eukaryota' or 'genus=Streptomyces'"
+    )]
+    view: Option<String>,
+
+    #[arg(
+        long,
+        help = "Taxonomy to take the lineages from (ncbi, gtdb, silva) [default: ncbi]"
+    )]
+    taxonomy: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["batch", "view"],
+        help = "Look the taxid up as it was in the last release up to this tag, e.g. '2023-06'"
+    )]
+    as_of: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "batch",
+        help = "Also show where the entry came from, when it changed and which fields are overridden"
+    )]
+    explain: bool,
+}
+
+#[derive(Debug, Args)]
+struct DescendantsOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(short, long, help = "TaxID to list the descendants of")]
+    taxid: TaxId,
+}
+
+#[derive(Debug, Args)]
+struct LcaOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "TaxID to include, given at least once"
+    )]
+    taxid: Vec<TaxId>,
+}
+
+#[derive(Debug, Args)]
+struct SearchOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(short, long, help = "Organism name to search for")]
+    name: String,
+
+    #[arg(
+        short,
+        long = "match",
+        default_value = "exact",
+        help = "How the name has to match (exact, prefix)"
+    )]
+    matching: String,
+
+    #[arg(short, long, help = "Ignore case when matching names")]
+    ignore_case: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "matching",
+        help = "Rank similar names by score instead, tolerating typos and abbreviations"
+    )]
+    fuzzy: bool,
+
+    #[arg(
+        long,
+        requires = "fuzzy",
+        default_value_t = 10,
+        help = "Number of fuzzy candidates to show"
+    )]
+    limit: usize,
+
+    #[arg(
+        long,
+        requires = "fuzzy",
+        default_value_t = asdb_taxa::search::DEFAULT_MIN_SCORE,
+        help = "Lowest score of fuzzy candidates, from 0 to 1"
+    )]
+    min_score: f64,
+
+    #[arg(
+        long,
+        help = "Only show entries in a clade, e.g. 'Eukaryota' or 'genus=Streptomyces'"
+    )]
+    view: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ResolveOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Output format (tsv, json) [default: tsv, json with --json]"
+    )]
+    format: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only show entries in a clade, e.g. 'Eukaryota' or 'genus=Streptomyces'"
+    )]
+    view: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct AnnotateOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(short, long, help = "TSV file to annotate, stdin if not given")]
+    input: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Column holding the taxid, counting from 1"
+    )]
+    taxid_column: u64,
+
+    #[arg(
+        long,
+        help = "The first row is a header, append the column names to it"
+    )]
+    header: bool,
+
+    #[arg(
+        long,
+        help = "Only show entries in a clade, e.g. 'Eukaryota' or 'genus=Streptomyces'"
+    )]
+    view: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct VerifyOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        env = "ASDB_TAXA_DATADIR",
+        help = "ASDB json data directory or tarball to determine needed taxids, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        help = "PostgreSQL connection string of the antiSMASH database to check"
+    )]
+    dsn: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ValidateOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        requires = "mergeddump",
+        help = "TaxonDB ranked lineage dump file to check the entries against"
+    )]
+    taxdump: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        requires = "taxdump",
+        help = "TaxonDB merged ID dump file to check the entries against"
+    )]
+    mergeddump: Option<String>,
+
+    #[arg(
+        long,
+        requires = "taxdump",
+        help = "TaxonDB deleted nodes dump file to check the entries against"
+    )]
+    delnodes: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = asdb_taxa::parallel::default_jobs(),
+        help = "Number of threads parsing the taxdump"
+    )]
+    jobs: usize,
+}
+
+#[derive(Debug, Args)]
+struct GcOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+
+    #[arg(
+        short,
+        long,
+        help = "ASDB json data directory or tarball, to also drop merged and deleted taxids it doesn't reference, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+}
+
+#[derive(Debug, Args)]
+struct CountOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        long,
+        default_value = "phylum",
+        help = "Rank to group by, e.g. superkingdom, phylum, family or genus"
+    )]
+    by: Rank,
+
+    #[arg(
+        short,
+        long,
+        help = "ASDB json data directory or tarball to also count the records referencing each group in, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+}
+
+#[derive(Debug, Args)]
+struct TopOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        long,
+        default_value = "genus",
+        help = "Rank of the taxa to rank, e.g. phylum, family or genus"
+    )]
+    rank: Rank,
+
+    #[arg(short, default_value_t = 10, help = "Number of taxa to show")]
+    n: usize,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        env = "ASDB_TAXA_DATADIR",
+        help = "ASDB json data directory or tarball to count the references in, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+}
+
+#[derive(Debug, Args)]
+struct TaxidsOpts {
+    #[arg(
+        short,
+        long,
+        required = true,
+        env = "ASDB_TAXA_DATADIR",
+        help = "ASDB json data directory or tarball to scan, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = asdb_taxa::scan::MAX_SCAN_DEPTH,
+        help = "Levels of subdirectories of the datadir to scan"
+    )]
+    max_depth: usize,
+
+    #[arg(
+        long,
+        help = "Only scan data files matching this glob, e.g. 'GCF_*/*.json', can be given multiple times"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Skip data files and directories matching this glob, can be given multiple times"
+    )]
+    exclude: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+}
+
+#[derive(Debug, Args)]
+struct MissingOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        env = "ASDB_TAXA_DATADIR",
+        help = "ASDB json data directory or tarball to determine needed taxids, can be given multiple times"
+    )]
+    datadir: Vec<String>,
+
+    #[command(flatten)]
+    extract: ExtractOpts,
+}
+
+#[derive(Debug, Args)]
+struct ExportOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Export format (tsv, sql, genus-bundles, taxonomizr, ete3-tsv)",
+        default_value = "tsv"
+    )]
+    format: String,
+
+    #[arg(
+        short,
+        long,
+        help = "File to export to, defaults to stdout, or directory for genus-bundles"
+    )]
+    output: Option<String>,
+
+    #[arg(long, help = "Maximum length of text columns")]
+    max_length: Option<usize>,
+
+    #[arg(long, help = "Maximum length of a specific column, e.g. 'name=255'")]
+    column_limit: Vec<String>,
+
+    #[arg(long, help = "File to write the list of truncated values to")]
+    truncation_report: Option<String>,
+
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "PostgreSQL connection string to load the sql export into directly"
+    )]
+    pg_url: Option<String>,
+
+    #[arg(long, help = "Also export entries only cached as ancestors")]
+    include_ancestors: bool,
+
+    #[arg(long, help = "Apply ICNP/ICN capitalization rules to names")]
+    canonicalize_names: bool,
+
+    #[arg(
+        long,
+        requires = "canonicalize_names",
+        help = "Mark the italic 'Candidatus' prefix as <i>Candidatus</i>"
+    )]
+    candidatus_markers: bool,
+
+    #[arg(
+        long = "where",
+        help = "Only include entries matching an expression, e.g. 'superkingdom=Bacteria AND genus~^Strepto'"
+    )]
+    where_expression: Option<String>,
+
+    #[arg(
+        long,
+        help = "Append the lineage as a single column (plain, greengenes, metaphlan)"
+    )]
+    lineage_format: Option<String>,
+
+    #[arg(
+        long,
+        help = "Append where each entry came from and when it was added and last updated"
+    )]
+    provenance: bool,
+
+    #[arg(
+        long,
+        help = "Taxonomy to take the lineages from (ncbi, gtdb, silva) [default: ncbi]"
+    )]
+    taxonomy: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct TreeOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Tree format (text, newick, dot)",
+        default_value = "text"
+    )]
+    format: String,
+
+    #[arg(
+        long,
+        help = "Only show this many levels below the root, for the text format"
+    )]
+    depth: Option<usize>,
+
+    #[arg(short, long, help = "File to write the tree to, defaults to stdout")]
+    output: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct RemapOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Output format (tsv, or sql for UPDATE statements)",
+        default_value = "tsv"
+    )]
+    format: String,
+
+    #[arg(short, long, help = "File to write to, defaults to stdout")]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "antismash.taxa",
+        help = "Table the sql format updates"
+    )]
+    table: String,
+
+    #[arg(
+        long,
+        default_value = "ncbi_taxid",
+        help = "Taxid column the sql format updates"
+    )]
+    column: String,
+}
+
+#[derive(Debug, Args)]
+struct SubsetOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(long, help = "File listing the taxids to keep, one per line")]
+    taxid_list: String,
+
+    #[arg(short, long, help = "Cache file to write the subset to")]
+    output: String,
+}
+
+#[derive(Debug, Args)]
+struct ExportDeltaOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(long, help = "Cache file the delta is layered on top of")]
+    baseline: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Delta file to write, compressed as its extension says"
+    )]
+    output: String,
+}
+
+#[derive(Debug, Args)]
+struct ConvertOpts {
+    #[arg(long, help = "Cache file to convert")]
+    from: String,
+
+    #[arg(
+        long,
+        help = "Cache file to write, in the format implied by its extension"
+    )]
+    to: String,
+
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+
+    #[arg(
+        long,
+        help = "Compression of the new cache (none, gzip, zstd), overriding the extension"
+    )]
+    compression: Option<String>,
+
+    #[arg(
+        long,
+        help = "Layout of the cache (json, pretty, ndjson, indexed), by default compact json"
+    )]
+    output_format: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ServeOpts {
+    #[arg(short, long, env = "ASDB_TAXA_CACHE", help = "Cache file to use")]
+    cache: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Address to listen on",
+        default_value = "127.0.0.1:8080"
+    )]
+    listen: String,
+
+    #[arg(
+        long,
+        help = "Number of connections handled at the same time",
+        default_value_t = asdb_taxa::serve::DEFAULT_WORKERS
+    )]
+    workers: usize,
+
+    #[arg(
+        long,
+        help = "Serve a filtered view under /NAME/api/v1/, e.g. 'fungi=superkingdom=Eukaryota'"
+    )]
+    namespace: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Count lookups, persisting the counts in this file and serving them under /admin/"
+    )]
+    counters: Option<String>,
+
+    #[arg(
+        long,
+        env = "ASDB_TAXA_ADMIN_TOKEN",
+        hide_env_values = true,
+        requires = "counters",
+        help = "Bearer token requests to /admin/ need to send, without one /admin/ isn't served"
+    )]
+    admin_token: Option<String>,
+
+    #[arg(
+        long,
+        help = "Seconds between writes of the counters file",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    flush_interval: u64,
+}
+
+#[cfg(feature = "network")]
+#[derive(Debug, Args)]
+struct FetchOpts {
+    #[arg(short, long, help = "Directory to store the taxdump files in")]
+    dest: String,
+
+    #[arg(short, long, help = "URL of the taxdump archive", default_value = asdb_taxa::fetch::TAXDUMP_URL)]
+    url: String,
+
+    #[arg(
+        long,
+        default_value_t = asdb_taxa::readahead::default_buffer_size(),
+        help = "Size of the chunks decompressed ahead of extraction, 0 to decompress sequentially"
+    )]
+    decompress_buffer: usize,
+}
+
+#[cfg(feature = "network")]
+#[derive(Debug, Args)]
+struct FetchCacheOpts {
+    #[arg(
+        short,
+        long,
+        help = "URL of the published cache, which may be compressed"
+    )]
+    url: String,
+
+    #[arg(
+        short,
+        long,
+        env = "ASDB_TAXA_CACHE",
+        help = "Cache file to install it as"
+    )]
+    cache: String,
+
+    #[arg(
+        long,
+        help = "MD5 checksum of the download [default: the one published as <url>.md5]"
+    )]
+    md5: Option<String>,
+
+    #[command(flatten)]
+    lock: LockOpts,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Keep this many backups of the previous cache, as <cache>.1, <cache>.2, ..."
+    )]
+    backups: usize,
+}
+
+/// An error with a description of what the CLI was trying to do
+#[derive(Debug)]
+struct CliError {
+    context: &'static str,
+    error: ASDBTaxonError,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.error)
+    }
+}
+
+trait Context<T> {
+    fn context(self, context: &'static str) -> Result<T, CliError>;
+}
+
+impl<T, E: Into<ASDBTaxonError>> Context<T> for Result<T, E> {
+    fn context(self, context: &'static str) -> Result<T, CliError> {
+        self.map_err(|err| CliError {
+            context,
+            error: err.into(),
+        })
+    }
+}
+
+/// The command line interface, plus the config file option read before the others
+fn command() -> Command {
+    Cli::command().arg(
+        Arg::new("config")
+            .long("config")
+            .value_name("CONFIG")
+            .global(true)
+            .env("ASDB_TAXA_CONFIG")
+            .help("Config file with default paths [default: ~/.config/asdb-taxa/config.toml]"),
+    )
+}
+
+/// Logs the messages of this crate to stderr, as plain text or JSON lines
+struct StderrLogger {
+    json: bool,
+}
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // dependencies log through the same facade, only their errors are of interest
+        metadata.target().starts_with("asdb_taxa") || metadata.level() == log::Level::Error
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level().as_str().to_lowercase();
+        let line = if self.json {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default();
+            json!({
+                "timestamp": timestamp,
+                "level": level,
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        } else {
+            format!("[{}] {}", level, record.args())
+        };
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: OnceLock<StderrLogger> = OnceLock::new();
+
+/// Log warnings and errors by default, each `-v` adding a more detailed level
+fn init_logging(verbose: u8, quiet: bool, json: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    if log::set_logger(LOGGER.get_or_init(|| StderrLogger { json })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Load the config file, if any
+fn load_config() -> Result<Config, CliError> {
+    // a lenient first pass, as the options the config provides are still missing
+    let explicit = command()
+        .ignore_errors(true)
+        .try_get_matches()
+        .ok()
+        .and_then(|matches| matches.get_one::<String>("config").map(PathBuf::from));
+    let path = match explicit {
+        Some(path) => path,
+        None => match Config::default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    Config::load_path(&path).context("Failed to load config file")
+}
+
+/// Make the values of `config` the defaults of the options reading the matching environment
+/// variables, so the environment and the command line still take precedence
+fn with_defaults(mut command: Command, config: &Config) -> Command {
+    let defaults = config.defaults();
+    let matching: Vec<(Id, String)> = command
+        .get_arguments()
+        .filter_map(|arg| {
+            let env = arg.get_env()?;
+            let (_, value) = defaults.iter().find(|(name, _)| env == *name)?;
+            Some((arg.get_id().clone(), value.to_string()))
+        })
+        .collect();
+    for (id, value) in matching {
+        // clap doesn't count defaults towards required options, unlike environment variables
+        command = command.mut_arg(id, |arg| {
+            arg.default_value(value)
+                .required(false)
+                .required_unless_present(Resettable::Reset)
+        });
+    }
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    for name in subcommands {
+        command = command.mut_subcommand(name, |subcommand| with_defaults(subcommand, config));
+    }
+    command
+}
+
+pub fn main() -> ExitCode {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            // the logging options are only parsed along with the config
+            init_logging(0, false, false);
+            log::error!("{}", err);
+            return ExitCode::from(err.error.exit_code());
+        }
+    };
+    let matches = with_defaults(command(), &config).get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    init_logging(cli.verbose, cli.quiet, cli.log_json);
+    DELTAS.get_or_init(|| cli.delta.clone());
+
+    let result = match cli.cmd {
+        Commands::Init(cfg) => init(cfg, cli.json),
+        Commands::Add(cfg) => add(cfg, cli.json),
+        Commands::AddCustom(cfg) => add_custom(cfg, cli.json),
+        Commands::Update(cfg) => update(cfg, cli.json),
+        Commands::History(cfg) => history(cfg, cli.json),
+        Commands::List(cfg) => list(cfg, cli.json),
+        Commands::Info(cfg) => info(cfg, cli.json),
+        Commands::Lookup(cfg) => lookup(cfg, cli.json),
+        Commands::Descendants(cfg) => descendants(cfg, cli.json),
+        Commands::Lca(cfg) => lca(cfg, cli.json),
+        Commands::Search(cfg) => search(cfg, cli.json),
+        Commands::Resolve(cfg) => resolve(cfg, cli.json),
+        Commands::Annotate(cfg) => annotate(cfg, cli.json),
+        Commands::Missing(cfg) => missing(cfg, cli.json),
+        Commands::Taxids(cfg) => taxids(cfg, cli.json),
+        Commands::Count(cfg) => count(cfg, cli.json),
+        Commands::Top(cfg) => top(cfg, cli.json),
+        Commands::Verify(cfg) => verify(cfg, cli.json),
+        Commands::Validate(cfg) => validate(cfg, cli.json),
+        Commands::Gc(cfg) => gc(cfg, cli.json),
+        Commands::Export(cfg) => export(cfg, cli.json),
+        Commands::Tree(cfg) => tree(cfg, cli.json),
+        Commands::Remap(cfg) => remap(cfg, cli.json),
+        Commands::Subset(cfg) => subset(cfg, cli.json),
+        Commands::ExportDelta(cfg) => export_delta(cfg, cli.json),
+        Commands::Convert(cfg) => convert(cfg, cli.json),
+        Commands::Serve(cfg) => serve(cfg),
+        #[cfg(feature = "network")]
+        Commands::Fetch(cfg) => fetch(cfg, cli.json),
+        #[cfg(feature = "network")]
+        Commands::FetchCache(cfg) => fetch_cache(cfg, cli.json),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            log::error!("{}", err);
+            ExitCode::from(err.error.exit_code())
+        }
+    }
+}
+
+/// Delta caches given with `--delta`, layered on top of every cache loaded
+static DELTAS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn deltas() -> &'static [String] {
+    DELTAS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn load_cache(path: &str) -> Result<TaxonCache, CliError> {
+    let mut taxon_cache = TaxonCache::new();
+    taxon_cache
+        .load_path(&PathBuf::from(path))
+        .context("Failed to load cache file")?;
+    for path in deltas() {
+        let count = Delta::load_path(&PathBuf::from(path))
+            .and_then(|delta| delta.apply_to(&mut taxon_cache))
+            .context("Failed to apply delta cache")?;
+        log::debug!("Applied {} entries of {}", count, path);
+    }
+    Ok(taxon_cache)
+}
+
+fn parse_view(view: Option<&str>) -> Result<ViewFilter, CliError> {
+    ViewFilter::parse(view.unwrap_or_default()).context("Failed to parse view filter")
+}
+
+fn parse_rank_filter(filter: &str) -> Result<ViewFilter, CliError> {
+    let (rank, value) = filter
+        .split_once('=')
+        .ok_or_else(|| ASDBTaxonError::InvalidFilter(filter.to_string()))
+        .context("Failed to parse filter")?;
+    let rank: Rank = rank.parse().context("Failed to parse filter")?;
+    Ok(ViewFilter::Rank(rank, value.trim().to_string()))
+}
+
+fn parse_where(expression: &str) -> Result<Expression, CliError> {
+    expression
+        .parse()
+        .context("Failed to parse filter expression")
+}
+
+fn parse_lineage_format(format: Option<&str>) -> Result<Option<LineageStyle>, CliError> {
+    format
+        .map(str::parse)
+        .transpose()
+        .context("Failed to parse lineage format")
+}
+
+fn parse_taxonomy(taxonomy: Option<&str>) -> Result<Taxonomy, CliError> {
+    taxonomy
+        .map(str::parse)
+        .transpose()
+        .map(Option::unwrap_or_default)
+        .context("Failed to parse taxonomy")
+}
+
+fn init(args: InitOpts, json: bool) -> Result<(), CliError> {
+    let mut taxon_cache = TaxonCache::new();
+
+    let cache_file = PathBuf::from(args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let compression = match args.compression {
+        Some(compression) => compression.parse().context("Failed to parse compression")?,
+        None => Compression::from_path(&cache_file),
+    };
+
+    let format: CacheFormat = match args.output_format {
+        Some(format) => format.parse().context("Failed to parse output format")?,
+        None => CacheFormat::default(),
+    };
+
+    let (manifest, report) = build(&mut taxon_cache, args.build, ScanManifest::new())?;
+
+    rotate_backups(&cache_file, args.backups)?;
+    taxon_cache
+        .save_path_as(&cache_file, compression, format)
+        .context("Failed to save cache")?;
+    save_manifest(&cache_file, manifest, &taxon_cache)?;
+    if json {
+        print_json(&build_summary(&taxon_cache, report.as_ref()))?;
+    }
+    check_complete(&taxon_cache)
+}
+
+fn add(args: AddOpts, json: bool) -> Result<(), CliError> {
+    let cache_file = PathBuf::from(&args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let mut taxon_cache = load_cache(&args.cache)?;
+    // keep the existing compression unless asked to change it
+    let compression = match args.compression {
+        Some(compression) => compression.parse().context("Failed to parse compression")?,
+        None => Compression::detect_file(&cache_file).context("Failed to load cache file")?,
+    };
+    let format = match args.output_format {
+        Some(format) => format.parse().context("Failed to parse output format")?,
+        None => CacheFormat::detect_file(&cache_file).context("Failed to load cache file")?,
+    };
+
+    let manifest = if args.full_rescan {
+        ScanManifest::new()
+    } else {
+        ScanManifest::load_path(&manifest::manifest_path(&cache_file))
+            .context("Failed to load scan manifest")?
+    };
+    let (manifest, report) = build(&mut taxon_cache, args.build, manifest)?;
+
+    rotate_backups(&cache_file, args.backups)?;
+    taxon_cache
+        .save_path_as(&cache_file, compression, format)
+        .context("Failed to save cache")?;
+    save_manifest(&cache_file, manifest, &taxon_cache)?;
+    if json {
+        print_json(&build_summary(&taxon_cache, report.as_ref()))?;
+    }
+    check_complete(&taxon_cache)
+}
+
+fn add_custom(args: AddCustomOpts, json: bool) -> Result<(), CliError> {
+    let cache_file = PathBuf::from(&args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let mut taxon_cache = load_cache(&args.cache)?;
+    let compression = Compression::detect_file(&cache_file).context("Failed to load cache file")?;
+    let format = CacheFormat::detect_file(&cache_file).context("Failed to load cache file")?;
+
+    let entries = custom::load_path(&PathBuf::from(args.file), taxon_cache.full_species)
+        .context("Failed to load custom taxa")?;
+    let count = taxon_cache
+        .insert_custom(entries)
+        .context("Failed to add custom taxa")?;
+
+    rotate_backups(&cache_file, args.backups)?;
+    taxon_cache
+        .save_path_as(&cache_file, compression, format)
+        .context("Failed to save cache")?;
+    log::info!("Added {} custom entries", count);
+    if json {
+        let mut summary = build_summary(&taxon_cache, None);
+        summary["added"] = count.into();
+        print_json(&summary)?;
+    }
+    Ok(())
+}
+
+fn update(args: UpdateOpts, json: bool) -> Result<(), CliError> {
+    let cache_file = PathBuf::from(&args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let mut taxon_cache = load_cache(&args.cache)?;
+    let compression = Compression::detect_file(&cache_file).context("Failed to load cache file")?;
+    let format = CacheFormat::detect_file(&cache_file).context("Failed to load cache file")?;
+
+    let mut builder = TaxonCacheBuilder::new()
+        .max_merge_depth(args.max_merge_depth)
+        .jobs(args.jobs)
+        .on_progress(progress_bar());
+    if let Some(release) = args.release {
+        builder = builder.release(release);
+    }
+    builder = dumps(
+        builder,
+        args.taxdump_dir,
+        args.taxdump,
+        args.mergeddump,
+        args.delnodes,
+    )?;
+    if let Some(nodes) = args.nodes {
+        builder = builder.nodes_dump(nodes);
+    }
+    if let Some(ref columns) = args.columns {
+        builder = builder.columns(columns.parse().context("Failed to parse column mapping")?);
+    } else if let Some(ref path) = args.columns_file {
+        builder = builder.columns(
+            ColumnMapping::load_path(&PathBuf::from(path))
+                .context("Failed to load column mapping")?,
+        );
+    }
+
+    let report = builder
+        .update(&mut taxon_cache)
+        .context("Failed to update cache")?;
+    rotate_backups(&cache_file, args.backups)?;
+    taxon_cache
+        .save_path_as(&cache_file, compression, format)
+        .context("Failed to save cache")?;
+
+    if json {
+        return print_json(&json!({ "entries": report.entries, "changes": report.changes }));
+    }
+    for change in &report.changes {
+        println!("{}", change);
+    }
+    eprintln!("{}", report);
+    Ok(())
+}
+
+fn history(args: HistoryOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let history = taxon_cache.history_of(args.taxid);
+    if json {
+        let mut changes = Vec::with_capacity(history.len());
+        for (record, change) in history {
+            let mut change = serde_json::to_value(change).context("Failed to serialize history")?;
+            change["updated_at"] = record.updated_at.into();
+            changes.push(change);
+        }
+        return print_json(&changes);
+    }
+    if history.is_empty() {
+        eprintln!("No changes recorded for taxid {}", args.taxid);
+    }
+    for (record, change) in history {
+        println!("{}\t{}", format_timestamp(record.updated_at), change);
+    }
+    Ok(())
+}
+
+/// Build into `taxon_cache`, returning `manifest` updated with the scanned files and the scan
+/// report if a datadir was scanned
+fn build(
+    taxon_cache: &mut TaxonCache,
+    args: BuildOpts,
+    manifest: ScanManifest,
+) -> Result<(Option<ScanManifest>, Option<ScanReport>), CliError> {
+    let mut builder = TaxonCacheBuilder::new()
+        .max_merge_depth(args.max_merge_depth)
+        .jobs(args.jobs)
+        .full_species(args.full_species)
+        .strict(args.strict)
+        .on_missing_taxid(|err| log::warn!("{}", err))
+        .on_progress(progress_bar());
+    if let Some(release) = args.release {
+        builder = builder.release(release);
+    }
+    builder = dumps(
+        builder,
+        args.taxdump_dir,
+        args.taxdump,
+        args.mergeddump,
+        args.delnodes,
+    )?;
+
+    if let Some(limit) = args.time_limit {
+        let limit = deadline::parse_duration(&limit).context("Failed to parse time limit")?;
+        builder = builder.time_limit(limit);
+    }
+
+    let scan_datadir = !args.datadir.is_empty();
+    if scan_datadir {
+        builder = builder.manifest(manifest);
+    }
+    for datadir in args.datadir {
+        builder = builder.datadir(datadir);
+    }
+    builder = builder.max_scan_depth(args.max_depth);
+    for pattern in args.include {
+        builder = builder.include(pattern.parse().context("Failed to parse include pattern")?);
+    }
+    for pattern in args.exclude {
+        builder = builder.exclude(pattern.parse().context("Failed to parse exclude pattern")?);
+    }
+    builder = builder.first_taxid_only(args.extract.first_taxid_only);
+    if let Some(ref path) = args.extract.accession2taxid {
+        builder = builder.accession2taxid(path);
+    }
+    for rule in args.extract.rules()? {
+        builder = builder.taxid_rule(rule);
+    }
+
+    if args.taxids_from_stdin || args.taxids_file.as_deref() == Some("-") {
+        let taxids = scan::read_taxid_list(io::stdin().lock())
+            .context("Failed to read taxid list from stdin")?;
+        builder = builder.taxids(taxids);
+    } else if let Some(path) = args.taxids_file {
+        builder = builder.taxid_list(path);
+    }
+
+    if !args.taxid.is_empty() {
+        builder = builder.taxids(args.taxid);
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some(ref dsn) = args.taxids_from_db {
+        let taxids =
+            asdb_taxa::db::fetch_taxids(dsn).context("Failed to fetch taxids from the database")?;
+        log::info!("Fetched {} taxids from the database", taxids.len());
+        builder = builder.taxids(taxids);
+    }
+
+    if let Some(nodes) = args.nodes {
+        builder = builder.nodes_dump(nodes);
+    }
+
+    if let Some(ref columns) = args.columns {
+        builder = builder.columns(columns.parse().context("Failed to parse column mapping")?);
+    } else if let Some(ref path) = args.columns_file {
+        builder = builder.columns(
+            ColumnMapping::load_path(&PathBuf::from(path))
+                .context("Failed to load column mapping")?,
+        );
+    }
+
+    for path in args.gtdb_metadata {
+        builder = builder.gtdb_metadata(path);
+    }
+    for path in args.gtdb_taxonomy {
+        builder = builder.gtdb_taxonomy(path);
+    }
+    if let Some(path) = args.overrides {
+        let mut overrides = Overrides::new();
+        overrides
+            .load_path(&PathBuf::from(path))
+            .context("Failed to load overrides")?;
+        builder = builder.overrides(overrides);
+    }
+
+    for path in args.silva_taxmap {
+        builder = builder.silva_taxmap(path);
+    }
+    for path in args.silva_ena_taxmap {
+        builder = builder.silva_ena_taxmap(path);
+    }
+
+    if let Some(path) = args.biosamples {
+        let mut biosamples = BioSampleMap::new();
+        biosamples
+            .load_path(&PathBuf::from(path))
+            .context("Failed to load BioSample mappings")?;
+        builder = builder.biosamples(biosamples);
+    }
+
+    #[cfg(feature = "network")]
+    if args.entrez_fallback {
+        builder =
+            builder.entrez_fallback(asdb_taxa::entrez::EntrezClient::new(args.entrez_api_key));
+    }
+
+    let report = builder
+        .run(taxon_cache)
+        .context("Failed to initialise cache")?;
+
+    if !scan_datadir {
+        return Ok((builder.take_manifest(), None));
+    }
+    eprintln!("{}", report);
+    Ok((builder.take_manifest(), Some(report)))
+}
+
+/// Configure the dumps given as options, finding the missing ones in the taxdump directory
+fn dumps(
+    mut builder: TaxonCacheBuilder,
+    dir: Option<String>,
+    taxdump: Option<String>,
+    mergeddump: Option<String>,
+    delnodes: Option<String>,
+) -> Result<TaxonCacheBuilder, CliError> {
+    let dir = match dir {
+        Some(dir) => Some(TaxdumpDir::open(dir).context("Failed to open taxdump directory")?),
+        None if taxdump.is_none() || mergeddump.is_none() => TaxdumpDir::discover(),
+        None => None,
+    };
+    if let Some(dir) = dir {
+        log::info!("Looking for dumps in {}", dir.path().display());
+        builder = builder.taxdump_dir(&dir);
+    }
+    if let Some(taxdump) = taxdump {
+        builder = builder.taxdump(taxdump);
+    }
+    if let Some(mergeddump) = mergeddump {
+        builder = builder.merged_id_dump(mergeddump);
+    }
+    if let Some(delnodes) = delnodes {
+        builder = builder.deleted_id_dump(delnodes);
+    }
+    Ok(builder)
+}
+
+/// Save the scan manifest next to the cache, unless the build was cut short
+fn save_manifest(
+    cache_file: &Path,
+    manifest: Option<ScanManifest>,
+    taxon_cache: &TaxonCache,
+) -> Result<(), CliError> {
+    let partial = taxon_cache
+        .metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.partial);
+    match manifest {
+        Some(manifest) if !partial => manifest
+            .save_path(&manifest::manifest_path(cache_file))
+            .context("Failed to save scan manifest"),
+        _ => Ok(()),
+    }
+}
+
+/// Summarise a freshly built or extended cache, with the datadir scan if there was one
+fn build_summary(taxon_cache: &TaxonCache, report: Option<&ScanReport>) -> serde_json::Value {
+    json!({
+        "entries": taxon_cache.len(),
+        "merged": taxon_cache.deprecated_ids.len(),
+        "deleted": taxon_cache.deleted_ids.len(),
+        "partial": taxon_cache.metadata.as_ref().is_some_and(|metadata| metadata.partial),
+        "scan": report,
+    })
+}
+
+/// Print `entry` as `taxid: name`, or all of it as JSON
+fn print_entry(entry: &NcbiTaxEntry, json: bool) -> Result<(), CliError> {
+    if json {
+        return print_json(entry);
+    }
+    println!("{}: {}", entry.tax_id, entry.name);
+    Ok(())
+}
+
+/// Print `entry` with where it came from and when, and the fields overridden in `cache`
+fn print_provenance(cache: &TaxonCache, entry: &NcbiTaxEntry, json: bool) -> Result<(), CliError> {
+    let overridden = Provenance::overridden(cache, entry.tax_id);
+    if json {
+        let mut value = serde_json::to_value(entry).context("Failed to serialize results")?;
+        value["overridden"] = serde_json::json!(overridden);
+        return print_json(&value);
+    }
+    print_entry(entry, false)?;
+    println!("  source: {}", entry.source.as_str());
+    match entry.provenance {
+        Some(ref provenance) => println!("  {}", provenance),
+        None => println!("  no provenance recorded"),
+    }
+    if !overridden.is_empty() {
+        let fields: Vec<_> = overridden.iter().map(|field| field.to_string()).collect();
+        println!("  overridden: {}", fields.join(", "));
+    }
+    Ok(())
+}
+
+/// Refuse a JSON summary of data written to stdout, as they would be mixed up
+fn require_output(json: bool, output: Option<&str>) -> Result<(), CliError> {
+    if json && output.is_none() {
+        return Err(ASDBTaxonError::MissingInput("output file".to_string()))
+            .context("JSON summaries need the data written elsewhere");
+    }
+    Ok(())
+}
+
+/// Print `value` as pretty JSON on stdout
+fn print_json(value: &impl Serialize) -> Result<(), CliError> {
+    let json = serde_json::to_string_pretty(value).context("Failed to serialize results")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Move the cache about to be overwritten to the newest of `keep` backups
+fn rotate_backups(cache_file: &Path, keep: usize) -> Result<(), CliError> {
+    if asdb_taxa::backup::rotate(cache_file, keep).context("Failed to back up cache")? {
+        log::info!(
+            "Backed up the previous cache to {}",
+            asdb_taxa::backup::backup_path(cache_file, 1).display()
+        );
+    }
+    Ok(())
+}
+
+fn datadirs(paths: Vec<String>) -> Vec<PathBuf> {
+    paths.into_iter().map(PathBuf::from).collect()
+}
+
+/// Fail with a distinct exit code if the build stopped at its time limit
+fn check_complete(taxon_cache: &TaxonCache) -> Result<(), CliError> {
+    match taxon_cache.metadata {
+        Some(ref metadata) if metadata.partial => {
+            Err(ASDBTaxonError::TimeLimitReached).context("Saved partial cache")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Render a progress bar on stderr, if it is a terminal
+fn progress_bar() -> impl FnMut(Stage, u64, u64) + Send {
+    const WIDTH: u64 = 40;
+    let interactive = io::stderr().is_terminal();
+    let mut last: Option<(Stage, u64)> = None;
+    let mut eta = Eta::new();
+    move |stage, done, total| {
+        if !interactive {
+            return;
+        }
+        let left = eta.update(stage, done, total);
+        let percent = (done * 100).checked_div(total).unwrap_or(100);
+        if last == Some((stage, percent)) {
+            return;
+        }
+        last = Some((stage, percent));
+        let filled = (percent * WIDTH / 100) as usize;
+        let left = match left {
+            Some(left) if done < total => {
+                let seconds = left.as_secs();
+                format!(" {}m{:02}s left", seconds / 60, seconds % 60)
+            }
+            _ => String::new(),
+        };
+        eprint!(
+            "\r{:>8} [{}{}] {:>3}%{:<16}",
+            stage,
+            "#".repeat(filled),
+            " ".repeat(WIDTH as usize - filled),
+            percent,
+            left
+        );
+        if done >= total {
+            eprintln!();
+        }
+    }
+}
+
+fn list(args: ListOpts, json: bool) -> Result<(), CliError> {
+    let mut filters = vec![parse_view(args.view.as_deref())?];
+    for filter in &args.filter {
+        filters.push(parse_rank_filter(filter)?);
+    }
+    if let Some(expression) = args.where_expression.as_deref() {
+        filters.push(ViewFilter::Where(parse_where(expression)?));
+    }
+    let filter = ViewFilter::And(filters);
+    let lineage = parse_lineage_format(args.lineage_format.as_deref())?;
+    let taxonomy = parse_taxonomy(args.taxonomy.as_deref())?;
+
+    let mut options = ListOptions::default();
+    if let Some(ref format) = args.format {
+        options.format = format.parse().context("Failed to parse format")?;
+    } else if json {
+        options.format = ListFormat::Json;
+    }
+    if let Some(ref fields) = args.fields {
+        options.fields = Field::parse_list(fields).context("Failed to parse fields")?;
+    } else if lineage.is_some() {
+        options.fields.push(Field::Lineage(Default::default()));
+    }
+    if let Some(style) = lineage {
+        for field in options.fields.iter_mut() {
+            if let Field::Lineage(ref mut field_style) = field {
+                *field_style = style;
+            }
+        }
+    }
+    options.offset = args.offset;
+    options.limit = args.limit;
+    let sort: Option<SortKey> = args
+        .sort
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .context("Failed to parse sort order")?;
+    let format = options.format;
+    let input =
+        compression::open(&PathBuf::from(&args.cache)).context("Failed to load cache file")?;
+
+    // stream the entries, so huge caches can be listed without loading them into memory
+    let mut writer = EntryWriter::new(options, BufWriter::new(io::stdout().lock()));
+    let mut sorted = Vec::new();
+    let mut result = Ok(());
+    asdb_taxa::stream::for_each_entry(input, &mut |entry| {
+        let entry = entry.with_taxonomy(taxonomy);
+        if result.is_ok() && filter.matches(&entry) {
+            match sort {
+                Some(_) => sorted.push(entry),
+                None if writer.is_full() => (),
+                None => result = writer.write(&entry),
+            }
+        }
+    })
+    .context("Failed to load cache file")?;
+    if let Some(sort) = sort {
+        sorted.sort_unstable_by(|a, b| sort.compare(a, b));
+        for entry in &sorted {
+            if writer.is_full() {
+                break;
+            }
+            result = result.and_then(|_| writer.write(entry));
+        }
+    }
+    result.context("Failed to write entries")?;
+    let listed = writer.finish().context("Failed to write entries")?;
+    // keep the output of the other formats parseable
+    if format == ListFormat::Text {
+        println!("\n{} entries total", listed);
+    }
+    Ok(())
+}
+
+fn info(args: InfoOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    if json {
+        return print_json(&json!({
+            "version": taxon_cache.version,
+            "metadata": taxon_cache.metadata,
+            "entries": taxon_cache.len(),
+            "merged": taxon_cache.deprecated_ids.len(),
+            "deleted": taxon_cache.deleted_ids.len(),
+            "updates": taxon_cache.history.len(),
+            "releases": taxon_cache.release_tags(),
+            "overrides": taxon_cache.overrides.taxa,
+        }));
+    }
+    match taxon_cache.metadata {
+        Some(ref metadata) => println!("{}", metadata),
+        None => println!("No build information, cache predates metadata support"),
+    }
+    println!("Cache format version {}", taxon_cache.version);
+    println!("{} entries", taxon_cache.len());
+    println!("{} merged taxids", taxon_cache.deprecated_ids.len());
+    println!("{} deleted taxids", taxon_cache.deleted_ids.len());
+    if !taxon_cache.history.is_empty() {
+        println!("{} recorded updates", taxon_cache.history.len());
+    }
+    if !taxon_cache.releases.is_empty() {
+        println!(
+            "Earlier releases {}",
+            taxon_cache
+                .releases
+                .iter()
+                .map(|release| release.tag.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if !taxon_cache.overrides.is_empty() {
+        println!(
+            "{} overridden fields on {} taxids",
+            taxon_cache.overrides.field_count(),
+            taxon_cache.overrides.taxa.len()
+        );
+        for (tax_id, fields) in &taxon_cache.overrides.taxa {
+            for (field, value) in fields {
+                println!("  {}: {} = {}", tax_id, field, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn lookup(args: LookupOpts, json: bool) -> Result<(), CliError> {
+    let plain = args.view.is_none() && args.as_of.is_none() && !args.explain && deltas().is_empty();
+    if let (Some(tax_id), true) = (args.taxid, plain) {
+        // single lookups in an indexed cache only need to read the entry
+        let indexed =
+            IndexedCache::open(&PathBuf::from(&args.cache)).context("Failed to load cache file")?;
+        if let Some(indexed) = indexed {
+            let taxonomy = parse_taxonomy(args.taxonomy.as_deref())?;
+            let entry = indexed
+                .lookup(tax_id)
+                .context("Failed to look up taxid")?
+                .with_taxonomy(taxonomy);
+            return print_entry(&entry, json);
+        }
+    }
+
+    let mut taxon_cache = load_cache(&args.cache)?;
+    let taxonomy = parse_taxonomy(args.taxonomy.as_deref())?;
+    if let Some(ref as_of) = args.as_of {
+        let tax_id = args
+            .taxid
+            .expect("clap requires a taxid without a batch file");
+        let entry = taxon_cache
+            .lookup_as_of(tax_id, as_of)
+            .context("Failed to look up taxid")?
+            .with_taxonomy(taxonomy);
+        if args.explain {
+            return print_provenance(&taxon_cache, &entry, json);
+        }
+        return print_entry(&entry, json);
+    }
+    if taxonomy != Taxonomy::Ncbi {
+        taxon_cache = taxon_cache.with_taxonomy(taxonomy);
+    }
+
+    let view = taxon_cache.view(parse_view(args.view.as_deref())?);
+    let Some(path) = args.batch else {
+        let tax_id = args
+            .taxid
+            .expect("clap requires a taxid without a batch file");
+        let entry = view.lookup(tax_id).context("Failed to look up taxid")?;
+        if args.explain {
+            return print_provenance(&taxon_cache, entry, json);
+        }
+        return print_entry(entry, json);
+    };
+
+    let format: ResolveFormat = args
+        .format
+        .as_deref()
+        .unwrap_or(if json { "json" } else { "tsv" })
+        .parse()
+        .context("Failed to parse format")?;
+    let input: Box<dyn io::BufRead> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(
+            fs::File::open(path).context("Failed to open batch file")?,
+        ))
+    };
+    let failed = asdb_taxa::resolve::resolve_lines(
+        &view,
+        input,
+        BufWriter::new(io::stdout().lock()),
+        format,
+    )
+    .context("Failed to look up taxids")?;
+    if failed > 0 {
+        log::warn!("{} taxids could not be found", failed);
+    }
+    Ok(())
+}
+
+fn descendants(args: DescendantsOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    if json {
+        let entries: Vec<_> = taxon_cache
+            .descendants(args.taxid)
+            .context("Failed to look up taxid")?
+            .collect();
+        return print_json(&entries);
+    }
+    let mut output = BufWriter::new(io::stdout().lock());
+    let mut listed = 0;
+    for entry in taxon_cache
+        .descendants(args.taxid)
+        .context("Failed to look up taxid")?
+    {
+        writeln!(output, "{}: {}", entry.tax_id, entry.name).context("Failed to write entries")?;
+        listed += 1;
+    }
+    writeln!(output, "\n{} entries total", listed).context("Failed to write entries")?;
+    Ok(())
+}
+
+fn lca(args: LcaOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let ancestor = taxon_cache
+        .lca(&args.taxid)
+        .context("Failed to find common ancestor")?;
+    if json {
+        return print_json(&ancestor);
+    }
+    // ancestors that aren't cached only have a name and lineage
+    let tax_id = ancestor
+        .tax_id()
+        .map_or_else(|| "-".to_string(), |tax_id| tax_id.to_string());
+    let entry = ancestor.entry();
+    println!(
+        "{}: {} ({})",
+        tax_id,
+        entry.name,
+        asdb_taxa::export::rank(entry)
+    );
+    Ok(())
+}
+
+fn search(args: SearchOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let query = NameQuery {
+        matching: args.matching.parse().context("Failed to parse match")?,
+        ignore_case: args.ignore_case,
+    };
+    let view = taxon_cache.view(parse_view(args.view.as_deref())?);
+    if args.fuzzy {
+        let candidates = view.find_by_name_fuzzy(&args.name, args.min_score);
+        if candidates.is_empty() {
+            return Err(ASDBTaxonError::NameNotFound(args.name)).context("Failed to search names");
+        }
+        let candidates = candidates.into_iter().take(args.limit);
+        if json {
+            let matches: Vec<_> = candidates
+                .map(|(entry, score)| json!({ "score": score, "entry": entry }))
+                .collect();
+            return print_json(&matches);
+        }
+        for (entry, score) in candidates {
+            println!("{:.3}\t{}: {}", score, entry.tax_id, entry.name);
+        }
+        return Ok(());
+    }
+    let entries = view.find_by_name_with(&args.name, &query);
+    if entries.is_empty() {
+        return Err(ASDBTaxonError::NameNotFound(args.name)).context("Failed to search names");
+    }
+    if json {
+        return print_json(&entries);
+    }
+    for entry in entries {
+        println!("{}: {}", entry.tax_id, entry.name);
+    }
+    Ok(())
+}
+
+fn resolve(args: ResolveOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let format: ResolveFormat = args
+        .format
+        .as_deref()
+        .unwrap_or(if json { "json" } else { "tsv" })
+        .parse()
+        .context("Failed to parse format")?;
+    let filter = parse_view(args.view.as_deref())?;
+    let failed = asdb_taxa::resolve::resolve_lines(
+        &taxon_cache.view(filter),
+        io::stdin().lock(),
+        io::stdout().lock(),
+        format,
+    )
+    .context("Failed to resolve taxids")?;
+    if failed > 0 {
+        log::info!("{} taxids could not be resolved", failed);
+    }
+    Ok(())
+}
+
+fn annotate(args: AnnotateOpts, json: bool) -> Result<(), CliError> {
+    if json {
+        return Err(ASDBTaxonError::InvalidFormat("json".to_string()))
+            .context("Annotated tables are always TSV");
+    }
+    let taxon_cache = load_cache(&args.cache)?;
+    let filter = parse_view(args.view.as_deref())?;
+    let input: Box<dyn io::BufRead> = match args.input {
+        Some(path) => Box::new(io::BufReader::new(
+            fs::File::open(path).context("Failed to open input table")?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    let failed = asdb_taxa::resolve::annotate_tsv(
+        &taxon_cache.view(filter),
+        input,
+        BufWriter::new(io::stdout().lock()),
+        args.taxid_column as usize,
+        args.header,
+    )
+    .context("Failed to annotate table")?;
+    if failed > 0 {
+        log::warn!("{} rows could not be annotated", failed);
+    }
+    Ok(())
+}
+
+fn missing(args: MissingOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let options = args.extract.scan_options()?;
+    let taxids = taxon_cache
+        .find_taxids_with(&datadirs(args.datadir), &options)
+        .context("Failed to scan datadir")?;
+
+    if json {
+        let mut sorted: Vec<TaxId> = taxids.into_iter().collect();
+        sorted.sort_unstable();
+        let missing: Vec<_> = sorted
+            .into_iter()
+            .filter_map(|tax_id| {
+                let err = taxon_cache.lookup(tax_id).err()?;
+                Some(json!({ "tax_id": tax_id, "error": err.to_string() }))
+            })
+            .collect();
+        return print_json(&missing);
+    }
+    let missing = taxon_cache.missing(&taxids);
+    for err in &missing {
+        println!("{}", err)
+    }
+    println!("\n{} taxids missing", missing.len());
+    Ok(())
+}
+
+fn count(args: CountOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let references = if args.datadir.is_empty() {
+        None
+    } else {
+        Some(count_references(args.datadir, args.extract)?)
+    };
+    let counts = GroupCounts::count(&taxon_cache, args.by, references.as_ref());
+    warn_unresolved(&counts);
+
+    if json {
+        return print_json(&counts);
+    }
+    let mut output = BufWriter::new(io::stdout().lock());
+    let header = match references {
+        Some(_) => format!("{}\ttaxa\trecords", args.by.as_str()),
+        None => format!("{}\ttaxa", args.by.as_str()),
+    };
+    writeln!(output, "{}", header).context("Failed to write counts")?;
+    for group in counts.groups {
+        match group.records {
+            Some(records) => writeln!(output, "{}\t{}\t{}", group.name, group.taxa, records),
+            None => writeln!(output, "{}\t{}", group.name, group.taxa),
+        }
+        .context("Failed to write counts")?;
+    }
+    Ok(())
+}
+
+fn top(args: TopOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let references = count_references(args.datadir, args.extract)?;
+    let counts = GroupCounts::count(&taxon_cache, args.rank, Some(&references)).top(args.n);
+    warn_unresolved(&counts);
+
+    if json {
+        return print_json(&counts);
+    }
+    let mut output = BufWriter::new(io::stdout().lock());
+    writeln!(output, "{}\trecords", args.rank.as_str()).context("Failed to write counts")?;
+    for group in counts.groups {
+        writeln!(
+            output,
+            "{}\t{}",
+            group.name,
+            group.records.unwrap_or_default()
+        )
+        .context("Failed to write counts")?;
+    }
+    Ok(())
+}
+
+/// Count the records in the datadirs at `paths` referencing each taxid
+fn count_references(
+    paths: Vec<String>,
+    extract: ExtractOpts,
+) -> Result<HashMap<TaxId, usize>, CliError> {
+    let options = extract.scan_options()?;
+    scan::count_references(&datadirs(paths), &options).context("Failed to scan datadir")
+}
+
+fn warn_unresolved(counts: &GroupCounts) {
+    if counts.unresolved_records > 0 {
+        log::warn!(
+            "{} records reference taxids missing from the cache",
+            counts.unresolved_records
+        );
+    }
+}
+
+fn taxids(args: TaxidsOpts, json: bool) -> Result<(), CliError> {
+    let mut options = args.extract.scan_options()?;
+    options.max_depth = args.max_depth;
+    for pattern in args.include {
+        options
+            .include
+            .push(pattern.parse().context("Failed to parse include pattern")?);
+    }
+    for pattern in args.exclude {
+        options
+            .exclude
+            .push(pattern.parse().context("Failed to parse exclude pattern")?);
+    }
+
+    let (taxids, report) = scan::scan_datadir_with(
+        &datadirs(args.datadir),
+        None,
+        &options,
+        None,
+        None,
+        &mut |_, _| (),
+        &mut |_, _| (),
+    )
+    .context("Failed to scan datadir")?;
+
+    let mut taxids: Vec<TaxId> = taxids.into_iter().collect();
+    taxids.sort_unstable();
+    if json {
+        return print_json(&json!({ "taxids": taxids, "scan": report }));
+    }
+    let mut output = BufWriter::new(io::stdout().lock());
+    for taxid in taxids {
+        writeln!(output, "{}", taxid).context("Failed to write taxids")?;
+    }
+    output.flush().context("Failed to write taxids")?;
+    eprintln!("{}", report);
+    Ok(())
+}
+
+fn verify(args: VerifyOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let options = args.extract.scan_options()?;
+    let taxids = taxon_cache
+        .find_taxids_with(&datadirs(args.datadir), &options)
+        .context("Failed to scan datadir")?;
+
+    #[cfg(feature = "postgres")]
+    let db = match args.dsn {
+        Some(ref dsn) => {
+            Some(asdb_taxa::db::fetch_taxa(dsn).context("Failed to fetch database taxa")?)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "postgres"))]
+    let db = None;
+
+    let report = asdb_taxa::verify::verify(&taxids, &taxon_cache, db.as_ref());
+    if json {
+        print_json(&report)?;
+    } else {
+        println!("{}", report);
+    }
+
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        Err(ASDBTaxonError::Inconsistent(report.discrepancies())).context("Verification failed")
+    }
+}
+
+fn gc(args: GcOpts, json: bool) -> Result<(), CliError> {
+    let cache_file = PathBuf::from(&args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let mut taxon_cache = load_cache(&args.cache)?;
+    let compression = Compression::detect_file(&cache_file).context("Failed to load cache file")?;
+    let format = CacheFormat::detect_file(&cache_file).context("Failed to load cache file")?;
+    let size_before = fs::metadata(&cache_file)
+        .context("Failed to load cache file")?
+        .len();
+
+    let referenced = if args.datadir.is_empty() {
+        None
+    } else {
+        let options = args.extract.scan_options()?;
+        Some(
+            taxon_cache
+                .find_taxids_with(&datadirs(args.datadir), &options)
+                .context("Failed to scan datadir")?,
+        )
+    };
+    let report = asdb_taxa::gc::collect(&mut taxon_cache, referenced.as_ref());
+
+    rotate_backups(&cache_file, args.backups)?;
+    taxon_cache
+        .save_path_as(&cache_file, compression, format)
+        .context("Failed to save cache")?;
+    let size_after = fs::metadata(&cache_file)
+        .context("Failed to save cache")?
+        .len();
+    if json {
+        return print_json(&json!({
+            "dangling": report.dangling,
+            "shortened": report.shortened,
+            "unreferenced_merged": report.unreferenced_merged,
+            "unreferenced_deleted": report.unreferenced_deleted,
+            "removed": report.removed(),
+            "size_before": size_before,
+            "size_after": size_after,
+        }));
+    }
+    println!("{}", report);
+    println!(
+        "{} bytes reclaimed ({} -> {} bytes)",
+        size_before.saturating_sub(size_after),
+        size_before,
+        size_after
+    );
+    Ok(())
+}
+
+fn validate(args: ValidateOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let mut report = asdb_taxa::validate::validate(&taxon_cache);
+
+    if let (Some(taxdump), Some(mergeddump)) = (args.taxdump, args.mergeddump) {
+        let mut builder = TaxonCacheBuilder::new()
+            .taxdump(taxdump)
+            .merged_id_dump(mergeddump)
+            .jobs(args.jobs);
+        if let Some(delnodes) = args.delnodes {
+            builder = builder.deleted_id_dump(delnodes);
+        }
+        // an update of a copy shows what the dumps would change
+        let mut updated = taxon_cache.clone();
+        let changes = builder
+            .update(&mut updated)
+            .context("Failed to check against the dumps")?
+            .changes;
+        report.changes = Some(changes);
+    }
+
+    if json {
+        print_json(&report)?;
+    } else {
+        println!("{}", report);
+    }
+
+    if report.is_valid() {
+        Ok(())
+    } else {
+        Err(ASDBTaxonError::Inconsistent(report.discrepancies())).context("Validation failed")
+    }
+}
+
+fn export(args: ExportOpts, json: bool) -> Result<(), CliError> {
+    let mut taxon_cache = load_cache(&args.cache)?;
+    let taxonomy = parse_taxonomy(args.taxonomy.as_deref())?;
+    if taxonomy != Taxonomy::Ncbi {
+        taxon_cache = taxon_cache.with_taxonomy(taxonomy);
+    }
+    if let Some(expression) = args.where_expression.as_deref() {
+        let expression = parse_where(expression)?;
+        taxon_cache = taxon_cache.subset_by(|entry| expression.matches(entry));
+    }
+
+    let format: ExportFormat = args.format.parse().context("Failed to parse format")?;
+    #[cfg(feature = "postgres")]
+    if args.pg_url.is_none() {
+        require_output(json, args.output.as_deref())?;
+    }
+    #[cfg(not(feature = "postgres"))]
+    require_output(json, args.output.as_deref())?;
+    let mut limits = ColumnLimits::new(args.max_length);
+    for spec in args.column_limit {
+        limits.add(&spec).context("Failed to parse column limit")?;
+    }
+    let options = ExportOptions {
+        limits,
+        include_ancestors: args.include_ancestors,
+        canonicalize: args
+            .canonicalize_names
+            .then(|| Canonicalizer::new(args.candidatus_markers)),
+        lineage: parse_lineage_format(args.lineage_format.as_deref())?,
+        provenance: args.provenance,
+    };
+
+    if format.is_directory() {
+        let dir = args
+            .output
+            .ok_or_else(|| ASDBTaxonError::MissingInput("output directory".to_string()))
+            .context("Failed to export cache")?;
+        let genera =
+            asdb_taxa::export::export_genus_bundles(&taxon_cache, &PathBuf::from(&dir), &options)
+                .context("Failed to export cache")?;
+        log::info!("Exported {} genera", genera);
+        if json {
+            print_json(&json!({ "output": dir, "genera": genera }))?;
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some(ref dsn) = args.pg_url {
+        let mut script = Vec::new();
+        let truncations =
+            asdb_taxa::export::export(&taxon_cache, ExportFormat::Sql, &mut script, &options)
+                .context("Failed to export cache")?;
+        asdb_taxa::db::execute(dsn, &script).context("Failed to load taxa into the database")?;
+        if json {
+            print_json(&json!({ "truncated": truncations.len() }))?;
+        }
+        return report_truncations(truncations, args.truncation_report);
+    }
+
+    let output: Box<dyn Write> = match args.output {
+        Some(ref path) => Box::new(BufWriter::new(
+            fs::File::create(path).context("Failed to create output file")?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    let truncations = asdb_taxa::export::export(&taxon_cache, format, output, &options)
+        .context("Failed to export cache")?;
+    if json {
+        print_json(&json!({ "output": args.output, "truncated": truncations.len() }))?;
+    }
+    report_truncations(truncations, args.truncation_report)
+}
+
+/// Write the truncated values to `report`, if given, and warn about them
+fn report_truncations(
+    truncations: Vec<Truncation>,
+    report: Option<String>,
+) -> Result<(), CliError> {
+    if let Some(path) = report {
+        let mut report = fs::File::create(path).context("Failed to create truncation report")?;
+        for truncation in &truncations {
+            writeln!(report, "{}", truncation).context("Failed to write truncation report")?;
+        }
+    }
+    if !truncations.is_empty() {
+        log::warn!("{} values truncated", truncations.len());
+    }
+    Ok(())
+}
+
+fn tree(args: TreeOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let format: TreeFormat = args.format.parse().context("Failed to parse format")?;
+    require_output(json, args.output.as_deref())?;
+    let output: Box<dyn Write> = match args.output {
+        Some(ref path) => Box::new(BufWriter::new(
+            fs::File::create(path).context("Failed to create output file")?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    asdb_taxa::tree::write_tree(&taxon_cache, format, args.depth, output)
+        .context("Failed to write tree")?;
+    if json {
+        print_json(&json!({ "output": args.output }))?;
+    }
+    Ok(())
+}
+
+fn remap(args: RemapOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let format: RemapFormat = args.format.parse().context("Failed to parse format")?;
+    let target =
+        RemapTarget::new(&args.table, &args.column).context("Failed to parse remap target")?;
+    require_output(json, args.output.as_deref())?;
+    let output: Box<dyn Write> = match args.output {
+        Some(ref path) => Box::new(BufWriter::new(
+            fs::File::create(path).context("Failed to create output file")?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    let count = asdb_taxa::remap::write_remap(&taxon_cache, output, format, &target)
+        .context("Failed to write remappings")?;
+    log::info!("Wrote {} remapped taxids", count);
+    if json {
+        print_json(&json!({ "output": args.output, "remapped": count }))?;
+    }
+    Ok(())
+}
+
+fn subset(args: SubsetOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    let taxids = fs::File::open(args.taxid_list)
+        .map_err(ASDBTaxonError::from)
+        .and_then(scan::read_taxid_list)
+        .context("Failed to load taxid list")?;
+
+    let taxids: Vec<TaxId> = taxids.into_iter().collect();
+    let subset = taxon_cache.subset(&taxids);
+    subset
+        .save_path(&PathBuf::from(&args.output))
+        .context("Failed to save cache")?;
+    if json {
+        print_json(&json!({ "output": args.output, "entries": subset.len() }))?;
+    }
+    Ok(())
+}
+
+fn export_delta(args: ExportDeltaOpts, json: bool) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+    // the baseline is what collaborators already have, without any deltas
+    let mut baseline = TaxonCache::new();
+    baseline
+        .load_path(&PathBuf::from(&args.baseline))
+        .context("Failed to load baseline cache")?;
+
+    let delta = Delta::between(&baseline, &taxon_cache).context("Failed to compare caches")?;
+    delta
+        .save_path(&PathBuf::from(&args.output))
+        .context("Failed to save delta")?;
+    log::info!(
+        "Wrote {} added or changed and {} removed entries",
+        delta.entries.len(),
+        delta.removed.len()
+    );
+    if json {
+        print_json(&json!({
+            "output": args.output,
+            "entries": delta.entries.len(),
+            "removed": delta.removed.len(),
+            "merged": delta.merged.len(),
+            "deleted": delta.deleted.len(),
+        }))?;
+    }
+    Ok(())
+}
+
+fn convert(args: ConvertOpts, json: bool) -> Result<(), CliError> {
+    let target = PathBuf::from(args.to);
+    let _lock = args.lock.acquire(&target)?;
+    let compression = match args.compression {
+        Some(compression) => compression.parse().context("Failed to parse compression")?,
+        None => match target.extension().and_then(|ext| ext.to_str()) {
+            Some("json") | Some("gz") | Some("zst") => Compression::from_path(&target),
+            _ => {
+                return Err(ASDBTaxonError::InvalidFormat(target.display().to_string()))
+                    .context("Unsupported cache format, use .json, .json.gz or .json.zst")
+            }
+        },
+    };
+
+    let format: CacheFormat = match args.output_format {
+        Some(format) => format.parse().context("Failed to parse output format")?,
+        None => CacheFormat::default(),
+    };
+
+    let taxon_cache = load_cache(&args.from)?;
+    rotate_backups(&target, args.backups)?;
+    let count = taxon_cache
+        .save_path_as(&target, compression, format)
+        .context("Failed to save cache")?;
+    log::info!("Converted {} entries", count);
+    if json {
+        print_json(&json!({ "output": target, "entries": count }))?;
+    }
+    Ok(())
+}
+
+fn serve(args: ServeOpts) -> Result<(), CliError> {
+    let taxon_cache = load_cache(&args.cache)?;
+
+    let mut server = Server::new(Arc::new(taxon_cache)).with_workers(args.workers);
+    for namespace in args.namespace {
+        let (name, filter) = namespace
+            .split_once('=')
+            .ok_or_else(|| ASDBTaxonError::InvalidFilter(namespace.clone()))
+            .context("Namespaces need to be given as NAME=FILTER")?;
+        server.add_namespace(name, parse_view(Some(filter))?);
+    }
+
+    let mut flusher = None;
+    if let Some(path) = args.counters {
+        let telemetry =
+            Arc::new(Telemetry::with_file(path).context("Failed to load counters file")?);
+        flusher = Some(
+            telemetry
+                .spawn_flusher(Duration::from_secs(args.flush_interval))
+                .context("Failed to start writing the counters file")?,
+        );
+        server = server.with_telemetry(telemetry);
+    }
+    if let Some(token) = args.admin_token {
+        server = server.with_admin_token(token);
+    }
+
+    eprintln!("Listening on {}", args.listen);
+    let served = server.serve(&args.listen).context("Failed to run server");
+    if let Some(flusher) = flusher {
+        flusher.stop();
+    }
+    served
+}
+
+#[cfg(feature = "network")]
+fn fetch(args: FetchOpts, json: bool) -> Result<(), CliError> {
+    let paths = asdb_taxa::fetch::fetch_taxdump_with(
+        &args.url,
+        &PathBuf::from(args.dest),
+        args.decompress_buffer,
+    )
+    .context("Failed to fetch taxdump")?;
+    if json {
+        return print_json(&paths);
+    }
+    for path in paths {
+        println!("{}", path.display())
+    }
+    Ok(())
+}
+
+#[cfg(feature = "network")]
+fn fetch_cache(args: FetchCacheOpts, json: bool) -> Result<(), CliError> {
+    let cache_file = PathBuf::from(&args.cache);
+    let _lock = args.lock.acquire(&cache_file)?;
+    let taxon_cache = asdb_taxa::fetch::fetch_cache(&args.url, args.md5.as_deref(), &cache_file)
+        .context("Failed to fetch cache")?;
+    rotate_backups(&cache_file, args.backups)?;
+    let count = taxon_cache
+        .save_path(&cache_file)
+        .context("Failed to save cache")?;
+    log::info!("Installed {} entries", count);
+    if json {
+        return print_json(&build_summary(&taxon_cache, None));
+    }
+    if let Some(ref metadata) = taxon_cache.metadata {
+        println!("{}", metadata);
     }
-    println!("\n{} entries total", taxon_cache.mappings.len())
+    Ok(())
 }
@@ -0,0 +1,149 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Record of the data files already scanned, so incremental builds can skip them
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomically;
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+
+/// Size and modification time of a data file when scanned, and the first taxid found in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileState {
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch
+    pub modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taxid: Option<TaxId>,
+}
+
+impl FileState {
+    /// Current state of the file at `path`, without a taxid
+    pub fn from_path(path: &Path) -> Result<FileState, ASDBTaxonError> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|age| age.as_nanos() as u64)
+            .unwrap_or_default();
+        Ok(FileState {
+            size: metadata.len(),
+            modified,
+            taxid: None,
+        })
+    }
+
+    /// Whether the file looks the same as in `other`, ignoring the taxid
+    pub fn unchanged(&self, other: &FileState) -> bool {
+        self.size == other.size && self.modified == other.modified
+    }
+}
+
+/// Data files scanned by earlier builds, keyed by absolute path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScanManifest {
+    pub files: HashMap<String, FileState>,
+}
+
+impl ScanManifest {
+    pub fn new() -> ScanManifest {
+        Default::default()
+    }
+
+    /// Manifest stored in `path`, or an empty one if that doesn't exist yet
+    pub fn load_path(path: &Path) -> Result<ScanManifest, ASDBTaxonError> {
+        match fs::read_to_string(path) {
+            Ok(json_data) => Ok(serde_json::from_str(&json_data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ScanManifest::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save_path(&self, path: &Path) -> Result<(), ASDBTaxonError> {
+        let json_data = serde_json::to_string(self)?;
+        write_atomically(path, json_data.as_bytes())
+    }
+
+    /// Whether `path` was scanned before and hasn't changed since
+    pub fn is_unchanged(&self, path: &Path, state: &FileState) -> bool {
+        self.files
+            .get(&key(path))
+            .is_some_and(|known| known.unchanged(state))
+    }
+
+    /// Remember that `path` was scanned in `state`
+    pub fn record(&mut self, path: &Path, state: FileState) {
+        self.files.insert(key(path), state);
+    }
+}
+
+/// Where the manifest for `cache_file` is kept, next to it
+pub fn manifest_path(cache_file: &Path) -> PathBuf {
+    let mut path = cache_file.as_os_str().to_owned();
+    path.push(".manifest");
+    PathBuf::from(path)
+}
+
+fn key(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_manifest() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let data = dir.join("a.json");
+        fs::write(&data, r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+
+        let mut manifest = ScanManifest::new();
+        let state = FileState::from_path(&data).unwrap();
+        assert!(!manifest.is_unchanged(&data, &state));
+        manifest.record(
+            &data,
+            FileState {
+                taxid: Some(tid(1883)),
+                ..state
+            },
+        );
+        assert!(manifest.is_unchanged(&data, &state));
+
+        let path = manifest_path(&dir.join("cache.json"));
+        manifest.save_path(&path).unwrap();
+        let loaded = ScanManifest::load_path(&path).unwrap();
+        assert_eq!(loaded, manifest);
+
+        fs::write(&data, r#"{"dbxrefs": ["taxon:5062", "taxon:1883"]}"#).unwrap();
+        let changed = FileState::from_path(&data).unwrap();
+        let missing = ScanManifest::load_path(&dir.join("missing")).unwrap();
+
+        assert!(!loaded.is_unchanged(&data, &changed));
+        assert!(missing.files.is_empty());
+    }
+}
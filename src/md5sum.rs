@@ -0,0 +1,51 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MD5 checksums, as published next to the NCBI downloads
+
+#[cfg(feature = "fs")]
+use std::io::{self, Read};
+
+#[cfg(feature = "fs")]
+use md5::{Digest, Md5};
+
+/// Hex digest of everything read from `reader`
+#[cfg(feature = "fs")]
+pub(crate) fn md5_hex(mut reader: impl Read) -> io::Result<String> {
+    let mut md5 = Md5::new();
+    io::copy(&mut reader, &mut md5)?;
+    Ok(hex(&md5.finalize()))
+}
+
+/// Lowercase hex representation of a digest
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_md5_hex() {
+        assert_eq!(
+            md5_hex("".as_bytes()).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert_eq!(
+            md5_hex("The quick brown fox jumps over the lazy dog".as_bytes()).unwrap(),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+    }
+}
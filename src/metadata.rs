@@ -0,0 +1,177 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Provenance of a cache: which taxdump it was built from, when and how
+
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "fs")]
+use crate::errors::ASDBTaxonError;
+#[cfg(feature = "fs")]
+use crate::md5sum::md5_hex;
+#[cfg(feature = "fs")]
+use crate::remote;
+
+/// Information on the last build of a cache
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CacheMetadata {
+    /// Version of asdb-taxa that built the cache
+    pub crate_version: String,
+    /// Build time, in seconds since the Unix epoch
+    pub built_at: u64,
+    #[serde(default)]
+    pub datadirs: Vec<String>,
+    pub taxdump: Option<DumpInfo>,
+    pub entry_count: usize,
+    /// The build stopped at its time limit, so entries may be missing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Tag of the taxonomy release, see [CacheMetadata::release_tag]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+}
+
+/// Identifies the version of a dump file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DumpInfo {
+    pub path: String,
+    /// Modification time of the file, in seconds since the Unix epoch
+    pub modified: Option<u64>,
+    pub md5: String,
+}
+
+impl DumpInfo {
+    /// Version of a local dump; URLs are only streamed once, so just their path is recorded
+    #[cfg(feature = "fs")]
+    pub fn from_path(path: &Path) -> Result<DumpInfo, ASDBTaxonError> {
+        if remote::is_remote(path) {
+            return Ok(DumpInfo {
+                path: path.display().to_string(),
+                ..Default::default()
+            });
+        }
+        let modified = fs::metadata(path)?
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs());
+        Ok(DumpInfo {
+            path: path.display().to_string(),
+            modified,
+            md5: md5_hex(fs::File::open(path)?)?,
+        })
+    }
+}
+
+impl CacheMetadata {
+    /// Metadata for a build by this version, happening now
+    pub fn new() -> CacheMetadata {
+        CacheMetadata {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            built_at: now(),
+            ..Default::default()
+        }
+    }
+
+    /// The release tag the cache was built with, or else the date of its taxdump or build
+    pub fn release_tag(&self) -> String {
+        if let Some(ref release) = self.release {
+            return release.clone();
+        }
+        let date = self
+            .taxdump
+            .as_ref()
+            .and_then(|taxdump| taxdump.modified)
+            .unwrap_or(self.built_at);
+        format_timestamp(date)[..10].to_string()
+    }
+}
+
+impl fmt::Display for CacheMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Built by asdb-taxa {}", self.crate_version)?;
+        writeln!(f, "Built at {}", format_timestamp(self.built_at))?;
+        for datadir in &self.datadirs {
+            writeln!(f, "Datadir {}", datadir)?;
+        }
+        if let Some(ref taxdump) = self.taxdump {
+            writeln!(f, "Taxdump {}", taxdump.path)?;
+            if let Some(modified) = taxdump.modified {
+                writeln!(f, "Taxdump date {}", format_timestamp(modified))?;
+            }
+            if !taxdump.md5.is_empty() {
+                writeln!(f, "Taxdump MD5 {}", taxdump.md5)?;
+            }
+        }
+        writeln!(f, "Release {}", self.release_tag())?;
+        if self.partial {
+            writeln!(f, "Partial build, stopped at the time limit")?;
+        }
+        write!(f, "{} entries at build time", self.entry_count)
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|age| age.as_secs())
+        .unwrap_or_default()
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 UTC timestamp
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20Z");
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parent links between taxa, as found in nodes.dmp
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Read};
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+
+/// The NCBI taxonomy tree, as a mapping of each taxid to its parent and rank
+#[derive(Debug, Clone, Default)]
+pub struct TaxonTree {
+    parents: HashMap<TaxId, TaxId>,
+    /// Index into `rank_names` for each taxid, as there are only a few dozen ranks
+    ranks: HashMap<TaxId, u16>,
+    rank_names: Vec<String>,
+}
+
+impl TaxonTree {
+    pub fn new() -> TaxonTree {
+        Default::default()
+    }
+
+    /// Read the parent links and ranks from a nodes.dmp file
+    pub fn load(nodes: impl Read) -> Result<TaxonTree, ASDBTaxonError> {
+        let mut tree = TaxonTree::new();
+        for line in io::BufReader::new(nodes).lines().map_while(Result::ok) {
+            let parts: Vec<&str> = line.trim().splitn(4, '|').map(|part| part.trim()).collect();
+            if parts.len() < 2 {
+                continue;
+            }
+            let tax_id = parts[0].parse()?;
+            tree.insert(tax_id, parts[1].parse()?);
+            if let Some(rank) = parts.get(2).filter(|rank| !rank.is_empty()) {
+                tree.set_rank(tax_id, rank);
+            }
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    pub fn insert(&mut self, tax_id: TaxId, parent_id: TaxId) {
+        self.parents.insert(tax_id, parent_id);
+    }
+
+    pub fn set_rank(&mut self, tax_id: TaxId, rank: &str) {
+        let index = match self.rank_names.iter().position(|name| name == rank) {
+            Some(index) => index,
+            None => {
+                self.rank_names.push(rank.to_string());
+                self.rank_names.len() - 1
+            }
+        };
+        self.ranks.insert(tax_id, index as u16);
+    }
+
+    /// Rank of `tax_id`, e.g. "genus" or "no rank"
+    pub fn rank(&self, tax_id: TaxId) -> Option<&str> {
+        self.ranks
+            .get(&tax_id)
+            .map(|index| self.rank_names[*index as usize].as_str())
+    }
+
+    /// Parent of `tax_id`, or `None` for the root and unknown taxids
+    pub fn parent(&self, tax_id: TaxId) -> Option<TaxId> {
+        self.parents
+            .get(&tax_id)
+            .copied()
+            .filter(|parent| *parent != tax_id)
+    }
+
+    /// All ancestors of `tax_id`, nearest first, not including the root
+    pub fn ancestors(&self, tax_id: TaxId) -> Vec<TaxId> {
+        let mut ancestors = Vec::new();
+        let mut current = tax_id;
+        while let Some(parent) = self.parent(current) {
+            if self.parent(parent).is_none() || ancestors.contains(&parent) {
+                break;
+            }
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+
+    /// Add the ancestors of all `taxids` to the set, returning the ones that weren't in it yet
+    pub fn add_ancestors(&self, taxids: &mut HashSet<TaxId>) -> HashSet<TaxId> {
+        let mut added = HashSet::new();
+        for tax_id in taxids.iter() {
+            for ancestor in self.ancestors(*tax_id) {
+                if !taxids.contains(&ancestor) && !added.insert(ancestor) {
+                    // the rest of this chain was already added via a sibling
+                    break;
+                }
+            }
+        }
+        taxids.extend(added.iter());
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_ancestors() {
+        let nodes = "1\t|\t1\t|\tno rank\t|\n\
+                     2\t|\t1\t|\tsuperkingdom\t|\n\
+                     1883\t|\t2\t|\tgenus\t|\n\
+                     12345\t|\t1883\t|\tspecies\t|\n\
+                     23456\t|\t1883\t|\tspecies\t|\n";
+        let tree = TaxonTree::load(nodes.as_bytes()).unwrap();
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.parent(tid(12345)), Some(tid(1883)));
+        assert_eq!(tree.parent(tid(1)), None);
+        assert_eq!(tree.rank(tid(1883)), Some("genus"));
+        assert_eq!(tree.rank(tid(23456)), Some("species"));
+        assert_eq!(tree.rank(tid(42)), None);
+        assert_eq!(tree.ancestors(tid(12345)), vec![1883, 2]);
+        assert!(tree.ancestors(tid(42)).is_empty());
+
+        let mut taxids: HashSet<TaxId> = [tid(12345), tid(23456), tid(2)].into_iter().collect();
+        let added = tree.add_ancestors(&mut taxids);
+        assert_eq!(added, [tid(1883)].into_iter().collect());
+        assert_eq!(taxids.len(), 4);
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capitalization of taxon names following the ICNP/ICN conventions
+
+/// Lowercase words NCBI uses for names of organisms that were never properly classified
+const PLACEHOLDERS: [&str; 6] = [
+    "uncultured",
+    "unidentified",
+    "unclassified",
+    "environmental",
+    "unknown",
+    "mixed",
+];
+
+/// Words after which the next word is an epithet again, e.g. `subsp. lividans`
+const EPITHET_MARKERS: [&str; 7] = ["subsp.", "var.", "f.", "pv.", "bv.", "cf.", "aff."];
+
+const CANDIDATUS: &str = "Candidatus";
+
+/// Canonicalizes the casing of names, e.g. "candidatus PELAGIBACTER Ubique" to
+/// "Candidatus Pelagibacter ubique"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Canonicalizer {
+    /// Wrap the Candidatus prefix in `<i></i>`, as it is italicized while the name isn't
+    pub candidatus_markers: bool,
+}
+
+impl Canonicalizer {
+    pub fn new(candidatus_markers: bool) -> Canonicalizer {
+        Canonicalizer { candidatus_markers }
+    }
+
+    /// Canonicalize a full organism name, such as the `name` column of an entry
+    pub fn name(&self, name: &str) -> String {
+        let mut words: Vec<String> = Vec::new();
+        let mut rest = name.split(' ').peekable();
+
+        if rest.next_if(|word| is_candidatus(word)).is_some() {
+            words.push(self.candidatus());
+        }
+        while let Some(word) =
+            rest.next_if(|word| PLACEHOLDERS.contains(&word.to_lowercase().as_str()))
+        {
+            words.push(word.to_lowercase());
+        }
+        let Some(genus) = rest.next() else {
+            return words.join(" ");
+        };
+        words.push(capitalize(genus));
+
+        let mut epithet = true;
+        for word in rest {
+            let lower = word.to_lowercase();
+            if EPITHET_MARKERS.contains(&lower.as_str()) || lower == "sp." {
+                epithet = lower != "sp.";
+                words.push(lower);
+            } else if epithet {
+                words.push(lowercase(word));
+                epithet = false;
+            } else {
+                // strain designations and the like stay untouched
+                words.push(word.to_string());
+            }
+        }
+        words.join(" ")
+    }
+
+    /// Canonicalize a single-word name of a genus or higher rank
+    pub fn uninomial(&self, name: &str) -> String {
+        match name.split_once(' ') {
+            Some((prefix, rest)) if is_candidatus(prefix) => {
+                format!("{} {}", self.candidatus(), capitalize(rest))
+            }
+            _ => capitalize(name),
+        }
+    }
+
+    /// Canonicalize a species epithet
+    pub fn epithet(&self, epithet: &str) -> String {
+        lowercase(epithet)
+    }
+
+    fn candidatus(&self) -> String {
+        if self.candidatus_markers {
+            format!("<i>{}</i>", CANDIDATUS)
+        } else {
+            CANDIDATUS.to_string()
+        }
+    }
+}
+
+fn is_candidatus(word: &str) -> bool {
+    word.eq_ignore_ascii_case(CANDIDATUS) || word.eq_ignore_ascii_case("Ca.")
+}
+
+/// Whether a word is a plain latinized name, rather than e.g. "SAR11" or "Unknown"
+fn is_latin(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_alphabetic() || c == '-') && word != "Unknown"
+}
+
+fn capitalize(word: &str) -> String {
+    // misclassified names are given in brackets, e.g. "[Clostridium]"
+    let (open, inner, close) = match word.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+        Some(inner) => ("[", inner, "]"),
+        None => ("", word, ""),
+    };
+    if !is_latin(inner) {
+        return word.to_string();
+    }
+    let mut chars = inner.chars();
+    let first = chars
+        .next()
+        .map(|c| c.to_uppercase().to_string())
+        .unwrap_or_default();
+    format!(
+        "{}{}{}{}",
+        open,
+        first,
+        chars.as_str().to_lowercase(),
+        close
+    )
+}
+
+fn lowercase(word: &str) -> String {
+    if is_latin(word) {
+        word.to_lowercase()
+    } else {
+        word.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let plain = Canonicalizer::new(false);
+        assert_eq!(
+            plain.name("candidatus PELAGIBACTER Ubique HTCC1062"),
+            "Candidatus Pelagibacter ubique HTCC1062"
+        );
+        assert_eq!(
+            plain.name("Streptomyces Lividans subsp. Lividans TK24"),
+            "Streptomyces lividans subsp. lividans TK24"
+        );
+        assert_eq!(
+            plain.name("Streptomyces SP. NBC12345"),
+            "Streptomyces sp. NBC12345"
+        );
+        assert_eq!(
+            plain.name("Uncultured streptomyces sp."),
+            "uncultured Streptomyces sp."
+        );
+        assert_eq!(
+            plain.name("[clostridium] Innocuum"),
+            "[Clostridium] innocuum"
+        );
+        assert_eq!(
+            plain.name("SAR11 cluster bacterium"),
+            "SAR11 cluster bacterium"
+        );
+        assert_eq!(plain.uninomial("STREPTOMYCES"), "Streptomyces");
+        assert_eq!(plain.uninomial("Unknown"), "Unknown");
+        assert_eq!(plain.epithet("Coelicolor"), "coelicolor");
+
+        let marked = Canonicalizer::new(true);
+        assert_eq!(
+            marked.uninomial("Candidatus pelagibacter"),
+            "<i>Candidatus</i> Pelagibacter"
+        );
+    }
+}
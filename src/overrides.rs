@@ -0,0 +1,213 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Curated corrections of entry fields, for lineages the NCBI has wrong or not yet updated
+//!
+//! Overrides are kept in the cache and applied again after every build, so they survive `add`
+//! and rebuilt entries. They are read from TSV files with `taxid<TAB>field<TAB>value` lines or
+//! JSON files like `{"1902": {"genus": "Streptomyces"}}`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+
+/// Entry field an override replaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Field {
+    Name,
+    Strain,
+    Lineage(Rank),
+}
+
+impl Field {
+    fn apply(&self, entry: &mut NcbiTaxEntry, value: &str) {
+        match self {
+            Field::Name => entry.name = value.to_string(),
+            Field::Strain => entry.strain = Some(value.to_string()),
+            Field::Lineage(rank) => *entry.rank_value_mut(*rank) = value.into(),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "name" => Ok(Field::Name),
+            "strain" => Ok(Field::Strain),
+            other => other.parse().map(Field::Lineage).map_err(|_| {
+                ASDBTaxonError::InvalidFormat(format!("cannot override field '{}'", s))
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for Field {
+    type Error = ASDBTaxonError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Field> for String {
+    fn from(field: Field) -> String {
+        field.to_string()
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Name => write!(f, "name"),
+            Field::Strain => write!(f, "strain"),
+            Field::Lineage(rank) => write!(f, "{}", rank),
+        }
+    }
+}
+
+/// Field values to replace, by taxid
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Overrides {
+    pub taxa: BTreeMap<TaxId, BTreeMap<Field, String>>,
+}
+
+impl Overrides {
+    pub fn new() -> Overrides {
+        Default::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taxa.is_empty()
+    }
+
+    /// Number of overridden fields, over all taxids
+    pub fn field_count(&self) -> usize {
+        self.taxa.values().map(BTreeMap::len).sum()
+    }
+
+    /// Load `taxid<TAB>field<TAB>value` lines, skipping empty lines and `#` comments
+    pub fn load_tsv(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let mut loaded = 0;
+        for line in io::BufReader::new(input).lines() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let [tax_id, field, value] = line.splitn(3, '\t').collect::<Vec<_>>()[..] else {
+                return Err(ASDBTaxonError::InvalidFormat(format!(
+                    "override line '{}' needs a taxid, field and value",
+                    line
+                )));
+            };
+            self.taxa
+                .entry(tax_id.trim().parse()?)
+                .or_default()
+                .insert(field.parse()?, value.trim().to_string());
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Load a JSON object mapping taxids to objects of field values
+    pub fn load_json(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let loaded: Overrides = serde_json::from_reader(io::BufReader::new(input))?;
+        let count = loaded.field_count();
+        self.merge(loaded);
+        Ok(count)
+    }
+
+    /// Load an overrides file, picking the format based on the file name
+    #[cfg(feature = "fs")]
+    pub fn load_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        let input = fs::File::open(path)?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            self.load_json(input)
+        } else {
+            self.load_tsv(input)
+        }
+    }
+
+    /// Add all of `other`, replacing values of fields both override
+    pub fn merge(&mut self, other: Overrides) {
+        for (tax_id, fields) in other.taxa {
+            self.taxa.entry(tax_id).or_default().extend(fields);
+        }
+    }
+
+    /// Set the overridden fields of `entry`, returning whether there were any
+    pub fn apply(&self, entry: &mut NcbiTaxEntry) -> bool {
+        let Some(fields) = self.taxa.get(&entry.tax_id) else {
+            return false;
+        };
+        for (field, value) in fields {
+            field.apply(entry, value);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_overrides() {
+        let mut overrides = Overrides::new();
+        let tsv = "# curated\n1902\tgenus\tStreptomyces\n1902\tphylum\tActinomycetota\n\n23456\tname\tStreptomyces examplis NBC12345\n";
+        assert_eq!(overrides.load_tsv(tsv.as_bytes()).unwrap(), 3);
+        let json = r#"{"1902": {"family": "Streptomycetaceae", "genus": "Kitasatospora"}}"#;
+        assert_eq!(overrides.load_json(json.as_bytes()).unwrap(), 2);
+        assert_eq!(overrides.taxa.len(), 2);
+        assert_eq!(overrides.field_count(), 4);
+
+        let mut entry = NcbiTaxEntry {
+            genus: "Streptomyces".into(),
+            ..NcbiTaxEntry::new(tid(1902))
+        };
+        assert!(overrides.apply(&mut entry));
+        assert_eq!(&*entry.genus, "Kitasatospora");
+        assert_eq!(&*entry.phylum, "Actinomycetota");
+
+        let roundtrip: Overrides =
+            serde_json::from_str(&serde_json::to_string(&overrides).unwrap()).unwrap();
+        assert_eq!(roundtrip, overrides);
+
+        for invalid in [
+            "1902\tgenus\n",
+            "1902\tserotype\tO157\n",
+            "x\tgenus\tStreptomyces\n",
+        ] {
+            assert!(
+                Overrides::new().load_tsv(invalid.as_bytes()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
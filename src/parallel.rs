@@ -0,0 +1,274 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing line-based dumps on a pool of worker threads
+//!
+//! The input is read in the calling thread and cut into chunks of whole lines, which the workers
+//! parse. The parsed chunks are handed back in input order, so the result is the same as parsing
+//! line by line, e.g. when a later line replaces the entry of an earlier one.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::ops::ControlFlow;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Default size of the chunks handed to the workers
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Number of worker threads to use by default, one per CPU
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|cpus| cpus.get())
+        .unwrap_or(1)
+}
+
+/// Parse the lines of `input` in chunks with `parse` on `jobs` threads, calling `on_chunk` with
+/// the parsed chunks in input order
+///
+/// Chunks are read `chunk_size` bytes at a time and end with a complete line. Reading stops early
+/// once `on_chunk` breaks. A panic in `parse` fails the whole run with an error.
+pub fn map_chunks<T, E>(
+    mut input: impl Read,
+    jobs: usize,
+    chunk_size: usize,
+    parse: impl Fn(&str) -> Result<T, E> + Sync,
+    mut on_chunk: impl FnMut(T) -> Result<ControlFlow<()>, E>,
+) -> Result<(), E>
+where
+    T: Send,
+    E: Send + From<io::Error>,
+{
+    let (chunk_sender, chunk_receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(jobs * 2);
+    let (result_sender, result_receiver) = mpsc::sync_channel(jobs * 2);
+    let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+
+    thread::scope(|scope| {
+        // owned by the scope closure, so returning early on errors stops the workers
+        let chunk_sender = chunk_sender;
+        for _ in 0..jobs.max(1) {
+            let chunks = Arc::clone(&chunk_receiver);
+            let results = result_sender.clone();
+            let parse = &parse;
+            scope.spawn(move || loop {
+                // the lock is only held while waiting for the next chunk
+                let next = match chunks.lock() {
+                    Ok(chunks) => chunks.recv(),
+                    Err(_) => return,
+                };
+                let Ok((index, chunk)) = next else {
+                    return;
+                };
+                let parsed = String::from_utf8(chunk)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into())
+                    .and_then(|text| {
+                        panic::catch_unwind(AssertUnwindSafe(|| parse(&text))).unwrap_or_else(
+                            |_| Err(io::Error::other("parsing a chunk panicked").into()),
+                        )
+                    });
+                // a failed send means the consumer stopped after an error
+                if results.send((index, parsed)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut collector = Collector {
+            results: result_receiver,
+            pending: BTreeMap::new(),
+            next: 0,
+        };
+        let mut carry = Vec::new();
+        let mut sent = 0;
+        loop {
+            let mut chunk = std::mem::take(&mut carry);
+            let done = fill(&mut input, &mut chunk, chunk_size)?;
+            if !done {
+                match chunk.iter().rposition(|byte| *byte == b'\n') {
+                    Some(end) => carry = chunk.split_off(end + 1),
+                    // a single line longer than the chunk size, keep reading
+                    None => {
+                        carry = chunk;
+                        continue;
+                    }
+                }
+            }
+            if !chunk.is_empty() {
+                let mut job = (sent, chunk);
+                // both channels are bounded, so keep handing on results while the workers are busy
+                loop {
+                    match chunk_sender.try_send(job) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(unsent)) => {
+                            job = unsent;
+                            if collector.drain(true, &mut on_chunk)?.is_break() {
+                                return Ok(());
+                            }
+                        }
+                        Err(TrySendError::Disconnected(_)) => return Err(workers_stopped().into()),
+                    }
+                }
+                sent += 1;
+            }
+            if collector.drain(false, &mut on_chunk)?.is_break() {
+                return Ok(());
+            }
+            if done {
+                break;
+            }
+        }
+        drop(chunk_sender);
+        while collector.next < sent {
+            if collector.drain(true, &mut on_chunk)?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Append up to `size` bytes of `input` to `chunk`, returning whether the input ended
+fn fill(input: &mut impl Read, chunk: &mut Vec<u8>, size: usize) -> io::Result<bool> {
+    let size = size.max(1);
+    let read = input.take(size as u64).read_to_end(chunk)?;
+    Ok(read < size)
+}
+
+fn workers_stopped() -> io::Error {
+    io::Error::other("the parsing workers stopped early")
+}
+
+/// Puts the parsed chunks back into input order
+struct Collector<T, E> {
+    results: Receiver<(usize, Result<T, E>)>,
+    pending: BTreeMap<usize, T>,
+    next: usize,
+}
+
+impl<T, E> Collector<T, E> {
+    /// Hand all parsed chunks that are next in line to `on_chunk`, waiting for one if `block`
+    ///
+    /// Waiting fails if all workers stopped, as no more chunks can arrive then.
+    fn drain(
+        &mut self,
+        block: bool,
+        on_chunk: &mut impl FnMut(T) -> Result<ControlFlow<()>, E>,
+    ) -> Result<ControlFlow<()>, E>
+    where
+        E: From<io::Error>,
+    {
+        let received = if block {
+            Some(self.results.recv().map_err(|_| workers_stopped())?)
+        } else {
+            self.results.try_recv().ok()
+        };
+        if let Some((index, parsed)) = received {
+            self.pending.insert(index, parsed?);
+            while let Ok((index, parsed)) = self.results.try_recv() {
+                self.pending.insert(index, parsed?);
+            }
+        }
+        while let Some(parsed) = self.pending.remove(&self.next) {
+            self.next += 1;
+            if on_chunk(parsed)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the numbers of all lines, stopping after the chunk with `stop_at`
+    fn line_numbers(
+        input: &str,
+        jobs: usize,
+        chunk_size: usize,
+        stop_at: u32,
+    ) -> Result<Vec<u32>, io::Error> {
+        let mut numbers = Vec::new();
+        map_chunks(
+            input.as_bytes(),
+            jobs,
+            chunk_size,
+            |chunk| {
+                chunk
+                    .lines()
+                    .map(|line| {
+                        line.parse::<u32>()
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                    })
+                    .collect::<Result<Vec<u32>, io::Error>>()
+            },
+            |parsed| {
+                numbers.extend(parsed);
+                Ok(match numbers.last() {
+                    Some(&last) if last >= stop_at => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                })
+            },
+        )?;
+        Ok(numbers)
+    }
+
+    #[test]
+    fn test_map_chunks() {
+        let expected: Vec<u32> = (0..10_000).collect();
+        let mut input: String = expected.iter().map(|n| format!("{}\n", n)).collect();
+        for (jobs, chunk_size) in [(1, 1), (4, 3), (4, 1000), (16, DEFAULT_CHUNK_SIZE)] {
+            assert_eq!(
+                line_numbers(&input, jobs, chunk_size, u32::MAX).unwrap(),
+                expected
+            );
+        }
+        // the last line doesn't need a newline
+        input.pop();
+        assert_eq!(line_numbers(&input, 4, 100, u32::MAX).unwrap(), expected);
+        assert!(line_numbers("", 4, 100, u32::MAX).unwrap().is_empty());
+
+        let first = line_numbers(&input, 4, 100, 50).unwrap();
+        assert!(first.len() < expected.len());
+        assert_eq!(first, expected[..first.len()]);
+        assert!(first.contains(&50));
+
+        input.push_str("\nnot a number\n");
+        assert!(line_numbers(&input, 4, 100, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_map_chunks_panic() {
+        let input: String = (0..1000).map(|n| format!("{}\n", n)).collect();
+        for jobs in [1, 4] {
+            let result = map_chunks(
+                input.as_bytes(),
+                jobs,
+                10,
+                |chunk| {
+                    if chunk.lines().any(|line| line == "500") {
+                        panic!("cannot parse {:?}", chunk);
+                    }
+                    Ok::<_, io::Error>(())
+                },
+                |()| Ok(ControlFlow::Continue(())),
+            );
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Other);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Progress reporting for long-running operations
+
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+/// Don't report progress more often than every this many bytes
+const REPORT_INTERVAL: u64 = 1 << 20;
+
+/// Long-running phases of building a cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Scanning data files, counted in files
+    Scanning,
+    /// Parsing the taxdump, counted in bytes
+    Parsing,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Scanning => write!(f, "scanning"),
+            Stage::Parsing => write!(f, "parsing"),
+        }
+    }
+}
+
+/// Called with the current stage, the amount of work done and the total amount of work
+pub type ProgressHook = Box<dyn FnMut(Stage, u64, u64) + Send>;
+
+/// Observer of the progress of a build, e.g. for a GUI or web service showing it to users
+///
+/// Unlike a [ProgressHook], an observer is shared, so whoever registered it can keep a handle to
+/// query it from other threads while the build runs. Closures with the same arguments are
+/// observers as well.
+pub trait ProgressObserver: Send + Sync {
+    /// Called periodically with the current stage, the amount of work done and the total
+    fn on_stage(&self, stage: Stage, done: u64, total: u64);
+}
+
+impl<F: Fn(Stage, u64, u64) + Send + Sync> ProgressObserver for F {
+    fn on_stage(&self, stage: Stage, done: u64, total: u64) {
+        self(stage, done, total)
+    }
+}
+
+/// Estimate of the time left in a stage, from the rate of progress since it started
+#[derive(Debug, Clone)]
+pub struct Eta {
+    stage: Option<Stage>,
+    started: Instant,
+}
+
+impl Default for Eta {
+    fn default() -> Self {
+        Eta {
+            stage: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Eta {
+    pub fn new() -> Eta {
+        Default::default()
+    }
+
+    /// Record the progress of `stage`, returning the time left if there's enough to go by
+    ///
+    /// A new stage restarts the clock.
+    pub fn update(&mut self, stage: Stage, done: u64, total: u64) -> Option<Duration> {
+        if self.stage != Some(stage) {
+            self.stage = Some(stage);
+            self.started = Instant::now();
+        }
+        estimate(self.started.elapsed(), done, total)
+    }
+}
+
+/// Time left for `total` after taking `elapsed` for `done`, assuming a constant rate
+pub fn estimate(elapsed: Duration, done: u64, total: u64) -> Option<Duration> {
+    if done == 0 || done > total {
+        return None;
+    }
+    Some(elapsed.mul_f64((total - done) as f64 / done as f64))
+}
+
+/// Reader reporting how many bytes have been read so far
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    done: u64,
+    total: u64,
+    last_report: u64,
+    callback: &'a mut dyn FnMut(u64, u64),
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub fn new(inner: R, total: u64, callback: &'a mut dyn FnMut(u64, u64)) -> Self {
+        ProgressReader {
+            inner,
+            done: 0,
+            total,
+            last_report: 0,
+            callback,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.done += read as u64;
+        if read == 0 || self.done - self.last_report >= REPORT_INTERVAL {
+            self.last_report = self.done;
+            (self.callback)(self.done, self.total);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_reader() {
+        let data = vec![0u8; 3 * REPORT_INTERVAL as usize];
+        let mut reports = Vec::new();
+        let mut callback = |done, total| reports.push((done, total));
+        let mut reader = ProgressReader::new(data.as_slice(), data.len() as u64, &mut callback);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        let total = data.len() as u64;
+        assert_eq!(reports.last(), Some(&(total, total)));
+        assert!(reports.len() >= 3);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(estimate(minute, 25, 100), Some(Duration::from_secs(180)));
+        assert_eq!(estimate(minute, 100, 100), Some(Duration::ZERO));
+        assert_eq!(estimate(minute, 0, 100), None);
+        // streamed dumps have no known size
+        assert_eq!(estimate(minute, 100, 0), None);
+
+        let mut eta = Eta::new();
+        assert_eq!(eta.update(Stage::Scanning, 0, 10), None);
+        assert!(eta.update(Stage::Scanning, 5, 10).is_some());
+        assert!(eta.update(Stage::Parsing, 5, 10).unwrap() < minute);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where each cache entry came from, and when
+//!
+//! Builds, updates and custom insertions [stamp] the entries they add or change with a
+//! [Provenance], next to the [EntrySource](crate::taxa::EntrySource) every entry has. Overridden
+//! fields are kept with the overrides of the cache, see [Provenance::overridden].
+//! Entries of caches built before provenance was tracked get theirs once they change.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::{format_timestamp, DumpInfo};
+use crate::overrides::Field;
+use crate::taxa::{EntrySource, NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+/// When an entry was added and last changed, and the dump it was read from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Provenance {
+    /// File name of the taxdump the entry was last read from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump: Option<String>,
+    /// Modification time of that taxdump, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_modified: Option<u64>,
+    /// In seconds since the Unix epoch
+    pub added_at: u64,
+    /// In seconds since the Unix epoch
+    pub updated_at: u64,
+}
+
+impl Provenance {
+    /// Provenance of an entry added at `at`, read from `dump` if given
+    pub fn new(at: u64, dump: Option<&DumpInfo>) -> Provenance {
+        let mut provenance = Provenance {
+            added_at: at,
+            updated_at: at,
+            ..Default::default()
+        };
+        provenance.read_from(dump);
+        provenance
+    }
+
+    fn read_from(&mut self, dump: Option<&DumpInfo>) {
+        self.dump = dump.map(|dump| {
+            Path::new(&dump.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| dump.path.clone())
+        });
+        self.dump_modified = dump.and_then(|dump| dump.modified);
+    }
+
+    /// The fields of the entry with `tax_id` the overrides of `cache` replace
+    pub fn overridden(cache: &TaxonCache, tax_id: TaxId) -> Vec<Field> {
+        cache
+            .overrides
+            .taxa
+            .get(&tax_id)
+            .map(|fields| fields.keys().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref dump) = self.dump {
+            write!(f, "read from {}", dump)?;
+            if let Some(modified) = self.dump_modified {
+                write!(f, " of {}", format_timestamp(modified))?;
+            }
+            write!(f, ", ")?;
+        }
+        write!(
+            f,
+            "added {}, last updated {}",
+            format_timestamp(self.added_at),
+            format_timestamp(self.updated_at)
+        )
+    }
+}
+
+/// Stamp the entries of `cache` that are new or changed compared to `before`, as of `now`
+///
+/// Entries read from the taxdump are stamped with `dump`, unchanged entries keep their
+/// provenance.
+pub fn stamp(
+    cache: &mut TaxonCache,
+    before: &HashMap<TaxId, NcbiTaxEntry>,
+    dump: Option<&DumpInfo>,
+    now: u64,
+) {
+    for (tax_id, entry) in cache.mappings_mut().iter_mut() {
+        stamp_entry(entry, before.get(tax_id), dump, now);
+    }
+}
+
+/// Like [stamp], only stamping the entries of `taxids`, e.g. the ones a build resolved
+///
+/// `before` only needs to hold the previous versions of those entries.
+pub fn stamp_taxids(
+    cache: &mut TaxonCache,
+    taxids: &HashSet<TaxId>,
+    before: &HashMap<TaxId, NcbiTaxEntry>,
+    dump: Option<&DumpInfo>,
+    now: u64,
+) {
+    for tax_id in taxids {
+        if let Some(entry) = cache.mappings_mut().get_mut(tax_id) {
+            stamp_entry(entry, before.get(tax_id), dump, now);
+        }
+    }
+}
+
+/// Stamp `entry` if it's new or changed compared to its `previous` version, see [stamp]
+pub fn stamp_entry(
+    entry: &mut NcbiTaxEntry,
+    previous: Option<&NcbiTaxEntry>,
+    dump: Option<&DumpInfo>,
+    now: u64,
+) {
+    let dump = dump.filter(|_| entry.source == EntrySource::Taxdump);
+    let Some(previous) = previous else {
+        entry.provenance = Some(Provenance::new(now, dump));
+        return;
+    };
+    let provenance = previous.provenance.clone();
+    if same_content(previous, entry) {
+        entry.provenance = provenance;
+        return;
+    }
+    entry.provenance = Some(match provenance {
+        Some(mut provenance) => {
+            provenance.updated_at = now;
+            provenance.read_from(dump);
+            provenance
+        }
+        None => Provenance::new(now, dump),
+    });
+}
+
+/// Whether an update from `a` to `b` changed anything worth recording
+pub(crate) fn same_content(a: &NcbiTaxEntry, b: &NcbiTaxEntry) -> bool {
+    a.name == b.name
+        && a.strain == b.strain
+        && a.source == b.source
+        && Rank::ALL
+            .into_iter()
+            .all(|rank| a.rank_value(rank) == b.rank_value(rank))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, genus: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: format!("{} examplis", genus),
+            genus: genus.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_stamp() {
+        let dump = DumpInfo {
+            path: "dumps/rankedlineage.dmp".to_string(),
+            modified: Some(1000),
+            md5: String::new(),
+        };
+        let mut cache = TaxonCache::new();
+        cache.insert(entry(tid(1), "Streptomyces"));
+        cache.insert(entry(tid(2), "Aspergillus"));
+        stamp(&mut cache, &HashMap::new(), Some(&dump), 2000);
+        let provenance = cache.mappings[&tid(1)].provenance.clone().unwrap();
+        assert_eq!(provenance.dump.as_deref(), Some("rankedlineage.dmp"));
+        assert_eq!(provenance.dump_modified, Some(1000));
+        assert_eq!((provenance.added_at, provenance.updated_at), (2000, 2000));
+
+        let before = cache.mappings.clone();
+        cache.insert(entry(tid(1), "Kitasatospora"));
+        let mut custom = entry(tid(3), "Examplia");
+        custom.source = EntrySource::Custom;
+        cache.insert(custom);
+        stamp(&mut cache, &before, None, 3000);
+        let provenance = cache.mappings[&tid(1)].provenance.clone().unwrap();
+        assert_eq!((provenance.added_at, provenance.updated_at), (2000, 3000));
+        assert_eq!(provenance.dump, None);
+        assert_eq!(
+            cache.mappings[&tid(2)].provenance,
+            before[&tid(2)].provenance
+        );
+        assert_eq!(
+            cache.mappings[&tid(3)].provenance,
+            Some(Provenance::new(3000, None))
+        );
+        assert_eq!(
+            cache.mappings[&tid(1)]
+                .provenance
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "added 1970-01-01T00:33:20Z, last updated 1970-01-01T00:50:00Z"
+        );
+    }
+}
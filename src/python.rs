@@ -0,0 +1,221 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Python bindings, built into the `asdb_taxa` module with maturin
+//!
+//! Entries are handed to Python as dicts with the same keys as in the JSON cache, so scripts
+//! reading the cache file directly can switch over without changes to how they use the entries.
+
+// the code generated by #[pymethods] converts the error of every PyResult
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyKeyError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+
+use crate::errors::ASDBTaxonError;
+use crate::search::{NameMatch, NameQuery};
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+impl From<ASDBTaxonError> for PyErr {
+    fn from(err: ASDBTaxonError) -> PyErr {
+        match err {
+            ASDBTaxonError::Io(_) => PyOSError::new_err(err.to_string()),
+            ASDBTaxonError::NotFound(_) | ASDBTaxonError::Deleted(_) => {
+                PyKeyError::new_err(err.to_string())
+            }
+            _ => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// Convert a JSON value to the Python object `json.loads` would give
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(value) => value.into_py(py),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => number.into_py(py),
+            None => number.as_f64().unwrap_or_default().into_py(py),
+        },
+        Value::String(value) => value.into_py(py),
+        Value::Array(values) => {
+            let list = PyList::empty_bound(py);
+            for value in values {
+                list.append(to_python(py, value)?)?;
+            }
+            list.into_py(py)
+        }
+        Value::Object(fields) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in fields {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+fn entry_to_python(py: Python<'_>, entry: &NcbiTaxEntry) -> PyResult<PyObject> {
+    let value = serde_json::to_value(entry).map_err(ASDBTaxonError::from)?;
+    to_python(py, &value)
+}
+
+/// A loaded cache, see [TaxonCache]
+#[pyclass(name = "TaxonCache", module = "asdb_taxa", frozen)]
+pub struct PyTaxonCache {
+    cache: TaxonCache,
+}
+
+#[pymethods]
+impl PyTaxonCache {
+    /// Load the cache at `path`, in any format and compression
+    #[staticmethod]
+    fn load(py: Python<'_>, path: PathBuf) -> PyResult<PyTaxonCache> {
+        let mut cache = TaxonCache::new();
+        py.allow_threads(|| cache.load_path(&path))?;
+        Ok(PyTaxonCache { cache })
+    }
+
+    /// The entry of `taxid`, following merged taxids, or `None` if there is none
+    ///
+    /// With `as_of`, the entry as it was in the last release up to that tag.
+    #[pyo3(signature = (taxid, as_of=None))]
+    fn get(&self, py: Python<'_>, taxid: i64, as_of: Option<&str>) -> PyResult<PyObject> {
+        let taxid = TaxId::new(taxid)?;
+        let found = match as_of {
+            Some(as_of) => self.cache.lookup_as_of(taxid, as_of),
+            None => self.cache.lookup(taxid),
+        };
+        match found {
+            Ok(entry) => entry_to_python(py, entry),
+            Err(ASDBTaxonError::NotFound(_) | ASDBTaxonError::Deleted(_)) => Ok(py.None()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Entries named `name`, or starting with it with `prefix`, sorted by name
+    #[pyo3(signature = (name, prefix=false, ignore_case=false))]
+    fn search(
+        &self,
+        py: Python<'_>,
+        name: &str,
+        prefix: bool,
+        ignore_case: bool,
+    ) -> PyResult<Vec<PyObject>> {
+        let query = NameQuery {
+            matching: if prefix {
+                NameMatch::Prefix
+            } else {
+                NameMatch::Exact
+            },
+            ignore_case,
+        };
+        self.cache
+            .find_by_name_with(name, &query)
+            .into_iter()
+            .map(|entry| entry_to_python(py, entry))
+            .collect()
+    }
+
+    /// Tags of the releases kept in the cache and the current one, oldest first
+    fn release_tags(&self) -> Vec<String> {
+        self.cache.release_tags()
+    }
+
+    fn __getitem__(&self, py: Python<'_>, taxid: i64) -> PyResult<PyObject> {
+        entry_to_python(py, self.cache.lookup(TaxId::new(taxid)?)?)
+    }
+
+    fn __contains__(&self, taxid: i64) -> bool {
+        TaxId::new(taxid).is_ok_and(|taxid| self.cache.lookup(taxid).is_ok())
+    }
+
+    fn __len__(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// All entries, sorted by taxid
+    fn __iter__(slf: Py<PyTaxonCache>) -> EntryIter {
+        let taxids = slf
+            .get()
+            .cache
+            .iter_sorted_by_taxid()
+            .map(|entry| entry.tax_id)
+            .collect();
+        EntryIter {
+            cache: slf,
+            taxids,
+            next: 0,
+        }
+    }
+}
+
+/// Iterator over the entries of a [PyTaxonCache]
+#[pyclass(module = "asdb_taxa")]
+pub struct EntryIter {
+    cache: Py<PyTaxonCache>,
+    taxids: Vec<TaxId>,
+    next: usize,
+}
+
+#[pymethods]
+impl EntryIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let Some(&taxid) = slf.taxids.get(slf.next) else {
+            return Ok(None);
+        };
+        slf.next += 1;
+        let py = slf.py();
+        let entry = slf.cache.get().cache.lookup(taxid)?;
+        entry_to_python(py, entry).map(Some)
+    }
+}
+
+#[pymodule]
+fn asdb_taxa(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTaxonCache>()?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_to_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let value = serde_json::json!({"tax_id": 1883, "name": "Streptomyces", "rank": null});
+            let dict = to_python(py, &value).unwrap();
+            let dict = dict.downcast_bound::<PyDict>(py).unwrap();
+            let tax_id: i64 = dict.get_item("tax_id").unwrap().unwrap().extract().unwrap();
+            assert_eq!(tax_id, 1883);
+            assert!(dict.get_item("rank").unwrap().unwrap().is_none());
+
+            let err: PyErr = ASDBTaxonError::NotFound(tid(1)).into();
+            assert!(err.is_instance_of::<PyKeyError>(py));
+        });
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading ahead in a background thread, e.g. to decompress while the data is being parsed
+
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Default size of the chunks handed from the reading thread to the consumer
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Buffer size to use by default, reading in the calling thread if there is only one CPU
+pub fn default_buffer_size() -> usize {
+    match thread::available_parallelism() {
+        Ok(cpus) if cpus.get() > 1 => DEFAULT_BUFFER_SIZE,
+        _ => 0,
+    }
+}
+
+/// Number of chunks the reading thread may get ahead of the consumer
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Reader for the chunks sent by the background thread
+struct ChannelReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.current = chunk?;
+                    self.pos = 0;
+                }
+                // sender is gone after the end of the input
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Call `consumer` with a reader for `input`, reading `input` in a separate thread
+///
+/// The reading thread gets at most a few chunks of `buffer_size` bytes ahead of the consumer.
+/// A `buffer_size` of 0 reads `input` in the calling thread instead.
+pub fn with_read_ahead<R, T, E>(
+    mut input: R,
+    buffer_size: usize,
+    consumer: impl FnOnce(&mut dyn Read) -> Result<T, E>,
+) -> Result<T, E>
+where
+    R: Read + Send,
+{
+    if buffer_size == 0 {
+        return consumer(&mut input);
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+    thread::scope(|scope| {
+        scope.spawn(move || loop {
+            let mut chunk = vec![0; buffer_size];
+            let mut filled = 0;
+            let result = loop {
+                match input.read(&mut chunk[filled..]) {
+                    Ok(0) => break Ok(()),
+                    Ok(read) => {
+                        filled += read;
+                        if filled == buffer_size {
+                            break Ok(());
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                    Err(err) => break Err(err),
+                }
+            };
+            chunk.truncate(filled);
+            let done = filled < buffer_size;
+            let message = match result {
+                Ok(()) if chunk.is_empty() => return,
+                Ok(()) => Ok(chunk),
+                Err(err) => Err(err),
+            };
+            let failed = message.is_err();
+            // a failed send means the consumer is done already
+            if sender.send(message).is_err() || done || failed {
+                return;
+            }
+        });
+
+        let mut reader = ChannelReader {
+            chunks: receiver,
+            current: Vec::new(),
+            pos: 0,
+        };
+        // dropping the reader stops the reading thread if the consumer returns early
+        consumer(&mut reader)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_read_ahead() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for buffer_size in [0, 1, 7, 4096, DEFAULT_BUFFER_SIZE] {
+            let out = with_read_ahead(data.as_slice(), buffer_size, |reader| {
+                let mut out = Vec::new();
+                reader.read_to_end(&mut out).map(|_| out)
+            })
+            .unwrap();
+            assert_eq!(out, data, "buffer size {}", buffer_size);
+        }
+
+        // stopping early must not block on the reading thread
+        let first = with_read_ahead(data.as_slice(), 16, |reader| {
+            let mut first = [0u8; 4];
+            reader.read_exact(&mut first).map(|_| first)
+        })
+        .unwrap();
+        assert_eq!(first, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_read_ahead_error() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken"))
+            }
+        }
+        let result = with_read_ahead(Failing, 16, |reader| reader.read_to_end(&mut Vec::new()));
+        assert_eq!(result.unwrap_err().to_string(), "broken");
+    }
+}
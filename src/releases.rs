@@ -0,0 +1,194 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Earlier taxonomy releases of a cache, for looking up entries as they were
+//!
+//! Every [update](crate::builder::TaxonCacheBuilder::update) keeps what it changes of the release
+//! it replaces as a [Release] in [TaxonCache::releases]: the entries it changed or dropped, the
+//! merges it redirected and the taxids it added. Going through the releases from an old one up to
+//! the current entries finds the entries of that release, see [TaxonCache::lookup_as_of].
+//!
+//! Releases are tagged with the date of their taxdump unless named otherwise, and a tag matches
+//! all releases up to it, so `2023-06` finds the last release of June 2023 or before.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::provenance::same_content;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+/// What the next release changed of an earlier one
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Release {
+    pub tag: String,
+    /// Entries as they were in this release, of the taxids the next one changed or dropped
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub entries: HashMap<TaxId, NcbiTaxEntry>,
+    /// Merges of this release the next one redirected
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub merged: HashMap<TaxId, TaxId>,
+    /// Taxids only known since the next release
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub absent: HashSet<TaxId>,
+}
+
+impl Release {
+    /// Release `tag` as it was before updating to `cache`, with its `entries`, merged and
+    /// deleted taxids
+    pub fn compare(
+        tag: String,
+        entries: &HashMap<TaxId, NcbiTaxEntry>,
+        deprecated_ids: &HashMap<TaxId, TaxId>,
+        deleted_ids: &HashSet<TaxId>,
+        cache: &TaxonCache,
+    ) -> Release {
+        let changed = entries
+            .iter()
+            .filter(|(tax_id, entry)| {
+                cache
+                    .mappings
+                    .get(tax_id)
+                    .is_none_or(|current| !same_content(entry, current))
+            })
+            .map(|(tax_id, entry)| (*tax_id, entry.clone()))
+            .collect();
+        let merged = deprecated_ids
+            .iter()
+            .filter(|(tax_id, into)| cache.deprecated_ids.get(tax_id) != Some(into))
+            .map(|(tax_id, into)| (*tax_id, *into))
+            .collect();
+        let absent = cache
+            .mappings
+            .keys()
+            .chain(cache.deprecated_ids.keys())
+            .chain(cache.deleted_ids.iter())
+            .filter(|tax_id| {
+                !entries.contains_key(tax_id)
+                    && !deprecated_ids.contains_key(tax_id)
+                    && !deleted_ids.contains(tax_id)
+            })
+            .copied()
+            .collect();
+        Release {
+            tag,
+            entries: changed,
+            merged,
+            absent,
+        }
+    }
+
+    /// Whether the next release changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.merged.is_empty() && self.absent.is_empty()
+    }
+}
+
+/// Whether the release tagged `tag` is one of those up to `as_of`
+pub fn matches(tag: &str, as_of: &str) -> bool {
+    tag.get(..as_of.len()).unwrap_or(tag) <= as_of
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ASDBTaxonError;
+    use crate::metadata::CacheMetadata;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, genus: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: format!("{} examplis", genus),
+            genus: genus.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("2023-06-01", "2023-06"));
+        assert!(matches("2023-05-31", "2023-06"));
+        assert!(!matches("2023-07-01", "2023-06"));
+        assert!(matches("2023-06-01", "2023-06-01"));
+        assert!(!matches("2023-06-02", "2023-06-01"));
+    }
+
+    #[test]
+    fn test_lookup_as_of() {
+        let mut cache = TaxonCache::new();
+        cache.insert(entry(tid(1), "Streptomyces"));
+        cache.insert(entry(tid(2), "Aspergillus"));
+        cache.deprecated_ids.insert(tid(10), tid(2));
+        let before = cache.clone();
+
+        // the next release moves 1 to another genus, merges 2 into 3 and deletes 4
+        cache.mappings.remove(&tid(2));
+        cache.insert(entry(tid(1), "Kitasatospora"));
+        cache.insert(entry(tid(3), "Aspergillus"));
+        cache.deprecated_ids.insert(tid(2), tid(3));
+        cache.deprecated_ids.insert(tid(10), tid(3));
+        cache.deleted_ids.insert(tid(4));
+        let release = Release::compare(
+            "2023-01-01".to_string(),
+            &before.mappings,
+            &before.deprecated_ids,
+            &before.deleted_ids,
+            &cache,
+        );
+        assert_eq!(release.entries.len(), 2);
+        assert_eq!(release.merged, HashMap::from([(tid(10), tid(2))]));
+        assert_eq!(release.absent, HashSet::from([tid(3), tid(4)]));
+        cache.releases.push(release);
+        cache.metadata = Some(CacheMetadata {
+            release: Some("2023-07-01".to_string()),
+            ..Default::default()
+        });
+
+        let genus = |tax_id, as_of| {
+            cache
+                .lookup_as_of(tax_id, as_of)
+                .map(|entry| (entry.tax_id, entry.genus.to_string()))
+        };
+        assert_eq!(
+            genus(tid(1), "2023-06").unwrap(),
+            (tid(1), "Streptomyces".into())
+        );
+        assert_eq!(
+            genus(tid(1), "2023-07").unwrap(),
+            (tid(1), "Kitasatospora".into())
+        );
+        assert_eq!(
+            genus(tid(1), "2024").unwrap(),
+            (tid(1), "Kitasatospora".into())
+        );
+        assert_eq!(
+            genus(tid(10), "2023-06").unwrap(),
+            (tid(2), "Aspergillus".into())
+        );
+        assert_eq!(
+            genus(tid(2), "2023-07").unwrap(),
+            (tid(3), "Aspergillus".into())
+        );
+        assert!(matches!(
+            genus(tid(3), "2023-06"),
+            Err(ASDBTaxonError::NotFound(tax_id)) if tax_id == 3
+        ));
+        assert!(matches!(
+            genus(tid(1), "2022"),
+            Err(ASDBTaxonError::ReleaseNotFound(_))
+        ));
+        assert_eq!(cache.release_tags(), vec!["2023-01-01", "2023-07-01"]);
+    }
+}
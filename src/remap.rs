@@ -0,0 +1,159 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mappings of merged taxids to their current ones, for updating downstream database tables
+
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+use crate::TaxonCache;
+
+/// Output formats of [write_remap]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemapFormat {
+    /// `old_taxid<TAB>new_taxid` lines with a header
+    #[default]
+    Tsv,
+    /// `UPDATE` statements in a transaction
+    Sql,
+}
+
+impl FromStr for RemapFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(RemapFormat::Tsv),
+            "sql" => Ok(RemapFormat::Sql),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Where the `UPDATE` statements of [RemapFormat::Sql] change the taxids
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemapTarget {
+    pub table: String,
+    pub column: String,
+}
+
+impl RemapTarget {
+    /// A target table and column, which have to be plain SQL identifiers like `antismash.taxa`
+    pub fn new(table: &str, column: &str) -> Result<RemapTarget, ASDBTaxonError> {
+        for identifier in [table, column] {
+            let valid = !identifier.is_empty()
+                && identifier
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+            if !valid {
+                return Err(ASDBTaxonError::InvalidFormat(format!(
+                    "'{}' is not a plain SQL identifier",
+                    identifier
+                )));
+            }
+        }
+        Ok(RemapTarget {
+            table: table.to_string(),
+            column: column.to_string(),
+        })
+    }
+}
+
+impl Default for RemapTarget {
+    fn default() -> RemapTarget {
+        RemapTarget {
+            table: "antismash.taxa".to_string(),
+            column: "ncbi_taxid".to_string(),
+        }
+    }
+}
+
+/// All merged taxids with their current ones, sorted by merged taxid
+pub fn remappings(cache: &TaxonCache) -> Vec<(TaxId, TaxId)> {
+    let mut remappings: Vec<(TaxId, TaxId)> = cache
+        .deprecated_ids
+        .iter()
+        .map(|(old, new)| (*old, *new))
+        .collect();
+    remappings.sort();
+    remappings
+}
+
+/// Write the [remappings] of `cache` in `format`, returning their number
+pub fn write_remap(
+    cache: &TaxonCache,
+    mut output: impl Write,
+    format: RemapFormat,
+    target: &RemapTarget,
+) -> Result<usize, ASDBTaxonError> {
+    let remappings = remappings(cache);
+    match format {
+        RemapFormat::Tsv => {
+            writeln!(output, "old_taxid\tnew_taxid")?;
+            for (old, new) in &remappings {
+                writeln!(output, "{}\t{}", old, new)?;
+            }
+        }
+        RemapFormat::Sql => {
+            writeln!(output, "BEGIN;")?;
+            for (old, new) in &remappings {
+                writeln!(
+                    output,
+                    "UPDATE {} SET {} = {} WHERE {} = {};",
+                    target.table, target.column, new, target.column, old
+                )?;
+            }
+            writeln!(output, "COMMIT;")?;
+        }
+    }
+    Ok(remappings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_write_remap() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.deprecated_ids.insert(tid(5062), tid(5063));
+
+        let mut tsv = Vec::new();
+        let count =
+            write_remap(&cache, &mut tsv, RemapFormat::Tsv, &RemapTarget::default()).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            "old_taxid\tnew_taxid\n5062\t5063\n12345\t23456\n"
+        );
+
+        let mut sql = Vec::new();
+        let target = RemapTarget::new("antismash.genomes", "tax_id").unwrap();
+        write_remap(&cache, &mut sql, RemapFormat::Sql, &target).unwrap();
+        assert_eq!(
+            String::from_utf8(sql).unwrap(),
+            "BEGIN;\n\
+UPDATE antismash.genomes SET tax_id = 5063 WHERE tax_id = 5062;\n\
+UPDATE antismash.genomes SET tax_id = 23456 WHERE tax_id = 12345;\n\
+COMMIT;\n"
+        );
+
+        assert!(RemapTarget::new("taxa; DROP TABLE taxa", "tax_id").is_err());
+        assert!(RemapTarget::new("taxa", "").is_err());
+    }
+}
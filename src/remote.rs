@@ -0,0 +1,181 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading inputs from URLs as well as local files
+//!
+//! Dumps and caches given as URLs are streamed rather than downloaded first: `http://` and
+//! `https://` URLs with the `network` feature, see [crate::fetch::open_url], `s3://` URLs
+//! through the AWS command line tool (`aws s3 cp <url> -`) with the `s3` feature, which needs to
+//! be on the `PATH`.
+
+use std::fs;
+#[cfg(feature = "s3")]
+use std::io;
+use std::io::Read;
+use std::path::Path;
+#[cfg(feature = "s3")]
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::errors::ASDBTaxonError;
+
+/// URL schemes read remotely, with the feature needed for them
+const SCHEMES: [(&str, &str); 3] = [
+    ("http://", "network"),
+    ("https://", "network"),
+    ("s3://", "s3"),
+];
+
+/// Whether `path` is a URL rather than a local path
+pub fn is_remote(path: &Path) -> bool {
+    scheme(path).is_some()
+}
+
+fn scheme(path: &Path) -> Option<(&'static str, &'static str)> {
+    let path = path.to_str()?;
+    SCHEMES
+        .into_iter()
+        .find(|(scheme, _)| path.starts_with(scheme))
+}
+
+/// Open a local file or stream a URL
+pub fn open(path: &Path) -> Result<Box<dyn Read + Send>, ASDBTaxonError> {
+    let Some((scheme, feature)) = scheme(path) else {
+        return Ok(Box::new(fs::File::open(path)?));
+    };
+    let url = path.to_str().unwrap_or_default();
+    match scheme {
+        #[cfg(feature = "network")]
+        "http://" | "https://" => crate::fetch::open_url(url),
+        #[cfg(feature = "s3")]
+        "s3://" => {
+            let mut aws = Command::new("aws");
+            aws.args(["s3", "cp", "--quiet", url, "-"]);
+            Ok(Box::new(RemoteReader::spawn(aws, url)?))
+        }
+        _ => Err(ASDBTaxonError::InvalidFormat(format!(
+            "{} needs asdb-taxa built with the {} feature",
+            url, feature
+        ))),
+    }
+}
+
+/// Size of a local file, URLs have none
+pub fn size(path: &Path) -> Result<Option<u64>, ASDBTaxonError> {
+    if is_remote(path) {
+        return Ok(None);
+    }
+    Ok(Some(fs::metadata(path)?.len()))
+}
+
+/// Output of a download tool, failing with its error message if the download does
+#[cfg(feature = "s3")]
+struct RemoteReader {
+    url: String,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+#[cfg(feature = "s3")]
+impl RemoteReader {
+    fn spawn(mut command: Command, url: &str) -> io::Result<RemoteReader> {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                io::Error::new(err.kind(), format!("failed to run {}: {}", program, err))
+            })?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(RemoteReader {
+            url: url.to_string(),
+            child,
+            stdout,
+        })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let mut message = String::new();
+            if let Some(ref mut stderr) = self.child.stderr {
+                stderr.read_to_string(&mut message)?;
+            }
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "failed to download {}: {}",
+                    self.url,
+                    message.trim()
+                )));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Drop for RemoteReader {
+    fn drop(&mut self) {
+        // stop downloads that were only read partially, e.g. to detect the compression
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "s3")]
+    use crate::temp_dir;
+
+    #[test]
+    fn test_open() {
+        assert!(is_remote(Path::new(
+            "https://example.org/rankedlineage.dmp"
+        )));
+        assert!(is_remote(Path::new("s3://bucket/cache.json.zst")));
+        assert!(!is_remote(Path::new("dumps/rankedlineage.dmp")));
+        assert_eq!(size(Path::new("s3://bucket/cache.json")).unwrap(), None);
+
+        #[cfg(not(feature = "s3"))]
+        assert!(matches!(
+            open(Path::new("s3://bucket/cache.json")),
+            Err(ASDBTaxonError::InvalidFormat(_))
+        ));
+        #[cfg(feature = "s3")]
+        {
+            let tmp = temp_dir();
+            let path = tmp.path().join("merged.dmp");
+            fs::write(&path, "23456\t|\t34567\t|\n").unwrap();
+            let mut data = String::new();
+            // any tool writing the download to stdout will do
+            let mut cat = Command::new("cat");
+            cat.arg(&path);
+            RemoteReader::spawn(cat, "s3://bucket/merged.dmp")
+                .unwrap()
+                .read_to_string(&mut data)
+                .unwrap();
+            assert_eq!(data, "23456\t|\t34567\t|\n");
+
+            let mut cat = Command::new("cat");
+            cat.arg("/nonexistent");
+            let mut reader = RemoteReader::spawn(cat, "s3://bucket/nonexistent").unwrap();
+            assert!(reader.read_to_string(&mut data).is_err());
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming resolution of taxids, one per line, for use in shell pipelines
+
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use serde_json::json;
+
+use crate::errors::ASDBTaxonError;
+use crate::export::{column_values, normalize, COLUMNS};
+use crate::taxa::TaxId;
+use crate::view::CacheView;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveFormat {
+    /// The input, the lineage columns of the export and an error column
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for ResolveFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(ResolveFormat::Tsv),
+            "json" => Ok(ResolveFormat::Json),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Resolve one taxid per input line, writing one result line per input line in the same order
+///
+/// Lines that can't be resolved are passed through with an error, returning the number of those.
+pub fn resolve_lines(
+    view: &CacheView,
+    input: impl BufRead,
+    mut output: impl Write,
+    format: ResolveFormat,
+) -> Result<usize, ASDBTaxonError> {
+    let mut failed = 0;
+    for line in input.lines() {
+        let line = line?;
+        let query = line.trim();
+        let result = query
+            .parse::<TaxId>()
+            .and_then(|tax_id| view.lookup(tax_id));
+        if result.is_err() {
+            failed += 1;
+        }
+
+        match format {
+            ResolveFormat::Tsv => {
+                let (tax_id, values, error) = match result {
+                    Ok(entry) => (
+                        entry.tax_id.to_string(),
+                        column_values(entry).map(|value| normalize(value).into_owned()),
+                        String::new(),
+                    ),
+                    Err(err) => (String::new(), Default::default(), err.to_string()),
+                };
+                writeln!(
+                    output,
+                    "{}\t{}\t{}\t{}",
+                    normalize(query),
+                    tax_id,
+                    values.join("\t"),
+                    error
+                )?;
+            }
+            ResolveFormat::Json => {
+                let value = match result {
+                    Ok(entry) => json!({ "input": query, "entry": entry }),
+                    Err(err) => json!({ "input": query, "error": err.to_string() }),
+                };
+                writeln!(output, "{}", value)?;
+            }
+        }
+        output.flush()?;
+    }
+    Ok(failed)
+}
+
+/// Append the lineage columns, species to superkingdom, to every row of a TSV table
+///
+/// The taxid is read from the 1-based `taxid_column`, merged IDs are resolved. Rows whose taxid
+/// can't be resolved get empty columns, returning the number of those. With `header`, the first
+/// row gets the column names appended instead.
+pub fn annotate_tsv(
+    view: &CacheView,
+    input: impl BufRead,
+    mut output: impl Write,
+    taxid_column: usize,
+    header: bool,
+) -> Result<usize, ASDBTaxonError> {
+    const LINEAGE_COLUMNS: usize = 8;
+    let mut failed = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            writeln!(output)?;
+            continue;
+        }
+        if header && number == 0 {
+            writeln!(output, "{}\t{}", line, COLUMNS[2..].join("\t"))?;
+            continue;
+        }
+        let entry = line
+            .split('\t')
+            .nth(taxid_column.saturating_sub(1))
+            .ok_or_else(|| ASDBTaxonError::InvalidFormat(format!("row {}", number + 1)))
+            .and_then(|value| value.trim().parse::<TaxId>())
+            .and_then(|tax_id| view.lookup(tax_id));
+        let values = match entry {
+            Ok(entry) => column_values(entry)[1..]
+                .iter()
+                .map(|value| normalize(value).into_owned())
+                .collect(),
+            Err(_) => {
+                failed += 1;
+                vec![String::new(); LINEAGE_COLUMNS]
+            }
+        };
+        writeln!(output, "{}\t{}", line, values.join("\t"))?;
+    }
+    output.flush()?;
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::{tid, NcbiTaxEntry};
+    use crate::view::ViewFilter;
+    use crate::TaxonCache;
+
+    #[test]
+    fn test_resolve_lines() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.mappings.insert(
+            tid(23456),
+            NcbiTaxEntry {
+                name: "Streptomyces examplis".to_string(),
+                genus: "Streptomyces".into(),
+                ..NcbiTaxEntry::new(tid(23456))
+            },
+        );
+        let view = cache.view(ViewFilter::All);
+
+        let mut out = Vec::new();
+        let failed = resolve_lines(
+            &view,
+            "42\n12345\nabc\n".as_bytes(),
+            &mut out,
+            ResolveFormat::Tsv,
+        )
+        .unwrap();
+        assert_eq!(failed, 2);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "42\t\t\t\t\t\t\t\t\t\t\tTaxID not found: 42");
+        assert_eq!(
+            lines[1],
+            "12345\t23456\tStreptomyces examplis\t\tStreptomyces\t\t\t\t\t\t\t"
+        );
+        assert!(lines[2].ends_with("Invalid TaxID: abc"));
+
+        let mut out = Vec::new();
+        resolve_lines(&view, "12345\n".as_bytes(), &mut out, ResolveFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["entry"]["tax_id"], 23456);
+    }
+
+    #[test]
+    fn test_annotate_tsv() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.mappings.insert(
+            tid(23456),
+            NcbiTaxEntry {
+                name: "Streptomyces examplis".to_string(),
+                genus: "Streptomyces".into(),
+                superkingdom: "Bacteria".into(),
+                ..NcbiTaxEntry::new(tid(23456))
+            },
+        );
+        let view = cache.view(ViewFilter::All);
+
+        let input = "hit\tscore\ttaxid\nBGC1\t0.9\t12345\nBGC2\t0.5\t42\nBGC3\n";
+        let mut out = Vec::new();
+        let failed = annotate_tsv(&view, input.as_bytes(), &mut out, 3, true).unwrap();
+        assert_eq!(failed, 2);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "hit\tscore\ttaxid\tspecies\tgenus\tfamily\torder\tclass\tphylum\tkingdom\tsuperkingdom"
+        );
+        assert_eq!(
+            lines[1],
+            "BGC1\t0.9\t12345\t\tStreptomyces\t\t\t\t\t\tBacteria"
+        );
+        assert_eq!(lines[2], "BGC2\t0.5\t42\t\t\t\t\t\t\t\t");
+        assert_eq!(lines[3].split('\t').count(), 9);
+    }
+}
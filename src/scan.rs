@@ -0,0 +1,952 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Discovery of the taxids referenced by ASDB data files
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, info, warn};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::accession::{self, AccessionFinder};
+use crate::biosample::BioSampleMap;
+use crate::cancel::CancelToken;
+use crate::compression;
+use crate::deadline::Deadline;
+use crate::errors::ASDBTaxonError;
+use crate::extract::{self, TaxidExtractor, TaxidRules, TaxidSource};
+use crate::glob::Pattern;
+use crate::manifest::{FileState, ScanManifest};
+use crate::remote;
+use crate::tarball;
+use crate::taxa::TaxId;
+
+/// Default limit on how deep nested directories in a datadir are scanned
+pub const MAX_SCAN_DEPTH: usize = 8;
+
+/// Which files in a datadir to scan
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Levels of subdirectories to descend into
+    pub max_depth: usize,
+    /// Only scan data files matching one of these, if any are given
+    pub include: Vec<Pattern>,
+    /// Skip files and directories matching any of these
+    pub exclude: Vec<Pattern>,
+    /// How to find the taxid in the contents of JSON and GenBank files
+    pub rules: TaxidRules,
+    /// Extractors for other kinds of files, the first one with a matching pattern is used
+    pub extractors: Vec<(Pattern, Arc<dyn TaxidExtractor>)>,
+    /// Only use the first taxid of each file, instead of all it references
+    pub first_taxid_only: bool,
+    /// NCBI `accession2taxid` file to map the record accessions of otherwise unresolved files
+    pub accession2taxid: Option<PathBuf>,
+    /// Checked before every data file, failing the scan with [ASDBTaxonError::Cancelled]
+    pub cancel: Option<CancelToken>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            max_depth: MAX_SCAN_DEPTH,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            rules: TaxidRules::new(),
+            extractors: Vec::new(),
+            first_taxid_only: false,
+            accession2taxid: None,
+            cancel: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Scan files matching `pattern` with `extractor`, e.g. for a custom file format
+    pub fn register(&mut self, pattern: Pattern, extractor: impl TaxidExtractor + 'static) {
+        self.extractors.push((pattern, Arc::new(extractor)));
+    }
+
+    fn check_cancelled(&self) -> Result<(), ASDBTaxonError> {
+        match self.cancel {
+            Some(ref token) => token.check(),
+            None => Ok(()),
+        }
+    }
+
+    fn registered(&self, relative: &str) -> Option<&dyn TaxidExtractor> {
+        self.extractors
+            .iter()
+            .find(|(pattern, _)| pattern.matches(relative))
+            .map(|(_, extractor)| extractor.as_ref())
+    }
+
+    fn excludes(&self, relative: &str) -> bool {
+        self.exclude.iter().any(|pattern| pattern.matches(relative))
+    }
+
+    fn includes(&self, relative: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(relative))
+    }
+}
+
+/// Statistics on how the taxids of the scanned data files were resolved
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ScanReport {
+    pub files_scanned: usize,
+    /// Files skipped because the manifest shows they were scanned before
+    pub files_unchanged: usize,
+    pub by_taxon_xref: usize,
+    pub by_biosample: usize,
+    pub by_bioproject: usize,
+    pub by_accession: usize,
+    /// Record accessions missing from the `accession2taxid` file
+    pub unmapped_accessions: Vec<String>,
+    pub unresolved: Vec<PathBuf>,
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} files scanned", self.files_scanned)?;
+        if self.files_unchanged > 0 {
+            writeln!(f, "{} files unchanged", self.files_unchanged)?;
+        }
+        writeln!(f, "{} resolved via taxon xref", self.by_taxon_xref)?;
+        writeln!(f, "{} resolved via BioSample", self.by_biosample)?;
+        writeln!(f, "{} resolved via BioProject", self.by_bioproject)?;
+        if self.by_accession > 0 || !self.unmapped_accessions.is_empty() {
+            writeln!(f, "{} resolved via accession", self.by_accession)?;
+            writeln!(
+                f,
+                "{} accessions not in accession2taxid",
+                self.unmapped_accessions.len()
+            )?;
+        }
+        write!(f, "{} unresolved", self.unresolved.len())
+    }
+}
+
+/// Scan all json files in `datadir` and its subdirectories for the taxids they reference
+///
+/// GenBank and EMBL files (`*.gbk`, `*.gbff`, `*.embl`, ...) are scanned as well, and gzipped
+/// files (`*.json.gz`, `*.gbk.gz`, ...) are decompressed on the fly.
+/// A `datadir` can also be a tar archive of data files (`*.tar`, `*.tar.gz`, `*.tgz` or
+/// `*.tar.zst`, or a URL to one), which is streamed rather than unpacked. Archive members are
+/// only scanned for the built-in file kinds, and always in full, as the manifest only keeps
+/// track of files on disk.
+/// Files without a usable `taxon:` xref fall back to looking up their BioSample, then their
+/// BioProject accession in `biosamples`, if provided, and then to their record accessions, see
+/// [ScanOptions::accession2taxid].
+pub fn scan_datadir(
+    datadir: &Path,
+    biosamples: Option<&BioSampleMap>,
+) -> Result<(HashSet<TaxId>, ScanReport), ASDBTaxonError> {
+    scan_datadirs(&[datadir.to_path_buf()], biosamples)
+}
+
+/// Like [scan_datadir], for several datadirs at once
+pub fn scan_datadirs(
+    datadirs: &[PathBuf],
+    biosamples: Option<&BioSampleMap>,
+) -> Result<(HashSet<TaxId>, ScanReport), ASDBTaxonError> {
+    scan_datadir_with(
+        datadirs,
+        biosamples,
+        &ScanOptions::default(),
+        None,
+        None,
+        &mut |_, _| (),
+        &mut |_, _| (),
+    )
+}
+
+/// Count the data files referencing each taxid, by the first taxid found in each
+pub fn count_references(
+    datadirs: &[PathBuf],
+    options: &ScanOptions,
+) -> Result<HashMap<TaxId, usize>, ASDBTaxonError> {
+    let mut references = HashMap::new();
+    scan_datadir_with(
+        datadirs,
+        None,
+        options,
+        None,
+        None,
+        &mut |_, taxid| {
+            if let Some(taxid) = taxid {
+                *references.entry(taxid).or_insert(0) += 1;
+            }
+        },
+        &mut |_, _| (),
+    )?;
+    Ok(references)
+}
+
+/// Like [scan_datadirs], only scanning the files selected by `options`,
+/// stopping early at the `deadline`,
+/// skipping files the `manifest` lists as unchanged and recording the others in it,
+/// calling `on_file` with every file scanned and the first taxid it resolved to,
+/// and `on_progress` with the number of files scanned so far and the total number of files
+pub fn scan_datadir_with(
+    datadirs: &[PathBuf],
+    biosamples: Option<&BioSampleMap>,
+    options: &ScanOptions,
+    deadline: Option<&Deadline>,
+    manifest: Option<&mut ScanManifest>,
+    on_file: &mut dyn FnMut(&Path, Option<TaxId>),
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(HashSet<TaxId>, ScanReport), ASDBTaxonError> {
+    let resolver = Resolver {
+        rules: &options.rules,
+        all_taxids: !options.first_taxid_only,
+        biosamples,
+        biosample_re: Regex::new(r#""(SAM(?:N|EA|D)\d+)""#)?,
+        bioproject_re: Regex::new(r#""(?:BioProject:)?(PRJ(?:NA|EB|DB)\d+)""#)?,
+        accession_finder: match options.accession2taxid {
+            Some(_) => Some(AccessionFinder::new()?),
+            None => None,
+        },
+    };
+    let mut collector = Collector {
+        taxids: HashSet::new(),
+        report: ScanReport::default(),
+        manifest,
+        on_file,
+    };
+    let mut entries = Vec::new();
+    let mut archives = Vec::new();
+    let mut seen = HashSet::new();
+    for datadir in datadirs {
+        if is_archive(datadir) {
+            let key = if remote::is_remote(datadir) {
+                datadir.clone()
+            } else {
+                fs::canonicalize(datadir)?
+            };
+            if seen.insert(key) {
+                archives.push(datadir.clone());
+            } else {
+                debug!("Skipping {}, already scanned", datadir.display());
+            }
+            continue;
+        }
+        find_data_files(
+            datadir,
+            datadir,
+            options.max_depth,
+            options,
+            &mut seen,
+            &mut entries,
+        )?;
+    }
+
+    entries.sort();
+    // archives count as one unit of progress each, their members aren't known up front
+    let total = (entries.len() + archives.len()) as u64;
+    let mut done = 0;
+    // files only resolvable via their accessions, looked up together after the scan
+    let mut pending = Vec::new();
+
+    for (path, relative) in entries {
+        options.check_cancelled()?;
+        if deadline.is_some_and(|deadline| deadline.check()) {
+            info!("Time limit reached after scanning {} files", done);
+            break;
+        }
+        on_progress(done, total);
+        done += 1;
+        let state = match collector.manifest {
+            Some(ref manifest) => {
+                let state = FileState::from_path(&path)?;
+                if manifest.is_unchanged(&path, &state) {
+                    debug!("Skipping {}, unchanged since the last scan", path.display());
+                    collector.report.files_unchanged += 1;
+                    continue;
+                }
+                Some(state)
+            }
+            None => None,
+        };
+        let report = &mut collector.report;
+        report.files_scanned += 1;
+        let resolved = match options.registered(&relative) {
+            Some(extractor) => {
+                let mut found = extractor.extract(&path)?;
+                if options.first_taxid_only {
+                    found.truncate(1);
+                }
+                if !found.is_empty() {
+                    report.by_taxon_xref += 1;
+                }
+                found
+            }
+            None => {
+                let content = extract::read_data_file(&path)?;
+                match resolver.resolve(report, &path, &content) {
+                    Resolved::Taxids(taxids) => taxids,
+                    Resolved::Accessions(accessions) => {
+                        pending.push((path, state, accessions));
+                        continue;
+                    }
+                }
+            }
+        };
+        collector.record(path, state, resolved);
+    }
+
+    for archive in archives {
+        if deadline.is_some_and(|deadline| deadline.reached()) {
+            break;
+        }
+        on_progress(done, total);
+        done += 1;
+        let scanned = tarball::for_each_entry(compression::open(&archive)?, |name, contents| {
+            let relative = name.trim_start_matches("./");
+            if !selects_member(options, relative) {
+                return Ok(());
+            }
+            options.check_cancelled()?;
+            if deadline.is_some_and(|deadline| deadline.check()) {
+                return Err(ASDBTaxonError::TimeLimitReached);
+            }
+            let path = archive.join(relative);
+            collector.report.files_scanned += 1;
+            let content = extract::read_data(contents, &path)?;
+            match resolver.resolve(&mut collector.report, &path, &content) {
+                Resolved::Taxids(taxids) => collector.record(path, None, taxids),
+                Resolved::Accessions(accessions) => pending.push((path, None, accessions)),
+            }
+            Ok(())
+        });
+        match scanned {
+            Err(ASDBTaxonError::TimeLimitReached) => {
+                info!("Time limit reached while scanning {}", archive.display());
+                break;
+            }
+            scanned => scanned?,
+        }
+    }
+
+    if let Some(ref accession2taxid) = options.accession2taxid {
+        if !pending.is_empty() && !deadline.is_some_and(|deadline| deadline.reached()) {
+            let needed: HashSet<String> = pending
+                .iter()
+                .flat_map(|(_, _, accessions)| accessions.iter().cloned())
+                .collect();
+            let mappings = accession::lookup_path(accession2taxid, &needed)?;
+            for (path, state, accessions) in pending {
+                let mut resolved = Vec::new();
+                for accession in accessions {
+                    match mappings.get(&accession) {
+                        Some(taxid) if !resolved.contains(taxid) => resolved.push(*taxid),
+                        Some(_) => (),
+                        None => {
+                            warn!(
+                                "Accession {} of {} not found in accession2taxid",
+                                accession,
+                                path.display()
+                            );
+                            collector.report.unmapped_accessions.push(accession);
+                        }
+                    }
+                }
+                if options.first_taxid_only {
+                    resolved.truncate(1);
+                }
+                if !resolved.is_empty() {
+                    collector.report.by_accession += 1;
+                }
+                collector.record(path, state, resolved);
+            }
+        }
+    }
+
+    on_progress(total, total);
+    info!(
+        "Found {} taxids in {} files",
+        collector.taxids.len(),
+        collector.report.files_scanned
+    );
+    Ok((collector.taxids, collector.report))
+}
+
+/// Taxids found in the contents of a data file, or else its record accessions to look up
+enum Resolved {
+    Taxids(Vec<TaxId>),
+    Accessions(Vec<String>),
+}
+
+/// Resolves the contents of data files to taxids with the built-in fallbacks
+struct Resolver<'a> {
+    rules: &'a TaxidRules,
+    all_taxids: bool,
+    biosamples: Option<&'a BioSampleMap>,
+    biosample_re: Regex,
+    bioproject_re: Regex,
+    accession_finder: Option<AccessionFinder>,
+}
+
+impl Resolver<'_> {
+    /// Taxids of the data file at `path`, via its xrefs, BioSample, BioProject or record
+    /// accessions
+    fn resolve(&self, report: &mut ScanReport, path: &Path, content: &str) -> Resolved {
+        let source = TaxidSource::from_path(path).unwrap_or(TaxidSource::Json);
+        let xref_taxids = self.rules.extract(source, content, self.all_taxids);
+        let mapped_taxid = |accession_re: &Regex| {
+            self.biosamples.and_then(|map| {
+                accession_re
+                    .captures_iter(content)
+                    .find_map(|cap| map.get(&cap[1]))
+            })
+        };
+
+        if !xref_taxids.is_empty() {
+            report.by_taxon_xref += 1;
+            Resolved::Taxids(xref_taxids)
+        } else if let Some(taxid) = mapped_taxid(&self.biosample_re) {
+            report.by_biosample += 1;
+            Resolved::Taxids(vec![taxid])
+        } else if let Some(taxid) = mapped_taxid(&self.bioproject_re) {
+            report.by_bioproject += 1;
+            Resolved::Taxids(vec![taxid])
+        } else {
+            let accessions = self
+                .accession_finder
+                .as_ref()
+                .map(|finder| finder.find(source, content))
+                .unwrap_or_default();
+            if accessions.is_empty() {
+                Resolved::Taxids(Vec::new())
+            } else {
+                Resolved::Accessions(accessions)
+            }
+        }
+    }
+}
+
+/// Taxids and report of a scan in progress, with where to record every scanned file
+struct Collector<'a> {
+    taxids: HashSet<TaxId>,
+    report: ScanReport,
+    manifest: Option<&'a mut ScanManifest>,
+    on_file: &'a mut dyn FnMut(&Path, Option<TaxId>),
+}
+
+impl Collector<'_> {
+    /// Add the taxids `path` resolved to, telling the callback and manifest about it
+    fn record(&mut self, path: PathBuf, state: Option<FileState>, resolved: Vec<TaxId>) {
+        match resolved.as_slice() {
+            [] => debug!("Scanned {}: no taxid found", path.display()),
+            [taxid] => debug!("Scanned {}: taxid {}", path.display(), taxid),
+            taxids => debug!("Scanned {}: taxids {:?}", path.display(), taxids),
+        }
+        let first = resolved.first().copied();
+        (self.on_file)(&path, first);
+        if let (Some(manifest), Some(state)) = (self.manifest.as_deref_mut(), state) {
+            manifest.record(
+                &path,
+                FileState {
+                    taxid: first,
+                    ..state
+                },
+            );
+        }
+        if resolved.is_empty() {
+            self.report.unresolved.push(path);
+        }
+        self.taxids.extend(resolved);
+    }
+}
+
+/// Collect the data files in `dir` selected by `options`, descending at most `depth` levels
+///
+/// Files are collected with their path relative to `root`. Directories already seen, e.g. via
+/// a symlink loop, are skipped.
+fn find_data_files(
+    root: &Path,
+    dir: &Path,
+    depth: usize,
+    options: &ScanOptions,
+    seen: &mut HashSet<PathBuf>,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<(), ASDBTaxonError> {
+    if !seen.insert(fs::canonicalize(dir)?) {
+        debug!("Skipping {}, already scanned", dir.display());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative = relative_path(root, &path);
+        if options.excludes(&relative) {
+            debug!("Skipping {}, excluded", path.display());
+        } else if path.is_dir() {
+            if depth > 0 {
+                find_data_files(root, &path, depth - 1, options, seen, files)?;
+            } else {
+                debug!("Skipping {}, too deeply nested", path.display());
+            }
+        } else if (is_data_file(&path) || options.registered(&relative).is_some())
+            && options.includes(&relative)
+        {
+            files.push((path, relative));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the archive member at `relative` is a data file selected by `options`
+///
+/// Like [find_data_files] does for directories, members below an excluded or too deeply nested
+/// directory are skipped.
+fn selects_member(options: &ScanOptions, relative: &str) -> bool {
+    let dirs: Vec<&str> = relative
+        .match_indices('/')
+        .map(|(end, _)| &relative[..end])
+        .collect();
+    if dirs.len() > options.max_depth {
+        debug!("Skipping {}, too deeply nested", relative);
+        return false;
+    }
+    if dirs.iter().any(|dir| options.excludes(dir)) || options.excludes(relative) {
+        debug!("Skipping {}, excluded", relative);
+        return false;
+    }
+    is_data_file(Path::new(relative)) && options.includes(relative)
+}
+
+/// Whether the datadir at `path` is a tar archive of data files rather than a directory
+fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [".tar", ".tar.gz", ".tgz", ".tar.zst"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+        && !path.is_dir()
+}
+
+/// Whether `path` is a data file to scan, JSON or GenBank, plain or gzipped
+fn is_data_file(path: &Path) -> bool {
+    TaxidSource::from_path(path).is_some()
+}
+
+/// `path` relative to `root`, with `/` separators for matching against glob patterns
+fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let parts: Vec<String> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+/// Read a list of taxids, one per line, skipping empty lines and `#` comments
+pub fn read_taxid_list(input: impl Read) -> Result<HashSet<TaxId>, ASDBTaxonError> {
+    let mut taxids = HashSet::new();
+    for line in io::BufReader::new(input).lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        taxids.insert(line.parse::<TaxId>()?);
+    }
+    info!("Read {} taxids from list", taxids.len());
+    Ok(taxids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gzip;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_scan_datadir() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(
+            datadir.join("b.json"),
+            r#"{"dbxrefs": ["taxon:0", "BioSample:SAMN00000001"], "biosample": "SAMN00000001"}"#,
+        )
+        .unwrap();
+        fs::write(datadir.join("c.json"), r#"{"dbxrefs": []}"#).unwrap();
+        fs::write(
+            datadir.join("d.json"),
+            r#"{"dbxrefs": ["BioSample:SAMN00000002", "BioProject:PRJNA0000003"]}"#,
+        )
+        .unwrap();
+        fs::write(datadir.join("ignored.txt"), r#""taxon:1""#).unwrap();
+
+        let mut biosamples = BioSampleMap::new();
+        biosamples
+            .mappings
+            .insert("SAMN00000001".to_string(), tid(5062));
+        biosamples
+            .mappings
+            .insert("PRJNA0000003".to_string(), tid(1902));
+
+        let (taxids, report) = scan_datadir(datadir, Some(&biosamples)).unwrap();
+        let (plain_taxids, plain_report) = scan_datadir(datadir, None).unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062), tid(1902)]));
+        assert_eq!(report.files_scanned, 4);
+        assert_eq!(report.by_taxon_xref, 1);
+        assert_eq!(report.by_biosample, 1);
+        assert_eq!(report.by_bioproject, 1);
+        assert_eq!(report.unresolved, vec![datadir.join("c.json")]);
+
+        assert_eq!(plain_taxids, HashSet::from([tid(1883)]));
+        assert_eq!(plain_report.unresolved.len(), 3);
+    }
+
+    #[test]
+    fn test_count_references() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(datadir.join("b.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(datadir.join("c.json"), r#"{"dbxrefs": ["taxon:5062"]}"#).unwrap();
+        fs::write(datadir.join("d.json"), r#"{"dbxrefs": []}"#).unwrap();
+
+        let references =
+            count_references(&[datadir.to_path_buf()], &ScanOptions::default()).unwrap();
+
+        assert_eq!(references, HashMap::from([(tid(1883), 2), (tid(5062), 1)]));
+    }
+
+    #[test]
+    fn test_scan_nested() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        let nested = datadir.join("GCF_000001").join("results");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(nested.join("b.json"), r#"{"dbxrefs": ["taxon:5062"]}"#).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(datadir, nested.join("loop")).unwrap();
+
+        let (taxids, report) = scan_datadir(datadir, None).unwrap();
+        let options = ScanOptions {
+            max_depth: 1,
+            ..Default::default()
+        };
+        let (shallow, _) = scan_datadir_with(
+            &[datadir.to_path_buf()],
+            None,
+            &options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )
+        .unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062)]));
+        assert_eq!(report.files_scanned, 2);
+        assert_eq!(shallow, HashSet::from([tid(1883)]));
+    }
+
+    #[test]
+    fn test_scan_patterns() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        let nested = datadir.join("GCF_000001");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(datadir.join("config")).unwrap();
+        fs::write(
+            nested.join("NC_003888.json"),
+            r#"{"dbxrefs": ["taxon:1883"]}"#,
+        )
+        .unwrap();
+        fs::write(nested.join("regions.json"), r#"{"dbxrefs": ["taxon:1"]}"#).unwrap();
+        fs::write(datadir.join("config/a.json"), r#"{"dbxrefs": ["taxon:2"]}"#).unwrap();
+
+        let options = ScanOptions {
+            include: vec!["GCF_*/*.json".parse().unwrap(), "config/*".parse().unwrap()],
+            exclude: vec!["regions.json".parse().unwrap(), "config".parse().unwrap()],
+            ..Default::default()
+        };
+        let (taxids, report) = scan_datadir_with(
+            &[datadir.to_path_buf()],
+            None,
+            &options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )
+        .unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883)]));
+        assert_eq!(report.files_scanned, 1);
+    }
+
+    #[test]
+    fn test_scan_datadirs() {
+        let tmp = temp_dir();
+        let base = tmp.path();
+        let (first, second) = (base.join("vol1"), base.join("vol2"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(second.join("b.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(second.join("c.json"), r#"{"dbxrefs": ["taxon:5062"]}"#).unwrap();
+
+        // the same directory given twice is only scanned once
+        let (taxids, report) =
+            scan_datadirs(&[first.clone(), second.clone(), second], None).unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062)]));
+        assert_eq!(report.files_scanned, 3);
+    }
+
+    #[test]
+    fn test_scan_incremental() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        let scan = |manifest: &mut ScanManifest| {
+            scan_datadir_with(
+                &[datadir.to_path_buf()],
+                None,
+                &ScanOptions::default(),
+                None,
+                Some(manifest),
+                &mut |_, _| (),
+                &mut |_, _| (),
+            )
+            .unwrap()
+        };
+
+        let mut manifest = ScanManifest::new();
+        let (first, _) = scan(&mut manifest);
+        fs::write(datadir.join("b.json"), r#"{"dbxrefs": ["taxon:5062"]}"#).unwrap();
+        let (second, report) = scan(&mut manifest);
+
+        assert_eq!(first, HashSet::from([tid(1883)]));
+        assert_eq!(second, HashSet::from([tid(5062)]));
+        assert_eq!(report.files_scanned, 1);
+        assert_eq!(report.files_unchanged, 1);
+        assert_eq!(manifest.files.len(), 2);
+    }
+
+    #[test]
+    fn test_scan_all_taxids() {
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(
+            datadir.join("multi.json"),
+            r#"{"records": [{"dbxrefs": ["taxon:1883"]}, {"dbxrefs": ["taxon:5062"]}]}"#,
+        )
+        .unwrap();
+
+        let (all, report) = scan_datadir(datadir, None).unwrap();
+        let options = ScanOptions {
+            first_taxid_only: true,
+            ..Default::default()
+        };
+        let (first, _) = scan_datadir_with(
+            &[datadir.to_path_buf()],
+            None,
+            &options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )
+        .unwrap();
+
+        assert_eq!(all, HashSet::from([tid(1883), tid(5062)]));
+        assert_eq!(report.by_taxon_xref, 1);
+        assert_eq!(first, HashSet::from([tid(1883)]));
+    }
+
+    #[test]
+    fn test_scan_registered() {
+        struct LineExtractor;
+        impl TaxidExtractor for LineExtractor {
+            fn extract(&self, path: &Path) -> Result<Vec<TaxId>, ASDBTaxonError> {
+                let content = fs::read_to_string(path)?;
+                Ok(content
+                    .lines()
+                    .filter_map(|line| line.parse().ok())
+                    .collect())
+            }
+        }
+
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(datadir.join("b.taxa"), "5062\n100226\n").unwrap();
+
+        let mut options = ScanOptions::default();
+        options.register("*.taxa".parse().unwrap(), LineExtractor);
+        let (taxids, report) = scan_datadir_with(
+            &[datadir.to_path_buf()],
+            None,
+            &options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )
+        .unwrap();
+        let (default_taxids, _) = scan_datadir(datadir, None).unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062), tid(100226)]));
+        assert_eq!(report.files_scanned, 2);
+        assert_eq!(default_taxids, HashSet::from([tid(1883)]));
+    }
+
+    #[test]
+    fn test_scan_accessions() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let datadir = dir.join("data");
+        fs::create_dir_all(&datadir).unwrap();
+        fs::write(
+            datadir.join("a.json"),
+            r#"{"records": [{"id": "NC_003888.3"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            datadir.join("b.json"),
+            r#"{"records": [{"id": "NC_999999.1"}]}"#,
+        )
+        .unwrap();
+        fs::write(datadir.join("c.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        fs::write(
+            dir.join("nucl_gb.accession2taxid"),
+            "accession\taccession.version\ttaxid\tgi\nNC_003888\tNC_003888.3\t100226\t1\n",
+        )
+        .unwrap();
+
+        let options = ScanOptions {
+            accession2taxid: Some(dir.join("nucl_gb.accession2taxid")),
+            ..Default::default()
+        };
+        let mut scanned = Vec::new();
+        let (taxids, report) = scan_datadir_with(
+            &[datadir.to_path_buf()],
+            None,
+            &options,
+            None,
+            None,
+            &mut |path, taxid| scanned.push((path.to_path_buf(), taxid)),
+            &mut |_, _| (),
+        )
+        .unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(100226)]));
+        assert_eq!(report.files_scanned, 3);
+        assert_eq!(report.by_accession, 1);
+        assert_eq!(report.unmapped_accessions, vec!["NC_999999"]);
+        assert_eq!(report.unresolved, vec![datadir.join("b.json")]);
+        assert_eq!(scanned.len(), 3);
+    }
+
+    #[test]
+    fn test_scan_gzipped_and_genbank() {
+        use std::io::Write;
+
+        let tmp = temp_dir();
+        let datadir = tmp.path();
+        fs::write(datadir.join("a.json"), r#"{"dbxrefs": ["taxon:1883"]}"#).unwrap();
+        let mut encoder = gzip::encoder(fs::File::create(datadir.join("b.json.gz")).unwrap());
+        encoder
+            .write_all(br#"{"dbxrefs": ["taxon:5062"]}"#)
+            .unwrap();
+        encoder.finish().unwrap();
+        fs::write(datadir.join("notes.txt.gz"), r#""taxon:1""#).unwrap();
+        fs::write(
+            datadir.join("c.gbk"),
+            "     source          1..10\n                     /db_xref=\"taxon:100226\"\n",
+        )
+        .unwrap();
+
+        let (taxids, report) = scan_datadir(datadir, None).unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062), tid(100226)]));
+        assert_eq!(report.files_scanned, 3);
+    }
+
+    #[test]
+    fn test_scan_archive() {
+        use std::io::Write;
+
+        fn member(archive: &mut tar::Builder<Vec<u8>>, name: &str, content: &[u8]) {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            archive.append_data(&mut header, name, content).unwrap();
+        }
+
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        let mut archive = tar::Builder::new(Vec::new());
+        member(
+            &mut archive,
+            "./batch/a.json",
+            br#"{"dbxrefs": ["taxon:1883"]}"#,
+        );
+        member(&mut archive, "./batch/notes.txt", br#""taxon:1""#);
+        member(
+            &mut archive,
+            "./batch/config/b.json",
+            br#"{"dbxrefs": ["taxon:2"]}"#,
+        );
+        member(&mut archive, "./batch/c.json", br#"{"dbxrefs": []}"#);
+        let path = dir.join("results.tar.gz");
+        let mut encoder = gzip::encoder(fs::File::create(&path).unwrap());
+        encoder.write_all(&archive.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap();
+
+        let options = ScanOptions {
+            exclude: vec!["config".parse().unwrap()],
+            ..Default::default()
+        };
+        let (taxids, report) = scan_datadir_with(
+            std::slice::from_ref(&path),
+            None,
+            &options,
+            None,
+            None,
+            &mut |_, _| (),
+            &mut |_, _| (),
+        )
+        .unwrap();
+
+        assert_eq!(taxids, HashSet::from([tid(1883)]));
+        assert_eq!(report.files_scanned, 2);
+        assert_eq!(report.unresolved, vec![path.join("batch/c.json")]);
+    }
+
+    #[test]
+    fn test_read_taxid_list() {
+        let input = "# needed taxids\n1883\n\n5062  # Aspergillus\n1883\n";
+        let taxids = read_taxid_list(input.as_bytes()).unwrap();
+        assert_eq!(taxids, HashSet::from([tid(1883), tid(5062)]));
+
+        assert!(matches!(
+            read_taxid_list("1883\nStreptomyces\n".as_bytes()),
+            Err(ASDBTaxonError::InvalidTaxId(_))
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioning of the cache file layout, upgrading older caches on load
+//!
+//! Fields that are simply added to the layout should be `#[serde(default)]` and don't need a new
+//! version. Bump [CACHE_VERSION] and add a migration when existing data needs to be rewritten.
+
+use serde_json::{Map, Value};
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::strain_from_name;
+
+/// Version of the cache layout written by this version of the crate
+pub const CACHE_VERSION: u64 = 5;
+
+/// Version of caches written before the layout was versioned
+const UNVERSIONED: u64 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), ASDBTaxonError>;
+
+/// Migrations from each version to the next, starting at [UNVERSIONED]
+const MIGRATIONS: [Migration; 4] = [migrate_v1, migrate_v2, migrate_v3, migrate_v4];
+
+/// Version of a parsed cache file
+pub fn version(cache: &Value) -> u64 {
+    cache
+        .get("version")
+        .and_then(Value::as_u64)
+        .unwrap_or(UNVERSIONED)
+}
+
+/// Upgrade a parsed cache file to [CACHE_VERSION]
+pub fn migrate(cache: &mut Value) -> Result<(), ASDBTaxonError> {
+    let mut current = version(cache);
+    if !(UNVERSIONED..=CACHE_VERSION).contains(&current) {
+        return Err(ASDBTaxonError::UnsupportedCacheVersion(current));
+    }
+    let Value::Object(ref mut fields) = cache else {
+        return Err(ASDBTaxonError::InvalidFormat(
+            "cache is not a JSON object".to_string(),
+        ));
+    };
+    while current < CACHE_VERSION {
+        MIGRATIONS[(current - UNVERSIONED) as usize](fields)?;
+        current += 1;
+        fields.insert("version".to_string(), Value::from(current));
+    }
+    Ok(())
+}
+
+/// Unversioned caches only lack the version field, but make sure this is a cache at all
+fn migrate_v1(fields: &mut Map<String, Value>) -> Result<(), ASDBTaxonError> {
+    if !fields.get("mappings").is_some_and(Value::is_object) {
+        return Err(ASDBTaxonError::InvalidFormat(
+            "cache has no mappings".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds can scan several datadirs now, so the metadata lists them
+fn migrate_v2(fields: &mut Map<String, Value>) -> Result<(), ASDBTaxonError> {
+    if let Some(Value::Object(metadata)) = fields.get_mut("metadata") {
+        let datadirs = match metadata.remove("datadir") {
+            Some(Value::String(datadir)) => vec![Value::String(datadir)],
+            _ => Vec::new(),
+        };
+        metadata.insert("datadirs".to_string(), Value::Array(datadirs));
+    }
+    Ok(())
+}
+
+/// Entries have a strain field now, split off their names
+fn migrate_v3(fields: &mut Map<String, Value>) -> Result<(), ASDBTaxonError> {
+    let Some(Value::Object(mappings)) = fields.get_mut("mappings") else {
+        return Ok(());
+    };
+    for entry in mappings.values_mut() {
+        let Value::Object(entry) = entry else {
+            continue;
+        };
+        let name = entry
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let species = entry
+            .get("species")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if let Some(strain) = strain_from_name(name, species) {
+            entry.insert("strain".to_string(), Value::String(strain));
+        }
+    }
+    Ok(())
+}
+
+/// Entries can have classifications by several taxonomies, GTDB lineages moved there
+fn migrate_v4(fields: &mut Map<String, Value>) -> Result<(), ASDBTaxonError> {
+    let Some(Value::Object(mappings)) = fields.get_mut("mappings") else {
+        return Ok(());
+    };
+    for entry in mappings.values_mut() {
+        let Value::Object(entry) = entry else {
+            continue;
+        };
+        if let Some(gtdb) = entry.remove("gtdb") {
+            let mut classifications = Map::new();
+            classifications.insert("gtdb".to_string(), gtdb);
+            entry.insert(
+                "classifications".to_string(),
+                Value::Object(classifications),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrate() {
+        let mut cache = json!({
+            "deprecated_ids": {},
+            "mappings": {"1883": {"tax_id": 1883, "name": "Streptomyces"}}
+        });
+        assert_eq!(version(&cache), 1);
+        migrate(&mut cache).unwrap();
+        assert_eq!(version(&cache), CACHE_VERSION);
+        assert!(migrate(&mut json!({"version": 1, "taxa": []})).is_err());
+
+        let mut cache = json!({
+            "version": 2,
+            "metadata": {"crate_version": "0.1.0", "built_at": 0, "datadir": "data", "entry_count": 0},
+            "mappings": {}
+        });
+        migrate(&mut cache).unwrap();
+        assert_eq!(cache["metadata"]["datadirs"], json!(["data"]));
+        assert!(cache["metadata"].get("datadir").is_none());
+
+        let mut cache = json!({
+            "version": 3,
+            "mappings": {"23456": {"tax_id": 23456, "name": "Streptomyces examplis NBC12345", "species": "examplis"}}
+        });
+        migrate(&mut cache).unwrap();
+        assert_eq!(cache["mappings"]["23456"]["strain"], json!("NBC12345"));
+
+        let mut cache = json!({
+            "version": 4,
+            "mappings": {"1902": {"tax_id": 1902, "gtdb": {"genus": "Streptomyces"}}}
+        });
+        migrate(&mut cache).unwrap();
+        assert!(cache["mappings"]["1902"].get("gtdb").is_none());
+        assert_eq!(
+            cache["mappings"]["1902"]["classifications"]["gtdb"]["genus"],
+            json!("Streptomyces")
+        );
+
+        let mut future = json!({"version": CACHE_VERSION + 1, "mappings": {}});
+        assert!(matches!(
+            migrate(&mut future),
+            Err(ASDBTaxonError::UnsupportedCacheVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Looking up taxids by organism name
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+
+/// How a name has to match the query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameMatch {
+    /// The whole name
+    #[default]
+    Exact,
+    /// The start of the name
+    Prefix,
+}
+
+impl FromStr for NameMatch {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(NameMatch::Exact),
+            "prefix" => Ok(NameMatch::Prefix),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Options of a name search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameQuery {
+    pub matching: NameMatch,
+    pub ignore_case: bool,
+}
+
+/// Lowest score [NameIndex::fuzzy] considers a candidate at all
+pub const DEFAULT_MIN_SCORE: f64 = 0.5;
+
+/// Entry names sorted for binary search, as given and lowercased
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    names: Vec<(String, TaxId)>,
+    folded: Vec<(String, TaxId)>,
+}
+
+impl NameIndex {
+    pub fn build(mappings: &HashMap<TaxId, NcbiTaxEntry>) -> NameIndex {
+        let mut names: Vec<(String, TaxId)> = mappings
+            .values()
+            .map(|entry| (entry.name.clone(), entry.tax_id))
+            .collect();
+        let mut folded: Vec<(String, TaxId)> = names
+            .iter()
+            .map(|(name, tax_id)| (name.to_lowercase(), *tax_id))
+            .collect();
+        names.sort_unstable();
+        folded.sort_unstable();
+        NameIndex { names, folded }
+    }
+
+    /// Taxids of the names matching `name`, sorted by name
+    pub fn find(&self, name: &str, query: &NameQuery) -> Vec<TaxId> {
+        let (names, name) = if query.ignore_case {
+            (&self.folded, name.to_lowercase())
+        } else {
+            (&self.names, name.to_string())
+        };
+        let start = names.partition_point(|(indexed, _)| indexed.as_str() < name.as_str());
+        names[start..]
+            .iter()
+            .take_while(|(indexed, _)| match query.matching {
+                NameMatch::Exact => *indexed == name,
+                NameMatch::Prefix => indexed.starts_with(&name),
+            })
+            .map(|(_, tax_id)| *tax_id)
+            .collect()
+    }
+
+    /// Taxids of the names similar to `name` scoring at least `min_score`, best first
+    ///
+    /// Case is ignored, and abbreviated words like the `S.` in `S. albidoflavus` match any word
+    /// with that start.
+    pub fn fuzzy(&self, name: &str, min_score: f64) -> Vec<(TaxId, f64)> {
+        let query = name.to_lowercase();
+        let query_trigrams = trigrams(&query);
+        let mut candidates: Vec<(&str, TaxId, f64)> = self
+            .folded
+            .iter()
+            .filter_map(|(indexed, tax_id)| {
+                let score = match expand_abbreviations(&query, indexed) {
+                    Some(expanded) => similarity(&expanded, &trigrams(&expanded), indexed),
+                    None => similarity(&query, &query_trigrams, indexed),
+                };
+                (score >= min_score).then_some((indexed.as_str(), *tax_id, score))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(b.0)).then(a.1.cmp(&b.1)));
+        candidates
+            .into_iter()
+            .map(|(_, tax_id, score)| (tax_id, score))
+            .collect()
+    }
+}
+
+/// Similarity of two names between 0 and 1, the better of trigram and edit distance similarity
+fn similarity(query: &str, query_trigrams: &HashSet<String>, name: &str) -> f64 {
+    if query == name {
+        return 1.0;
+    }
+    let name_trigrams = trigrams(name);
+    let shared = query_trigrams.intersection(&name_trigrams).count();
+    let dice = 2.0 * shared as f64 / (query_trigrams.len() + name_trigrams.len()).max(1) as f64;
+
+    let longest = query.chars().count().max(name.chars().count()).max(1);
+    let edit = 1.0 - edit_distance(query, name) as f64 / longest as f64;
+    dice.max(edit)
+}
+
+/// Character trigrams of the words in `name`, padded so word starts count extra
+fn trigrams(name: &str) -> HashSet<String> {
+    let mut trigrams = HashSet::new();
+    for word in name.split_whitespace() {
+        let padded: Vec<char> = format!("  {} ", word).chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert(window.iter().collect());
+        }
+    }
+    trigrams
+}
+
+/// Levenshtein distance between `a` and `b`, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// `query` with abbreviated words replaced by the words of `name` they abbreviate, if any
+fn expand_abbreviations(query: &str, name: &str) -> Option<String> {
+    if !query.contains('.') {
+        return None;
+    }
+    let name_words: Vec<&str> = name.split_whitespace().collect();
+    let mut expanded = false;
+    let words: Vec<&str> = query
+        .split_whitespace()
+        .enumerate()
+        .map(
+            |(i, word)| match (word.strip_suffix('.'), name_words.get(i)) {
+                (Some(start), Some(full)) if !start.is_empty() && full.starts_with(start) => {
+                    expanded = true;
+                    *full
+                }
+                _ => word,
+            },
+        )
+        .collect();
+    expanded.then(|| words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_name_index() {
+        let mut mappings = HashMap::new();
+        for (tax_id, name) in [
+            (tid(100226), "Streptomyces coelicolor A3(2)"),
+            (tid(1902), "Streptomyces coelicolor"),
+            (tid(1883), "Streptomyces"),
+            (tid(5062), "Aspergillus oryzae"),
+        ] {
+            mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    name: name.to_string(),
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        let index = NameIndex::build(&mappings);
+
+        let exact = NameQuery::default();
+        assert_eq!(index.find("Streptomyces coelicolor", &exact), vec![1902]);
+        assert!(index.find("streptomyces coelicolor", &exact).is_empty());
+
+        let prefix = NameQuery {
+            matching: NameMatch::Prefix,
+            ignore_case: true,
+        };
+        assert_eq!(index.find("streptomyces c", &prefix), vec![1902, 100226]);
+        assert_eq!(index.find("STREP", &prefix).len(), 3);
+        assert!(index.find("Bacillus", &prefix).is_empty());
+
+        let fuzzy = index.fuzzy("Streptomyces coelicolr", DEFAULT_MIN_SCORE);
+        assert_eq!(fuzzy[0].0, 1902);
+        assert!(fuzzy[0].1 < 1.0);
+        assert!(fuzzy.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(!fuzzy.iter().any(|(tax_id, _)| *tax_id == 5062));
+
+        let abbreviated = index.fuzzy("S. coelicolor", DEFAULT_MIN_SCORE);
+        assert_eq!(abbreviated[0], (tid(1902), 1.0));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+}
@@ -0,0 +1,581 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal HTTP server exposing a cache as a REST API
+//!
+//! Endpoints, all returning JSON:
+//! * `GET /api/v1/taxa/{taxid}` looks up a single taxid
+//! * `POST /api/v1/taxa` with a JSON list of taxids looks up all of them
+//! * `GET /api/v1/search?name=...` finds entries by (partial) name
+//!
+//! Additional namespaces serve the same endpoints under `/{namespace}/api/v1/`, restricted to
+//! the entries visible through that namespace's [ViewFilter].
+//!
+//! With [Telemetry] enabled, `GET /admin/popular?limit=100` lists the most looked up taxids.
+//! Admin endpoints are only served with a token set by [Server::with_admin_token], which requests
+//! need to send as `Authorization: Bearer {token}`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::errors::ASDBTaxonError;
+use crate::shared::SharedTaxonCache;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::telemetry::Telemetry;
+use crate::view::{CacheView, ViewFilter};
+
+const API_PREFIX: &str = "/api/v1/";
+const ADMIN_PREFIX: &str = "/admin/";
+
+/// Don't accept request bodies larger than this
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+/// Don't accept request lines or headers longer than this
+const MAX_LINE_SIZE: u64 = 8 * 1024;
+/// Don't accept more headers than this
+const MAX_HEADERS: usize = 100;
+/// Time a client gets for each read or write before its connection is dropped
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Default number of connections handled at the same time, see [Server::with_workers]
+pub const DEFAULT_WORKERS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: &impl Serialize) -> Response {
+        Response {
+            status,
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &json!({ "error": message }))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Serves lookups on a shared cache, optionally through filtered namespaces
+#[derive(Debug, Clone)]
+pub struct Server {
+    cache: SharedTaxonCache,
+    namespaces: BTreeMap<String, ViewFilter>,
+    telemetry: Option<Arc<Telemetry>>,
+    admin_token: Option<String>,
+    workers: usize,
+}
+
+impl Server {
+    /// Serve `cache`, which can be a [SharedTaxonCache] to update it while serving
+    pub fn new(cache: impl Into<SharedTaxonCache>) -> Server {
+        Server {
+            cache: cache.into(),
+            namespaces: BTreeMap::new(),
+            telemetry: None,
+            admin_token: None,
+            workers: DEFAULT_WORKERS,
+        }
+    }
+
+    /// Handle up to `workers` connections at the same time, further ones wait to be handled
+    pub fn with_workers(mut self, workers: usize) -> Server {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Count all lookups in `telemetry`
+    pub fn with_telemetry(mut self, telemetry: Arc<Telemetry>) -> Server {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Serve the admin endpoints to requests authorized with `token`
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Server {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// Expose the entries matching `filter` under `/{name}/api/v1/`
+    pub fn add_namespace(&mut self, name: &str, filter: ViewFilter) {
+        self.namespaces
+            .insert(name.trim_matches('/').to_string(), filter);
+    }
+
+    /// Handle a single request without credentials
+    pub fn handle(&self, method: &str, target: &str, body: &[u8]) -> Response {
+        self.handle_authorized(method, target, None, body)
+    }
+
+    /// Handle a single request, `token` being the bearer token of its `Authorization` header
+    pub fn handle_authorized(
+        &self,
+        method: &str,
+        target: &str,
+        token: Option<&str>,
+        body: &[u8],
+    ) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if let Some(route) = path.strip_prefix(ADMIN_PREFIX) {
+            return match self.admin_token {
+                None => Response::error(404, "no such endpoint"),
+                Some(ref admin_token)
+                    if token.is_some_and(|token| same_token(token, admin_token)) =>
+                {
+                    self.handle_admin(method, route, query)
+                }
+                Some(_) => Response::error(401, "missing or invalid admin token"),
+            };
+        }
+        let Some((filter, route)) = self.route(path) else {
+            return Response::error(404, "no such endpoint");
+        };
+        let cache = self.cache.snapshot();
+        let view = cache.view(filter);
+
+        match (method, route.trim_end_matches('/')) {
+            ("GET", "search") => search(&view, query),
+            ("POST", "taxa") => self.bulk_lookup(&view, body),
+            ("GET", taxa) if taxa.starts_with("taxa/") => match taxa[5..].parse::<TaxId>() {
+                Ok(tax_id) => match self.lookup(&view, tax_id) {
+                    Ok(entry) => Response::json(200, entry),
+                    Err(err) => Response::error(404, &err.to_string()),
+                },
+                Err(_) => Response::error(400, "invalid taxid"),
+            },
+            (_, "search") | (_, "taxa") => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "no such endpoint"),
+        }
+    }
+
+    fn handle_admin(&self, method: &str, route: &str, query: &str) -> Response {
+        let Some(ref telemetry) = self.telemetry else {
+            return Response::error(404, "no such endpoint");
+        };
+        match (method, route.trim_end_matches('/')) {
+            ("GET", "popular") => {
+                let limit = match query_param(query, "limit").map(|limit| limit.parse()) {
+                    None => 100,
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => return Response::error(400, "invalid limit"),
+                };
+                let counters = telemetry.snapshot();
+                let popular: Vec<serde_json::Value> = counters
+                    .popular(limit)
+                    .into_iter()
+                    .map(|(tax_id, count)| json!({ "tax_id": tax_id, "count": count }))
+                    .collect();
+                Response::json(
+                    200,
+                    &json!({ "total_lookups": counters.total_lookups, "popular": popular }),
+                )
+            }
+            (_, "popular") => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "no such endpoint"),
+        }
+    }
+
+    fn lookup<'a>(
+        &self,
+        view: &CacheView<'a>,
+        tax_id: TaxId,
+    ) -> Result<&'a NcbiTaxEntry, ASDBTaxonError> {
+        if let Some(ref telemetry) = self.telemetry {
+            telemetry.record(tax_id);
+        }
+        view.lookup(tax_id)
+    }
+
+    fn bulk_lookup(&self, view: &CacheView, body: &[u8]) -> Response {
+        let tax_ids: Vec<TaxId> = match serde_json::from_slice(body) {
+            Ok(tax_ids) => tax_ids,
+            Err(err) => {
+                return Response::error(400, &format!("expected a list of taxids: {}", err))
+            }
+        };
+        let results: BTreeMap<String, serde_json::Value> = tax_ids
+            .iter()
+            .map(|tax_id| {
+                let value = match self.lookup(view, *tax_id) {
+                    Ok(entry) => serde_json::to_value(entry).unwrap_or_default(),
+                    Err(err) => json!({ "error": err.to_string() }),
+                };
+                (tax_id.to_string(), value)
+            })
+            .collect();
+        Response::json(200, &results)
+    }
+
+    /// Split a request path into the view filter of its namespace and the API route
+    fn route<'a>(&self, path: &'a str) -> Option<(ViewFilter, &'a str)> {
+        if let Some(route) = path.strip_prefix(API_PREFIX) {
+            return Some((ViewFilter::All, route));
+        }
+        let (namespace, rest) = path.trim_start_matches('/').split_once('/')?;
+        let filter = self.namespaces.get(namespace)?;
+        let route = rest.strip_prefix(&API_PREFIX[1..])?;
+        Some((filter.clone(), route))
+    }
+
+    /// Listen on `address` and serve requests until the process is killed
+    ///
+    /// Connections are handled by a fixed pool of workers, see [Server::with_workers]. Once all
+    /// of them are busy and as many connections are queued, no further ones are accepted.
+    pub fn serve(&self, address: &str) -> Result<(), ASDBTaxonError> {
+        let listener = TcpListener::bind(address)?;
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(self.workers);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..self.workers {
+            let server = self.clone();
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || loop {
+                let next = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+                let Ok(stream) = next else {
+                    return;
+                };
+                let _ = server.handle_connection(stream);
+            });
+        }
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let Some(request_line) = read_line(&mut reader)? else {
+            return reject(
+                stream,
+                reader,
+                &Response::error(431, "request line too long"),
+            );
+        };
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return write_response(&mut stream, &Response::error(400, "malformed request"));
+        };
+
+        let mut content_length = 0;
+        let mut token = None;
+        let mut headers = 0;
+        loop {
+            let Some(header) = read_line(&mut reader)? else {
+                return reject(stream, reader, &Response::error(431, "header too long"));
+            };
+            if header.is_empty() || header.trim().is_empty() {
+                break;
+            }
+            headers += 1;
+            if headers > MAX_HEADERS {
+                return reject(stream, reader, &Response::error(431, "too many headers"));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    let Ok(length) = value.trim().parse() else {
+                        return reject(
+                            stream,
+                            reader,
+                            &Response::error(400, "invalid content length"),
+                        );
+                    };
+                    content_length = length;
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    token = value.trim().strip_prefix("Bearer ").map(str::to_owned);
+                }
+            }
+        }
+        if content_length > MAX_BODY_SIZE {
+            return reject(stream, reader, &Response::error(413, "request too large"));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = self.handle_authorized(method, target, token.as_deref(), &body);
+        write_response(&mut stream, &response)
+    }
+}
+
+/// Read a line of up to [MAX_LINE_SIZE] bytes, `None` if it is longer
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE_SIZE).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE_SIZE && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+/// Answer a request that wasn't read completely
+///
+/// Closing a connection with unread input resets it, possibly before the client read the
+/// response, so read on for a bit until the client closes the connection as well.
+fn reject(mut stream: TcpStream, reader: impl Read, response: &Response) -> io::Result<()> {
+    write_response(&mut stream, response)?;
+    stream.shutdown(Shutdown::Write)?;
+    // the response is out, whatever happens to the rest of the request
+    let _ = io::copy(&mut reader.take(MAX_BODY_SIZE as u64), &mut io::sink());
+    Ok(())
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    let challenge = if response.status == 401 {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        challenge,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Compare tokens in time independent of where they differ, so they can't be guessed bytewise
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn search(view: &CacheView, query: &str) -> Response {
+    match query_param(query, "name") {
+        Some(name) if !name.is_empty() => {
+            let mut entries = view.search_name(&name);
+            entries.sort_by_key(|entry| entry.tax_id);
+            Response::json(200, &entries)
+        }
+        _ => Response::error(400, "missing name parameter"),
+    }
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|param| {
+        param
+            .split_once('=')
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| percent_decode(value))
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+    use crate::TaxonCache;
+
+    fn server() -> Server {
+        let mut cache = TaxonCache::new();
+        for (tax_id, name, superkingdom) in [
+            (tid(1883), "Streptomyces coelicolor A3(2)", "Bacteria"),
+            (tid(5062), "Aspergillus oryzae", "Eukaryota"),
+        ] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    name: name.to_string(),
+                    superkingdom: superkingdom.into(),
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        let mut server = Server::new(Arc::new(cache));
+        server.add_namespace("fungi", ViewFilter::parse("Eukaryota").unwrap());
+        server
+    }
+
+    #[test]
+    fn test_shared_update() {
+        let shared = SharedTaxonCache::new(TaxonCache::new());
+        let server = Server::new(shared.clone());
+        assert_eq!(server.handle("GET", "/api/v1/taxa/5062", b"").status, 404);
+
+        shared
+            .update(|cache| -> Result<(), ASDBTaxonError> {
+                cache.insert(NcbiTaxEntry {
+                    name: "Aspergillus oryzae".to_string(),
+                    ..NcbiTaxEntry::new(tid(5062))
+                });
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(server.handle("GET", "/api/v1/taxa/5062", b"").status, 200);
+    }
+
+    #[test]
+    fn test_handle() {
+        let server = server();
+
+        let response = server.handle("GET", "/api/v1/taxa/1883", b"");
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("Streptomyces coelicolor"));
+
+        assert_eq!(server.handle("GET", "/api/v1/taxa/42", b"").status, 404);
+        assert_eq!(server.handle("GET", "/api/v1/taxa/abc", b"").status, 400);
+        assert_eq!(
+            server.handle("GET", "/fungi/api/v1/taxa/1883", b"").status,
+            404
+        );
+        assert_eq!(
+            server.handle("GET", "/fungi/api/v1/taxa/5062", b"").status,
+            200
+        );
+        assert_eq!(
+            server.handle("GET", "/plants/api/v1/taxa/5062", b"").status,
+            404
+        );
+
+        let response = server.handle("POST", "/api/v1/taxa", b"[1883, 42]");
+        assert_eq!(response.status, 200);
+        let results: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(results["1883"]["name"], "Streptomyces coelicolor A3(2)");
+        assert_eq!(results["42"]["error"], "TaxID not found: 42");
+
+        let response = server.handle("GET", "/api/v1/search?name=a3%282%29", b"");
+        let results: Vec<NcbiTaxEntry> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].tax_id, 1883);
+
+        let response = server.handle("GET", "/fungi/api/v1/search?name=coelicolor", b"");
+        assert_eq!(response.body, "[]");
+        assert_eq!(server.handle("GET", "/admin/popular", b"").status, 404);
+    }
+
+    #[test]
+    fn test_popular() {
+        let server = server().with_telemetry(Arc::new(Telemetry::new()));
+        assert_eq!(server.handle("GET", "/admin/popular", b"").status, 404);
+        let server = server.with_admin_token("secret");
+        server.handle("GET", "/api/v1/taxa/5062", b"");
+        server.handle("POST", "/fungi/api/v1/taxa", b"[1883, 5062]");
+
+        assert_eq!(server.handle("GET", "/admin/popular", b"").status, 401);
+        let wrong = server.handle_authorized("GET", "/admin/popular", Some("secreT"), b"");
+        assert_eq!(wrong.status, 401);
+        let response =
+            server.handle_authorized("GET", "/admin/popular?limit=1", Some("secret"), b"");
+        assert_eq!(response.status, 200);
+        let popular: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(popular["total_lookups"], 3);
+        assert_eq!(popular["popular"], json!([{ "tax_id": 5062, "count": 2 }]));
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let request = |request: String| -> String {
+            let server = server()
+                .with_telemetry(Arc::new(Telemetry::new()))
+                .with_admin_token("secret");
+            let mut client = TcpStream::connect(address).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let handler = thread::spawn(move || server.handle_connection(stream));
+            client.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            drop(client);
+            handler.join().unwrap().unwrap();
+            response
+        };
+
+        let response = request("GET /api/v1/taxa/1883 HTTP/1.1\r\nHost: localhost\r\n\r\n".into());
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        let response = request(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10000)));
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+        let response = request(format!(
+            "GET /api/v1/taxa/1883 HTTP/1.1\r\n{}\r\n",
+            "X-Padding: a\r\n".repeat(MAX_HEADERS + 1)
+        ));
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+        let response =
+            request("POST /api/v1/taxa HTTP/1.1\r\nContent-Length: lots\r\n\r\n[1883]".into());
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        let response = request("GET /admin/popular HTTP/1.1\r\n\r\n".into());
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        assert!(
+            response.contains("WWW-Authenticate: Bearer\r\n"),
+            "{}",
+            response
+        );
+        let response =
+            request("GET /admin/popular HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n".into());
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A cache shared between threads, which can be replaced or updated while it's being read
+//!
+//! Readers take a [snapshot](SharedTaxonCache::snapshot) of the current cache, which stays valid
+//! for as long as they hold it. [SharedTaxonCache::update] works on a copy and swaps it in when
+//! done, so lookups are never blocked by a running update.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+/// Cheaply cloneable handle to a cache shared between threads
+#[derive(Debug, Clone)]
+pub struct SharedTaxonCache {
+    current: Arc<RwLock<Arc<TaxonCache>>>,
+    /// Held while updating, so concurrent updates don't overwrite each other
+    updating: Arc<Mutex<()>>,
+}
+
+impl SharedTaxonCache {
+    pub fn new(cache: TaxonCache) -> SharedTaxonCache {
+        SharedTaxonCache::from(Arc::new(cache))
+    }
+
+    /// The current cache, unaffected by later replacements
+    pub fn snapshot(&self) -> Arc<TaxonCache> {
+        // the lock only guards swapping the pointer, so a poisoned one still holds a valid cache
+        let current = self.current.read().unwrap_or_else(|err| err.into_inner());
+        Arc::clone(&current)
+    }
+
+    /// Look up `tax_id` in the current cache
+    pub fn lookup(&self, tax_id: TaxId) -> Result<NcbiTaxEntry, ASDBTaxonError> {
+        self.snapshot().lookup(tax_id).cloned()
+    }
+
+    /// Replace the current cache, returning the previous one
+    pub fn replace(&self, cache: TaxonCache) -> Arc<TaxonCache> {
+        let _updating = self.updating.lock().unwrap_or_else(|err| err.into_inner());
+        self.swap(Arc::new(cache))
+    }
+
+    /// Apply `update` to a copy of the current cache and replace the cache with it if it succeeds
+    ///
+    /// Lookups see the previous cache until the update is done. Concurrent updates run one after
+    /// the other, each on the result of the previous one.
+    pub fn update<T, E>(
+        &self,
+        update: impl FnOnce(&mut TaxonCache) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let _updating = self.updating.lock().unwrap_or_else(|err| err.into_inner());
+        let mut cache = TaxonCache::clone(&self.snapshot());
+        let result = update(&mut cache)?;
+        self.swap(Arc::new(cache));
+        Ok(result)
+    }
+
+    fn swap(&self, cache: Arc<TaxonCache>) -> Arc<TaxonCache> {
+        let mut current = self.current.write().unwrap_or_else(|err| err.into_inner());
+        std::mem::replace(&mut *current, cache)
+    }
+}
+
+impl Default for SharedTaxonCache {
+    fn default() -> SharedTaxonCache {
+        SharedTaxonCache::new(TaxonCache::new())
+    }
+}
+
+impl From<Arc<TaxonCache>> for SharedTaxonCache {
+    fn from(cache: Arc<TaxonCache>) -> SharedTaxonCache {
+        SharedTaxonCache {
+            current: Arc::new(RwLock::new(cache)),
+            updating: Arc::new(Mutex::new(())),
+        }
+    }
+}
+
+impl From<TaxonCache> for SharedTaxonCache {
+    fn from(cache: TaxonCache) -> SharedTaxonCache {
+        SharedTaxonCache::new(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+    use std::thread;
+
+    fn entry(tax_id: TaxId, name: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: name.to_string(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_shared_cache() {
+        let mut cache = TaxonCache::new();
+        cache.insert(entry(tid(23456), "Streptomyces examplis"));
+        let shared = SharedTaxonCache::new(cache);
+        let before = shared.snapshot();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(shared.lookup(tid(23456)).unwrap().tax_id, 23456);
+                    }
+                })
+            })
+            .collect();
+        let added = shared
+            .update(|cache| -> Result<usize, ASDBTaxonError> {
+                cache.insert(entry(tid(5062), "Aspergillus oryzae"));
+                Ok(cache.len())
+            })
+            .unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(added, 2);
+        assert_eq!(shared.lookup(tid(5062)).unwrap().name, "Aspergillus oryzae");
+        // snapshots taken before keep the old cache
+        assert!(before.lookup(tid(5062)).is_err());
+
+        // failed updates leave the cache as it was
+        let failed = shared.update(|cache| -> Result<(), ASDBTaxonError> {
+            cache.remove(tid(23456));
+            Err(ASDBTaxonError::InvalidFormat("broken dump".to_string()))
+        });
+        assert!(failed.is_err());
+        assert!(shared.lookup(tid(23456)).is_ok());
+
+        let previous = shared.replace(TaxonCache::new());
+        assert_eq!(previous.len(), 2);
+        assert!(shared.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SILVA classifications of NCBI taxids
+//!
+//! SILVA classifies rRNA sequences, identified by `accession.start.stop`. Its own taxmap
+//! (`taxmap_slv_ssu_ref_nr_138.2.txt`) has the SILVA path of each sequence, the ENA taxmap
+//! (`taxmap_embl-ebi_ena_ssu_ref_nr99_138.2.txt`) the NCBI taxid. A taxid gets the
+//! classification most of its sequences have.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use crate::compression;
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+use crate::taxonomy::{most_common, Classification};
+
+/// SILVA classifications by sequence and NCBI taxid
+#[derive(Debug, Clone, Default)]
+pub struct SilvaMap {
+    /// Lineages by sequence, like `AB000389.1.1509`
+    pub lineages: HashMap<String, Classification>,
+    /// NCBI taxids by sequence
+    pub taxids: HashMap<String, TaxId>,
+}
+
+impl SilvaMap {
+    pub fn new() -> SilvaMap {
+        Default::default()
+    }
+
+    /// Load the lineages of a SILVA taxmap
+    pub fn load_taxmap(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let before = self.lineages.len();
+        for_each_row(input, &mut |sequence, row| {
+            let path = row.path.trim_end_matches(';');
+            let ranks: Vec<&str> = path.split(';').collect();
+            let rank = |index: usize| match ranks.get(index) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => "Unknown".to_string(),
+            };
+            let genus = rank(5);
+            // organism names only give the species if they match the classification
+            let species = match row.organism.strip_prefix(&genus) {
+                Some(rest) if rest.starts_with(' ') && genus != "Unknown" => row
+                    .organism
+                    .split(' ')
+                    .take(2)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                _ => "Unknown".to_string(),
+            };
+            let classification = Classification {
+                domain: rank(0),
+                phylum: rank(1),
+                class: rank(2),
+                order: rank(3),
+                family: rank(4),
+                genus,
+                species,
+            };
+            self.lineages.insert(sequence, classification);
+            Ok(())
+        })?;
+        Ok(self.lineages.len() - before)
+    }
+
+    /// Load the NCBI taxids of the ENA taxmap
+    pub fn load_ena_taxmap(&mut self, input: impl Read) -> Result<usize, ASDBTaxonError> {
+        let before = self.taxids.len();
+        for_each_row(input, &mut |sequence, row| {
+            self.taxids.insert(sequence, row.taxid.parse()?);
+            Ok(())
+        })?;
+        Ok(self.taxids.len() - before)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_taxmap_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        self.load_taxmap(compression::open(path)?)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_ena_taxmap_path(&mut self, path: &Path) -> Result<usize, ASDBTaxonError> {
+        self.load_ena_taxmap(compression::open(path)?)
+    }
+
+    /// The most common lineage of the sequences of every taxid, see [most_common]
+    pub fn by_taxid(&self) -> HashMap<TaxId, Classification> {
+        most_common(self.taxids.iter().filter_map(|(sequence, taxid)| {
+            self.lineages.get(sequence).map(|lineage| (*taxid, lineage))
+        }))
+    }
+}
+
+/// The fields of a taxmap line used here
+struct Row<'a> {
+    path: &'a str,
+    organism: &'a str,
+    taxid: &'a str,
+}
+
+/// Call `on_row` with the sequence ID and fields of every line of a taxmap
+///
+/// Both taxmaps have the columns `primaryAccession start stop path organism_name taxid`, with a
+/// header line.
+fn for_each_row(
+    input: impl Read,
+    on_row: &mut dyn FnMut(String, Row) -> Result<(), ASDBTaxonError>,
+) -> Result<(), ASDBTaxonError> {
+    for line in io::BufReader::new(input).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let [accession, start, stop, path, organism, taxid, ..] = fields[..] else {
+            continue;
+        };
+        if accession == "primaryAccession" {
+            continue;
+        }
+        let sequence = format!("{}.{}.{}", accession, start, stop);
+        on_row(
+            sequence,
+            Row {
+                path,
+                organism,
+                taxid,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_by_taxid() {
+        let taxmap = "primaryAccession\tstart\tstop\tpath\torganism_name\ttaxid\n\
+AB184533\t1\t1469\tBacteria;Actinobacteriota;Actinobacteria;Streptomycetales;Streptomycetaceae;Streptomyces;\tStreptomyces coelicolor\t50735\n\
+AL645882\t1\t1527\tBacteria;Actinobacteriota;Actinobacteria;Streptomycetales;Streptomycetaceae;Streptomyces;\tStreptomyces coelicolor A3(2)\t50735\n\
+KF000001\t1\t1400\tBacteria;Actinobacteriota;\tuncultured bacterium\t12\n";
+        let ena = "primaryAccession\tstart\tstop\tpath\torganism_name\ttaxid\n\
+AB184533\t1\t1469\tBacteria;Actinomycetota;\tStreptomyces coelicolor\t1902\n\
+AL645882\t1\t1527\tBacteria;Actinomycetota;\tStreptomyces coelicolor A3(2)\t100226\n\
+KF000001\t1\t1400\tBacteria;\tuncultured bacterium\t77133\n";
+
+        let mut map = SilvaMap::new();
+        assert_eq!(map.load_taxmap(taxmap.as_bytes()).unwrap(), 3);
+        assert_eq!(map.load_ena_taxmap(ena.as_bytes()).unwrap(), 3);
+
+        let by_taxid = map.by_taxid();
+        assert_eq!(by_taxid.len(), 3);
+        assert_eq!(by_taxid[&tid(1902)].phylum, "Actinobacteriota");
+        assert_eq!(by_taxid[&tid(1902)].species, "Streptomyces coelicolor");
+        assert_eq!(by_taxid[&tid(100226)].species, "Streptomyces coelicolor");
+        assert_eq!(by_taxid[&tid(77133)].class, "Unknown");
+        assert_eq!(by_taxid[&tid(77133)].species, "Unknown");
+    }
+}
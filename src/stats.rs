@@ -0,0 +1,211 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counting the cached taxa and the data files referencing them by lineage
+//!
+//! References are counted per taxid, as [crate::scan::count_references] finds them in a
+//! datadir. Merged taxids are followed to the entry they were merged into.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+/// Name of the group of entries without a name at the counted rank
+pub const UNKNOWN: &str = "Unknown";
+
+/// Counts of a single group, e.g. a phylum
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupCount {
+    pub name: String,
+    /// Cached taxa in the group, not counting the ones only cached as ancestors
+    pub taxa: usize,
+    /// Data files referencing a taxon of the group, if references were counted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub records: Option<usize>,
+}
+
+/// Counts of the groups at a rank, sorted by name, or by records after [GroupCounts::top]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupCounts {
+    pub rank: Rank,
+    pub groups: Vec<GroupCount>,
+    /// Data files referencing taxids the cache has no entry for
+    pub unresolved_records: usize,
+}
+
+impl GroupCounts {
+    /// Count the entries of `cache` by their name at `rank`, and the `references` to them
+    pub fn count(
+        cache: &TaxonCache,
+        rank: Rank,
+        references: Option<&HashMap<TaxId, usize>>,
+    ) -> GroupCounts {
+        let mut groups: BTreeMap<&str, GroupCount> = BTreeMap::new();
+        for entry in cache.iter().filter(|entry| !entry.ancestor_only) {
+            group(&mut groups, group_name(entry, rank), references.is_some()).taxa += 1;
+        }
+
+        let mut unresolved_records = 0;
+        for (tax_id, count) in references.into_iter().flatten() {
+            match cache.lookup(*tax_id) {
+                Ok(entry) => {
+                    let group = group(&mut groups, group_name(entry, rank), true);
+                    *group.records.get_or_insert(0) += count;
+                }
+                Err(_) => unresolved_records += count,
+            }
+        }
+
+        GroupCounts {
+            rank,
+            groups: groups.into_values().collect(),
+            unresolved_records,
+        }
+    }
+
+    /// The `n` groups referenced by the most records, most first, leaving out unreferenced ones
+    pub fn top(mut self, n: usize) -> GroupCounts {
+        self.groups
+            .retain(|group| group.records.unwrap_or_default() > 0);
+        // sorting is stable, so groups with as many records stay sorted by name
+        self.groups
+            .sort_by_key(|group| Reverse(group.records.unwrap_or_default()));
+        self.groups.truncate(n);
+        self
+    }
+}
+
+/// Name of the group of `entry` at `rank`
+///
+/// The lineage of rankedlineage.dmp leaves out the taxon itself, so taxa at `rank` form their
+/// own group, if their rank is known.
+fn group_name(entry: &NcbiTaxEntry, rank: Rank) -> &str {
+    match entry.rank_value(rank) {
+        "" | UNKNOWN if entry.taxon_rank() == Some(rank) => &entry.name,
+        "" => UNKNOWN,
+        name => name,
+    }
+}
+
+/// The counts of the group `name`, added if new
+fn group<'a, 'b>(
+    groups: &'b mut BTreeMap<&'a str, GroupCount>,
+    name: &'a str,
+    with_records: bool,
+) -> &'b mut GroupCount {
+    groups.entry(name).or_insert_with(|| GroupCount {
+        name: name.to_string(),
+        taxa: 0,
+        records: with_records.then_some(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn cache() -> TaxonCache {
+        let mut cache = TaxonCache::new();
+        for (tax_id, phylum, ancestor_only) in [
+            (tid(23456), "Actinomycetota", false),
+            (tid(1883), "Actinomycetota", false),
+            (tid(5062), "Ascomycota", false),
+            (tid(2), "", true),
+            (tid(9000001), "", false),
+        ] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    phylum: phylum.into(),
+                    ancestor_only,
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        cache.mappings.insert(
+            tid(201174),
+            NcbiTaxEntry {
+                name: "Actinomycetota".to_string(),
+                rank: Some("phylum".to_string()),
+                ..NcbiTaxEntry::new(tid(201174))
+            },
+        );
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache
+    }
+
+    #[test]
+    fn test_count() {
+        let counts = GroupCounts::count(&cache(), Rank::Phylum, None);
+        let summary: Vec<(&str, usize, Option<usize>)> = counts
+            .groups
+            .iter()
+            .map(|group| (group.name.as_str(), group.taxa, group.records))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Actinomycetota", 3, None),
+                ("Ascomycota", 1, None),
+                ("Unknown", 1, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_references() {
+        let references = HashMap::from([(tid(12345), 3), (tid(1883), 1), (tid(99999), 2)]);
+        let counts = GroupCounts::count(&cache(), Rank::Phylum, Some(&references));
+        let records: Vec<(&str, Option<usize>)> = counts
+            .groups
+            .iter()
+            .map(|group| (group.name.as_str(), group.records))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ("Actinomycetota", Some(4)),
+                ("Ascomycota", Some(0)),
+                ("Unknown", Some(0))
+            ]
+        );
+        assert_eq!(counts.unresolved_records, 2);
+    }
+
+    #[test]
+    fn test_top() {
+        let references = HashMap::from([(tid(12345), 3), (tid(5062), 3), (tid(1883), 1)]);
+        let counts = GroupCounts::count(&cache(), Rank::Phylum, Some(&references));
+        let top = |n| -> Vec<String> {
+            counts
+                .clone()
+                .top(n)
+                .groups
+                .into_iter()
+                .map(|group| group.name)
+                .collect()
+        };
+        assert_eq!(top(1), vec!["Actinomycetota"]);
+        assert_eq!(top(5), vec!["Actinomycetota", "Ascomycota"]);
+        assert!(GroupCounts::count(&cache(), Rank::Phylum, None)
+            .top(5)
+            .groups
+            .is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading cache entries one at a time, without loading the whole cache into memory
+
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+use crate::errors::ASDBTaxonError;
+use crate::format::{self, CacheFormat};
+use crate::schema::CACHE_VERSION;
+use crate::taxa::NcbiTaxEntry;
+
+/// Call `on_entry` for every entry of the cache in `input`, in file order
+///
+/// Only a single entry is kept in memory at a time. Returns the number of entries read.
+pub fn for_each_entry(
+    input: impl Read,
+    on_entry: &mut dyn FnMut(NcbiTaxEntry),
+) -> Result<usize, ASDBTaxonError> {
+    let (head, input) = format::peek(input)?;
+    if CacheFormat::detect(&head) == CacheFormat::Ndjson {
+        return for_each_ndjson_entry(input, on_entry);
+    }
+    let mut deserializer = serde_json::Deserializer::from_reader(io::BufReader::new(input));
+    let count = deserializer.deserialize_map(CacheVisitor { on_entry })?;
+    deserializer.end()?;
+    Ok(count)
+}
+
+#[derive(Deserialize)]
+struct NdjsonHeader {
+    #[serde(default)]
+    version: u64,
+}
+
+fn for_each_ndjson_entry(
+    input: impl Read,
+    on_entry: &mut dyn FnMut(NcbiTaxEntry),
+) -> Result<usize, ASDBTaxonError> {
+    let mut lines = io::BufReader::new(input).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let header: NdjsonHeader = serde_json::from_str(&header)?;
+    if header.version > CACHE_VERSION {
+        return Err(ASDBTaxonError::UnsupportedCacheVersion(header.version));
+    }
+    let mut count = 0;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        on_entry(serde_json::from_str(&line)?);
+        count += 1;
+    }
+    Ok(count)
+}
+
+struct CacheVisitor<'a> {
+    on_entry: &'a mut dyn FnMut(NcbiTaxEntry),
+}
+
+impl<'de> Visitor<'de> for CacheVisitor<'_> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a taxon cache")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => {
+                    let version: u64 = map.next_value()?;
+                    if version > CACHE_VERSION {
+                        return Err(de::Error::custom(ASDBTaxonError::UnsupportedCacheVersion(
+                            version,
+                        )));
+                    }
+                }
+                "mappings" => {
+                    count += map.next_value_seed(EntriesSeed {
+                        on_entry: &mut *self.on_entry,
+                    })?
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+struct EntriesSeed<'a> {
+    on_entry: &'a mut dyn FnMut(NcbiTaxEntry),
+}
+
+impl<'de> DeserializeSeed<'de> for EntriesSeed<'_> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntriesSeed<'_> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of taxids to entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some((_, entry)) = map.next_entry::<IgnoredAny, NcbiTaxEntry>()? {
+            (self.on_entry)(entry);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+    use crate::TaxonCache;
+
+    #[test]
+    fn test_for_each_entry() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        for tax_id in [tid(23456), tid(5062)] {
+            cache.mappings.insert(
+                tax_id,
+                NcbiTaxEntry {
+                    ..NcbiTaxEntry::new(tax_id)
+                },
+            );
+        }
+        let mut data = Vec::new();
+        cache.save(&mut data).unwrap();
+
+        let mut tax_ids = Vec::new();
+        let count =
+            for_each_entry(data.as_slice(), &mut |entry| tax_ids.push(entry.tax_id)).unwrap();
+        tax_ids.sort();
+        assert_eq!(count, 2);
+        assert_eq!(tax_ids, vec![5062, 23456]);
+
+        let mut ndjson = Vec::new();
+        cache.save_as(&mut ndjson, CacheFormat::Ndjson).unwrap();
+        let mut ndjson_ids = Vec::new();
+        for_each_entry(ndjson.as_slice(), &mut |entry| {
+            ndjson_ids.push(entry.tax_id)
+        })
+        .unwrap();
+        assert_eq!(ndjson_ids, tax_ids);
+
+        let future = format!(r#"{{"version": {}, "mappings": {{}}}}"#, CACHE_VERSION + 1);
+        assert!(for_each_entry(future.as_bytes(), &mut |_| ()).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming over the files of tar archives, on top of the tar crate
+
+use std::io::Read;
+
+use crate::errors::ASDBTaxonError;
+
+/// Call `handler` with the path and contents of every regular file in the archive
+pub(crate) fn for_each_entry<R, F>(reader: R, mut handler: F) -> Result<(), ASDBTaxonError>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read) -> Result<(), ASDBTaxonError>,
+{
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        handler(&name, &mut entry)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_entry() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let long_name = format!("taxdump/{}/names.dmp", "long".repeat(30));
+        for (name, contents) in [
+            ("taxdump/merged.dmp", "12345\t|\t1\t|\n"),
+            (long_name.as_str(), "abc"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let mut seen = Vec::new();
+        for_each_entry(archive.as_slice(), |name, contents| {
+            let mut text = String::new();
+            contents.read_to_string(&mut text)?;
+            seen.push((name.to_string(), text));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            seen,
+            vec![
+                (
+                    "taxdump/merged.dmp".to_string(),
+                    "12345\t|\t1\t|\n".to_string()
+                ),
+                (long_name, "abc".to_string()),
+            ]
+        );
+    }
+}
@@ -14,19 +14,618 @@
 
 //! Taxonomy helper classes
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 
+use crate::errors::ASDBTaxonError;
+use crate::provenance::Provenance;
+use crate::taxonomy::{Classification, Taxonomy};
+
 /// Everything the NCBI will tell us about a taxid
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NcbiTaxEntry {
-    pub tax_id: i64,
+    pub tax_id: TaxId,
     pub name: String,
-    pub species: String,
-    pub genus: String,
-    pub family: String,
-    pub order: String,
-    pub class: String,
-    pub phylum: String,
-    pub kingdom: String,
-    pub superkingdom: String,
-}
\ No newline at end of file
+    pub species: Arc<str>,
+    pub genus: Arc<str>,
+    pub family: Arc<str>,
+    pub order: Arc<str>,
+    pub class: Arc<str>,
+    pub phylum: Arc<str>,
+    pub kingdom: Arc<str>,
+    pub superkingdom: Arc<str>,
+    /// Virus realm, from dumps with the realm and domain columns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    /// Domain, from dumps with the realm and domain columns; also stored as the superkingdom
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "EntrySource::is_taxdump")]
+    pub source: EntrySource,
+    /// Parent taxid, if the cache was built with nodes.dmp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<TaxId>,
+    /// Rank of the taxon, if the cache was built with nodes.dmp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<String>,
+    /// Classifications by other taxonomies, like GTDB
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub classifications: BTreeMap<Taxonomy, Classification>,
+    /// Strain designation, the part of the name after the species, e.g. `NBC12345`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strain: Option<String>,
+    /// Only in the cache as an ancestor of a requested taxid
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ancestor_only: bool,
+    /// When the entry was added and last changed, see [crate::provenance]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+impl NcbiTaxEntry {
+    /// An entry for `tax_id` with an empty lineage, to fill in field by field
+    pub fn new(tax_id: TaxId) -> Self {
+        NcbiTaxEntry {
+            tax_id,
+            name: String::new(),
+            species: Arc::from(""),
+            genus: Arc::from(""),
+            family: Arc::from(""),
+            order: Arc::from(""),
+            class: Arc::from(""),
+            phylum: Arc::from(""),
+            kingdom: Arc::from(""),
+            superkingdom: Arc::from(""),
+            realm: None,
+            domain: None,
+            source: EntrySource::default(),
+            parent_id: None,
+            rank: None,
+            classifications: BTreeMap::new(),
+            strain: None,
+            ancestor_only: false,
+            provenance: None,
+        }
+    }
+
+    /// Name of the lineage at `rank`, `Unknown` if the NCBI doesn't list one
+    pub fn rank_value(&self, rank: Rank) -> &str {
+        match rank {
+            Rank::Superkingdom => &self.superkingdom,
+            Rank::Kingdom => &self.kingdom,
+            Rank::Phylum => &self.phylum,
+            Rank::Class => &self.class,
+            Rank::Order => &self.order,
+            Rank::Family => &self.family,
+            Rank::Genus => &self.genus,
+            Rank::Species => &self.species,
+        }
+    }
+
+    pub fn rank_value_mut(&mut self, rank: Rank) -> &mut Arc<str> {
+        match rank {
+            Rank::Superkingdom => &mut self.superkingdom,
+            Rank::Kingdom => &mut self.kingdom,
+            Rank::Phylum => &mut self.phylum,
+            Rank::Class => &mut self.class,
+            Rank::Order => &mut self.order,
+            Rank::Family => &mut self.family,
+            Rank::Genus => &mut self.genus,
+            Rank::Species => &mut self.species,
+        }
+    }
+
+    /// The species with the genus the cache usually leaves off, `None` if unknown
+    pub fn species_name(&self) -> Option<String> {
+        let known = |value: &str| !value.is_empty() && value != "Unknown";
+        if !known(&self.species) {
+            return None;
+        }
+        if known(&self.genus) && !self.species.starts_with(&format!("{} ", self.genus)) {
+            return Some(format!("{} {}", self.genus, self.species));
+        }
+        Some(self.species.to_string())
+    }
+
+    /// Rank of the taxon itself, from nodes.dmp or else inferred from the lineage
+    ///
+    /// Without nodes.dmp, entries named like their species or one of the higher ranks have that
+    /// rank, and everything else, like strains, none.
+    pub fn taxon_rank(&self) -> Option<Rank> {
+        if let Some(ref rank) = self.rank {
+            return rank.parse().ok();
+        }
+        if self.species_name().as_deref() == Some(self.name.as_str()) {
+            return Some(Rank::Species);
+        }
+        Rank::ALL
+            .into_iter()
+            .rev()
+            .filter(|rank| *rank != Rank::Species)
+            .find(|rank| self.rank_value(*rank) == self.name)
+    }
+
+    /// The lineage from superkingdom down to species as a single string in `style`
+    pub fn lineage_string(&self, style: LineageStyle) -> String {
+        let known = |value: &str| !value.is_empty() && value != "Unknown";
+        let value = |rank: Rank| -> Option<String> {
+            let value = self.rank_value(rank);
+            if !known(value) {
+                return None;
+            }
+            if rank == Rank::Species && style != LineageStyle::Greengenes {
+                return self.species_name();
+            }
+            Some(value.to_string())
+        };
+
+        match style {
+            LineageStyle::Plain => Rank::ALL
+                .into_iter()
+                .filter_map(value)
+                .collect::<Vec<_>>()
+                .join(";"),
+            LineageStyle::Greengenes => LineageStyle::PREFIXED
+                .into_iter()
+                .map(|rank| format!("{}__{}", rank_letter(rank), value(rank).unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join("; "),
+            LineageStyle::Metaphlan => {
+                let mut levels: Vec<Option<String>> =
+                    LineageStyle::PREFIXED.into_iter().map(value).collect();
+                while levels.last().is_some_and(Option::is_none) {
+                    levels.pop();
+                }
+                levels
+                    .into_iter()
+                    .zip(LineageStyle::PREFIXED)
+                    .map(|(value, rank)| {
+                        format!(
+                            "{}__{}",
+                            rank_letter(rank),
+                            value.unwrap_or_default().replace(' ', "_")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("|")
+            }
+        }
+    }
+}
+
+/// The part of `name` following the species epithet, if any
+///
+/// `species` may be the epithet or the whole binomial as listed in rankedlineage.dmp, so
+/// `Streptomyces examplis NBC12345` of species `examplis` has the strain `NBC12345`.
+pub fn strain_from_name(name: &str, species: &str) -> Option<String> {
+    let epithet = species.split_whitespace().next_back()?;
+    if epithet == "Unknown" {
+        return None;
+    }
+    let words: Vec<&str> = name.split_whitespace().collect();
+    // the epithet follows at least the genus
+    let position = words.iter().skip(1).position(|word| *word == epithet)? + 1;
+    let strain = words[position + 1..].join(" ");
+    (!strain.is_empty()).then_some(strain)
+}
+
+/// Open nomenclature qualifiers that belong to the epithet, as in `Streptomyces sp. SPB78`
+const SPECIES_QUALIFIERS: [&str; 2] = ["sp.", "cf."];
+
+/// The part of a rankedlineage species column to store as [NcbiTaxEntry::species]
+///
+/// That's the whole name with `full_species`, otherwise the epithet after the genus and a leading
+/// `Candidatus`: usually the last word, but all words from a qualifier like `sp.` or `cf.` on.
+pub fn species_value(species: &str, full_species: bool) -> String {
+    if full_species {
+        return species.to_string();
+    }
+    let words: Vec<&str> = species.split_whitespace().collect();
+    let genus_words = if words.first() == Some(&"Candidatus") {
+        2
+    } else {
+        1
+    };
+    let epithet = words.get(genus_words..).unwrap_or_default();
+    match epithet
+        .iter()
+        .position(|word| SPECIES_QUALIFIERS.contains(word))
+    {
+        Some(qualifier) => epithet[qualifier..].join(" "),
+        None => epithet
+            .last()
+            .or(words.last())
+            .map_or(species, |word| word)
+            .to_string(),
+    }
+}
+
+/// Conventions for writing a lineage as a single string
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineageStyle {
+    /// Semicolon-delimited names of the known ranks, `Bacteria;Actinomycetota;...`
+    #[default]
+    Plain,
+    /// Greengenes/QIIME, `k__Bacteria; p__Actinomycetota; ...; s__coelicolor`
+    Greengenes,
+    /// MetaPhlAn, `k__Bacteria|p__Actinomycetota|...|s__Streptomyces_coelicolor`
+    Metaphlan,
+}
+
+impl LineageStyle {
+    /// The seven ranks of the prefixed styles, superkingdom standing in for the kingdom
+    const PREFIXED: [Rank; 7] = [
+        Rank::Superkingdom,
+        Rank::Phylum,
+        Rank::Class,
+        Rank::Order,
+        Rank::Family,
+        Rank::Genus,
+        Rank::Species,
+    ];
+}
+
+impl FromStr for LineageStyle {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LineageStyle::Plain),
+            "greengenes" | "qiime" => Ok(LineageStyle::Greengenes),
+            "metaphlan" => Ok(LineageStyle::Metaphlan),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+fn rank_letter(rank: Rank) -> char {
+    match rank {
+        Rank::Superkingdom | Rank::Kingdom => 'k',
+        Rank::Phylum => 'p',
+        Rank::Class => 'c',
+        Rank::Order => 'o',
+        Rank::Family => 'f',
+        Rank::Genus => 'g',
+        Rank::Species => 's',
+    }
+}
+
+/// The ranks of the lineage columns in rankedlineage.dmp, ordered from the top down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rank {
+    Superkingdom,
+    Kingdom,
+    Phylum,
+    Class,
+    Order,
+    Family,
+    Genus,
+    Species,
+}
+
+impl Rank {
+    /// All ranks, from superkingdom down to species
+    pub const ALL: [Rank; 8] = [
+        Rank::Superkingdom,
+        Rank::Kingdom,
+        Rank::Phylum,
+        Rank::Class,
+        Rank::Order,
+        Rank::Family,
+        Rank::Genus,
+        Rank::Species,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rank::Superkingdom => "superkingdom",
+            Rank::Kingdom => "kingdom",
+            Rank::Phylum => "phylum",
+            Rank::Class => "class",
+            Rank::Order => "order",
+            Rank::Family => "family",
+            Rank::Genus => "genus",
+            Rank::Species => "species",
+        }
+    }
+
+    /// The next rank up, `None` for superkingdom
+    pub fn parent(&self) -> Option<Rank> {
+        let index = Rank::ALL.iter().position(|rank| rank == self)?;
+        index.checked_sub(1).map(|index| Rank::ALL[index])
+    }
+
+    /// The next rank down, `None` for species
+    pub fn child(&self) -> Option<Rank> {
+        let index = Rank::ALL.iter().position(|rank| rank == self)?;
+        Rank::ALL.get(index + 1).copied()
+    }
+}
+
+impl FromStr for Rank {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = match s.trim().to_lowercase().as_str() {
+            // the NCBI renamed the superkingdom rank
+            "domain" => "superkingdom".to_string(),
+            name => name.to_string(),
+        };
+        Rank::ALL
+            .into_iter()
+            .find(|rank| rank.as_str() == name)
+            .ok_or_else(|| ASDBTaxonError::InvalidFilter(s.to_string()))
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Where the data of a cache entry came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntrySource {
+    /// The rankedlineage.dmp taxdump file
+    #[default]
+    Taxdump,
+    /// The NCBI E-utilities web service
+    Entrez,
+    /// Specified by the user, for taxa the NCBI doesn't know
+    Custom,
+}
+
+impl EntrySource {
+    pub fn is_taxdump(&self) -> bool {
+        *self == EntrySource::Taxdump
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntrySource::Taxdump => "taxdump",
+            EntrySource::Entrez => "entrez",
+            EntrySource::Custom => "custom",
+        }
+    }
+}
+
+/// An NCBI taxid, always positive
+///
+/// Parses from the plain number or the `taxon:12345` notation of GFF and GO annotations, and
+/// serializes as the plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct TaxId(i64);
+
+impl TaxId {
+    /// The root of the taxonomy
+    pub const ROOT: TaxId = TaxId(1);
+
+    pub fn new(value: i64) -> Result<TaxId, ASDBTaxonError> {
+        if value <= 0 {
+            return Err(ASDBTaxonError::InvalidTaxId(value.to_string()));
+        }
+        Ok(TaxId(value))
+    }
+
+    pub fn get(self) -> i64 {
+        self.0
+    }
+}
+
+/// Shorthand for the taxids written out in tests
+#[cfg(test)]
+pub(crate) fn tid(value: i64) -> TaxId {
+    TaxId::new(value).expect("test taxids are positive")
+}
+
+impl TryFrom<i64> for TaxId {
+    type Error = ASDBTaxonError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        TaxId::new(value)
+    }
+}
+
+impl From<TaxId> for i64 {
+    fn from(tax_id: TaxId) -> i64 {
+        tax_id.0
+    }
+}
+
+impl PartialEq<i64> for TaxId {
+    fn eq(&self, other: &i64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl FromStr for TaxId {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = match s.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("taxon:") => &s[6..],
+            _ => s,
+        };
+        number
+            .parse::<i64>()
+            .ok()
+            .and_then(|value| TaxId::new(value).ok())
+            .ok_or_else(|| ASDBTaxonError::InvalidTaxId(s.to_string()))
+    }
+}
+
+impl fmt::Display for TaxId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_tax_id() {
+        assert_eq!(TaxId::new(1883).unwrap().get(), 1883);
+        assert!(matches!(
+            TaxId::new(0),
+            Err(ASDBTaxonError::InvalidTaxId(value)) if value == "0"
+        ));
+        assert!(TaxId::new(-5).is_err());
+
+        assert_eq!("1883".parse::<TaxId>().unwrap(), 1883);
+        assert_eq!("taxon:1883".parse::<TaxId>().unwrap(), 1883);
+        assert_eq!("Taxon:1883".parse::<TaxId>().unwrap(), 1883);
+        for invalid in ["", "0", "-1883", "taxon:", "taxon:0", "txid1883", " 1883"] {
+            assert!(
+                matches!(invalid.parse::<TaxId>(), Err(ASDBTaxonError::InvalidTaxId(ref value)) if value == invalid),
+                "{:?}",
+                invalid
+            );
+        }
+
+        let tax_id: TaxId = serde_json::from_str("1883").unwrap();
+        assert_eq!(serde_json::to_string(&tax_id).unwrap(), "1883");
+        assert!(serde_json::from_str::<TaxId>("0").is_err());
+        let keyed: HashMap<TaxId, TaxId> = serde_json::from_str(r#"{"5": 1883}"#).unwrap();
+        assert_eq!(keyed[&TaxId::new(5).unwrap()], 1883);
+        assert_eq!(serde_json::to_string(&keyed).unwrap(), r#"{"5":1883}"#);
+    }
+
+    #[test]
+    fn test_rank() {
+        assert!(Rank::Superkingdom < Rank::Phylum);
+        assert!(Rank::Genus < Rank::Species);
+        assert_eq!(Rank::Genus.parent(), Some(Rank::Family));
+        assert_eq!(Rank::Genus.child(), Some(Rank::Species));
+        assert_eq!(Rank::Superkingdom.parent(), None);
+        assert_eq!(Rank::Species.child(), None);
+        assert_eq!(" Phylum".parse::<Rank>().unwrap(), Rank::Phylum);
+        assert_eq!("domain".parse::<Rank>().unwrap(), Rank::Superkingdom);
+
+        let entry = NcbiTaxEntry {
+            genus: "Streptomyces".into(),
+            ..NcbiTaxEntry::new(tid(1))
+        };
+        assert_eq!(entry.rank_value(Rank::Genus), "Streptomyces");
+        assert_eq!(entry.rank_value(Rank::Family), "");
+    }
+
+    #[test]
+    fn test_species_value() {
+        assert_eq!(
+            species_value("Streptomyces coelicolor", false),
+            "coelicolor"
+        );
+        assert_eq!(
+            species_value("Streptomyces coelicolor", true),
+            "Streptomyces coelicolor"
+        );
+        assert_eq!(
+            species_value("Candidatus Koribacter versatilis", false),
+            "versatilis"
+        );
+        assert_eq!(species_value("Streptomyces sp. SPB78", false), "sp. SPB78");
+        assert_eq!(
+            species_value("Candidatus Pelagibacter sp. HTCC7211", false),
+            "sp. HTCC7211"
+        );
+        assert_eq!(species_value("Bacillus cf. cereus", false), "cf. cereus");
+        assert_eq!(species_value("Unknown", false), "Unknown");
+
+        let mut entry = NcbiTaxEntry {
+            genus: "Streptomyces".into(),
+            species: "sp. SPB78".into(),
+            ..NcbiTaxEntry::new(tid(1))
+        };
+        assert_eq!(
+            entry.species_name(),
+            Some("Streptomyces sp. SPB78".to_string())
+        );
+        entry.species = "Streptomyces coelicolor".into();
+        assert_eq!(
+            entry.species_name(),
+            Some("Streptomyces coelicolor".to_string())
+        );
+        entry.species = "Unknown".into();
+        assert_eq!(entry.species_name(), None);
+    }
+
+    #[test]
+    fn test_strain_from_name() {
+        assert_eq!(
+            strain_from_name("Streptomyces examplis NBC12345", "examplis"),
+            Some("NBC12345".to_string())
+        );
+        assert_eq!(
+            strain_from_name(
+                "Streptomyces coelicolor A3(2) substr. M145",
+                "Streptomyces coelicolor"
+            ),
+            Some("A3(2) substr. M145".to_string())
+        );
+        assert_eq!(
+            strain_from_name("Streptomyces sp. SPB78 strain X", "SPB78"),
+            Some("strain X".to_string())
+        );
+        assert_eq!(
+            strain_from_name("Streptomyces coelicolor", "coelicolor"),
+            None
+        );
+        assert_eq!(strain_from_name("Streptomyces", "Unknown"), None);
+        assert_eq!(strain_from_name("Aspergillus oryzae", "niger"), None);
+    }
+
+    #[test]
+    fn test_lineage_string() {
+        let entry = NcbiTaxEntry {
+            name: "Streptomyces coelicolor".to_string(),
+            species: "coelicolor".into(),
+            genus: "Streptomyces".into(),
+            family: "Streptomycetaceae".into(),
+            order: "Kitasatosporales".into(),
+            class: "Unknown".into(),
+            phylum: "Actinomycetota".into(),
+            kingdom: "Unknown".into(),
+            superkingdom: "Bacteria".into(),
+            ..NcbiTaxEntry::new(tid(1902))
+        };
+        assert_eq!(
+            entry.lineage_string(LineageStyle::Plain),
+            "Bacteria;Actinomycetota;Kitasatosporales;Streptomycetaceae;Streptomyces;Streptomyces coelicolor"
+        );
+        assert_eq!(
+            entry.lineage_string(LineageStyle::Greengenes),
+            "k__Bacteria; p__Actinomycetota; c__; o__Kitasatosporales; f__Streptomycetaceae; g__Streptomyces; s__coelicolor"
+        );
+        assert_eq!(
+            entry.lineage_string(LineageStyle::Metaphlan),
+            "k__Bacteria|p__Actinomycetota|c__|o__Kitasatosporales|f__Streptomycetaceae|g__Streptomyces|s__Streptomyces_coelicolor"
+        );
+
+        let genus = NcbiTaxEntry {
+            genus: "Streptomyces".into(),
+            species: "Unknown".into(),
+            superkingdom: "Bacteria".into(),
+            ..NcbiTaxEntry::new(tid(1))
+        };
+        assert_eq!(
+            genus.lineage_string(LineageStyle::Metaphlan),
+            "k__Bacteria|p__|c__|o__|f__|g__Streptomyces"
+        );
+        assert_eq!(
+            "qiime".parse::<LineageStyle>().unwrap(),
+            LineageStyle::Greengenes
+        );
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding the dump files of an extracted taxdump
+//!
+//! The NCBI taxdump archives extract to a flat directory of `.dmp` files, which is also how
+//! TaxonKit keeps them in `~/.taxonkit`. A [TaxdumpDir] finds the dumps in there by name.
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ASDBTaxonError;
+
+pub const RANKEDLINEAGE: &str = "rankedlineage.dmp";
+pub const MERGED: &str = "merged.dmp";
+pub const DELNODES: &str = "delnodes.dmp";
+pub const NAMES: &str = "names.dmp";
+pub const NODES: &str = "nodes.dmp";
+
+/// A directory of extracted dump files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaxdumpDir {
+    dir: PathBuf,
+}
+
+impl TaxdumpDir {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<TaxdumpDir, ASDBTaxonError> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a directory", dir.display()),
+            )
+            .into());
+        }
+        Ok(TaxdumpDir { dir })
+    }
+
+    /// The TaxonKit data directory, `$TAXONKIT_DB` or else `~/.taxonkit`, if it exists
+    pub fn discover() -> Option<TaxdumpDir> {
+        let dir = match env::var_os("TAXONKIT_DB").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".taxonkit"),
+        };
+        TaxdumpDir::open(dir).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The dump called `name`, like [MERGED], if the directory has it
+    pub fn find(&self, name: &str) -> Option<PathBuf> {
+        let path = self.dir.join(name);
+        path.is_file().then_some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn test_find() {
+        let tmp = temp_dir();
+        let dir = tmp.path();
+        for name in [RANKEDLINEAGE, MERGED, NODES] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let taxdump = TaxdumpDir::open(dir).unwrap();
+        assert_eq!(taxdump.find(MERGED), Some(dir.join(MERGED)));
+        assert_eq!(taxdump.find(DELNODES), None);
+        assert!(TaxdumpDir::open(dir.join(MERGED)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+        assert!(TaxdumpDir::open(dir).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Classifications of cache entries by other taxonomies than the NCBI one
+//!
+//! The NCBI lineage stays in the regular entry fields, others like GTDB and SILVA are stored in
+//! [NcbiTaxEntry::classifications]. [NcbiTaxEntry::with_taxonomy] swaps one of them in, so
+//! everything reading the lineage fields can work with any taxonomy.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ASDBTaxonError;
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+
+/// Source of a classification
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Taxonomy {
+    #[default]
+    Ncbi,
+    Gtdb,
+    Silva,
+}
+
+impl Taxonomy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Taxonomy::Ncbi => "ncbi",
+            Taxonomy::Gtdb => "gtdb",
+            Taxonomy::Silva => "silva",
+        }
+    }
+}
+
+impl FromStr for Taxonomy {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "ncbi" => Ok(Taxonomy::Ncbi),
+            "gtdb" => Ok(Taxonomy::Gtdb),
+            "silva" => Ok(Taxonomy::Silva),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Taxonomy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A ranked lineage, with `Unknown` for empty ranks
+///
+/// Parsed from and displayed as a prefixed lineage string like `d__Bacteria;p__...;s__`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Classification {
+    pub domain: String,
+    pub phylum: String,
+    pub class: String,
+    pub order: String,
+    pub family: String,
+    pub genus: String,
+    /// The full species name, e.g. `Streptomyces coelicolor`
+    pub species: String,
+}
+
+impl Classification {
+    fn ranks(&self) -> [(char, &str); 7] {
+        [
+            ('d', &self.domain),
+            ('p', &self.phylum),
+            ('c', &self.class),
+            ('o', &self.order),
+            ('f', &self.family),
+            ('g', &self.genus),
+            ('s', &self.species),
+        ]
+    }
+
+    /// The NCBI classification of `entry`
+    pub fn from_entry(entry: &NcbiTaxEntry) -> Classification {
+        Classification {
+            domain: entry.superkingdom.to_string(),
+            phylum: entry.phylum.to_string(),
+            class: entry.class.to_string(),
+            order: entry.order.to_string(),
+            family: entry.family.to_string(),
+            genus: entry.genus.to_string(),
+            species: entry
+                .species_name()
+                .unwrap_or_else(|| "Unknown".to_string()),
+        }
+    }
+}
+
+impl FromStr for Classification {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut classification = Classification::default();
+        let mut levels = s.trim().split(';');
+        for (letter, value) in [
+            ('d', &mut classification.domain),
+            ('p', &mut classification.phylum),
+            ('c', &mut classification.class),
+            ('o', &mut classification.order),
+            ('f', &mut classification.family),
+            ('g', &mut classification.genus),
+            ('s', &mut classification.species),
+        ] {
+            let name = levels
+                .next()
+                .and_then(|level| level.trim().strip_prefix(&format!("{}__", letter)))
+                .ok_or_else(|| ASDBTaxonError::InvalidFormat(format!("lineage '{}'", s)))?;
+            *value = match name {
+                "" => "Unknown".to_string(),
+                name => name.to_string(),
+            };
+        }
+        Ok(classification)
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let levels: Vec<String> = self
+            .ranks()
+            .into_iter()
+            .map(|(letter, name)| match name {
+                "Unknown" => format!("{}__", letter),
+                name => format!("{}__{}", letter, name),
+            })
+            .collect();
+        write!(f, "{}", levels.join(";"))
+    }
+}
+
+impl NcbiTaxEntry {
+    /// The classification of this entry by `taxonomy`, if it has one
+    pub fn classification(&self, taxonomy: Taxonomy) -> Option<Classification> {
+        match taxonomy {
+            Taxonomy::Ncbi => Some(Classification::from_entry(self)),
+            other => self.classifications.get(&other).cloned(),
+        }
+    }
+
+    /// Copy of this entry with the lineage fields taken from `taxonomy`, all `Unknown` if this
+    /// entry isn't classified by it
+    pub fn with_taxonomy(&self, taxonomy: Taxonomy) -> NcbiTaxEntry {
+        let mut entry = self.clone();
+        if taxonomy == Taxonomy::Ncbi {
+            return entry;
+        }
+        let classification = self.classifications.get(&taxonomy);
+        for rank in Rank::ALL {
+            let value = match (classification, rank) {
+                (Some(classification), Rank::Superkingdom) => classification.domain.as_str(),
+                (Some(classification), Rank::Phylum) => &classification.phylum,
+                (Some(classification), Rank::Class) => &classification.class,
+                (Some(classification), Rank::Order) => &classification.order,
+                (Some(classification), Rank::Family) => &classification.family,
+                (Some(classification), Rank::Genus) => &classification.genus,
+                (Some(classification), Rank::Species) => &classification.species,
+                _ => "Unknown",
+            };
+            *entry.rank_value_mut(rank) = value.into();
+        }
+        entry.domain = None;
+        entry.realm = None;
+        entry
+    }
+}
+
+/// The classification most often assigned to each taxid, ties going to the first sorted one
+///
+/// Taxonomies like GTDB and SILVA classify genomes or sequences rather than taxa, so a taxid can
+/// end up with several classifications.
+pub fn most_common<'a>(
+    assignments: impl Iterator<Item = (TaxId, &'a Classification)>,
+) -> HashMap<TaxId, Classification> {
+    let mut counts: HashMap<TaxId, HashMap<&Classification, usize>> = HashMap::new();
+    for (tax_id, classification) in assignments {
+        *counts
+            .entry(tax_id)
+            .or_default()
+            .entry(classification)
+            .or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .filter_map(|(tax_id, classifications)| {
+            classifications
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+                .map(|(classification, _)| (tax_id, classification.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_classification() {
+        let text = "d__Bacteria;p__Actinomycetota;c__Actinomycetes;o__Streptomycetales;f__Streptomycetaceae;g__Streptomyces;s__";
+        let classification: Classification = text.parse().unwrap();
+        assert_eq!(classification.genus, "Streptomyces");
+        assert_eq!(classification.species, "Unknown");
+        assert_eq!(classification.to_string(), text);
+
+        assert!("d__Bacteria;p__Actinomycetota"
+            .parse::<Classification>()
+            .is_err());
+        assert!("Bacteria;Actinomycetota;a;b;c;d;e"
+            .parse::<Classification>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_with_taxonomy() {
+        let mut entry = NcbiTaxEntry {
+            name: "Streptomyces coelicolor".to_string(),
+            species: "coelicolor".into(),
+            genus: "Streptomyces".into(),
+            kingdom: "Bacillati".into(),
+            superkingdom: "Bacteria".into(),
+            ..NcbiTaxEntry::new(tid(1902))
+        };
+        assert_eq!(
+            entry.classification(Taxonomy::Ncbi).unwrap().species,
+            "Streptomyces coelicolor"
+        );
+        assert_eq!(entry.classification(Taxonomy::Gtdb), None);
+        assert_eq!(&*entry.with_taxonomy(Taxonomy::Gtdb).genus, "Unknown");
+
+        entry.classifications.insert(
+            Taxonomy::Gtdb,
+            "d__Bacteria;p__Actinomycetota;c__Actinomycetes;o__Streptomycetales;f__Streptomycetaceae;g__Streptomyces;s__Streptomyces violaceoruber"
+                .parse()
+                .unwrap(),
+        );
+        let gtdb = entry.with_taxonomy(Taxonomy::Gtdb);
+        assert_eq!(gtdb.name, entry.name);
+        assert_eq!(&*gtdb.superkingdom, "Bacteria");
+        assert_eq!(&*gtdb.kingdom, "Unknown");
+        assert_eq!(&*gtdb.species, "Streptomyces violaceoruber");
+        assert_eq!(
+            gtdb.species_name().as_deref(),
+            Some("Streptomyces violaceoruber")
+        );
+
+        assert_eq!("GTDB".parse::<Taxonomy>().unwrap(), Taxonomy::Gtdb);
+        assert!("rdp".parse::<Taxonomy>().is_err());
+    }
+}
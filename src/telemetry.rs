@@ -0,0 +1,198 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lookup counters for serve mode, persisted across restarts
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomically;
+use crate::errors::ASDBTaxonError;
+use crate::taxa::TaxId;
+
+/// Cumulative lookup statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Counters {
+    pub total_lookups: u64,
+    /// Number of lookups per taxid
+    pub popularity: HashMap<TaxId, u64>,
+}
+
+impl Counters {
+    /// The `limit` most looked up taxids with their counts, most popular first
+    pub fn popular(&self, limit: usize) -> Vec<(TaxId, u64)> {
+        let mut popular: Vec<(TaxId, u64)> = self
+            .popularity
+            .iter()
+            .map(|(tax_id, count)| (*tax_id, *count))
+            .collect();
+        popular.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        popular.truncate(limit);
+        popular
+    }
+}
+
+/// Thread-safe lookup counters, optionally backed by a file
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    counters: Mutex<Counters>,
+    path: Option<PathBuf>,
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        Default::default()
+    }
+
+    /// Counters persisted in `path`, continuing from its contents if it exists
+    pub fn with_file(path: impl Into<PathBuf>) -> Result<Telemetry, ASDBTaxonError> {
+        let path = path.into();
+        let counters = match fs::read_to_string(&path) {
+            Ok(json_data) => serde_json::from_str(&json_data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Counters::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Telemetry {
+            counters: Mutex::new(counters),
+            path: Some(path),
+        })
+    }
+
+    /// Count a lookup of `tax_id`
+    pub fn record(&self, tax_id: TaxId) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.total_lookups += 1;
+        *counters.popularity.entry(tax_id).or_default() += 1;
+    }
+
+    /// A copy of the current counts
+    pub fn snapshot(&self) -> Counters {
+        self.counters.lock().unwrap().clone()
+    }
+
+    /// Write the counters to the backing file, if there is one
+    pub fn flush(&self) -> Result<(), ASDBTaxonError> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let json_data = serde_json::to_string(&self.snapshot())?;
+        write_atomically(path, json_data.as_bytes())
+    }
+
+    /// Flush the counters every `interval` in a background thread, and once more when the
+    /// returned [Flusher] is stopped or dropped
+    pub fn spawn_flusher(self: &Arc<Self>, interval: Duration) -> Result<Flusher, ASDBTaxonError> {
+        if interval.is_zero() {
+            return Err(ASDBTaxonError::InvalidFormat(
+                "the flush interval needs to be positive".to_string(),
+            ));
+        }
+        let telemetry = Arc::clone(self);
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || loop {
+            let last = match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+            };
+            if let Err(err) = telemetry.flush() {
+                warn!("Failed to write counters: {}", err);
+            }
+            if last {
+                return;
+            }
+        });
+        Ok(Flusher {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+/// Background thread flushing [Telemetry], see [Telemetry::spawn_flusher]
+#[derive(Debug)]
+pub struct Flusher {
+    stop: Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Flusher {
+    /// Flush one last time and wait for the thread to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = self.stop.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+    use crate::temp_dir;
+
+    #[test]
+    fn test_persistence() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("counters.json");
+        let telemetry = Telemetry::with_file(&path).unwrap();
+        for tax_id in [tid(1883), tid(5062), tid(1883)] {
+            telemetry.record(tax_id);
+        }
+        telemetry.flush().unwrap();
+
+        let restarted = Telemetry::with_file(&path).unwrap();
+        restarted.record(tid(5062));
+        restarted.record(tid(42));
+
+        let counters = restarted.snapshot();
+        assert_eq!(counters.total_lookups, 5);
+        assert_eq!(counters.popular(2), vec![(tid(1883), 2), (tid(5062), 2)]);
+    }
+
+    #[test]
+    fn test_flusher() {
+        let tmp = temp_dir();
+        let path = tmp.path().join("flusher.json");
+        let telemetry = Arc::new(Telemetry::with_file(&path).unwrap());
+        assert!(matches!(
+            telemetry.spawn_flusher(Duration::ZERO),
+            Err(ASDBTaxonError::InvalidFormat(_))
+        ));
+
+        let flusher = telemetry.spawn_flusher(Duration::from_secs(3600)).unwrap();
+        telemetry.record(tid(1883));
+        flusher.stop();
+        let counters = Telemetry::with_file(&path).unwrap().snapshot();
+        assert_eq!(counters.total_lookups, 1);
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The taxonomy tree of the cached taxa, as indented text, Newick or GraphViz DOT
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::ser::Error;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+use crate::errors::ASDBTaxonError;
+use crate::export::{self, normalize};
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// Indented text for reading at the terminal, with entry counts
+    Text,
+    /// Newick, e.g. for iTOL
+    Newick,
+    /// A GraphViz digraph
+    Dot,
+}
+
+impl FromStr for TreeFormat {
+    type Err = ASDBTaxonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TreeFormat::Text),
+            "newick" => Ok(TreeFormat::Newick),
+            "dot" => Ok(TreeFormat::Dot),
+            _ => Err(ASDBTaxonError::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    label: String,
+    tax_id: Option<TaxId>,
+    rank: Option<String>,
+    /// Cached for its own sake, rather than only as an ancestor
+    counted: bool,
+    children: Vec<usize>,
+}
+
+/// Tree of all cached entries, ancestors included, below a single root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTree {
+    nodes: Vec<Node>,
+}
+
+impl CacheTree {
+    /// Build the tree from the parent links of a cache built with nodes.dmp, or else by nesting
+    /// the entries by their lineage columns
+    pub fn new(cache: &TaxonCache) -> CacheTree {
+        let mut entries: Vec<&NcbiTaxEntry> = cache.iter().collect();
+        entries.sort_by_key(|entry| entry.tax_id);
+        let mut tree = CacheTree {
+            nodes: vec![Node {
+                label: "root".to_string(),
+                tax_id: None,
+                rank: None,
+                counted: false,
+                children: Vec::new(),
+            }],
+        };
+        if entries.iter().any(|entry| entry.parent_id.is_some()) {
+            tree.link_parents(cache, &entries);
+        } else {
+            tree.nest_ranks(&entries);
+        }
+        let labels: Vec<String> = tree.nodes.iter().map(|node| node.label.clone()).collect();
+        for node in tree.nodes.iter_mut() {
+            node.children.sort_by(|a, b| labels[*a].cmp(&labels[*b]));
+        }
+        tree
+    }
+
+    fn add(&mut self, label: &str, rank: &str, parent: usize) -> usize {
+        self.nodes.push(Node {
+            label: normalize(label).into_owned(),
+            tax_id: None,
+            rank: Some(rank.to_string()),
+            counted: false,
+            children: Vec::new(),
+        });
+        let index = self.nodes.len() - 1;
+        self.nodes[parent].children.push(index);
+        index
+    }
+
+    fn link_parents(&mut self, cache: &TaxonCache, entries: &[&NcbiTaxEntry]) {
+        let mut index: HashMap<TaxId, usize> = HashMap::new();
+        for entry in entries {
+            if entry.tax_id == TaxId::ROOT {
+                self.nodes[0].label = normalize(&entry.name).into_owned();
+                self.nodes[0].set_entry(entry);
+                index.insert(entry.tax_id, 0);
+                continue;
+            }
+            self.nodes.push(Node {
+                label: normalize(&entry.name).into_owned(),
+                tax_id: None,
+                rank: None,
+                counted: false,
+                children: Vec::new(),
+            });
+            let node = self.nodes.len() - 1;
+            self.nodes[node].set_entry(entry);
+            index.insert(entry.tax_id, node);
+        }
+        for entry in entries {
+            if entry.tax_id == TaxId::ROOT {
+                continue;
+            }
+            // taxa whose parent isn't cached hang off the root
+            let parent = entry
+                .parent_id
+                .filter(|parent_id| cache.mappings.contains_key(parent_id))
+                .and_then(|parent_id| index.get(&parent_id))
+                .copied()
+                .unwrap_or(0);
+            self.nodes[parent].children.push(index[&entry.tax_id]);
+        }
+    }
+
+    fn nest_ranks(&mut self, entries: &[&NcbiTaxEntry]) {
+        let mut index: HashMap<Vec<&str>, usize> = HashMap::new();
+        for entry in entries {
+            let mut path: Vec<&str> = Vec::new();
+            let mut parent = 0;
+            for rank in Rank::ALL.into_iter().filter(|rank| *rank != Rank::Species) {
+                let value = entry.rank_value(rank);
+                if value.is_empty() || value == "Unknown" {
+                    continue;
+                }
+                path.push(value);
+                parent = match index.get(&path) {
+                    Some(node) => *node,
+                    None => {
+                        let node = self.add(value, rank.as_str(), parent);
+                        index.insert(path.clone(), node);
+                        node
+                    }
+                };
+            }
+            // an entry for a genus or higher is the node of its lineage column
+            if path.last() == Some(&entry.name.as_str()) && self.nodes[parent].tax_id.is_none() {
+                self.nodes[parent].set_entry(entry);
+                continue;
+            }
+            let node = self.add(&entry.name, "", parent);
+            self.nodes[node].set_entry(entry);
+        }
+    }
+
+    /// Number of counted entries in the subtree of every node
+    fn counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.nodes.len()];
+        self.count(0, &mut counts);
+        counts
+    }
+
+    fn count(&self, node: usize, counts: &mut [usize]) -> usize {
+        let mut count = usize::from(self.nodes[node].counted);
+        for child in &self.nodes[node].children {
+            count += self.count(*child, counts);
+        }
+        counts[node] = count;
+        count
+    }
+
+    /// Write the tree as indented text with the rank and number of cached entries of every node,
+    /// down to `depth` levels below the root if given
+    pub fn write_text(
+        &self,
+        mut output: impl Write,
+        depth: Option<usize>,
+    ) -> Result<(), ASDBTaxonError> {
+        let counts = self.counts();
+        let mut stack = vec![(0, 0)];
+        while let Some((node, level)) = stack.pop() {
+            let Node { label, rank, .. } = &self.nodes[node];
+            let rank = rank
+                .as_deref()
+                .map(|rank| format!(" [{}]", rank))
+                .unwrap_or_default();
+            writeln!(
+                output,
+                "{}{}{} ({})",
+                "  ".repeat(level),
+                label,
+                rank,
+                counts[node]
+            )?;
+            if depth.is_some_and(|depth| level >= depth) {
+                continue;
+            }
+            for child in self.nodes[node].children.iter().rev() {
+                stack.push((*child, level + 1));
+            }
+        }
+        Ok(())
+    }
+
+    /// The tree in Newick format, labelled with the taxon names
+    pub fn to_newick(&self) -> String {
+        let mut newick = String::new();
+        self.newick_node(0, &mut newick);
+        newick.push(';');
+        newick
+    }
+
+    fn newick_node(&self, node: usize, newick: &mut String) {
+        let children = &self.nodes[node].children;
+        if !children.is_empty() {
+            newick.push('(');
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    newick.push(',');
+                }
+                self.newick_node(*child, newick);
+            }
+            newick.push(')');
+        }
+        newick.push_str(&newick_label(&self.nodes[node].label));
+    }
+
+    /// Write the tree as a GraphViz digraph, with the taxids in the node tooltips
+    pub fn write_dot(&self, mut output: impl Write) -> Result<(), ASDBTaxonError> {
+        writeln!(output, "digraph taxonomy {{")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let tooltip = node
+                .tax_id
+                .map(|tax_id| format!(", tooltip=\"taxid {}\"", tax_id))
+                .unwrap_or_default();
+            writeln!(
+                output,
+                "  n{} [label=\"{}\"{}];",
+                i,
+                node.label.replace('\\', "\\\\").replace('"', "\\\""),
+                tooltip
+            )?;
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            for child in &node.children {
+                writeln!(output, "  n{} -> n{};", i, child)?;
+            }
+        }
+        writeln!(output, "}}")?;
+        Ok(())
+    }
+}
+
+impl Node {
+    fn set_entry(&mut self, entry: &NcbiTaxEntry) {
+        self.tax_id = Some(entry.tax_id);
+        self.rank = Some(export::rank(entry).to_string());
+        self.counted = !entry.ancestor_only;
+    }
+}
+
+/// Quote labels with characters Newick reserves, doubling any single quotes
+fn newick_label(label: &str) -> String {
+    if label
+        .chars()
+        .any(|c| " ()[]':;,".contains(c) || c.is_whitespace())
+    {
+        return format!("'{}'", label.replace('\'', "''"));
+    }
+    label.to_string()
+}
+
+/// Lowest common ancestor of some taxa, see [TaxonCache::lca]
+#[derive(Debug, Clone)]
+pub enum Ancestor {
+    /// The cached entry of the ancestor
+    Cached(NcbiTaxEntry),
+    /// An ancestor that isn't cached itself, as a bare entry with the name and lineage its
+    /// descendants share, but without a taxid of its own
+    Uncached(NcbiTaxEntry),
+}
+
+impl Ancestor {
+    /// Taxid of the ancestor, `None` if it isn't cached
+    pub fn tax_id(&self) -> Option<TaxId> {
+        match self {
+            Ancestor::Cached(entry) => Some(entry.tax_id),
+            Ancestor::Uncached(_) => None,
+        }
+    }
+
+    pub fn entry(&self) -> &NcbiTaxEntry {
+        match self {
+            Ancestor::Cached(entry) | Ancestor::Uncached(entry) => entry,
+        }
+    }
+}
+
+/// The entry, with a `tax_id` of `null` if the ancestor isn't cached
+impl Serialize for Ancestor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Ancestor::Cached(entry) => entry.serialize(serializer),
+            Ancestor::Uncached(entry) => {
+                let mut value = serde_json::to_value(entry).map_err(S::Error::custom)?;
+                value["tax_id"] = Value::Null;
+                value.serialize(serializer)
+            }
+        }
+    }
+}
+
+/// Iterator over the cached descendants of a taxon, see [TaxonCache::descendants]
+///
+/// Taxa come before their descendants, siblings in order of their taxids.
+#[derive(Debug, Clone)]
+pub struct Descendants<'a> {
+    children: HashMap<TaxId, Vec<&'a NcbiTaxEntry>>,
+    stack: Vec<&'a NcbiTaxEntry>,
+}
+
+impl<'a> Descendants<'a> {
+    /// Descendants of `ancestor` by the parent links, or if the cache was built without
+    /// nodes.dmp, the entries naming it at its rank in their lineage
+    pub fn new(cache: &'a TaxonCache, ancestor: &'a NcbiTaxEntry) -> Descendants<'a> {
+        let mut entries: Vec<&NcbiTaxEntry> = cache.iter().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.tax_id));
+
+        let mut children: HashMap<TaxId, Vec<&NcbiTaxEntry>> = HashMap::new();
+        if entries.iter().any(|entry| entry.parent_id.is_some()) {
+            for entry in entries {
+                match entry.parent_id {
+                    Some(parent_id) if parent_id != entry.tax_id => {
+                        children.entry(parent_id).or_default().push(entry)
+                    }
+                    _ => (),
+                }
+            }
+        } else if let Ok(rank) = export::rank(ancestor).parse::<Rank>() {
+            children.insert(
+                ancestor.tax_id,
+                entries
+                    .into_iter()
+                    .filter(|entry| {
+                        entry.tax_id != ancestor.tax_id && entry.rank_value(rank) == ancestor.name
+                    })
+                    .collect(),
+            );
+        }
+        let stack = children.remove(&ancestor.tax_id).unwrap_or_default();
+        Descendants { children, stack }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a NcbiTaxEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.stack.pop()?;
+        // removing the children also guards against cycles
+        if let Some(children) = self.children.remove(&entry.tax_id) {
+            self.stack.extend(children);
+        }
+        Some(entry)
+    }
+}
+
+/// Write the tree of the cached taxa in `format`, limiting text output to `depth` levels
+pub fn write_tree(
+    cache: &TaxonCache,
+    format: TreeFormat,
+    depth: Option<usize>,
+    mut output: impl Write,
+) -> Result<(), ASDBTaxonError> {
+    let tree = CacheTree::new(cache);
+    match format {
+        TreeFormat::Text => tree.write_text(output, depth)?,
+        TreeFormat::Newick => writeln!(output, "{}", tree.to_newick())?,
+        TreeFormat::Dot => tree.write_dot(output)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, name: &str, parent_id: Option<TaxId>) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: name.to_string(),
+            genus: "Streptomyces".into(),
+            superkingdom: "Bacteria".into(),
+            parent_id,
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    fn cache(entries: Vec<NcbiTaxEntry>) -> TaxonCache {
+        let mut cache = TaxonCache::new();
+        for entry in entries {
+            cache.mappings.insert(entry.tax_id, entry);
+        }
+        cache
+    }
+
+    #[test]
+    fn test_parent_links() {
+        let cache = cache(vec![
+            entry(tid(2), "Bacteria", Some(tid(1))),
+            entry(tid(1883), "Streptomyces", Some(tid(2))),
+            entry(tid(1902), "Streptomyces coelicolor", Some(tid(1883))),
+            entry(tid(1911), "Streptomyces griseus", Some(tid(1883))),
+            entry(tid(5062), "Aspergillus oryzae", Some(tid(5052))),
+        ]);
+        assert_eq!(
+            CacheTree::new(&cache).to_newick(),
+            "('Aspergillus oryzae',(('Streptomyces coelicolor','Streptomyces griseus')Streptomyces)Bacteria)root;"
+        );
+
+        let mut dot = Vec::new();
+        CacheTree::new(&cache).write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph taxonomy {\n  n0 [label=\"root\"];\n"));
+        assert!(dot.contains("[label=\"Streptomyces\", tooltip=\"taxid 1883\"];"));
+        assert_eq!(dot.matches(" -> ").count(), 5);
+    }
+
+    #[test]
+    fn test_rank_nesting() {
+        let cache = cache(vec![
+            entry(tid(1883), "Streptomyces", None),
+            entry(tid(1902), "Streptomyces coelicolor", None),
+            entry(tid(1911), "Streptomyces griseus", None),
+        ]);
+        let tree = CacheTree::new(&cache);
+        assert_eq!(tree.nodes.len(), 5);
+        assert_eq!(
+            tree.to_newick(),
+            "((('Streptomyces coelicolor','Streptomyces griseus')Streptomyces)Bacteria)root;"
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        let mut ancestor = entry(tid(1883), "Streptomyces", Some(tid(1)));
+        ancestor.rank = Some("genus".to_string());
+        ancestor.ancestor_only = true;
+        let cache = cache(vec![
+            ancestor,
+            entry(tid(1902), "Streptomyces coelicolor", Some(tid(1883))),
+            entry(tid(1911), "Streptomyces griseus", Some(tid(1883))),
+        ]);
+        let tree = CacheTree::new(&cache);
+
+        let mut text = Vec::new();
+        tree.write_text(&mut text, None).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "root (2)\n  Streptomyces [genus] (2)\n    Streptomyces coelicolor [no rank] (1)\n    Streptomyces griseus [no rank] (1)\n"
+        );
+
+        let mut text = Vec::new();
+        tree.write_text(&mut text, Some(1)).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "root (2)\n  Streptomyces [genus] (2)\n"
+        );
+    }
+
+    #[test]
+    fn test_descendants() {
+        let cache = cache(vec![
+            entry(tid(2), "Bacteria", Some(tid(1))),
+            entry(tid(1883), "Streptomyces", Some(tid(2))),
+            entry(tid(1911), "Streptomyces griseus", Some(tid(1883))),
+            entry(tid(1902), "Streptomyces coelicolor", Some(tid(1883))),
+            entry(
+                tid(100226),
+                "Streptomyces coelicolor A3(2)",
+                Some(tid(1902)),
+            ),
+            entry(tid(5062), "Aspergillus oryzae", Some(tid(5052))),
+        ]);
+        let taxids = |tax_id| -> Vec<TaxId> {
+            Descendants::new(&cache, &cache.mappings[&tax_id])
+                .map(|entry| entry.tax_id)
+                .collect()
+        };
+        assert_eq!(taxids(tid(1883)), vec![1902, 100226, 1911]);
+        assert_eq!(taxids(tid(2)), vec![1883, 1902, 100226, 1911]);
+        assert!(taxids(tid(5062)).is_empty());
+
+        let mut cache = cache;
+        for entry in cache.mappings.values_mut() {
+            entry.parent_id = None;
+        }
+        cache.mappings.get_mut(&tid(2)).unwrap().genus = "Unknown".into();
+        cache.mappings.get_mut(&tid(5062)).unwrap().genus = "Aspergillus".into();
+        let genus = &cache.mappings[&tid(1883)];
+        let mut flat: Vec<TaxId> = Descendants::new(&cache, genus)
+            .map(|entry| entry.tax_id)
+            .collect();
+        flat.sort();
+        assert_eq!(flat, vec![1902, 1911, 100226]);
+    }
+
+    #[test]
+    fn test_newick_label() {
+        assert_eq!(newick_label("Streptomyces"), "Streptomyces");
+        assert_eq!(
+            newick_label("Streptomyces sp. 'ABC'"),
+            "'Streptomyces sp. ''ABC'''"
+        );
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Changes of the cache entries when refreshing a cache against newer taxdump files
+//!
+//! See [TaxonCacheBuilder::update](crate::builder::TaxonCacheBuilder::update), which re-resolves
+//! all cached taxids and compares the entries before and after. The changes of every update are
+//! kept in [TaxonCache::history], as an audit trail of why values changed between releases.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::DumpInfo;
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+/// How a single entry changed in an update
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum Change {
+    /// Newly cached, e.g. as the taxid another one was merged into
+    Added {
+        tax_id: TaxId,
+    },
+    /// No longer in the taxdump
+    Removed {
+        tax_id: TaxId,
+    },
+    /// Listed in delnodes.dmp
+    Deleted {
+        tax_id: TaxId,
+    },
+    Merged {
+        tax_id: TaxId,
+        into: TaxId,
+    },
+    Renamed {
+        tax_id: TaxId,
+        from: String,
+        to: String,
+    },
+    Lineage {
+        tax_id: TaxId,
+        rank: Rank,
+        from: String,
+        to: String,
+    },
+}
+
+impl Change {
+    pub fn tax_id(&self) -> TaxId {
+        match *self {
+            Change::Added { tax_id }
+            | Change::Removed { tax_id }
+            | Change::Deleted { tax_id }
+            | Change::Merged { tax_id, .. }
+            | Change::Renamed { tax_id, .. }
+            | Change::Lineage { tax_id, .. } => tax_id,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added { tax_id } => write!(f, "{}: added", tax_id),
+            Change::Removed { tax_id } => write!(f, "{}: removed", tax_id),
+            Change::Deleted { tax_id } => write!(f, "{}: deleted", tax_id),
+            Change::Merged { tax_id, into } => write!(f, "{}: merged into {}", tax_id, into),
+            Change::Renamed { tax_id, from, to } => {
+                write!(f, "{}: renamed from '{}' to '{}'", tax_id, from, to)
+            }
+            Change::Lineage {
+                tax_id,
+                rank,
+                from,
+                to,
+            } => write!(
+                f,
+                "{}: {} changed from '{}' to '{}'",
+                tax_id, rank, from, to
+            ),
+        }
+    }
+}
+
+/// The changes of one update, as kept in the cache history
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpdateRecord {
+    /// Update time, in seconds since the Unix epoch
+    pub updated_at: u64,
+    /// The taxdump updated against
+    pub taxdump: Option<DumpInfo>,
+    pub changes: Vec<Change>,
+}
+
+impl UpdateRecord {
+    /// Whether `change` concerns `tax_id`, including merges into it
+    fn concerns(change: &Change, tax_id: TaxId) -> bool {
+        change.tax_id() == tax_id
+            || matches!(change, Change::Merged { into, .. } if *into == tax_id)
+    }
+
+    /// The changes concerning `tax_id`
+    pub fn changes_of(&self, tax_id: TaxId) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(move |change| UpdateRecord::concerns(change, tax_id))
+    }
+}
+
+/// All changes of an update, sorted by taxid
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateReport {
+    /// Number of entries before the update
+    pub entries: usize,
+    pub changes: Vec<Change>,
+}
+
+impl UpdateReport {
+    /// Compare the entries `before` the update with the updated `cache`
+    pub fn compare(before: &HashMap<TaxId, NcbiTaxEntry>, cache: &TaxonCache) -> UpdateReport {
+        let mut changes = Vec::new();
+        for (tax_id, old) in before {
+            let Some(new) = cache.mappings.get(tax_id) else {
+                changes.push(match cache.deprecated_ids.get(tax_id) {
+                    Some(into) => Change::Merged {
+                        tax_id: *tax_id,
+                        into: *into,
+                    },
+                    None if cache.deleted_ids.contains(tax_id) => {
+                        Change::Deleted { tax_id: *tax_id }
+                    }
+                    None => Change::Removed { tax_id: *tax_id },
+                });
+                continue;
+            };
+            if old.name != new.name {
+                changes.push(Change::Renamed {
+                    tax_id: *tax_id,
+                    from: old.name.clone(),
+                    to: new.name.clone(),
+                });
+            }
+            for rank in Rank::ALL {
+                if old.rank_value(rank) != new.rank_value(rank) {
+                    changes.push(Change::Lineage {
+                        tax_id: *tax_id,
+                        rank,
+                        from: old.rank_value(rank).to_string(),
+                        to: new.rank_value(rank).to_string(),
+                    });
+                }
+            }
+        }
+        for tax_id in cache.mappings.keys() {
+            if !before.contains_key(tax_id) {
+                changes.push(Change::Added { tax_id: *tax_id });
+            }
+        }
+        // the sort is stable, keeping the ranks of a taxid in order
+        changes.sort_by_key(Change::tax_id);
+        UpdateReport {
+            entries: before.len(),
+            changes,
+        }
+    }
+
+    /// Number of changes matching `predicate`
+    fn count(&self, predicate: fn(&Change) -> bool) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| predicate(change))
+            .count()
+    }
+}
+
+impl fmt::Display for UpdateReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lineages = self.changes.iter().filter_map(|change| match change {
+            Change::Lineage { tax_id, .. } => Some(*tax_id),
+            _ => None,
+        });
+        let mut lineages: Vec<TaxId> = lineages.collect();
+        lineages.dedup();
+
+        writeln!(f, "{} entries checked", self.entries)?;
+        writeln!(
+            f,
+            "{} added",
+            self.count(|change| matches!(change, Change::Added { .. }))
+        )?;
+        writeln!(
+            f,
+            "{} removed",
+            self.count(|change| matches!(change, Change::Removed { .. }))
+        )?;
+        writeln!(
+            f,
+            "{} deleted",
+            self.count(|change| matches!(change, Change::Deleted { .. }))
+        )?;
+        writeln!(
+            f,
+            "{} merged",
+            self.count(|change| matches!(change, Change::Merged { .. }))
+        )?;
+        writeln!(
+            f,
+            "{} renamed",
+            self.count(|change| matches!(change, Change::Renamed { .. }))
+        )?;
+        write!(f, "{} lineages changed", lineages.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    #[test]
+    fn test_compare() {
+        let entry = |tax_id, name: &str, genus: &str| NcbiTaxEntry {
+            name: name.to_string(),
+            genus: genus.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        };
+        let before = HashMap::from([
+            (
+                tid(1),
+                entry(tid(1), "Streptomyces coelicolor", "Streptomyces"),
+            ),
+            (
+                tid(2),
+                entry(tid(2), "Streptomyces lividans", "Streptomyces"),
+            ),
+            (tid(3), entry(tid(3), "Nocardia sp.", "Nocardia")),
+            (tid(4), entry(tid(4), "Bacterium", "Unknown")),
+        ]);
+        let mut cache = TaxonCache::new();
+        cache.mappings.insert(
+            tid(1),
+            entry(tid(1), "Streptomyces coelicolor A3(2)", "Kitasatospora"),
+        );
+        cache.mappings.insert(
+            tid(5),
+            entry(tid(5), "Streptomyces lividans", "Streptomyces"),
+        );
+        cache.deprecated_ids.insert(tid(2), tid(5));
+        cache.deleted_ids.insert(tid(3));
+
+        let report = UpdateReport::compare(&before, &cache);
+        assert_eq!(
+            report.changes,
+            vec![
+                Change::Renamed {
+                    tax_id: tid(1),
+                    from: "Streptomyces coelicolor".to_string(),
+                    to: "Streptomyces coelicolor A3(2)".to_string(),
+                },
+                Change::Lineage {
+                    tax_id: tid(1),
+                    rank: Rank::Genus,
+                    from: "Streptomyces".to_string(),
+                    to: "Kitasatospora".to_string(),
+                },
+                Change::Merged {
+                    tax_id: tid(2),
+                    into: tid(5)
+                },
+                Change::Deleted { tax_id: tid(3) },
+                Change::Removed { tax_id: tid(4) },
+                Change::Added { tax_id: tid(5) },
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "4 entries checked\n1 added\n1 removed\n1 deleted\n1 merged\n1 renamed\n1 lineages changed"
+        );
+
+        let json = serde_json::to_string(&report.changes[1]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"change":"lineage","tax_id":1,"rank":"genus","from":"Streptomyces","to":"Kitasatospora"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Change>(&json).unwrap(),
+            report.changes[1]
+        );
+    }
+}
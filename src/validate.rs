@@ -0,0 +1,185 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Integrity checks of a cache on its own, and optionally against the dumps it was built from
+//!
+//! Unlike [crate::verify], which checks a cache against the datadir and database using it, this
+//! only looks at the cache itself: merges have to point somewhere and entries need names, while
+//! [TaxId] already ensures taxids are positive. Entries that changed in the dumps are found by
+//! re-resolving a copy of the cache with
+//! [TaxonCacheBuilder::update](crate::builder::TaxonCacheBuilder::update).
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::taxa::TaxId;
+use crate::update::Change;
+use crate::TaxonCache;
+
+/// An internal inconsistency of a cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// A merged taxid pointing to a taxid neither cached nor deleted
+    MissingMergeTarget {
+        tax_id: TaxId,
+        into: TaxId,
+    },
+    /// An entry cached under a different taxid than its own
+    TaxIdMismatch {
+        key: TaxId,
+        tax_id: TaxId,
+    },
+    EmptyName {
+        tax_id: TaxId,
+    },
+}
+
+impl Problem {
+    pub fn tax_id(&self) -> TaxId {
+        match *self {
+            Problem::MissingMergeTarget { tax_id, .. } | Problem::EmptyName { tax_id } => tax_id,
+            Problem::TaxIdMismatch { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::MissingMergeTarget { tax_id, into } => {
+                write!(f, "missing merge target\t{}\t{}", tax_id, into)
+            }
+            Problem::TaxIdMismatch { key, tax_id } => {
+                write!(f, "taxid mismatch\t{}\t{}", key, tax_id)
+            }
+            Problem::EmptyName { tax_id } => write!(f, "empty name\t{}", tax_id),
+        }
+    }
+}
+
+/// All problems found by [validate], sorted by taxid
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationReport {
+    pub entries: usize,
+    pub problems: Vec<Problem>,
+    /// Entries that differ from the dumps, if they were checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Vec<Change>>,
+}
+
+impl ValidationReport {
+    pub fn discrepancies(&self) -> usize {
+        self.problems.len() + self.changes.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.discrepancies() == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{}", problem)?;
+        }
+        for change in self.changes.iter().flatten() {
+            writeln!(f, "differs from dump\t{}", change)?;
+        }
+        write!(f, "{} entries", self.entries)?;
+        if self.changes.is_some() {
+            write!(f, " checked against the dumps")?;
+        }
+        write!(f, ": {} discrepancies", self.discrepancies())
+    }
+}
+
+/// Check the internal consistency of `cache`
+pub fn validate(cache: &TaxonCache) -> ValidationReport {
+    let mut problems = Vec::new();
+    for (key, entry) in &cache.mappings {
+        if entry.tax_id != *key {
+            problems.push(Problem::TaxIdMismatch {
+                key: *key,
+                tax_id: entry.tax_id,
+            });
+        }
+        if entry.name.trim().is_empty() {
+            problems.push(Problem::EmptyName { tax_id: *key });
+        }
+    }
+    for (tax_id, into) in &cache.deprecated_ids {
+        if !cache.mappings.contains_key(into) && !cache.deleted_ids.contains(into) {
+            problems.push(Problem::MissingMergeTarget {
+                tax_id: *tax_id,
+                into: *into,
+            });
+        }
+    }
+    // stable, so the problems of a taxid stay in the order checked
+    problems.sort_by_key(Problem::tax_id);
+    ValidationReport {
+        entries: cache.mappings.len(),
+        problems,
+        changes: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::{tid, NcbiTaxEntry};
+
+    fn entry(tax_id: TaxId, name: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: name.to_string(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut cache = TaxonCache::new();
+        cache.insert(entry(tid(23456), "Streptomyces examplis"));
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache.deprecated_ids.insert(tid(11111), tid(99999));
+        cache.deleted_ids.insert(tid(99999));
+        let report = validate(&cache);
+        assert!(report.is_valid());
+        assert_eq!(report.to_string(), "1 entries: 0 discrepancies");
+
+        cache.mappings.insert(tid(5062), entry(tid(5063), " "));
+        cache.deprecated_ids.insert(tid(22222), tid(33333));
+        let report = validate(&cache);
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::TaxIdMismatch {
+                    key: tid(5062),
+                    tax_id: tid(5063)
+                },
+                Problem::EmptyName { tax_id: tid(5062) },
+                Problem::MissingMergeTarget {
+                    tax_id: tid(22222),
+                    into: tid(33333)
+                },
+            ]
+        );
+        assert_eq!(report.discrepancies(), 3);
+        assert!(report
+            .to_string()
+            .starts_with("taxid mismatch\t5062\t5063\nempty name\t5062\n"));
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Consistency checks between a datadir, a cache and the database taxa table
+//!
+//! All taxids needed by the datadir should be in the cache, all cache entries should be in the
+//! database, and the lineages in cache and database should agree.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::export::{column_values, COLUMNS};
+use crate::taxa::{NcbiTaxEntry, TaxId};
+use crate::TaxonCache;
+
+/// A lineage column that differs between cache and database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineageMismatch {
+    pub tax_id: TaxId,
+    pub column: String,
+    pub cache: String,
+    pub db: String,
+}
+
+/// All discrepancies found by [verify], with lists sorted by taxid
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub datadir_taxids: usize,
+    pub cache_entries: usize,
+    /// Number of database entries, if a database was checked
+    pub db_entries: Option<usize>,
+    /// Datadir taxids that can't be looked up in the cache
+    pub missing_from_cache: Vec<TaxId>,
+    /// Cache entries without a database entry
+    pub missing_from_db: Vec<TaxId>,
+    pub lineage_mismatches: Vec<LineageMismatch>,
+}
+
+impl VerifyReport {
+    pub fn discrepancies(&self) -> usize {
+        self.missing_from_cache.len() + self.missing_from_db.len() + self.lineage_mismatches.len()
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies() == 0
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for tax_id in &self.missing_from_cache {
+            writeln!(f, "missing from cache\t{}", tax_id)?;
+        }
+        for tax_id in &self.missing_from_db {
+            writeln!(f, "missing from database\t{}", tax_id)?;
+        }
+        for mismatch in &self.lineage_mismatches {
+            writeln!(
+                f,
+                "lineage mismatch\t{}\t{}\t{}\t{}",
+                mismatch.tax_id, mismatch.column, mismatch.cache, mismatch.db
+            )?;
+        }
+        write!(
+            f,
+            "{} datadir taxids, {} cache entries",
+            self.datadir_taxids, self.cache_entries
+        )?;
+        if let Some(db_entries) = self.db_entries {
+            write!(f, ", {} database entries", db_entries)?;
+        }
+        write!(f, ": {} discrepancies", self.discrepancies())
+    }
+}
+
+/// Check that `taxids` ⊆ `cache` ⊆ `db`, and that cache and database lineages are equal
+///
+/// Entries only cached as ancestors are not expected in the database.
+pub fn verify(
+    taxids: &HashSet<TaxId>,
+    cache: &TaxonCache,
+    db: Option<&HashMap<TaxId, NcbiTaxEntry>>,
+) -> VerifyReport {
+    let mut report = VerifyReport {
+        datadir_taxids: taxids.len(),
+        cache_entries: cache.mappings.len(),
+        db_entries: db.map(|db| db.len()),
+        ..Default::default()
+    };
+
+    report.missing_from_cache = taxids
+        .iter()
+        .filter(|tax_id| cache.lookup(**tax_id).is_err())
+        .copied()
+        .collect();
+    report.missing_from_cache.sort();
+
+    let Some(db) = db else {
+        return report;
+    };
+
+    let mut entries: Vec<&NcbiTaxEntry> = cache
+        .mappings
+        .values()
+        .filter(|entry| !entry.ancestor_only)
+        .collect();
+    entries.sort_by_key(|entry| entry.tax_id);
+    for entry in entries {
+        let Some(db_entry) = db.get(&entry.tax_id) else {
+            report.missing_from_db.push(entry.tax_id);
+            continue;
+        };
+        for ((cache_value, db_value), column) in column_values(entry)
+            .iter()
+            .zip(column_values(db_entry).iter())
+            .zip(COLUMNS[1..].iter())
+        {
+            if !same_value(cache_value, db_value) {
+                report.lineage_mismatches.push(LineageMismatch {
+                    tax_id: entry.tax_id,
+                    column: column.to_string(),
+                    cache: cache_value.to_string(),
+                    db: db_value.to_string(),
+                });
+            }
+        }
+    }
+    report
+}
+
+/// The cache marks missing ranks as "Unknown", the database might leave them empty
+fn same_value(cache: &str, db: &str) -> bool {
+    let unknown = |value: &str| value.is_empty() || value == "Unknown";
+    cache == db || (unknown(cache) && unknown(db))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, genus: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: format!("{} examplis", genus),
+            genus: genus.into(),
+            family: "Unknown".into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut cache = TaxonCache::new();
+        cache.deprecated_ids.insert(tid(12345), tid(23456));
+        cache
+            .mappings
+            .insert(tid(23456), entry(tid(23456), "Streptomyces"));
+        cache
+            .mappings
+            .insert(tid(5062), entry(tid(5062), "Aspergillus"));
+        let taxids: HashSet<TaxId> = [tid(12345), tid(5062), tid(42)].into_iter().collect();
+
+        let report = verify(&taxids, &cache, None);
+        assert_eq!(report.missing_from_cache, vec![42]);
+        assert_eq!(report.discrepancies(), 1);
+
+        let mut db = HashMap::new();
+        let mut db_entry = entry(tid(23456), "Streptomycetes");
+        db_entry.family = "".into();
+        db.insert(tid(23456), db_entry);
+        let report = verify(&taxids, &cache, Some(&db));
+        assert_eq!(report.missing_from_db, vec![5062]);
+        assert_eq!(report.lineage_mismatches.len(), 2);
+        assert_eq!(report.lineage_mismatches[1].column, "genus");
+        assert_eq!(report.discrepancies(), 4);
+    }
+}
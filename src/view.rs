@@ -0,0 +1,201 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-time filtered views on a taxon cache
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::errors::ASDBTaxonError;
+use crate::filter::Expression;
+use crate::search::NameQuery;
+use crate::taxa::{NcbiTaxEntry, Rank, TaxId};
+use crate::TaxonCache;
+
+/// Predicate deciding which cache entries are visible through a view
+#[derive(Clone)]
+pub enum ViewFilter {
+    /// Everything is visible
+    All,
+    /// Only entries with the given name at any rank of their lineage
+    Clade(String),
+    /// Only entries with the given value at a specific rank
+    Rank(Rank, String),
+    /// Only entries accepted by a custom predicate
+    Custom(Arc<dyn Fn(&NcbiTaxEntry) -> bool + Send + Sync>),
+    /// Only entries accepted by all of the given filters
+    And(Vec<ViewFilter>),
+    /// Only entries matching a filter expression
+    Where(Expression),
+}
+
+impl ViewFilter {
+    /// Parse a filter from a string like `Eukaryota` or `superkingdom=Eukaryota`
+    pub fn parse(filter: &str) -> Result<ViewFilter, ASDBTaxonError> {
+        match filter.split_once('=') {
+            Some((rank, value)) => {
+                let rank: Rank = rank
+                    .parse()
+                    .map_err(|_| ASDBTaxonError::InvalidFilter(filter.to_string()))?;
+                Ok(ViewFilter::Rank(rank, value.trim().to_string()))
+            }
+            None if filter.trim().is_empty() => Ok(ViewFilter::All),
+            None => Ok(ViewFilter::Clade(filter.trim().to_string())),
+        }
+    }
+
+    pub fn matches(&self, entry: &NcbiTaxEntry) -> bool {
+        match self {
+            ViewFilter::All => true,
+            ViewFilter::Clade(name) => Rank::ALL
+                .into_iter()
+                .filter(|rank| *rank != Rank::Species)
+                .any(|rank| entry.rank_value(rank) == name),
+            ViewFilter::Rank(rank, value) => entry.rank_value(*rank) == value,
+            ViewFilter::Custom(predicate) => predicate(entry),
+            ViewFilter::And(filters) => filters.iter().all(|filter| filter.matches(entry)),
+            ViewFilter::Where(expression) => expression.matches(entry),
+        }
+    }
+}
+
+impl fmt::Debug for ViewFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ViewFilter::All => write!(f, "All"),
+            ViewFilter::Clade(name) => write!(f, "Clade({:?})", name),
+            ViewFilter::Rank(rank, value) => write!(f, "Rank({:?}, {:?})", rank, value),
+            ViewFilter::Custom(_) => write!(f, "Custom(..)"),
+            ViewFilter::And(filters) => write!(f, "And({:?})", filters),
+            ViewFilter::Where(expression) => write!(f, "Where({:?})", expression),
+        }
+    }
+}
+
+/// A cache as seen through a filter, without copying any entries
+#[derive(Debug, Clone)]
+pub struct CacheView<'a> {
+    cache: &'a TaxonCache,
+    filter: ViewFilter,
+}
+
+impl<'a> CacheView<'a> {
+    pub fn new(cache: &'a TaxonCache, filter: ViewFilter) -> CacheView<'a> {
+        CacheView { cache, filter }
+    }
+
+    pub fn filter(&self) -> &ViewFilter {
+        &self.filter
+    }
+
+    /// Look up a taxid, treating entries hidden by the filter as not found
+    pub fn lookup(&self, tax_id: TaxId) -> Result<&'a NcbiTaxEntry, ASDBTaxonError> {
+        let entry = self.cache.lookup(tax_id)?;
+        if !self.filter.matches(entry) {
+            return Err(ASDBTaxonError::NotFound(tax_id));
+        }
+        Ok(entry)
+    }
+
+    /// List the reasons for all taxids that can't be looked up, sorted by taxid
+    pub fn missing(&self, taxids: &HashSet<TaxId>) -> Vec<ASDBTaxonError> {
+        let mut sorted: Vec<&TaxId> = taxids.iter().collect();
+        sorted.sort();
+        sorted
+            .into_iter()
+            .filter_map(|tax_id| self.lookup(*tax_id).err())
+            .collect()
+    }
+
+    /// Iterate over all visible entries
+    pub fn iter(&self) -> impl Iterator<Item = &'a NcbiTaxEntry> + '_ {
+        self.cache
+            .mappings
+            .values()
+            .filter(|entry| self.filter.matches(entry))
+    }
+
+    /// Find all visible entries with a name containing `query`, ignoring case
+    pub fn search_name(&self, query: &str) -> Vec<&'a NcbiTaxEntry> {
+        let query = query.to_lowercase();
+        self.iter()
+            .filter(|entry| entry.name.to_lowercase().contains(&query))
+            .collect()
+    }
+
+    /// Like [TaxonCache::find_by_name_with], only returning visible entries
+    pub fn find_by_name_with(&self, name: &str, query: &NameQuery) -> Vec<&'a NcbiTaxEntry> {
+        self.cache
+            .find_by_name_with(name, query)
+            .into_iter()
+            .filter(|entry| self.filter.matches(entry))
+            .collect()
+    }
+
+    /// Like [TaxonCache::find_by_name_fuzzy], only returning visible entries
+    pub fn find_by_name_fuzzy(&self, name: &str, min_score: f64) -> Vec<(&'a NcbiTaxEntry, f64)> {
+        self.cache
+            .find_by_name_fuzzy(name, min_score)
+            .into_iter()
+            .filter(|(entry, _)| self.filter.matches(entry))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxa::tid;
+
+    fn entry(tax_id: TaxId, name: &str, superkingdom: &str) -> NcbiTaxEntry {
+        NcbiTaxEntry {
+            name: name.to_string(),
+            superkingdom: superkingdom.into(),
+            ..NcbiTaxEntry::new(tax_id)
+        }
+    }
+
+    #[test]
+    fn test_view_filters_entries() {
+        let mut cache = TaxonCache::new();
+        cache
+            .mappings
+            .insert(tid(1), entry(tid(1), "Streptomyces examplis", "Bacteria"));
+        cache
+            .mappings
+            .insert(tid(2), entry(tid(2), "Aspergillus examplis", "Eukaryota"));
+        cache.deprecated_ids.insert(tid(3), tid(2));
+
+        let view = cache.view(ViewFilter::parse("superkingdom=Eukaryota").unwrap());
+        assert_eq!(view.len(), 1);
+        assert!(view.lookup(tid(2)).is_ok());
+        assert!(view.lookup(tid(3)).is_ok());
+        assert!(matches!(
+            view.lookup(tid(1)),
+            Err(ASDBTaxonError::NotFound(tax_id)) if tax_id == 1
+        ));
+
+        let view = cache.view(ViewFilter::parse("Bacteria").unwrap());
+        assert_eq!(view.iter().next().unwrap().tax_id, 1);
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JavaScript bindings for lookups in the browser, built with wasm-pack
+//!
+//! Without the `fs` feature there are no files to load from, so the cache is handed over as the
+//! bytes of a downloaded cache file, plain or gzipped. Entries are returned as JSON strings in
+//! the layout of the cache file, for `JSON.parse`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::errors::ASDBTaxonError;
+use crate::gzip::{self, is_gzip};
+use crate::search::{NameMatch, NameQuery};
+use crate::taxa::TaxId;
+use crate::TaxonCache;
+
+/// A loaded cache, see [TaxonCache]
+#[wasm_bindgen(js_name = TaxonCache)]
+pub struct WasmTaxonCache {
+    cache: TaxonCache,
+}
+
+#[wasm_bindgen(js_class = TaxonCache)]
+impl WasmTaxonCache {
+    /// Load a cache from the contents of a cache file in any format, plain or gzipped
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(data: &[u8]) -> Result<WasmTaxonCache, JsError> {
+        Ok(WasmTaxonCache {
+            cache: load_bytes(data)?,
+        })
+    }
+
+    /// The entry of `taxid` as JSON, following merged taxids, or `undefined` if there is none
+    ///
+    /// Throws for taxids of zero and below.
+    pub fn lookup(&self, taxid: i32) -> Result<Option<String>, JsError> {
+        match self.cache.lookup(TaxId::new(taxid.into())?) {
+            Ok(entry) => Ok(Some(serde_json::to_string(entry)?)),
+            Err(ASDBTaxonError::NotFound(_) | ASDBTaxonError::Deleted(_)) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// A JSON array of the entries named `name`, or starting with it with `prefix`, by name
+    pub fn search(&self, name: &str, prefix: bool, ignore_case: bool) -> Result<String, JsError> {
+        Ok(search_json(&self.cache, name, prefix, ignore_case)?)
+    }
+
+    /// Number of cached entries
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.cache.len()
+    }
+}
+
+fn load_bytes(data: &[u8]) -> Result<TaxonCache, ASDBTaxonError> {
+    let mut cache = TaxonCache::new();
+    if is_gzip(data) {
+        cache.load(gzip::decoder(data))?;
+    } else {
+        cache.load(data)?;
+    }
+    Ok(cache)
+}
+
+fn search_json(
+    cache: &TaxonCache,
+    name: &str,
+    prefix: bool,
+    ignore_case: bool,
+) -> Result<String, ASDBTaxonError> {
+    let query = NameQuery {
+        matching: if prefix {
+            NameMatch::Prefix
+        } else {
+            NameMatch::Exact
+        },
+        ignore_case,
+    };
+    Ok(serde_json::to_string(
+        &cache.find_by_name_with(name, &query),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_load_bytes() {
+        let json = br#"{"deprecated_ids": {"5": 1883}, "mappings": {"1883": {"tax_id": 1883, "name": "Streptomyces", "species": "Unknown", "genus": "Streptomyces", "family": "Streptomycetaceae", "order": "Kitasatosporales", "class": "Actinomycetes", "phylum": "Actinomycetota", "kingdom": "Unknown", "superkingdom": "Bacteria"}}}"#;
+        let mut encoder = gzip::encoder(Vec::new());
+        encoder.write_all(json).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        for data in [json.as_slice(), &gzipped] {
+            let cache = WasmTaxonCache::from_bytes(data).unwrap();
+            assert_eq!(cache.length(), 1);
+            let entry: serde_json::Value =
+                serde_json::from_str(&cache.lookup(5).unwrap().unwrap()).unwrap();
+            assert_eq!(entry["tax_id"], 1883);
+            assert_eq!(cache.lookup(2).unwrap(), None);
+        }
+
+        let cache = load_bytes(json).unwrap();
+        let found: Vec<serde_json::Value> =
+            serde_json::from_str(&search_json(&cache, "strep", true, true).unwrap()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(search_json(&cache, "strep", false, true).unwrap(), "[]");
+    }
+}
@@ -0,0 +1,1207 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! End-to-end tests of the command line tool, comparing against snapshots
+
+mod common;
+
+use asdb_taxa::lock::CacheLock;
+use common::{assert_snapshot, Workspace};
+
+#[test]
+fn test_init() {
+    let workspace = Workspace::with_fixtures("init");
+    let output = workspace.init(&["--datadir", "data", "--delnodes", "delnodes.dmp"]);
+    assert_snapshot("init", &output);
+    assert_snapshot(
+        "init_list",
+        &workspace.run(&["list", "--cache", "cache.json"]).sorted(),
+    );
+    assert_snapshot(
+        "init_list_filter",
+        &workspace
+            .run(&[
+                "list",
+                "--cache",
+                "cache.json",
+                "--filter",
+                "genus=Streptomyces",
+            ])
+            .sorted(),
+    );
+    assert_snapshot(
+        "init_list_lineage",
+        &workspace
+            .run(&[
+                "list",
+                "--cache",
+                "cache.json",
+                "--lineage-format",
+                "metaphlan",
+            ])
+            .sorted(),
+    );
+}
+
+#[test]
+fn test_list_formats() {
+    let workspace = Workspace::with_fixtures("list-formats");
+    workspace.init(&["--taxid", "5062", "--taxid", "23456", "--taxid", "1883"]);
+    let list = |extra: &[&str]| {
+        let mut args = vec!["list", "--cache", "cache.json"];
+        args.extend_from_slice(extra);
+        workspace.run(&args)
+    };
+    assert_snapshot(
+        "list_table",
+        &list(&[
+            "--format",
+            "table",
+            "--fields",
+            "tax_id,genus,superkingdom",
+            "--sort",
+            "name",
+        ]),
+    );
+    assert_snapshot(
+        "list_tsv_page",
+        &list(&[
+            "--format", "tsv", "--sort", "taxid", "--offset", "1", "--limit", "1",
+        ]),
+    );
+    assert_snapshot(
+        "list_json",
+        &list(&[
+            "--format",
+            "json",
+            "--fields",
+            "taxid,strain,lineage",
+            "--sort",
+            "genus",
+        ]),
+    );
+    assert_eq!(list(&["--fields", "tax_id,colour"]).code, 4);
+}
+
+#[test]
+fn test_init_without_inputs() {
+    let workspace = Workspace::with_fixtures("init-usage");
+    let output = workspace.run(&["init", "--cache", "cache.json"]);
+    assert_eq!(output.code, 2);
+    assert!(!workspace.path("cache.json").exists());
+}
+
+#[test]
+fn test_add() {
+    let workspace = Workspace::with_fixtures("add");
+    workspace.init(&["--taxid", "5062"]);
+    let output = workspace.run(&[
+        "add",
+        "--cache",
+        "cache.json",
+        "--mergeddump",
+        "merged.dmp",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--datadir",
+        "data",
+    ]);
+    assert_snapshot("add", &output);
+    assert_snapshot(
+        "add_list",
+        &workspace.run(&["list", "--cache", "cache.json"]).sorted(),
+    );
+}
+
+#[test]
+fn test_locked_cache() {
+    let workspace = Workspace::with_fixtures("locked-cache");
+    workspace.init(&["--taxid", "5062"]);
+    let add = [
+        "add",
+        "--cache",
+        "cache.json",
+        "--mergeddump",
+        "merged.dmp",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--taxid",
+        "23456",
+    ];
+
+    // another job holding the lock
+    let lock = CacheLock::acquire(&workspace.path("cache.json"), false).unwrap();
+    let output = workspace.run(&add);
+    assert_eq!(output.code, 8);
+    assert!(output.stderr.contains("Cache is locked by another process"));
+
+    let mut unlocked = add.to_vec();
+    unlocked.push("--no-lock");
+    assert_eq!(workspace.run(&unlocked).code, 0);
+    drop(lock);
+
+    let mut waiting = add.to_vec();
+    waiting.push("--wait");
+    assert_eq!(workspace.run(&waiting).code, 0);
+}
+
+#[test]
+fn test_backups() {
+    let workspace = Workspace::with_fixtures("backups");
+    workspace.init(&["--taxid", "5062"]);
+    let first = workspace.read("cache.json");
+    let add = |taxid: &str| {
+        workspace.run(&[
+            "add",
+            "--cache",
+            "cache.json",
+            "--mergeddump",
+            "merged.dmp",
+            "--taxdump",
+            "rankedlineage.dmp",
+            "--taxid",
+            taxid,
+            "--backups",
+            "1",
+        ])
+    };
+
+    assert_eq!(add("23456").code, 0);
+    assert_eq!(workspace.read("cache.json.1"), first);
+    let second = workspace.read("cache.json");
+    assert_eq!(add("1883").code, 0);
+    assert_eq!(workspace.read("cache.json.1"), second);
+    assert!(!workspace.path("cache.json.2").exists());
+
+    // a backup is a cache like any other
+    let output = workspace.run(&["lookup", "--cache", "cache.json.1", "--taxid", "1883"]);
+    assert_eq!(output.code, 5);
+}
+
+#[test]
+fn test_overrides() {
+    let workspace = Workspace::with_fixtures("overrides");
+    workspace.write(
+        "overrides.tsv",
+        "23456\tphylum\tActinobacteria\n5062\tname\tAspergillus oryzae RIB 40\n",
+    );
+    workspace.init(&["--taxid", "23456", "--overrides", "overrides.tsv"]);
+    workspace.run(&[
+        "add",
+        "--cache",
+        "cache.json",
+        "--mergeddump",
+        "merged.dmp",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--taxid",
+        "5062",
+    ]);
+    assert_snapshot(
+        "overrides_list",
+        &workspace
+            .run(&["list", "--cache", "cache.json", "--lineage-format", "plain"])
+            .sorted(),
+    );
+    let info = workspace.run(&["info", "--cache", "cache.json"]);
+    assert!(
+        info.stdout.contains("2 overridden fields on 2 taxids\n"),
+        "{}",
+        info.render()
+    );
+    assert!(
+        info.stdout.contains("  23456: phylum = Actinobacteria\n"),
+        "{}",
+        info.render()
+    );
+}
+
+#[test]
+fn test_add_custom() {
+    let workspace = Workspace::with_fixtures("add-custom");
+    workspace.init(&["--taxid", "23456"]);
+    workspace.write(
+        "custom.tsv",
+        "taxid\tname\tsuperkingdom\tgenus\tspecies\n\
+9000001\tStreptomyces sp. NBC01 isolate 7\tBacteria\tStreptomyces\tStreptomyces sp. NBC01\n",
+    );
+    let output = workspace.run(&[
+        "add-custom",
+        "--cache",
+        "cache.json",
+        "--file",
+        "custom.tsv",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    assert_snapshot(
+        "add_custom_list",
+        &workspace.run(&["list", "--cache", "cache.json", "--where", "source=custom"]),
+    );
+
+    workspace.write("ncbi.tsv", "taxid\tname\n23456\tShadowed\n");
+    assert_snapshot(
+        "add_custom_known",
+        &workspace.run(&["add-custom", "--cache", "cache.json", "--file", "ncbi.tsv"]),
+    );
+}
+
+#[test]
+fn test_update() {
+    let workspace = Workspace::with_fixtures("update");
+    workspace.init(&[
+        "--taxid",
+        "12345",
+        "--taxid",
+        "5062",
+        "--taxid",
+        "1883",
+        "--release",
+        "2023-01",
+    ]);
+    workspace.write(
+        "new/rankedlineage.dmp",
+        "23456\t|\tStreptomyces examplis NBC12345\t|\tStreptomyces examplis\t|\tStreptomyces\t|\tStreptomycetaceae\t|\tStreptomycetales\t|\tActinomycetes\t|\tActinomycetota\t|\tBacillati\t|\tBacteria\t|
+5063\t|\tAspergillus oryzae\t|\tAspergillus oryzae\t|\tAspergillus\t|\tAspergillaceae\t|\tEurotiales\t|\tEurotiomycetes\t|\tAscomycota\t|\tFungi\t|\tEukaryota\t|
+",
+    );
+    workspace.write("new/merged.dmp", "12345\t|\t23456\t|\n5062\t|\t5063\t|\n");
+    assert_snapshot(
+        "update",
+        &workspace.run(&[
+            "update",
+            "--cache",
+            "cache.json",
+            "--taxdump",
+            "new/rankedlineage.dmp",
+            "--mergeddump",
+            "new/merged.dmp",
+            "--release",
+            "2023-06",
+        ]),
+    );
+    assert_snapshot(
+        "update_list",
+        &workspace.run(&["list", "--cache", "cache.json"]).sorted(),
+    );
+
+    let history = workspace.run(&["history", "--cache", "cache.json", "--taxid", "5063"]);
+    let changes: Vec<&str> = history
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(_, change)| change)
+        .collect();
+    assert_eq!(
+        changes,
+        vec!["5062: merged into 5063", "5063: added"],
+        "{}",
+        history.render()
+    );
+
+    let as_of = |tag: &str| {
+        let args = [
+            "lookup",
+            "--cache",
+            "cache.json",
+            "--taxid",
+            "5062",
+            "--as-of",
+            tag,
+        ];
+        workspace.run(&args).stdout
+    };
+    assert_eq!(as_of("2023-03"), "5062: Aspergillus oryzae RIB40\n");
+    assert_eq!(as_of("2023-06"), "5063: Aspergillus oryzae\n");
+    let output = workspace.run(&[
+        "lookup",
+        "--cache",
+        "cache.json",
+        "--taxid",
+        "5062",
+        "--as-of",
+        "2022",
+    ]);
+    assert_eq!(output.code, 5, "{}", output.render());
+}
+
+#[test]
+fn test_add_incremental() {
+    let workspace = Workspace::with_fixtures("add-incremental");
+    workspace.init(&["--datadir", "data"]);
+    assert!(workspace.path("cache.json.manifest").exists());
+    workspace.write("data/d.json", r#"{"records":[{"dbxrefs":["taxon:1883"]}]}"#);
+    let add = |extra: &[&str]| {
+        let mut args = vec![
+            "add",
+            "--cache",
+            "cache.json",
+            "--mergeddump",
+            "merged.dmp",
+            "--taxdump",
+            "rankedlineage.dmp",
+            "--datadir",
+            "data",
+        ];
+        args.extend_from_slice(extra);
+        workspace.run(&args)
+    };
+    assert_snapshot("add_incremental", &add(&[]));
+    assert_snapshot("add_full_rescan", &add(&["--full-rescan"]));
+}
+
+#[test]
+fn test_corrupt_cache() {
+    let workspace = Workspace::with_fixtures("corrupt-cache");
+    workspace.init(&["--taxid", "5062", "--taxid", "23456"]);
+    let cache = workspace.read("cache.json");
+    let lookup = ["lookup", "--cache", "cache.json", "--taxid", "5062"];
+
+    workspace.write("cache.json", &cache[..cache.len() / 2]);
+    let output = workspace.run(&lookup);
+    assert_eq!(output.code, 4);
+    assert!(output.stderr.contains("Corrupt cache"));
+
+    workspace.write("cache.json", &cache.replace("RIB40", "RIB41"));
+    let output = workspace.run(&lookup);
+    assert_eq!(output.code, 4);
+    assert!(output.stderr.contains("Corrupt cache: checksum"));
+
+    workspace.write("cache.json", &cache);
+    assert_eq!(workspace.run(&lookup).code, 0);
+}
+
+#[test]
+fn test_output_format() {
+    let workspace = Workspace::with_fixtures("output-format");
+    workspace.init(&["--taxid", "5062", "--output-format", "ndjson"]);
+    let cache = workspace.read("cache.json");
+    assert!(cache.starts_with(r#"{"format":"ndjson""#));
+    assert_eq!(cache.lines().count(), 2);
+
+    let output = workspace.run(&[
+        "add",
+        "--cache",
+        "cache.json",
+        "--mergeddump",
+        "merged.dmp",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--taxid",
+        "12345",
+    ]);
+    assert_eq!(output.code, 0);
+    assert_eq!(workspace.read("cache.json").lines().count(), 3);
+    assert_snapshot(
+        "output_format_lookup",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "12345"]),
+    );
+
+    let output = workspace.run(&[
+        "convert",
+        "--from",
+        "cache.json",
+        "--to",
+        "pretty.json",
+        "--output-format",
+        "pretty",
+    ]);
+    assert_eq!(output.code, 0);
+    assert!(workspace
+        .read("pretty.json")
+        .starts_with("{\n  \"version\""));
+
+    let output = workspace.run(&[
+        "convert",
+        "--from",
+        "cache.json",
+        "--to",
+        "other.json",
+        "--output-format",
+        "yaml",
+    ]);
+    assert_ne!(output.code, 0);
+}
+
+#[test]
+fn test_indexed_lookup() {
+    let workspace = Workspace::with_fixtures("indexed-lookup");
+    workspace.init(&[
+        "--datadir",
+        "data",
+        "--delnodes",
+        "delnodes.dmp",
+        "--output-format",
+        "indexed",
+    ]);
+    assert!(workspace.path("cache.json.idx").exists());
+    // same results as looking up in the loaded cache
+    assert_snapshot(
+        "lookup_merged",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "12345"]),
+    );
+    assert_snapshot(
+        "lookup_deleted",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "99999"]),
+    );
+
+    let output = workspace.run(&[
+        "convert",
+        "--from",
+        "cache.json",
+        "--to",
+        "cache.json",
+        "--output-format",
+        "json",
+    ]);
+    assert_eq!(output.code, 0);
+    assert!(!workspace.path("cache.json.idx").exists());
+    assert_snapshot(
+        "lookup_merged",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "12345"]),
+    );
+
+    let output = workspace.run(&[
+        "convert",
+        "--from",
+        "cache.json",
+        "--to",
+        "cache.json.gz",
+        "--output-format",
+        "indexed",
+    ]);
+    assert_ne!(output.code, 0);
+}
+
+#[test]
+fn test_taxids() {
+    let workspace = Workspace::with_fixtures("taxids");
+    workspace.write("data/d.json", r#"{"records":[{"dbxrefs":["taxon:5062"]}]}"#);
+    assert_snapshot("taxids", &workspace.run(&["taxids", "--datadir", "data"]));
+    assert!(!workspace.path("cache.json").exists());
+}
+
+#[test]
+fn test_count() {
+    let workspace = Workspace::with_fixtures("count");
+    workspace.init(&["--datadir", "data", "--delnodes", "delnodes.dmp"]);
+    workspace.write(
+        "data/d.json",
+        r#"{"records":[{"dbxrefs":["taxon:23456"]}]}"#,
+    );
+    assert_snapshot(
+        "count_phylum",
+        &workspace.run(&["count", "--cache", "cache.json", "--by", "phylum"]),
+    );
+    assert_snapshot(
+        "count_records",
+        &workspace.run(&[
+            "count",
+            "--cache",
+            "cache.json",
+            "--by",
+            "superkingdom",
+            "--datadir",
+            "data",
+        ]),
+    );
+}
+
+#[test]
+fn test_top() {
+    let workspace = Workspace::with_fixtures("top");
+    workspace.init(&[
+        "--taxid",
+        "5062",
+        "--taxid",
+        "23456",
+        "--taxid",
+        "1883",
+        "--nodes",
+        "nodes.dmp",
+    ]);
+    workspace.write(
+        "data/d.json",
+        r#"{"records":[{"dbxrefs":["taxon:23456"]}]}"#,
+    );
+    workspace.write("data/e.json", r#"{"records":[{"dbxrefs":["taxon:1883"]}]}"#);
+    assert_snapshot(
+        "top_genus",
+        &workspace.run(&[
+            "top",
+            "--cache",
+            "cache.json",
+            "--rank",
+            "genus",
+            "-n",
+            "1",
+            "--datadir",
+            "data",
+        ]),
+    );
+    assert_snapshot(
+        "top_family",
+        &workspace.run(&[
+            "top",
+            "--cache",
+            "cache.json",
+            "--rank",
+            "family",
+            "--datadir",
+            "data",
+        ]),
+    );
+}
+
+#[test]
+fn test_lookup() {
+    let workspace = Workspace::with_fixtures("lookup");
+    workspace.init(&["--datadir", "data", "--delnodes", "delnodes.dmp"]);
+    assert_snapshot(
+        "lookup_merged",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "12345"]),
+    );
+    assert_snapshot(
+        "lookup_deleted",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "99999"]),
+    );
+    assert_snapshot(
+        "lookup_not_found",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "42"]),
+    );
+
+    workspace.write("ids.txt", "12345\n5062\n42\n");
+    assert_snapshot(
+        "lookup_batch",
+        &workspace.run(&["lookup", "--cache", "cache.json", "--batch", "ids.txt"]),
+    );
+}
+
+#[test]
+fn test_provenance() {
+    let workspace = Workspace::with_fixtures("provenance");
+    workspace.init(&["--datadir", "data"]);
+    let output = workspace.run(&[
+        "lookup",
+        "--cache",
+        "cache.json",
+        "--taxid",
+        "23456",
+        "--explain",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    assert!(
+        output.stdout.contains("  source: taxdump\n"),
+        "{}",
+        output.render()
+    );
+    assert!(
+        output.stdout.contains("  read from rankedlineage.dmp of "),
+        "{}",
+        output.render()
+    );
+    assert!(output.stdout.contains(", added "), "{}", output.render());
+    // -v only raises the log level
+    let output = workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "23456", "-v"]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    assert!(!output.stdout.contains("source:"), "{}", output.render());
+
+    let output = workspace.run(&["export", "--cache", "cache.json", "--provenance"]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    for line in output.stdout.lines() {
+        let values: Vec<_> = line.split('\t').collect();
+        assert_eq!(values.len(), 16, "{}", line);
+        assert_eq!(values[10..12], ["taxdump", "rankedlineage.dmp"]);
+        assert!(values[13].starts_with("20"), "{}", line);
+    }
+}
+
+#[test]
+fn test_delta() {
+    let workspace = Workspace::with_fixtures("delta");
+    workspace.init(&["--taxid", "12345", "--taxid", "5062"]);
+    workspace.write("base.json", &workspace.read("cache.json"));
+    workspace.write(
+        "new/rankedlineage.dmp",
+        "23456\t|\tStreptomyces examplis NBC12345\t|\tStreptomyces examplis\t|\tStreptomyces\t|\tStreptomycetaceae\t|\tStreptomycetales\t|\tActinomycetes\t|\tActinomycetota\t|\tBacillati\t|\tBacteria\t|
+5063\t|\tAspergillus oryzae\t|\tAspergillus oryzae\t|\tAspergillus\t|\tAspergillaceae\t|\tEurotiales\t|\tEurotiomycetes\t|\tAscomycota\t|\tFungi\t|\tEukaryota\t|
+",
+    );
+    workspace.write("new/merged.dmp", "12345\t|\t23456\t|\n5062\t|\t5063\t|\n");
+    let output = workspace.run(&[
+        "update",
+        "--cache",
+        "cache.json",
+        "--taxdump",
+        "new/rankedlineage.dmp",
+        "--mergeddump",
+        "new/merged.dmp",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+
+    let output = workspace.run(&[
+        "export-delta",
+        "--cache",
+        "cache.json",
+        "--baseline",
+        "base.json",
+        "--output",
+        "delta.json.gz",
+        "--json",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    let summary: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+    assert_eq!(
+        (summary["entries"].as_u64(), summary["removed"].as_u64()),
+        (Some(2), Some(1))
+    );
+
+    let lookup = |cache: &str| {
+        workspace.run(&[
+            "lookup",
+            "--cache",
+            cache,
+            "--taxid",
+            "5062",
+            "--delta",
+            "delta.json.gz",
+        ])
+    };
+    assert_eq!(lookup("base.json").stdout, "5063: Aspergillus oryzae\n");
+    // deltas only apply to their baseline
+    assert_eq!(lookup("cache.json").code, 4);
+    let output = workspace.run(&["lookup", "--cache", "delta.json.gz", "--taxid", "5062"]);
+    assert!(output.stderr.contains("delta cache"), "{}", output.render());
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn test_fetch_cache() {
+    use md5::Digest;
+
+    let workspace = Workspace::with_fixtures("fetch-cache");
+    workspace.init(&["--taxid", "12345", "--taxid", "5062"]);
+    let output = workspace.run(&["convert", "--from", "cache.json", "--to", "cache.json.gz"]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    let published = workspace.path("cache.json.gz");
+    let md5: String = md5::Md5::digest(std::fs::read(&published).unwrap())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    workspace.write("cache.json.gz.md5", &format!("{}  cache.json.gz\n", md5));
+    let url = format!("file://{}", published.display());
+
+    let output = workspace.run(&["fetch-cache", "--url", &url, "--cache", "mirror.json"]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    let output = workspace.run(&["lookup", "--cache", "mirror.json", "--taxid", "12345"]);
+    assert_eq!(output.stdout, "23456: Streptomyces examplis NBC12345\n");
+    assert!(!workspace.path("mirror.json.download").exists());
+
+    let output = workspace.run(&[
+        "fetch-cache",
+        "--url",
+        &url,
+        "--cache",
+        "other.json",
+        "--md5",
+        "d41d8cd98f00b204e9800998ecf8427e",
+    ]);
+    assert_eq!(output.code, 1, "{}", output.render());
+    assert!(
+        output.stderr.contains("Checksum mismatch"),
+        "{}",
+        output.render()
+    );
+    assert!(!workspace.path("other.json").exists());
+}
+
+#[test]
+fn test_descendants() {
+    let workspace = Workspace::with_fixtures("descendants");
+    workspace.init(&["--datadir", "data", "--nodes", "nodes.dmp"]);
+    assert_snapshot(
+        "descendants",
+        &workspace.run(&["descendants", "--cache", "cache.json", "--taxid", "1883"]),
+    );
+    assert_snapshot(
+        "descendants_not_found",
+        &workspace.run(&["descendants", "--cache", "cache.json", "--taxid", "42"]),
+    );
+}
+
+#[test]
+fn test_lca() {
+    let workspace = Workspace::with_fixtures("lca");
+    workspace.init(&["--datadir", "data", "--nodes", "nodes.dmp"]);
+    assert_snapshot(
+        "lca",
+        &workspace.run(&[
+            "lca",
+            "--cache",
+            "cache.json",
+            "--taxid",
+            "12345",
+            "--taxid",
+            "1883",
+        ]),
+    );
+    assert_snapshot(
+        "lca_root",
+        &workspace.run(&[
+            "lca",
+            "--cache",
+            "cache.json",
+            "--taxid",
+            "23456",
+            "--taxid",
+            "5062",
+        ]),
+    );
+}
+
+#[test]
+fn test_search() {
+    let workspace = Workspace::with_fixtures("search");
+    workspace.init(&["--datadir", "data"]);
+    assert_snapshot(
+        "search_prefix",
+        &workspace.run(&[
+            "search",
+            "--cache",
+            "cache.json",
+            "--name",
+            "streptomyces",
+            "--match",
+            "prefix",
+            "--ignore-case",
+        ]),
+    );
+    assert_snapshot(
+        "search_not_found",
+        &workspace.run(&["search", "--cache", "cache.json", "--name", "Streptomyces"]),
+    );
+    assert_snapshot(
+        "search_fuzzy",
+        &workspace.run(&[
+            "search",
+            "--cache",
+            "cache.json",
+            "--name",
+            "S. exampils NBC12345",
+            "--fuzzy",
+        ]),
+    );
+}
+
+#[test]
+fn test_export() {
+    let workspace = Workspace::with_fixtures("export");
+    workspace.init(&["--datadir", "data", "--nodes", "nodes.dmp"]);
+    assert_snapshot(
+        "export_tsv",
+        &workspace.run(&["export", "--cache", "cache.json"]),
+    );
+    assert_snapshot(
+        "export_ancestors",
+        &workspace.run(&["export", "--cache", "cache.json", "--include-ancestors"]),
+    );
+    assert_snapshot(
+        "export_where",
+        &workspace.run(&[
+            "export",
+            "--cache",
+            "cache.json",
+            "--where",
+            "superkingdom=Bacteria AND genus~^Strepto",
+        ]),
+    );
+    assert_snapshot(
+        "export_where_invalid",
+        &workspace.run(&["export", "--cache", "cache.json", "--where", "serotype=x"]),
+    );
+    assert_snapshot(
+        "export_sql",
+        &workspace.run(&["export", "--cache", "cache.json", "--format", "sql"]),
+    );
+    assert_snapshot(
+        "export_lineage",
+        &workspace.run(&[
+            "export",
+            "--cache",
+            "cache.json",
+            "--lineage-format",
+            "greengenes",
+        ]),
+    );
+}
+
+#[test]
+fn test_remap() {
+    let workspace = Workspace::with_fixtures("remap");
+    workspace.init(&["--datadir", "data"]);
+    assert_snapshot(
+        "remap_tsv",
+        &workspace.run(&["remap", "--cache", "cache.json"]),
+    );
+    assert_snapshot(
+        "remap_sql",
+        &workspace.run(&[
+            "remap",
+            "--cache",
+            "cache.json",
+            "--format",
+            "sql",
+            "--table",
+            "antismash.genomes",
+            "--column",
+            "tax_id",
+        ]),
+    );
+}
+
+#[test]
+fn test_tree() {
+    let workspace = Workspace::with_fixtures("tree");
+    workspace.init(&["--datadir", "data", "--nodes", "nodes.dmp"]);
+    assert_snapshot(
+        "tree_text",
+        &workspace.run(&["tree", "--cache", "cache.json", "--depth", "1"]),
+    );
+    assert_snapshot(
+        "tree_newick",
+        &workspace.run(&["tree", "--cache", "cache.json", "--format", "newick"]),
+    );
+    assert_snapshot(
+        "tree_dot",
+        &workspace.run(&["tree", "--cache", "cache.json", "--format", "dot"]),
+    );
+}
+
+#[test]
+fn test_gtdb() {
+    let workspace = Workspace::with_fixtures("gtdb");
+    workspace.write(
+        "bac120_metadata.tsv",
+        "accession\tgtdb_taxonomy\tncbi_taxid\n\
+RS_GCF_1\td__Bacteria;p__Actinomycetota;c__Actinomycetes;o__Streptomycetales;f__Streptomycetaceae;g__Streptomyces;s__Streptomyces examplis\t23456\n",
+    );
+    workspace.init(&[
+        "--datadir",
+        "data",
+        "--gtdb-metadata",
+        "bac120_metadata.tsv",
+    ]);
+    assert_snapshot(
+        "list_gtdb",
+        &workspace
+            .run(&[
+                "list",
+                "--cache",
+                "cache.json",
+                "--taxonomy",
+                "gtdb",
+                "--lineage-format",
+                "plain",
+            ])
+            .sorted(),
+    );
+    assert_snapshot(
+        "list_gtdb_where",
+        &workspace.run(&[
+            "list",
+            "--cache",
+            "cache.json",
+            "--where",
+            "gtdb~g__Streptomyces",
+        ]),
+    );
+}
+
+#[test]
+fn test_validate() {
+    let workspace = Workspace::with_fixtures("validate");
+    workspace.init(&["--taxid", "5062", "--taxid", "12345"]);
+    let dumps = ["--mergeddump", "merged.dmp", "--delnodes", "delnodes.dmp"];
+    let mut args = vec![
+        "validate",
+        "--cache",
+        "cache.json",
+        "--taxdump",
+        "rankedlineage.dmp",
+    ];
+    args.extend_from_slice(&dumps);
+    assert_snapshot("validate_clean", &workspace.run(&args));
+
+    workspace.write(
+        "renamed.dmp",
+        &workspace
+            .read("rankedlineage.dmp")
+            .replace("oryzae RIB40", "oryzae"),
+    );
+    args[4] = "renamed.dmp";
+    assert_snapshot("validate_changed", &workspace.run(&args));
+
+    // an edited cache without a checksum, which would fail to load otherwise
+    let mut cache: serde_json::Value = serde_json::from_str(&workspace.read("cache.json")).unwrap();
+    cache.as_object_mut().unwrap().remove("checksum");
+    cache["mappings"]["5062"]["name"] = "".into();
+    workspace.write("cache.json", &cache.to_string());
+    assert_snapshot(
+        "validate_broken",
+        &workspace.run(&["validate", "--cache", "cache.json"]),
+    );
+}
+
+#[test]
+fn test_gc() {
+    let workspace = Workspace::with_fixtures("gc");
+    workspace.init(&[
+        "--taxid",
+        "5062",
+        "--taxid",
+        "12345",
+        "--taxid",
+        "99999",
+        "--delnodes",
+        "delnodes.dmp",
+    ]);
+    let lookup =
+        |taxid: &str| workspace.run(&["lookup", "--cache", "cache.json", "--taxid", taxid]);
+
+    let output = workspace.run(&["gc", "--cache", "cache.json"]);
+    assert_eq!(output.code, 0);
+    assert!(output.stdout.contains("0 taxids removed\n"));
+    assert_eq!(lookup("12345").code, 0);
+
+    workspace.write(
+        "clean/a.json",
+        r#"{"records":[{"dbxrefs":["taxon:5062"]}]}"#,
+    );
+    let output = workspace.run(&["gc", "--cache", "cache.json", "--datadir", "clean"]);
+    assert_eq!(output.code, 0);
+    assert!(output
+        .stdout
+        .contains("1 merged and 1 deleted taxids not referenced by the datadir\n"));
+    assert!(output.stdout.contains("bytes reclaimed"));
+    assert_eq!(lookup("12345").code, 5);
+    assert_eq!(lookup("23456").code, 0);
+    assert_eq!(lookup("5062").code, 0);
+}
+
+#[test]
+fn test_verify() {
+    let workspace = Workspace::with_fixtures("verify");
+    workspace.init(&["--datadir", "data"]);
+    assert_snapshot(
+        "verify_missing",
+        &workspace.run(&["verify", "--cache", "cache.json", "--datadir", "data"]),
+    );
+
+    workspace.write(
+        "clean/a.json",
+        r#"{"records":[{"dbxrefs":["taxon:5062"]}]}"#,
+    );
+    assert_snapshot(
+        "verify_clean",
+        &workspace.run(&["verify", "--cache", "cache.json", "--datadir", "clean"]),
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let workspace = Workspace::with_fixtures("json-output");
+    let run = |args: &[&str]| -> serde_json::Value {
+        let output = workspace.run(args);
+        assert_eq!(output.code, 0, "{}", output.render());
+        serde_json::from_str(&output.stdout).unwrap_or_else(|_| panic!("{}", output.render()))
+    };
+
+    let summary = run(&[
+        "init",
+        "--cache",
+        "cache.json",
+        "--mergeddump",
+        "merged.dmp",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--taxid",
+        "5062",
+        "--taxid",
+        "12345",
+        "--json",
+    ]);
+    assert_eq!(summary["entries"], 2);
+    assert_eq!(summary["merged"], 1);
+    assert_eq!(summary["partial"], false);
+
+    let entry = run(&[
+        "lookup",
+        "--cache",
+        "cache.json",
+        "--taxid",
+        "12345",
+        "--json",
+    ]);
+    assert_eq!(entry["tax_id"], 23456);
+    assert_eq!(entry["genus"], "Streptomyces");
+
+    let entries = run(&["--json", "list", "--cache", "cache.json"]);
+    assert_eq!(entries.as_array().map(Vec::len), Some(2));
+    let info = run(&["info", "--cache", "cache.json", "--json"]);
+    assert_eq!(info["entries"], 2);
+    let ancestor = run(&[
+        "lca",
+        "--cache",
+        "cache.json",
+        "--taxid",
+        "5062",
+        "--taxid",
+        "23456",
+        "--json",
+    ]);
+    assert!(ancestor["tax_id"].is_i64());
+
+    workspace.write(
+        "renamed.dmp",
+        &workspace
+            .read("rankedlineage.dmp")
+            .replace("oryzae RIB40", "oryzae"),
+    );
+    let report = run(&[
+        "update",
+        "--cache",
+        "cache.json",
+        "--taxdump",
+        "renamed.dmp",
+        "--mergeddump",
+        "merged.dmp",
+        "--json",
+    ]);
+    assert_eq!(report["changes"][0]["change"], "renamed");
+    let history = run(&[
+        "history",
+        "--cache",
+        "cache.json",
+        "--taxid",
+        "5062",
+        "--json",
+    ]);
+    assert_eq!(history[0]["to"], "Aspergillus oryzae");
+    assert!(history[0]["updated_at"].is_u64());
+
+    // the data itself goes to stdout, so there is no room for a summary
+    let output = workspace.run(&["tree", "--cache", "cache.json", "--json"]);
+    assert_eq!(output.code, 2, "{}", output.render());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_config_defaults() {
+    let workspace = Workspace::with_fixtures("config-defaults");
+    workspace.write(
+        "config/asdb-taxa/config.toml",
+        r#"# paths relative to this file
+cache = "../../cache.json"
+taxdump = "../../rankedlineage.dmp"
+mergeddump = "../../merged.dmp"
+delnodes = "../../delnodes.dmp"
+datadir = "../../data"
+"#,
+    );
+    let output = workspace.run(&["init"]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    let listed = workspace.run(&["list"]);
+    assert!(listed.stdout.contains("23456: Streptomyces examplis"));
+
+    // environment variables take precedence over the config file, options over both
+    workspace.write(
+        "other/a.json",
+        r#"{"records":[{"dbxrefs":["taxon:1883"]}]}"#,
+    );
+    let env = [
+        ("ASDB_TAXA_CACHE", "other.json"),
+        ("ASDB_TAXA_DATADIR", "other"),
+    ];
+    let output = workspace.run_with_env(&["init"], "", &env);
+    assert_eq!(output.code, 0, "{}", output.render());
+    let listed = workspace.run_with_env(&["list", "--json"], "", &env);
+    assert!(
+        listed.stdout.contains("\"tax_id\":1883"),
+        "{}",
+        listed.render()
+    );
+    let listed = workspace.run_with_env(&["list", "--json", "--cache", "cache.json"], "", &env);
+    assert!(
+        !listed.stdout.contains("\"tax_id\":1883"),
+        "{}",
+        listed.render()
+    );
+
+    // an explicit config file replaces the default one
+    workspace.write("broken.toml", "cache = cache.json\n");
+    let output = workspace.run(&["list", "--config", "broken.toml"]);
+    assert_eq!(output.code, 4, "{}", output.render());
+    assert!(output.stderr.contains("Failed to load config file"));
+}
+
+#[test]
+fn test_taxdump_dir() {
+    let workspace = Workspace::with_fixtures("taxdump-dir");
+    for name in ["rankedlineage.dmp", "merged.dmp", "delnodes.dmp"] {
+        workspace.write(&format!("dumps/{}", name), &workspace.read(name));
+    }
+    let lookup = |cache: &str, taxid: &str| {
+        workspace
+            .run(&["lookup", "--cache", cache, "--taxid", taxid])
+            .code
+    };
+
+    let output = workspace.run(&[
+        "init",
+        "--cache",
+        "cache.json",
+        "--taxdump-dir",
+        "dumps",
+        "--taxid",
+        "12345",
+        "--taxid",
+        "99999",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    assert_eq!(lookup("cache.json", "12345"), 0);
+    // found delnodes.dmp as well
+    let output = workspace.run(&["lookup", "--cache", "cache.json", "--taxid", "99999"]);
+    assert!(
+        output.stderr.contains("TaxID deleted by NCBI"),
+        "{}",
+        output.render()
+    );
+
+    let output = workspace.run(&["init", "--cache", "found.json", "--taxid", "5062"]);
+    assert_eq!(output.code, 2, "{}", output.render());
+    assert!(output.stderr.contains("No merged ID dump given"));
+
+    // TaxonKit's data directory is used without asking
+    workspace.write("taxonkit/merged.dmp", &workspace.read("merged.dmp"));
+    let output = workspace.run(&[
+        "init",
+        "--cache",
+        "found.json",
+        "--taxdump",
+        "rankedlineage.dmp",
+        "--taxid",
+        "12345",
+    ]);
+    assert_eq!(output.code, 0, "{}", output.render());
+    assert_eq!(lookup("found.json", "12345"), 0);
+}
@@ -0,0 +1,218 @@
+// Copyright 2023 Danmarks Tekniske Universitet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Harness for running the `asdb-taxa` binary in a temporary workspace
+//!
+//! Commands run inside the workspace, so relative paths keep the output free of temporary
+//! directory names. Snapshots live in `tests/snapshots/`, run with `UPDATE_SNAPSHOTS=1` to
+//! (re)write them after an intended change of the output.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tempfile::TempDir;
+
+pub const RANKEDLINEAGE: &str = "\
+23456\t|\tStreptomyces examplis NBC12345\t|\tStreptomyces examplis\t|\tStreptomyces\t|\tStreptomycetaceae\t|\tStreptomycetales\t|\tActinomycetia\t|\tActinobacteria\t|\t\t|\tBacteria\t|
+5062\t|\tAspergillus oryzae RIB40\t|\tAspergillus oryzae\t|\tAspergillus\t|\tAspergillaceae\t|\tEurotiales\t|\tEurotiomycetes\t|\tAscomycota\t|\tFungi\t|\tEukaryota\t|
+1883\t|\tStreptomyces\t|\t\t|\t\t|\tStreptomycetaceae\t|\tStreptomycetales\t|\tActinomycetia\t|\tActinobacteria\t|\t\t|\tBacteria\t|
+";
+
+pub const MERGED: &str = "12345\t|\t23456\t|\n";
+
+pub const DELNODES: &str = "99999\t|\n";
+
+pub const NODES: &str = "\
+1\t|\t1\t|\tno rank\t|
+2\t|\t1\t|\tsuperkingdom\t|
+1883\t|\t2\t|\tgenus\t|
+23456\t|\t1883\t|\tspecies\t|
+5062\t|\t1\t|\tspecies\t|
+";
+
+/// A temporary directory with dumps and a datadir, removed when dropped
+pub struct Workspace {
+    dir: TempDir,
+}
+
+impl Workspace {
+    /// Empty workspace, named after the test `name` to find it while the test runs
+    pub fn new(name: &str) -> Workspace {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("asdb-taxa-cli-{}-", name))
+            .tempdir()
+            .unwrap();
+        Workspace { dir }
+    }
+
+    /// Workspace with the standard dumps and a datadir referencing a merged, a current and a
+    /// deleted taxid
+    pub fn with_fixtures(name: &str) -> Workspace {
+        let workspace = Workspace::new(name);
+        workspace.write("rankedlineage.dmp", RANKEDLINEAGE);
+        workspace.write("merged.dmp", MERGED);
+        workspace.write("delnodes.dmp", DELNODES);
+        workspace.write("nodes.dmp", NODES);
+        workspace.write(
+            "data/a.json",
+            r#"{"records":[{"dbxrefs":["taxon:12345"]}]}"#,
+        );
+        workspace.write("data/b.json", r#"{"records":[{"dbxrefs":["taxon:5062"]}]}"#);
+        workspace.write(
+            "data/c.json",
+            r#"{"records":[{"dbxrefs":["taxon:99999"]}]}"#,
+        );
+        workspace
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir.path().join(relative)
+    }
+
+    pub fn write(&self, relative: &str, content: &str) {
+        let path = self.path(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, content).unwrap();
+    }
+
+    pub fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path(relative)).unwrap()
+    }
+
+    /// Run the binary with `args` inside the workspace
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_stdin(args, "")
+    }
+
+    pub fn run_with_stdin(&self, args: &[&str], stdin: &str) -> Output {
+        self.run_with_env(args, stdin, &[])
+    }
+
+    /// Run the binary with `vars` as the only settings from the environment, a config file
+    /// being read from `config/asdb-taxa/config.toml` and TaxonKit's dumps from `taxonkit/` in
+    /// the workspace
+    pub fn run_with_env(&self, args: &[&str], stdin: &str, vars: &[(&str, &str)]) -> Output {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_asdb-taxa"));
+        for (name, _) in env::vars_os() {
+            if name.to_string_lossy().starts_with("ASDB_TAXA_") {
+                command.env_remove(name);
+            }
+        }
+        let mut child = command
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("TAXONKIT_DB", self.path("taxonkit"))
+            .envs(vars.iter().copied())
+            .args(args)
+            .current_dir(self.dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        Output {
+            code: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+
+    /// Initialise `cache.json` from the fixtures, panicking on failure
+    pub fn init(&self, extra: &[&str]) -> Output {
+        let mut args = vec![
+            "init",
+            "--cache",
+            "cache.json",
+            "--mergeddump",
+            "merged.dmp",
+            "--taxdump",
+            "rankedlineage.dmp",
+        ];
+        args.extend_from_slice(extra);
+        let output = self.run(&args);
+        assert_eq!(output.code, 0, "init failed:\n{}", output.render());
+        output
+    }
+}
+
+/// Exit code and output of a run
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    /// Sort the stdout lines up to the first empty one, for commands printing cache entries in
+    /// storage order
+    pub fn sorted(mut self) -> Output {
+        let lines: Vec<&str> = self.stdout.lines().collect();
+        let end = lines
+            .iter()
+            .position(|line| line.is_empty())
+            .unwrap_or(lines.len());
+        let mut entries = lines[..end].to_vec();
+        entries.sort();
+        entries.extend_from_slice(&lines[end..]);
+        self.stdout = entries.iter().map(|line| format!("{}\n", line)).collect();
+        self
+    }
+
+    /// Text form used for snapshots
+    pub fn render(&self) -> String {
+        format!(
+            "exit: {}\n--- stdout\n{}--- stderr\n{}",
+            self.code, self.stdout, self.stderr
+        )
+    }
+}
+
+/// Compare `output` to the snapshot `name`, or write it if `UPDATE_SNAPSHOTS` is set
+pub fn assert_snapshot(name: &str, output: &Output) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{}.snap", name));
+    let actual = output.render();
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it\n{}",
+            path.display(),
+            actual
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "output differs from snapshot {}, run with UPDATE_SNAPSHOTS=1 if this is intended",
+        name
+    );
+}